@@ -0,0 +1,411 @@
+//! Script a running node through its REST API and WebSocket events.
+//!
+//! Usage: corelink-cli [--host <host>] [--port <node port>] [--json] <command> [args...]
+
+use serde_json::Value;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: corelink-cli [--host <host>] [--port <node port>] [--json] <command>
+
+Commands:
+  status               Show node statistics
+  peers                List connected peers
+  files                List offered and downloading files
+  file <file_id>       Show a file's chunks, sources, speed and errors
+  offer <path>         Offer a file on the node's filesystem
+  upload <path>        Send a local file to the node and offer it
+  download <file_id>   Download a file offered by a peer
+  remove <file_id>     Cancel a download, withdraw an offer or delete a file
+  events [--msgpack] [<topic>...]
+                       Print WebSocket events as they happen, optionally
+                       received as MessagePack and only on topics such as
+                       files/* or consensus
+
+The node's API and its events at /ws listen on <node port> + 3000.";
+
+/// Where and how to reach the node
+#[derive(Debug, PartialEq)]
+struct Options {
+    host: String,
+    port: u16,
+    /// Print raw JSON instead of a summary
+    json: bool,
+    command: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            host: "127.0.0.1".to_string(),
+            port: 4001,
+            json: false,
+            command: Vec::new(),
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--host" => options.host = args.next().ok_or("--host needs a value")?,
+                "--port" => {
+                    let port = args.next().ok_or("--port needs a value")?;
+                    options.port = port
+                        .parse()
+                        .map_err(|_| format!("Invalid port: {}", port))?;
+                }
+                "--json" => options.json = true,
+                _ => {
+                    options.command.push(arg);
+                    options.command.extend(args.by_ref());
+                }
+            }
+        }
+        Ok(options)
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        format!("http://{}:{}{}", self.host, self.port as u32 + 3000, path)
+    }
+
+    fn ws_url(&self) -> String {
+        format!("ws://{}:{}/ws", self.host, self.port as u32 + 3000)
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("corelink-cli: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("corelink-cli: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let command: Vec<&str> = options.command.iter().map(String::as_str).collect();
+    match command.as_slice() {
+        ["status"] => {
+            let stats = get(options, "/api/v1/stats")?;
+            print(options, &stats, |stats| {
+                vec![
+                    format!("Peers:     {}", stats["peer_count"]),
+                    format!("Uploads:   {}", stats["active_uploads"]),
+                    format!("Downloads: {}", stats["active_downloads"]),
+                    format!("Uptime:    {}s", stats["uptime_seconds"]),
+                    format!(
+                        "Events:    {} client(s), {} dropped",
+                        stats["websocket_clients"], stats["websocket_events_dropped"]
+                    ),
+                ]
+            })
+        }
+        ["peers"] => {
+            let peers = get(options, "/api/v1/peers")?;
+            print(options, &peers, |peers| {
+                list(peers)
+                    .map(|peer| {
+                        format!(
+                            "{}  rtt {}  reputation {}",
+                            text(&peer["peer_id"]),
+                            peer["rtt_ms"]
+                                .as_f64()
+                                .map_or("?".to_string(), |rtt| format!("{:.0}ms", rtt)),
+                            peer["reputation"]
+                        )
+                    })
+                    .collect()
+            })
+        }
+        ["files"] => {
+            let files = get(options, "/api/v1/files")?;
+            print(options, &files, |files| {
+                list(files)
+                    .map(|file| {
+                        format!(
+                            "{}  {}  {} bytes  {} {:.1}%",
+                            text(&file["file_id"]),
+                            text(&file["name"]),
+                            file["size"],
+                            text(&file["status"]),
+                            file["progress"].as_f64().unwrap_or(0.0) * 100.0
+                        )
+                    })
+                    .collect()
+            })
+        }
+        ["offer", path] => {
+            // The node reads the file, so resolve it before handing it over
+            let path = std::fs::canonicalize(path).map_err(|e| format!("{}: {}", path, e))?;
+            let file = post(
+                options,
+                "/api/v1/files/offer",
+                serde_json::json!({ "path": path }),
+            )?;
+            print(options, &file, |file| {
+                vec![format!(
+                    "Offering {} as {}",
+                    text(&file["name"]),
+                    text(&file["file_id"])
+                )]
+            })
+        }
+        ["file", file_id] => {
+            let file = get(options, &format!("/api/v1/files/{}", file_id))?;
+            print(options, &file, |file| {
+                let mut lines = vec![
+                    format!("{}  {}", text(&file["name"]), text(&file["status"])),
+                    format!(
+                        "Chunks:  {}/{}  {}",
+                        file["chunks_received"],
+                        file["chunks"],
+                        text(&file["chunk_bitmap"])
+                    ),
+                    format!(
+                        "Speed:   {:.0} B/s  ETA {}",
+                        file["speed"].as_f64().unwrap_or_default(),
+                        file["eta_seconds"]
+                            .as_u64()
+                            .map_or("?".to_string(), |eta| format!("{}s", eta))
+                    ),
+                ];
+                lines.extend(list(&file["sources"]).map(|source| {
+                    format!(
+                        "Source:  {}  {} chunks{}",
+                        text(&source["peer_id"]),
+                        source["chunks_received"],
+                        if source["active"] == true {
+                            ""
+                        } else {
+                            "  (past)"
+                        }
+                    )
+                }));
+                lines.extend(
+                    list(&file["errors"])
+                        .map(|error| format!("Error:   {}", text(&error["message"]))),
+                );
+                lines
+            })
+        }
+        ["upload", path] => {
+            let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+            let name = std::path::Path::new(path)
+                .file_name()
+                .ok_or_else(|| format!("Not a file: {}", path))?
+                .to_string_lossy();
+            let file = response(
+                ureq::post(&options.api_url("/api/v1/files/upload"))
+                    .query("name", &name)
+                    .send(file),
+            )?;
+            print(options, &file, |file| {
+                vec![format!(
+                    "Uploaded {} as {}",
+                    text(&file["name"]),
+                    text(&file["file_id"])
+                )]
+            })
+        }
+        ["download", file_id] => {
+            let path = format!("/api/v1/files/{}/download", file_id);
+            let result = post(options, &path, serde_json::json!({}))?;
+            print(options, &result, |_| {
+                vec![format!("Downloading {}", file_id)]
+            })
+        }
+        ["remove", file_id] => {
+            let path = format!("/api/v1/files/{}", file_id);
+            let result = response(ureq::delete(&options.api_url(&path)).call())?;
+            print(options, &result, |result| {
+                vec![format!("{}: {}", file_id, text(&result["removed"]))]
+            })
+        }
+        ["events", "--msgpack", topics @ ..] => events(options, true, topics),
+        ["events", topics @ ..] => events(options, false, topics),
+        [] | ["help"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!(
+            "Unknown command '{}'\n\n{}",
+            command.join(" "),
+            USAGE
+        )),
+    }
+}
+
+fn get(options: &Options, path: &str) -> Result<Value, String> {
+    response(ureq::get(&options.api_url(path)).call())
+}
+
+fn post(options: &Options, path: &str, body: Value) -> Result<Value, String> {
+    response(ureq::post(&options.api_url(path)).send_json(body))
+}
+
+/// Read a JSON body, turning error statuses into the node's error message
+fn response(result: Result<ureq::Response, ureq::Error>) -> Result<Value, String> {
+    match result {
+        Ok(response) => response.into_json().map_err(|e| e.to_string()),
+        Err(ureq::Error::Status(code, response)) => {
+            let body: Value = response.into_json().unwrap_or_default();
+            Err(format!("{} ({})", error_message(&body), code))
+        }
+        Err(e) => Err(format!("Cannot reach node: {}", e)),
+    }
+}
+
+/// Print `value` as JSON or through `summary`
+fn print(
+    options: &Options,
+    value: &Value,
+    summary: impl Fn(&Value) -> Vec<String>,
+) -> Result<(), String> {
+    if options.json {
+        println!("{}", value);
+    } else {
+        for line in summary(value) {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Follow the event stream until the node goes away, one JSON event per line,
+/// warning about events missed along the way
+fn events(options: &Options, msgpack: bool, topics: &[&str]) -> Result<(), String> {
+    use tungstenite::client::IntoClientRequest;
+
+    let mut request = options
+        .ws_url()
+        .into_client_request()
+        .map_err(|e| e.to_string())?;
+    if msgpack {
+        request.headers_mut().insert(
+            "Sec-WebSocket-Protocol",
+            tungstenite::http::HeaderValue::from_static("corelink.msgpack"),
+        );
+    }
+    let (mut socket, _) =
+        tungstenite::connect(request).map_err(|e| format!("Cannot reach node: {}", e))?;
+    if !topics.is_empty() {
+        let join = serde_json::json!({ "type": "join", "topics": topics });
+        socket
+            .send(tungstenite::Message::Text(join.to_string()))
+            .map_err(|e| e.to_string())?;
+    }
+    let mut last_seq = None;
+    loop {
+        match socket.read() {
+            Ok(tungstenite::Message::Text(event)) => {
+                if let Ok(value) = serde_json::from_str(&event) {
+                    check_seq(&mut last_seq, &value);
+                }
+                println!("{}", event);
+            }
+            // MessagePack events, printed as JSON all the same
+            Ok(tungstenite::Message::Binary(event)) => {
+                match rmp_serde::from_slice::<Value>(&event) {
+                    Ok(event) => {
+                        check_seq(&mut last_seq, &event);
+                        println!("{}", event);
+                    }
+                    Err(e) => eprintln!("corelink-cli: undecodable event: {}", e),
+                }
+            }
+            Ok(tungstenite::Message::Close(_)) => return Ok(()),
+            Ok(_) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Note the sequence number of `event`, warning if events before it are
+/// missing. Replies to the client carry no number.
+fn check_seq(last_seq: &mut Option<u64>, event: &Value) -> Option<(u64, u64)> {
+    let seq = event["seq"].as_u64()?;
+    let missed = last_seq
+        .filter(|last| seq > last + 1)
+        .map(|last| (last + 1, seq - 1));
+    if let Some((first, last)) = missed {
+        eprintln!(
+            "corelink-cli: missed events {}..={}; refetch state over the REST API",
+            first, last
+        );
+    }
+    *last_seq = Some(seq);
+    missed
+}
+
+fn list(value: &Value) -> impl Iterator<Item = &Value> {
+    value.as_array().into_iter().flatten()
+}
+
+/// The message of a problem body, or the `error` of older nodes
+fn error_message(body: &Value) -> &str {
+    body["detail"]
+        .as_str()
+        .or_else(|| body["error"].as_str())
+        .unwrap_or("?")
+}
+
+fn text(value: &Value) -> &str {
+    value.as_str().unwrap_or("?")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let options = parse(&["--port", "4002", "--json", "offer", "notes.txt"]).unwrap();
+        assert_eq!(options.command, vec!["offer", "notes.txt"]);
+        assert!(options.json);
+        assert_eq!(
+            options.api_url("/api/v1/peers"),
+            "http://127.0.0.1:7002/api/v1/peers"
+        );
+        assert_eq!(options.ws_url(), "ws://127.0.0.1:7002/ws");
+
+        // Everything after the command belongs to it
+        let options = parse(&["download", "--port"]).unwrap();
+        assert_eq!(options.command, vec!["download", "--port"]);
+        assert_eq!(options.port, 4001);
+
+        assert!(parse(&["--port", "seventy"]).is_err());
+    }
+
+    #[test]
+    fn test_check_seq() {
+        let mut last_seq = None;
+        let event = |seq: u64| serde_json::json!({ "type": "PeerConnected", "seq": seq });
+        assert_eq!(check_seq(&mut last_seq, &event(4)), None);
+        assert_eq!(check_seq(&mut last_seq, &event(5)), None);
+        // Replies are not numbered
+        let reply = serde_json::json!({ "type": "Subscribed" });
+        assert_eq!(check_seq(&mut last_seq, &reply), None);
+        assert_eq!(check_seq(&mut last_seq, &event(9)), Some((6, 8)));
+        assert_eq!(last_seq, Some(9));
+    }
+
+    #[test]
+    fn test_error_message() {
+        let problem = serde_json::json!({ "status": 404, "detail": "No such file" });
+        assert_eq!(error_message(&problem), "No such file");
+        assert_eq!(error_message(&serde_json::json!({ "error": "old" })), "old");
+        assert_eq!(error_message(&Value::Null), "?");
+    }
+}
@@ -1,8 +1,96 @@
+use crate::{CoreLinkError, NodeId, Result};
+use std::collections::HashSet;
+
+/// Distinct peers that must have moved past our epoch before we follow them
+pub const EPOCH_QUORUM: usize = 3;
+
 #[derive(Default)]
-pub struct Consensus;
+pub struct Consensus {
+    epoch: u64,
+    /// Peers whose messages carried a later epoch than ours, this round
+    ahead: HashSet<NodeId>,
+}
 
 impl Consensus {
     pub fn new() -> Self {
-        Self
+        Self::default()
+    }
+
+    pub fn current_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Start a new epoch, e.g. after a leadership change.
+    pub fn advance_epoch(&mut self) -> u64 {
+        self.epoch += 1;
+        self.ahead.clear();
+        self.epoch
+    }
+
+    /// Check the epoch stamped on an incoming message from `from`, the
+    /// authenticated peer that sent it.
+    ///
+    /// Unstamped messages are accepted as-is and messages from an older
+    /// epoch are reported as stale. Stamps are unsigned, so a later epoch is
+    /// only a vote: once [`EPOCH_QUORUM`] distinct peers are ahead of us we
+    /// move to the next epoch, one step per round however far they claim.
+    pub fn observe_epoch(&mut self, from: NodeId, epoch: Option<u64>) -> Result<()> {
+        match epoch {
+            None => Ok(()),
+            Some(epoch) if epoch < self.epoch => Err(CoreLinkError::Consensus(format!(
+                "stale epoch {} (current {})",
+                epoch, self.epoch
+            ))),
+            Some(epoch) if epoch == self.epoch => Ok(()),
+            Some(_) => {
+                self.ahead.insert(from);
+                if self.ahead.len() >= EPOCH_QUORUM {
+                    self.advance_epoch();
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer() -> NodeId {
+        crate::identity::Identity::generate().node_id()
+    }
+
+    #[test]
+    fn test_observe_epoch() {
+        let mut consensus = Consensus::new();
+        assert_eq!(consensus.current_epoch(), 0);
+
+        assert!(consensus.observe_epoch(peer(), None).is_ok());
+        // A quorum of peers ahead moves us on by one epoch
+        for _ in 0..EPOCH_QUORUM {
+            assert!(consensus.observe_epoch(peer(), Some(3)).is_ok());
+        }
+        assert_eq!(consensus.current_epoch(), 1);
+
+        assert!(consensus.observe_epoch(peer(), Some(1)).is_ok());
+        assert!(consensus.observe_epoch(peer(), Some(0)).is_err());
+        assert_eq!(consensus.current_epoch(), 1);
+
+        assert_eq!(consensus.advance_epoch(), 2);
+        assert!(consensus.observe_epoch(peer(), Some(1)).is_err());
+    }
+
+    #[test]
+    fn test_hostile_epoch_is_not_adopted() {
+        let mut consensus = Consensus::new();
+        let hostile = peer();
+
+        for _ in 0..10 {
+            assert!(consensus.observe_epoch(hostile, Some(u64::MAX)).is_ok());
+        }
+        assert_eq!(consensus.current_epoch(), 0);
+        // Honest peers on the current epoch are still heard
+        assert!(consensus.observe_epoch(peer(), Some(0)).is_ok());
     }
 }
@@ -18,6 +18,16 @@ pub struct FileMetadata {
     pub chunk_hashes: Vec<[u8; 32]>,
     pub mime_type: Option<String>,
     pub created_at: u64,
+    /// 1 for a newly offered file, incremented each time a changed copy is re-offered
+    #[serde(default = "first_version")]
+    pub version: u32,
+    /// file_id of the version this one replaces
+    #[serde(default)]
+    pub previous_file_id: Option<String>,
+}
+
+fn first_version() -> u32 {
+    1
 }
 
 impl FileMetadata {
@@ -39,6 +49,8 @@ impl FileMetadata {
             chunk_hashes,
             mime_type: None,
             created_at,
+            version: first_version(),
+            previous_file_id: None,
         }
     }
 
@@ -46,6 +58,38 @@ impl FileMetadata {
         self.mime_type = Some(mime_type);
         self
     }
+
+    /// Mark this file as the next version of `previous`
+    pub fn with_previous_version(mut self, previous: &FileMetadata) -> Self {
+        self.version = previous.version + 1;
+        self.previous_file_id = Some(previous.file_id.clone());
+        self
+    }
+
+    /// Root of the Merkle tree over the chunk hashes. An odd hash out on a
+    /// level is carried up unchanged.
+    pub fn merkle_root(&self) -> [u8; 32] {
+        let mut level = self.chunk_hashes.clone();
+        if level.is_empty() {
+            return Sha256::digest([]).into();
+        }
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => {
+                        let mut hasher = Sha256::new();
+                        hasher.update(left);
+                        hasher.update(right);
+                        hasher.finalize().into()
+                    }
+                    [single] => *single,
+                    _ => unreachable!("chunks of two"),
+                })
+                .collect();
+        }
+        level[0]
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -105,6 +149,15 @@ impl FileTransfer {
         }
     }
 
+    /// Forget a chunk, e.g. one found corrupt during crash recovery
+    pub fn mark_chunk_missing(&mut self, chunk_index: u32) {
+        if self.downloaded_chunks.remove(&chunk_index) {
+            self.missing_chunks.push(chunk_index);
+            self.missing_chunks.sort_unstable();
+            self.progress = self.downloaded_chunks.len() as f32 / self.metadata.total_chunks as f32;
+        }
+    }
+
     pub fn is_complete(&self) -> bool {
         self.downloaded_chunks.len() == self.metadata.total_chunks as usize
     }
@@ -123,6 +176,19 @@ pub fn calculate_chunk_hash(data: &[u8]) -> [u8; 32] {
     hasher.finalize().into()
 }
 
+/// Proof of storage over a slice of chunk data: SHA256(nonce || data[offset..offset + length]).
+///
+/// Returns None if the slice lies outside the chunk.
+pub fn storage_proof(nonce: &[u8; 32], data: &[u8], offset: u32, length: u32) -> Option<[u8; 32]> {
+    let start = offset as usize;
+    let slice = data.get(start..start.checked_add(length as usize)?)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(nonce);
+    hasher.update(slice);
+    Some(hasher.finalize().into())
+}
+
 /// Verify that a chunk's data matches its hash
 pub fn verify_chunk(chunk: &FileChunk) -> bool {
     let calculated_hash = calculate_chunk_hash(&chunk.data);
@@ -175,6 +241,8 @@ pub fn split_file_to_chunks(
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs(),
+        version: first_version(),
+        previous_file_id: None,
     };
 
     Ok((metadata, chunks))
@@ -272,7 +340,11 @@ pub fn write_chunk_to_file(
 
     // Calculate offset
     let offset = chunk.chunk_index as u64 * metadata.chunk_size as u64;
+    write_chunk_at(&chunk.data, offset, output)
+}
 
+/// Write chunk data to a file at `offset`, without checking it
+pub fn write_chunk_at(data: &[u8], offset: u64, output: &Path) -> io::Result<()> {
     // Open file and seek to offset (don't truncate - we're writing chunks incrementally)
     let mut file = OpenOptions::new()
         .create(true)
@@ -281,12 +353,48 @@ pub fn write_chunk_to_file(
         .open(output)?;
 
     file.seek(SeekFrom::Start(offset))?;
-    file.write_all(&chunk.data)?;
-    file.flush()?;
+    file.write_all(data)?;
+    // Make the data durable before the caller records the chunk as downloaded,
+    // so a crash can never leave the bitmap ahead of the file
+    file.sync_data()?;
 
     Ok(())
 }
 
+/// Check whether the region of `path` holding chunk `chunk_index` matches its hash.
+///
+/// Used on recovery to drop chunks whose write was interrupted by a crash.
+pub fn chunk_on_disk_is_valid(
+    metadata: &FileMetadata,
+    chunk_index: u32,
+    path: &Path,
+) -> io::Result<bool> {
+    let Some(expected) = metadata.chunk_hashes.get(chunk_index as usize) else {
+        return Ok(false);
+    };
+
+    let offset = chunk_index as u64 * metadata.chunk_size as u64;
+    let len = (metadata.size.saturating_sub(offset)).min(metadata.chunk_size as u64);
+
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut data = vec![0u8; len as usize];
+    match file.read_exact(&mut data) {
+        Ok(()) => Ok(calculate_chunk_hash(&data) == *expected),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `name` contains every whitespace-separated term of `query`,
+/// ignoring case
+pub fn name_matches(name: &str, query: &str) -> bool {
+    let name = name.to_lowercase();
+    query
+        .split_whitespace()
+        .all(|term| name.contains(&term.to_lowercase()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -305,6 +413,27 @@ mod tests {
         assert_ne!(hash1, hash3);
     }
 
+    #[test]
+    fn test_merkle_root_covers_every_chunk() {
+        let hashes: Vec<[u8; 32]> = (0..3u8).map(|i| calculate_chunk_hash(&[i])).collect();
+        let metadata = FileMetadata::new("a.txt".to_string(), 3, hashes.clone());
+        assert_eq!(metadata.merkle_root(), metadata.clone().merkle_root());
+
+        let single = FileMetadata::new("a.txt".to_string(), 1, hashes[..1].to_vec());
+        assert_eq!(single.merkle_root(), hashes[0]);
+
+        let mut changed = metadata.clone();
+        changed.chunk_hashes[2] = calculate_chunk_hash(b"other");
+        assert_ne!(changed.merkle_root(), metadata.merkle_root());
+    }
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("Holiday Photos 2024.zip", "photos zip"));
+        assert!(name_matches("report.PDF", "Report"));
+        assert!(!name_matches("report.pdf", "report draft"));
+    }
+
     #[test]
     fn test_chunk_verification() {
         let data = b"Test data".to_vec();
@@ -345,6 +474,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_storage_proof() {
+        let data = b"proof of storage data";
+        let nonce = [1u8; 32];
+
+        let proof = storage_proof(&nonce, data, 3, 5).unwrap();
+        assert_eq!(storage_proof(&nonce, data, 3, 5), Some(proof));
+        assert_ne!(storage_proof(&[2u8; 32], data, 3, 5), Some(proof));
+        assert_ne!(
+            storage_proof(&nonce, b"proOF of storage data", 3, 5),
+            Some(proof)
+        );
+        assert_eq!(storage_proof(&nonce, data, 20, 5), None);
+    }
+
+    #[test]
+    fn test_torn_chunk_write_detected() -> io::Result<()> {
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(&[7u8; 100])?;
+        temp_file.flush()?;
+
+        let (metadata, chunks) = split_file_to_chunks(temp_file.path(), 64)?;
+
+        let output = NamedTempFile::new()?;
+        write_chunk_to_file(&chunks[1], &metadata, output.path())?;
+        assert!(chunk_on_disk_is_valid(&metadata, 1, output.path())?);
+        assert!(!chunk_on_disk_is_valid(&metadata, 0, output.path())?);
+
+        // Simulate a crash that left the chunk half written
+        let mut file = OpenOptions::new().write(true).open(output.path())?;
+        file.seek(SeekFrom::Start(80))?;
+        file.write_all(&[0u8; 10])?;
+        assert!(!chunk_on_disk_is_valid(&metadata, 1, output.path())?);
+
+        Ok(())
+    }
+
     #[test]
     fn test_file_transfer_progress() {
         let temp_file = NamedTempFile::new().unwrap();
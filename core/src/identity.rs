@@ -1,4 +1,5 @@
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, SECRET_KEY_LENGTH};
+use libp2p_identity::PeerId;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
@@ -11,6 +12,20 @@ impl NodeId {
         Self(*hash.as_bytes())
     }
 
+    /// NodeId of a libp2p peer. Peers with ed25519 keys get the same id as
+    /// [`NodeId::from_pubkey`]; other keys are hashed from the peer id bytes.
+    pub fn from_peer_id(peer_id: &PeerId) -> Self {
+        let pubkey = libp2p_identity::PublicKey::try_decode_protobuf(peer_id.as_ref().digest())
+            .ok()
+            .and_then(|key| key.try_into_ed25519().ok())
+            .and_then(|key| VerifyingKey::from_bytes(&key.to_bytes()).ok());
+
+        match pubkey {
+            Some(pubkey) => Self::from_pubkey(&pubkey),
+            None => Self(*blake3::hash(&peer_id.to_bytes()).as_bytes()),
+        }
+    }
+
     pub fn as_bytes(&self) -> &[u8; 32] {
         &self.0
     }
@@ -10,8 +10,12 @@ pub mod storage;
 pub use file::{FileChunk, FileMetadata, FileTransfer};
 pub use identity::{Identity, NodeId};
 pub use message::{Message, MessageType};
-pub use network::{NetworkState, PeerInfo};
-pub use protocol::{CoreLinkCodec, CoreLinkProtocol};
+pub use network::{NetworkEvent, NetworkState, PeerInfo, Traffic};
+pub use protocol::{
+    CoreLinkCodec, CoreLinkProtocol, TransferCodec, TransferRequest, TransferResponse,
+    MAX_CHUNK_BATCH, MESSAGE_PROTOCOL, PROTOCOL_VERSION, TRANSFER_PROTOCOL,
+};
+pub use storage::{BlockStore, ObjectStore, PinSet, Storage};
 
 #[derive(Debug, thiserror::Error)]
 pub enum CoreLinkError {
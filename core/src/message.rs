@@ -1,4 +1,5 @@
 use crate::{FileChunk, FileMetadata, NodeId};
+use libp2p_identity::{Keypair, PeerId, PublicKey, SigningError};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,6 +9,58 @@ pub struct Message {
     pub msg_type: MessageType,
     pub timestamp: u64,
     pub signature: Vec<u8>,
+    /// Consensus epoch the sender considered current when the message was built.
+    /// Absent on messages from nodes that predate epoch stamping.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+}
+
+impl Message {
+    pub fn new(from: NodeId, msg_type: MessageType) -> Self {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        Self {
+            from,
+            to: None,
+            msg_type,
+            timestamp,
+            signature: vec![],
+            epoch: None,
+        }
+    }
+
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = Some(epoch);
+        self
+    }
+
+    /// Sign the message as `keypair`'s node, replacing its sender
+    pub fn signed(mut self, keypair: &Keypair) -> Result<Self, SigningError> {
+        self.from = NodeId::from_peer_id(&keypair.public().to_peer_id());
+        self.signature = keypair.sign(&self.signed_bytes())?;
+        Ok(self)
+    }
+
+    /// Whether the message carries a valid signature by `peer`. Only peers
+    /// whose id embeds their public key, such as ed25519 ones, can be checked.
+    pub fn verify(&self, peer: &PeerId) -> bool {
+        let Ok(key) = PublicKey::try_decode_protobuf(peer.as_ref().digest()) else {
+            return false;
+        };
+        self.from == NodeId::from_peer_id(peer) && key.verify(&self.signed_bytes(), &self.signature)
+    }
+
+    /// What a signature covers: the message serialized without its signature
+    fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = Message {
+            signature: vec![],
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).expect("messages serialize to JSON")
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +72,9 @@ pub enum MessageType {
     Pong,
     // File transfer protocol messages
     FileOffer(FileMetadata),
+    // Chunks and files are requested over `TRANSFER_PROTOCOL` instead, chunks
+    // in batches where the peer supports it; these four only remain so that
+    // messages from older nodes still decode
     FileRequest {
         file_id: String,
         requester: NodeId,
@@ -40,12 +96,163 @@ pub enum MessageType {
         file_id: String,
         reason: String,
     },
+    /// The sender no longer offers this file
+    FileWithdraw {
+        file_id: String,
+    },
+    /// The sender declined to download a file offered to it. Only sent to
+    /// peers advertising [`OFFER_REJECTION_FEATURE`].
+    OfferRejected {
+        file_id: String,
+    },
+    /// Ask a holder to prove it stores a file by hashing `nonce` followed by
+    /// `length` bytes of chunk `chunk_index` starting at `offset`
+    StorageChallenge {
+        file_id: String,
+        chunk_index: u32,
+        offset: u32,
+        length: u32,
+        nonce: [u8; 32],
+    },
+    /// Answer to a StorageChallenge. `proof` is None if the data is not held.
+    StorageProof {
+        file_id: String,
+        nonce: [u8; 32],
+        proof: Option<[u8; 32]>,
+    },
+    /// Ask for offered files whose name matches `query`, as decided by
+    /// [`crate::file::name_matches`]
+    FileQuery {
+        query_id: u64,
+        query: String,
+        limit: u32,
+    },
+    /// Offered files matching a FileQuery
+    FileQueryResults {
+        query_id: u64,
+        files: Vec<FileMetadata>,
+    },
+    /// Every file the sender offers, sent to peers that connect later than
+    /// the offers went out. Only sent to peers advertising
+    /// [`FILE_CATALOG_FEATURE`].
+    FileCatalog(Vec<CatalogEntry>),
+    /// Message of an application built on the node, passed on untouched.
+    /// Always signed by its sender.
+    Custom {
+        msg_type: String,
+        payload: serde_json::Value,
+    },
+}
+
+impl MessageType {
+    /// Name of the variant, for logs and events
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageType::Discovery(_) => "Discovery",
+            MessageType::DataTransfer(_) => "DataTransfer",
+            MessageType::Consensus(_) => "Consensus",
+            MessageType::Ping => "Ping",
+            MessageType::Pong => "Pong",
+            MessageType::FileOffer(_) => "FileOffer",
+            MessageType::FileRequest { .. } => "FileRequest",
+            MessageType::ChunkRequest { .. } => "ChunkRequest",
+            MessageType::ChunkData(_) => "ChunkData",
+            MessageType::ChunkRequestBatch { .. } => "ChunkRequestBatch",
+            MessageType::TransferComplete { .. } => "TransferComplete",
+            MessageType::TransferCancel { .. } => "TransferCancel",
+            MessageType::FileWithdraw { .. } => "FileWithdraw",
+            MessageType::OfferRejected { .. } => "OfferRejected",
+            MessageType::StorageChallenge { .. } => "StorageChallenge",
+            MessageType::StorageProof { .. } => "StorageProof",
+            MessageType::FileQuery { .. } => "FileQuery",
+            MessageType::FileQueryResults { .. } => "FileQueryResults",
+            MessageType::FileCatalog(_) => "FileCatalog",
+            MessageType::Custom { .. } => "Custom",
+        }
+    }
+
+    /// Feature a peer must advertise in discovery to understand this
+    /// message, for messages added since peers advertise features
+    pub fn required_feature(&self) -> Option<&'static str> {
+        match self {
+            MessageType::OfferRejected { .. } => Some(OFFER_REJECTION_FEATURE),
+            MessageType::FileCatalog(_) => Some(FILE_CATALOG_FEATURE),
+            _ => None,
+        }
+    }
+
+    /// Whether this message changes the file registry and must therefore be
+    /// rejected when it carries a stale epoch.
+    pub fn affects_registry(&self) -> bool {
+        matches!(
+            self,
+            MessageType::FileOffer(_)
+                | MessageType::FileWithdraw { .. }
+                | MessageType::TransferComplete { .. }
+                | MessageType::TransferCancel { .. }
+        )
+    }
+}
+
+/// Understands [`MessageType::OfferRejected`]
+pub const OFFER_REJECTION_FEATURE: &str = "offer-rejection";
+
+/// Answers [`crate::TransferRequest::ChunkBatch`]
+pub const CHUNK_BATCH_FEATURE: &str = "chunk-batch";
+
+/// Understands [`MessageType::FileCatalog`]
+pub const FILE_CATALOG_FEATURE: &str = "file-catalog";
+
+/// Features this node understands, advertised in discovery
+pub const FEATURES: &[&str] = &[
+    OFFER_REJECTION_FEATURE,
+    CHUNK_BATCH_FEATURE,
+    FILE_CATALOG_FEATURE,
+];
+
+/// An offered file in a [`MessageType::FileCatalog`]; its metadata is
+/// fetched from the sender when the file is new to the receiver
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub file_id: String,
+    pub name: String,
+    pub size: u64,
+    /// [`FileMetadata::merkle_root`], to check the fetched metadata against
+    pub root: [u8; 32],
+}
+
+impl From<&FileMetadata> for CatalogEntry {
+    fn from(metadata: &FileMetadata) -> Self {
+        Self {
+            file_id: metadata.file_id.clone(),
+            name: metadata.name.clone(),
+            size: metadata.size,
+            root: metadata.merkle_root(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiscoveryMessage {
     pub capabilities: Vec<String>,
     pub protocol_version: String,
+    /// Free-form operator labels, e.g. a region or rack
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Addresses of peers the sender is connected to, so nodes without mDNS
+    /// can find each other
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Optional message kinds the sender understands, from [`FEATURES`];
+    /// empty from nodes that predate features
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Addresses the sender listens on, for peers to dial it back
+    #[serde(default)]
+    pub listen_addresses: Vec<String>,
+    /// Space the sender has left for files; None if it does not know
+    #[serde(default)]
+    pub free_bytes: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
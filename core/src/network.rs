@@ -1,18 +1,148 @@
 use crate::NodeId;
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast;
 
+/// Weight of a new sample in the smoothed RTT (as in TCP's SRTT)
+const RTT_GAIN: f64 = 0.125;
+/// Weight of a new sample in the smoothed throughput
+const THROUGHPUT_GAIN: f64 = 0.25;
+/// Events buffered per subscriber before it starts missing them
+const EVENT_CAPACITY: usize = 256;
+/// Addresses peers reported seeing us at that are kept; any peer can report
+/// any address, so rarely reported ones make way for new ones
+const MAX_OBSERVED_ADDRESSES: usize = 64;
+/// Distinct peers that must report an address before it counts as confirmed
+pub const MIN_OBSERVERS: usize = 3;
+/// Observers remembered per address, enough to tell the well observed apart
+const MAX_OBSERVERS: usize = 32;
+
+#[derive(Debug)]
 pub struct PeerInfo {
     pub node_id: NodeId,
     pub address: String,
     pub last_seen: u64,
     pub capabilities: Vec<String>,
+    /// Operator labels the peer advertises
+    pub labels: Vec<String>,
+    /// Addresses the peer advertises listening on
+    pub listen_addresses: Vec<String>,
+    /// Space the peer advertises having left for files
+    pub free_bytes: Option<u64>,
+    /// Smoothed round-trip time from pings
+    pub rtt: Option<Duration>,
+    /// Smoothed chunk throughput in bytes per second
+    pub throughput: Option<f64>,
+    /// Successful chunk transfers
+    pub transfers: u32,
+    /// Failed requests, pings and transfers
+    pub failures: u32,
+    /// Protocol bytes written to this peer
+    pub bytes_sent: u64,
+    /// Protocol bytes read from this peer
+    pub bytes_received: u64,
+}
+
+impl PeerInfo {
+    pub fn new(node_id: NodeId, address: String) -> Self {
+        Self {
+            node_id,
+            address,
+            last_seen: 0,
+            capabilities: Vec::new(),
+            labels: Vec::new(),
+            listen_addresses: Vec::new(),
+            free_bytes: None,
+            rtt: None,
+            throughput: None,
+            transfers: 0,
+            failures: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    /// Fraction of interactions with this peer that failed
+    pub fn failure_rate(&self) -> f64 {
+        let total = self.transfers + self.failures;
+        if total == 0 {
+            0.0
+        } else {
+            self.failures as f64 / total as f64
+        }
+    }
+
+    /// How good a source of data this peer is; higher is better.
+    ///
+    /// Throughput (in MB/s) counts for the peer, scaled down by its failure
+    /// rate; every 100ms of RTT and every 10% failure rate count against it.
+    /// Peers without measurements score 0.
+    pub fn score(&self) -> f64 {
+        let failure_rate = self.failure_rate();
+        let throughput = self.throughput.unwrap_or(0.0) / 1_000_000.0;
+        let rtt = self.rtt.map_or(0.0, |rtt| rtt.as_secs_f64() * 10.0);
+        throughput * (1.0 - failure_rate) - rtt - failure_rate * 10.0
+    }
+}
+
+/// An address other peers reach this node at
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternalAddress {
+    pub address: String,
+    /// How many distinct peers reported seeing us at this address
+    pub observed_by: u32,
+    /// Whether a reachability probe (AutoNAT) dialed us back on it and at
+    /// least [`MIN_OBSERVERS`] peers reported it
+    pub confirmed: bool,
 }
 
+/// What is known of an address peers reported seeing us at
 #[derive(Default)]
+struct ObservedAddress {
+    observers: HashSet<NodeId>,
+    /// Whether AutoNAT dialed us back on it
+    reachable: bool,
+}
+
+/// Protocol bytes exchanged with all peers since the node started
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Traffic {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// Change to the set of known peers, delivered to [`NetworkState::subscribe`]
+#[derive(Debug, Clone)]
+pub enum NetworkEvent {
+    PeerAdded(PeerInfo),
+    /// A peer's address, capabilities or measurements changed
+    PeerUpdated(PeerInfo),
+    PeerRemoved(NodeId),
+}
+
+/// Shared view of known peers and how well they perform, and of the
+/// addresses this node is reachable at.
+///
+/// Cloning yields another handle to the same state. Access is synchronous so
+/// it can be updated from the swarm's poll loop.
+#[derive(Clone)]
 pub struct NetworkState {
     peers: Arc<RwLock<HashMap<NodeId, PeerInfo>>>,
+    external_addresses: Arc<RwLock<HashMap<String, ObservedAddress>>>,
+    traffic: Arc<RwLock<Traffic>>,
+    events: broadcast::Sender<NetworkEvent>,
+}
+
+impl Default for NetworkState {
+    fn default() -> Self {
+        Self {
+            peers: Arc::default(),
+            external_addresses: Arc::default(),
+            traffic: Arc::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
 }
 
 impl NetworkState {
@@ -20,25 +150,244 @@ impl NetworkState {
         Self::default()
     }
 
-    pub async fn add_peer(&self, peer: PeerInfo) {
-        let mut peers = self.peers.write().await;
+    /// Receive every peer change made from now on. Slow subscribers miss
+    /// events (and see `RecvError::Lagged`) rather than holding up the node.
+    pub fn subscribe(&self) -> broadcast::Receiver<NetworkEvent> {
+        self.events.subscribe()
+    }
+
+    /// Add or replace a peer, keeping any measurements already recorded for it
+    pub fn add_peer(&self, mut peer: PeerInfo) {
+        let mut peers = self.peers.write().unwrap();
+        let existing = peers.get(&peer.node_id);
+        if let Some(existing) = existing {
+            peer.rtt = peer.rtt.or(existing.rtt);
+            peer.throughput = peer.throughput.or(existing.throughput);
+            peer.transfers = peer.transfers.max(existing.transfers);
+            peer.failures = peer.failures.max(existing.failures);
+            peer.bytes_sent = peer.bytes_sent.max(existing.bytes_sent);
+            peer.bytes_received = peer.bytes_received.max(existing.bytes_received);
+            if peer.capabilities.is_empty() {
+                peer.capabilities = existing.capabilities.clone();
+            }
+            if peer.labels.is_empty() {
+                peer.labels = existing.labels.clone();
+            }
+            if peer.listen_addresses.is_empty() {
+                peer.listen_addresses = existing.listen_addresses.clone();
+            }
+            peer.free_bytes = peer.free_bytes.or(existing.free_bytes);
+        }
+        let event = match existing {
+            Some(_) => NetworkEvent::PeerUpdated(peer.clone()),
+            None => NetworkEvent::PeerAdded(peer.clone()),
+        };
         peers.insert(peer.node_id, peer);
+        self.emit(event);
     }
 
-    pub async fn remove_peer(&self, node_id: &NodeId) {
-        let mut peers = self.peers.write().await;
-        peers.remove(node_id);
+    pub fn remove_peer(&self, node_id: &NodeId) {
+        let mut peers = self.peers.write().unwrap();
+        if peers.remove(node_id).is_some() {
+            self.emit(NetworkEvent::PeerRemoved(*node_id));
+        }
     }
 
-    pub async fn get_peer(&self, node_id: &NodeId) -> Option<PeerInfo> {
-        let peers = self.peers.read().await;
+    pub fn get_peer(&self, node_id: &NodeId) -> Option<PeerInfo> {
+        let peers = self.peers.read().unwrap();
         peers.get(node_id).cloned()
     }
 
-    pub async fn get_all_peers(&self) -> Vec<PeerInfo> {
-        let peers = self.peers.read().await;
+    pub fn get_all_peers(&self) -> Vec<PeerInfo> {
+        let peers = self.peers.read().unwrap();
         peers.values().cloned().collect()
     }
+
+    /// Record the capabilities (e.g. "storage", "compute") a peer advertises
+    pub fn set_capabilities(&self, node_id: &NodeId, capabilities: Vec<String>) {
+        self.update(node_id, |peer| peer.capabilities = capabilities);
+    }
+
+    /// Record the operator labels a peer advertises
+    pub fn set_labels(&self, node_id: &NodeId, labels: Vec<String>) {
+        self.update(node_id, |peer| peer.labels = labels);
+    }
+
+    /// Record the addresses a peer advertises listening on
+    pub fn set_listen_addresses(&self, node_id: &NodeId, addresses: Vec<String>) {
+        self.update(node_id, |peer| peer.listen_addresses = addresses);
+    }
+
+    /// Record how much space a peer advertises having left for files
+    pub fn set_free_bytes(&self, node_id: &NodeId, free_bytes: Option<u64>) {
+        self.update(node_id, |peer| peer.free_bytes = free_bytes);
+    }
+
+    /// Every known peer advertising `capability`
+    pub fn find_peers_with(&self, capability: &str) -> Vec<NodeId> {
+        let peers = self.peers.read().unwrap();
+        peers
+            .values()
+            .filter(|peer| peer.capabilities.iter().any(|c| c == capability))
+            .map(|peer| peer.node_id)
+            .collect()
+    }
+
+    /// Fold a ping round-trip time into the peer's smoothed RTT
+    pub fn record_rtt(&self, node_id: &NodeId, rtt: Duration) {
+        self.update(node_id, |peer| {
+            peer.rtt = Some(match peer.rtt {
+                Some(srtt) => srtt.mul_f64(1.0 - RTT_GAIN) + rtt.mul_f64(RTT_GAIN),
+                None => rtt,
+            });
+        });
+    }
+
+    /// Record `bytes` received from a peer `elapsed` after they were requested
+    pub fn record_transfer(&self, node_id: &NodeId, bytes: usize, elapsed: Duration) {
+        let sample = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+        self.update(node_id, |peer| {
+            peer.throughput = Some(match peer.throughput {
+                Some(throughput) => throughput * (1.0 - THROUGHPUT_GAIN) + sample * THROUGHPUT_GAIN,
+                None => sample,
+            });
+            peer.transfers = peer.transfers.saturating_add(1);
+        });
+    }
+
+    /// Count a failed ping, request or transfer against a peer
+    pub fn record_failure(&self, node_id: &NodeId) {
+        self.update(node_id, |peer| {
+            peer.failures = peer.failures.saturating_add(1);
+        });
+    }
+
+    /// Count a message written to a peer
+    pub fn record_sent(&self, node_id: &NodeId, bytes: usize) {
+        self.traffic.write().unwrap().sent += bytes as u64;
+        self.count_bytes(node_id, |peer| peer.bytes_sent += bytes as u64);
+    }
+
+    /// Count a message read from a peer
+    pub fn record_received(&self, node_id: &NodeId, bytes: usize) {
+        self.traffic.write().unwrap().received += bytes as u64;
+        self.count_bytes(node_id, |peer| peer.bytes_received += bytes as u64);
+    }
+
+    /// Bytes exchanged with all peers, including ones since removed
+    pub fn traffic(&self) -> Traffic {
+        *self.traffic.read().unwrap()
+    }
+
+    /// Order `candidates` from best to worst score
+    pub fn rank_peers(&self, candidates: &[NodeId]) -> Vec<NodeId> {
+        let peers = self.peers.read().unwrap();
+        let score = |node_id: &NodeId| peers.get(node_id).map_or(0.0, PeerInfo::score);
+
+        let mut ranked = candidates.to_vec();
+        ranked.sort_by(|a, b| score(b).total_cmp(&score(a)));
+        ranked
+    }
+
+    /// Note that `observer` reported seeing us at `address`
+    pub fn record_observed_address(&self, address: &str, observer: &NodeId) {
+        self.update_address(address, |entry| {
+            if entry.observers.len() < MAX_OBSERVERS {
+                entry.observers.insert(*observer);
+            }
+        });
+    }
+
+    /// Mark `address` as reachable from outside; it counts as confirmed
+    /// once enough peers have reported it too
+    pub fn confirm_external_address(&self, address: &str) {
+        self.update_address(address, |entry| entry.reachable = true);
+    }
+
+    /// Mark `address` as no longer known to be reachable
+    pub fn expire_external_address(&self, address: &str) {
+        if let Some(entry) = self.external_addresses.write().unwrap().get_mut(address) {
+            entry.reachable = false;
+        }
+    }
+
+    /// Addresses confirmed to be reachable from outside
+    pub fn confirmed_addresses(&self) -> Vec<String> {
+        self.external_addresses()
+            .into_iter()
+            .filter(|entry| entry.confirmed)
+            .map(|entry| entry.address)
+            .collect()
+    }
+
+    /// Every address peers have seen us at, confirmed ones first and then
+    /// by how often they were observed
+    pub fn external_addresses(&self) -> Vec<ExternalAddress> {
+        let addresses = self.external_addresses.read().unwrap();
+        let mut addresses: Vec<ExternalAddress> = addresses
+            .iter()
+            .map(|(address, entry)| ExternalAddress {
+                address: address.clone(),
+                observed_by: entry.observers.len() as u32,
+                confirmed: entry.reachable && entry.observers.len() >= MIN_OBSERVERS,
+            })
+            .collect();
+        addresses.sort_by(|a, b| {
+            b.confirmed
+                .cmp(&a.confirmed)
+                .then(b.observed_by.cmp(&a.observed_by))
+                .then(a.address.cmp(&b.address))
+        });
+        addresses
+    }
+
+    fn update_address(&self, address: &str, f: impl FnOnce(&mut ObservedAddress)) {
+        let mut addresses = self.external_addresses.write().unwrap();
+        if !addresses.contains_key(address) && addresses.len() >= MAX_OBSERVED_ADDRESSES {
+            // Make way by forgetting the least observed unreachable address
+            let Some(evicted) = addresses
+                .iter()
+                .filter(|(_, entry)| !entry.reachable)
+                .min_by_key(|(_, entry)| entry.observers.len())
+                .map(|(address, _)| address.clone())
+            else {
+                return;
+            };
+            addresses.remove(&evicted);
+        }
+        f(addresses.entry(address.to_string()).or_default());
+    }
+
+    /// Apply `f` to a peer's entry, creating an entry without an address if needed
+    fn update(&self, node_id: &NodeId, f: impl FnOnce(&mut PeerInfo)) {
+        let mut peers = self.peers.write().unwrap();
+        let added = !peers.contains_key(node_id);
+        let peer = peers
+            .entry(*node_id)
+            .or_insert_with(|| PeerInfo::new(*node_id, String::new()));
+        f(peer);
+        let peer = peer.clone();
+        self.emit(if added {
+            NetworkEvent::PeerAdded(peer)
+        } else {
+            NetworkEvent::PeerUpdated(peer)
+        });
+    }
+
+    /// Like `update`, but without an event: counters change with every
+    /// message, which would drown out the changes subscribers care about
+    fn count_bytes(&self, node_id: &NodeId, f: impl FnOnce(&mut PeerInfo)) {
+        let mut peers = self.peers.write().unwrap();
+        f(peers
+            .entry(*node_id)
+            .or_insert_with(|| PeerInfo::new(*node_id, String::new())));
+    }
+
+    /// Send while still holding the lock, so subscribers see changes in order
+    fn emit(&self, event: NetworkEvent) {
+        // No subscribers is fine
+        let _ = self.events.send(event);
+    }
 }
 
 impl Clone for PeerInfo {
@@ -48,6 +397,152 @@ impl Clone for PeerInfo {
             address: self.address.clone(),
             last_seen: self.last_seen,
             capabilities: self.capabilities.clone(),
+            labels: self.labels.clone(),
+            listen_addresses: self.listen_addresses.clone(),
+            free_bytes: self.free_bytes,
+            rtt: self.rtt,
+            throughput: self.throughput,
+            transfers: self.transfers,
+            failures: self.failures,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Identity;
+
+    #[test]
+    fn test_rank_peers_by_performance() {
+        let network = NetworkState::new();
+        let [fast, slow, flaky, unknown] = [(); 4].map(|_| Identity::generate().node_id());
+
+        network.record_rtt(&fast, Duration::from_millis(20));
+        network.record_transfer(&fast, 64 * 1024, Duration::from_millis(10));
+        network.record_rtt(&slow, Duration::from_millis(400));
+        network.record_transfer(&slow, 64 * 1024, Duration::from_millis(500));
+        network.record_transfer(&flaky, 64 * 1024, Duration::from_millis(10));
+        network.record_failure(&flaky);
+        network.record_failure(&flaky);
+
+        assert_eq!(
+            network.rank_peers(&[unknown, flaky, slow, fast]),
+            vec![fast, unknown, slow, flaky]
+        );
+
+        // RTT is smoothed rather than replaced by the latest sample
+        network.record_rtt(&fast, Duration::from_millis(100));
+        let rtt = network.get_peer(&fast).unwrap().rtt.unwrap();
+        assert_eq!(rtt, Duration::from_millis(30));
+
+        // Capabilities are queryable and survive reconnects
+        network.set_capabilities(&slow, vec!["storage".to_string()]);
+        network.add_peer(PeerInfo::new(slow, String::new()));
+        assert_eq!(network.find_peers_with("storage"), vec![slow]);
+        assert!(network.find_peers_with("compute").is_empty());
+
+        // Re-adding a peer keeps its measurements
+        network.add_peer(PeerInfo::new(fast, "/ip4/127.0.0.1/tcp/4001".to_string()));
+        let peer = network.get_peer(&fast).unwrap();
+        assert_eq!(peer.transfers, 1);
+        assert!(peer.rtt.is_some());
+    }
+
+    #[test]
+    fn test_subscribe_to_peer_changes() {
+        let network = NetworkState::new();
+        let mut events = network.subscribe();
+        let peer = Identity::generate().node_id();
+
+        network.add_peer(PeerInfo::new(peer, "/ip4/127.0.0.1/tcp/4001".to_string()));
+        network.record_rtt(&peer, Duration::from_millis(20));
+        network.remove_peer(&peer);
+        network.remove_peer(&peer);
+
+        assert!(matches!(events.try_recv(), Ok(NetworkEvent::PeerAdded(p)) if p.node_id == peer));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(NetworkEvent::PeerUpdated(p)) if p.rtt == Some(Duration::from_millis(20))
+        ));
+        assert!(matches!(events.try_recv(), Ok(NetworkEvent::PeerRemoved(id)) if id == peer));
+        // Removing an unknown peer is not an event
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_traffic_outlives_peers() {
+        let network = NetworkState::new();
+        let mut events = network.subscribe();
+        let peer = Identity::generate().node_id();
+
+        network.record_sent(&peer, 100);
+        network.record_received(&peer, 40);
+        network.record_received(&peer, 2);
+        let info = network.get_peer(&peer).unwrap();
+        assert_eq!((info.bytes_sent, info.bytes_received), (100, 42));
+        // Counting bytes is too frequent to notify subscribers
+        assert!(events.try_recv().is_err());
+
+        network.remove_peer(&peer);
+        assert_eq!(
+            network.traffic(),
+            Traffic {
+                sent: 100,
+                received: 42
+            }
+        );
+    }
+
+    #[test]
+    fn test_external_addresses() {
+        let network = NetworkState::new();
+        let public = "/ip4/203.0.113.7/tcp/4001";
+        let nat = "/ip4/198.51.100.2/tcp/51234";
+
+        let peers: Vec<NodeId> = (0..4).map(|_| Identity::generate().node_id()).collect();
+
+        // Reports from the same peer count once
+        for _ in 0..5 {
+            network.record_observed_address(nat, &peers[0]);
+        }
+        network.record_observed_address(nat, &peers[1]);
+        for peer in &peers[..MIN_OBSERVERS] {
+            network.record_observed_address(public, peer);
+        }
+        assert!(network.confirmed_addresses().is_empty());
+
+        // Reachable addresses need enough observers to count as confirmed
+        network.confirm_external_address(nat);
+        assert!(network.confirmed_addresses().is_empty());
+        // Confirmed addresses come first, however rarely they were observed
+        network.confirm_external_address(public);
+        assert_eq!(network.confirmed_addresses(), vec![public.to_string()]);
+        let all = network.external_addresses();
+        assert_eq!(all[0].address, public);
+        assert_eq!(all[1].observed_by, 2);
+
+        network.expire_external_address(public);
+        assert!(network.confirmed_addresses().is_empty());
+    }
+
+    #[test]
+    fn test_observed_addresses_are_capped() {
+        let network = NetworkState::new();
+        let kept = "/ip4/203.0.113.7/tcp/4001";
+        for _ in 0..2 {
+            network.record_observed_address(kept, &Identity::generate().node_id());
+        }
+
+        // One peer making up addresses cannot push out well observed ones
+        let spammer = Identity::generate().node_id();
+        for port in 0..MAX_OBSERVED_ADDRESSES * 2 {
+            network.record_observed_address(&format!("/ip4/10.0.0.1/tcp/{}", port), &spammer);
         }
+        let all = network.external_addresses();
+        assert_eq!(all.len(), MAX_OBSERVED_ADDRESSES);
+        assert_eq!(all[0].address, kept);
     }
 }
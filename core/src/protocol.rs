@@ -1,8 +1,32 @@
+use crate::{FileChunk, FileMetadata};
+use async_trait::async_trait;
 use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use libp2p_core::upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeInfo};
+use libp2p_request_response as request_response;
 use libp2p_swarm::StreamProtocol;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::io;
 
+/// Version of the CoreLink protocol a node reports through identify. Nodes
+/// whose major versions differ cannot understand each other.
+pub const PROTOCOL_VERSION: &str = "/corelink/1.0.0";
+
+/// Protocol of fire-and-forget messages, one or more per stream
+pub const MESSAGE_PROTOCOL: StreamProtocol = StreamProtocol::new("/corelink/msg/1.0.0");
+
+/// Protocol of requests for chunks and files, each answered on the stream
+/// it came in on
+pub const TRANSFER_PROTOCOL: StreamProtocol = StreamProtocol::new("/corelink/transfer/1.0.0");
+
+/// Most chunks asked for in one [`TransferRequest::ChunkBatch`]
+pub const MAX_CHUNK_BATCH: usize = 16;
+
+/// Largest transfer request read
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+/// Largest transfer response frame read. Chunk data is a JSON array of
+/// numbers, so a 64 KiB chunk takes up to about 256 KiB.
+const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
 #[derive(Debug, Clone)]
 pub struct CoreLinkProtocol;
 
@@ -45,32 +69,263 @@ where
 pub struct CoreLinkCodec;
 
 impl CoreLinkCodec {
-    pub async fn send_message<T>(stream: &mut T, msg: &crate::Message) -> io::Result<()>
+    /// Write one length-prefixed message, returning the bytes written
+    pub async fn send_message<T>(stream: &mut T, msg: &crate::Message) -> io::Result<usize>
     where
         T: AsyncWrite + Unpin,
     {
-        let json = serde_json::to_string(msg)?;
-        let len = json.len() as u32;
+        write_frame(stream, msg).await
+    }
 
-        stream.write_all(&len.to_be_bytes()).await?;
-        stream.write_all(json.as_bytes()).await?;
-        stream.flush().await?;
+    /// Read one length-prefixed message and the bytes it took up
+    pub async fn read_message<T>(stream: &mut T) -> io::Result<(crate::Message, usize)>
+    where
+        T: AsyncRead + Unpin,
+    {
+        read_frame(stream, usize::MAX).await
+    }
+}
+
+/// What a peer is asked for over [`TRANSFER_PROTOCOL`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransferRequest {
+    /// One chunk of a file
+    Chunk { file_id: String, chunk_index: u32 },
+    /// Up to [`MAX_CHUNK_BATCH`] chunks of a file. Only sent to peers
+    /// advertising [`crate::message::CHUNK_BATCH_FEATURE`].
+    ChunkBatch {
+        file_id: String,
+        chunk_indices: Vec<u32>,
+    },
+    /// The metadata of a file, by id
+    File { file_id: String },
+}
 
-        Ok(())
+/// Answer to a [`TransferRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TransferResponse {
+    Chunk(FileChunk),
+    /// Answer to a chunk batch: the chunks held, in the order asked for
+    Chunks(Vec<FileChunk>),
+    File(FileMetadata),
+    /// The peer does not hold what was asked for
+    NotFound,
+}
+
+/// First frame of a transfer response. A batch of chunks is announced here
+/// and streamed one frame per chunk after it, so no frame holds more than
+/// one chunk; the other responses frame like [`TransferResponse`].
+#[derive(Debug, Serialize, Deserialize)]
+enum ResponseFrame {
+    Chunk(FileChunk),
+    Chunks { count: u32 },
+    File(FileMetadata),
+    NotFound,
+}
+
+/// Frames transfer requests and responses like [`CoreLinkCodec`] frames
+/// messages, one per stream
+#[derive(Debug, Clone, Default)]
+pub struct TransferCodec;
+
+#[async_trait]
+impl request_response::Codec for TransferCodec {
+    type Protocol = StreamProtocol;
+    type Request = TransferRequest;
+    type Response = TransferResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        Ok(read_frame(io, MAX_REQUEST_BYTES).await?.0)
     }
 
-    pub async fn read_message<T>(stream: &mut T) -> io::Result<crate::Message>
+    async fn read_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
     where
-        T: AsyncRead + Unpin,
+        T: AsyncRead + Unpin + Send,
     {
-        let mut len_bytes = [0u8; 4];
-        stream.read_exact(&mut len_bytes).await?;
-        let len = u32::from_be_bytes(len_bytes) as usize;
+        let (frame, _) = read_frame(io, MAX_RESPONSE_BYTES).await?;
+        Ok(match frame {
+            ResponseFrame::Chunk(chunk) => TransferResponse::Chunk(chunk),
+            ResponseFrame::Chunks { count } => {
+                if count as usize > MAX_CHUNK_BATCH {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Batch of {} chunks is over the limit", count),
+                    ));
+                }
+                let mut chunks = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    chunks.push(read_frame(io, MAX_RESPONSE_BYTES).await?.0);
+                }
+                TransferResponse::Chunks(chunks)
+            }
+            ResponseFrame::File(metadata) => TransferResponse::File(metadata),
+            ResponseFrame::NotFound => TransferResponse::NotFound,
+        })
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_frame(io, &request).await.map(drop)
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let frame = match response {
+            TransferResponse::Chunk(chunk) => ResponseFrame::Chunk(chunk),
+            TransferResponse::Chunks(chunks) => {
+                let count = chunks.len() as u32;
+                write_frame(io, &ResponseFrame::Chunks { count }).await?;
+                for chunk in &chunks {
+                    write_frame(io, chunk).await?;
+                }
+                return Ok(());
+            }
+            TransferResponse::File(metadata) => ResponseFrame::File(metadata),
+            TransferResponse::NotFound => ResponseFrame::NotFound,
+        };
+        write_frame(io, &frame).await.map(drop)
+    }
+}
+
+/// Write `value` as length-prefixed JSON, returning the bytes written
+async fn write_frame<T, V>(stream: &mut T, value: &V) -> io::Result<usize>
+where
+    T: AsyncWrite + Unpin,
+    V: Serialize,
+{
+    let json = serde_json::to_string(value)?;
+    let len = json.len() as u32;
+
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(json.as_bytes()).await?;
+    stream.flush().await?;
+
+    Ok(frame_len(json.len()))
+}
+
+/// Read a value written by [`write_frame`] whose body is at most `max`
+/// bytes, and the bytes it took up
+async fn read_frame<T, V>(stream: &mut T, max: usize) -> io::Result<(V, usize)>
+where
+    T: AsyncRead + Unpin,
+    V: DeserializeOwned,
+{
+    let mut len_bytes = [0u8; 4];
+    stream.read_exact(&mut len_bytes).await?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    if len > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Frame of {} bytes is over the limit of {}", len, max),
+        ));
+    }
+
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+
+    let value = serde_json::from_slice(&buf)?;
+    Ok((value, frame_len(len)))
+}
+
+/// Size on the wire of a message with a `len`-byte body
+fn frame_len(len: usize) -> usize {
+    std::mem::size_of::<u32>() + len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::Cursor;
+    use request_response::Codec;
+
+    #[tokio::test]
+    async fn test_transfer_codec_round_trip() {
+        let chunk = FileChunk::new("abc".to_string(), 3, vec![1, 2, 3]);
+        let mut wire = Cursor::new(Vec::new());
+        TransferCodec
+            .write_response(
+                &TRANSFER_PROTOCOL,
+                &mut wire,
+                TransferResponse::Chunk(chunk.clone()),
+            )
+            .await
+            .unwrap();
+        wire.set_position(0);
+        match TransferCodec
+            .read_response(&TRANSFER_PROTOCOL, &mut wire)
+            .await
+            .unwrap()
+        {
+            TransferResponse::Chunk(read) => {
+                assert_eq!(read.chunk_index, 3);
+                assert_eq!(read.data, chunk.data);
+                assert_eq!(read.hash, chunk.hash);
+            }
+            other => panic!("Expected a chunk, got {:?}", other),
+        }
 
-        let mut buf = vec![0u8; len];
-        stream.read_exact(&mut buf).await?;
+        // A batch streams its chunks after the count, in the order given
+        let mut wire = Cursor::new(Vec::new());
+        let chunks: Vec<FileChunk> = [5, 2]
+            .into_iter()
+            .map(|index| FileChunk::new("abc".to_string(), index, vec![index as u8]))
+            .collect();
+        TransferCodec
+            .write_response(
+                &TRANSFER_PROTOCOL,
+                &mut wire,
+                TransferResponse::Chunks(chunks),
+            )
+            .await
+            .unwrap();
+        wire.set_position(0);
+        match TransferCodec
+            .read_response(&TRANSFER_PROTOCOL, &mut wire)
+            .await
+            .unwrap()
+        {
+            TransferResponse::Chunks(read) => {
+                let indices: Vec<u32> = read.iter().map(|chunk| chunk.chunk_index).collect();
+                assert_eq!(indices, [5, 2]);
+            }
+            other => panic!("Expected chunks, got {:?}", other),
+        }
 
-        let msg = serde_json::from_slice(&buf)?;
-        Ok(msg)
+        // Requests are small, so a large one is refused before it is read
+        let mut wire = Cursor::new(Vec::new());
+        let request = TransferRequest::File {
+            file_id: "x".repeat(MAX_REQUEST_BYTES),
+        };
+        TransferCodec
+            .write_request(&TRANSFER_PROTOCOL, &mut wire, request)
+            .await
+            .unwrap();
+        wire.set_position(0);
+        let error = TransferCodec
+            .read_request(&TRANSFER_PROTOCOL, &mut wire)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     }
 }
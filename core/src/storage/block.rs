@@ -0,0 +1,270 @@
+use super::object::{DiskObjectStore, ObjectStore};
+use crate::file::calculate_chunk_hash;
+use crate::{CoreLinkError, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Magic number opening every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Most a block is decompressed to when its chunk size is not known
+const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Content-addressed store of chunk data, keyed by the SHA256 hash of each block.
+///
+/// Blocks are stored under keys sharded by the first byte of their hash, so
+/// identical chunks shared between files are only stored once. With
+/// compression enabled, blocks that shrink are stored as zstd frames and
+/// decompressed transparently on read; uncompressed blocks remain readable.
+#[derive(Clone)]
+pub struct BlockStore {
+    backend: Arc<dyn ObjectStore>,
+    compression_level: Option<i32>,
+}
+
+/// Space taken by the blocks in a store
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockUsage {
+    pub blocks: usize,
+    /// Size of the block contents
+    pub raw_bytes: u64,
+    /// Size actually occupied in the backend, after compression
+    pub stored_bytes: u64,
+}
+
+impl BlockStore {
+    /// Open a block store on local disk
+    pub fn open(root: &Path) -> Result<Self> {
+        Ok(Self::with_backend(Arc::new(DiskObjectStore::open(root)?)))
+    }
+
+    /// Keep blocks in an arbitrary object store backend
+    pub fn with_backend(backend: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            backend,
+            compression_level: None,
+        }
+    }
+
+    /// Compress newly stored blocks with zstd at `level`
+    pub fn with_compression(mut self, level: i32) -> Self {
+        self.compression_level = Some(level);
+        self
+    }
+
+    pub fn backend(&self) -> &Arc<dyn ObjectStore> {
+        &self.backend
+    }
+
+    /// Store a block and return its hash. Storing an existing block is a no-op.
+    pub fn put(&self, data: &[u8]) -> Result<[u8; 32]> {
+        let hash = calculate_chunk_hash(data);
+        let key = block_key(&hash);
+        if self.backend.has(&key)? {
+            return Ok(hash);
+        }
+
+        match self.compression_level {
+            Some(level) => {
+                let compressed = zstd::bulk::compress(data, level)?;
+                // Incompressible blocks are kept as they are
+                if compressed.len() < data.len() {
+                    self.backend.put(&key, &compressed)?;
+                } else {
+                    self.backend.put(&key, data)?;
+                }
+            }
+            None => self.backend.put(&key, data)?,
+        }
+        Ok(hash)
+    }
+
+    /// Load a block, verifying that its contents still match the hash
+    pub fn get(&self, hash: &[u8; 32]) -> Result<Option<Vec<u8>>> {
+        self.get_chunk(hash, MAX_BLOCK_SIZE)
+    }
+
+    /// Load the block of a chunk no longer than `chunk_size`, never
+    /// decompressing it past that size
+    pub fn get_chunk(&self, hash: &[u8; 32], chunk_size: usize) -> Result<Option<Vec<u8>>> {
+        let Some(data) = self.backend.get(&block_key(hash))? else {
+            return Ok(None);
+        };
+
+        // A raw block may happen to start with the zstd magic, so the hash decides
+        if data.starts_with(&ZSTD_MAGIC) {
+            if let Some(decompressed) = decompress(&data, chunk_size) {
+                if calculate_chunk_hash(&decompressed) == *hash {
+                    return Ok(Some(decompressed));
+                }
+            }
+        }
+
+        if calculate_chunk_hash(&data) != *hash {
+            return Err(CoreLinkError::Storage(format!(
+                "block {} is corrupt",
+                hex::encode(hash)
+            )));
+        }
+
+        Ok(Some(data))
+    }
+
+    pub fn has(&self, hash: &[u8; 32]) -> bool {
+        self.backend.has(&block_key(hash)).unwrap_or(false)
+    }
+
+    /// Remove a block. Returns whether it was present.
+    pub fn delete(&self, hash: &[u8; 32]) -> Result<bool> {
+        self.backend.delete(&block_key(hash))
+    }
+
+    /// Raw and stored size of every block. Reads every block, so this is a full scan.
+    pub fn usage(&self) -> Result<BlockUsage> {
+        let mut usage = BlockUsage::default();
+        for (hash, stored) in self.list()? {
+            if let Some(data) = self.get(&hash)? {
+                usage.blocks += 1;
+                usage.raw_bytes += data.len() as u64;
+                usage.stored_bytes += stored;
+            }
+        }
+        Ok(usage)
+    }
+
+    /// List every stored block with its size in the backend, in bytes
+    pub fn list(&self) -> Result<Vec<([u8; 32], u64)>> {
+        Ok(self
+            .backend
+            .list()?
+            .into_iter()
+            .filter_map(|(key, size)| {
+                let mut hash = [0u8; 32];
+                // Skip anything in the backend that isn't a block
+                let name = key.rsplit('/').next()?;
+                hex::decode_to_slice(name, &mut hash).ok()?;
+                Some((hash, size))
+            })
+            .collect())
+    }
+}
+
+/// Decode zstd `data`, giving up once it would grow past `limit` bytes
+fn decompress(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    zstd::stream::read::Decoder::new(data)
+        .ok()?
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .ok()?;
+    (decompressed.len() <= limit).then_some(decompressed)
+}
+
+fn block_key(hash: &[u8; 32]) -> String {
+    let hex = hex::encode(hash);
+    format!("{}/{}", &hex[..2], hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryObjectStore;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_put_get_delete() -> Result<()> {
+        let dir = tempdir()?;
+        let store = BlockStore::open(dir.path())?;
+
+        let hash = store.put(b"block data")?;
+        assert_eq!(hash, calculate_chunk_hash(b"block data"));
+        assert!(store.has(&hash));
+        assert_eq!(store.get(&hash)?, Some(b"block data".to_vec()));
+
+        // Identical content maps to the same block
+        assert_eq!(store.put(b"block data")?, hash);
+
+        assert!(store.delete(&hash)?);
+        assert!(!store.has(&hash));
+        assert!(!store.delete(&hash)?);
+        assert_eq!(store.get(&hash)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_blocks() -> Result<()> {
+        let store = BlockStore::with_backend(Arc::new(MemoryObjectStore::new()));
+        assert!(store.list()?.is_empty());
+
+        let a = store.put(b"first")?;
+        let b = store.put(b"second block")?;
+
+        let mut blocks = store.list()?;
+        blocks.sort();
+        let mut expected = vec![(a, 5), (b, 12)];
+        expected.sort();
+        assert_eq!(blocks, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_blocks() -> Result<()> {
+        let backend: Arc<dyn ObjectStore> = Arc::new(MemoryObjectStore::new());
+        let plain = BlockStore::with_backend(backend.clone());
+        let store = BlockStore::with_backend(backend).with_compression(3);
+
+        // Blocks written before compression was enabled stay readable
+        let old = plain.put(b"stored before compression")?;
+        assert_eq!(
+            store.get(&old)?,
+            Some(b"stored before compression".to_vec())
+        );
+
+        let data = vec![b'a'; 64 * 1024];
+        let hash = store.put(&data)?;
+        assert_eq!(store.get(&hash)?, Some(data.clone()));
+        let (_, stored) = store.list()?.into_iter().find(|(h, _)| *h == hash).unwrap();
+        assert!(stored < data.len() as u64);
+
+        let usage = store.usage()?;
+        assert_eq!(usage.blocks, 2);
+        assert_eq!(usage.raw_bytes, data.len() as u64 + 25);
+        assert!(usage.stored_bytes < usage.raw_bytes);
+
+        // A raw block that looks like a zstd frame is still served as-is
+        let lookalike = [&ZSTD_MAGIC[..], b"not really zstd"].concat();
+        let hash = store.put(&lookalike)?;
+        assert_eq!(store.get(&hash)?, Some(lookalike));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompression_stops_at_chunk_size() -> Result<()> {
+        let store =
+            BlockStore::with_backend(Arc::new(MemoryObjectStore::new())).with_compression(3);
+        let data = vec![0u8; 64 * 1024];
+        let hash = store.put(&data)?;
+
+        assert_eq!(store.get_chunk(&hash, data.len())?, Some(data.clone()));
+        // A block inflating past its chunk is not decoded, so it is corrupt
+        assert!(store.get_chunk(&hash, 1024).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_block_detected() -> Result<()> {
+        let store = BlockStore::with_backend(Arc::new(MemoryObjectStore::new()));
+
+        let hash = store.put(b"original")?;
+        store.backend().put(&block_key(&hash), b"tampered")?;
+
+        assert!(store.get(&hash).is_err());
+
+        Ok(())
+    }
+}
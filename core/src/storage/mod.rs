@@ -0,0 +1,971 @@
+mod block;
+mod encrypted;
+mod object;
+mod pins;
+mod s3;
+mod tiered;
+
+pub use block::{BlockStore, BlockUsage};
+pub use encrypted::EncryptedObjectStore;
+pub use object::{migrate, DiskObjectStore, MemoryObjectStore, ObjectStore};
+pub use pins::PinSet;
+pub use s3::{S3Config, S3ObjectStore};
+pub use tiered::{TieredObjectStore, TieringPolicy, TieringReport};
+
+use crate::{CoreLinkError, Result};
+use sled::transaction::TransactionResult;
+use sled::Transactional;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Version of the on-disk layout. Bump when the key layout or value encoding changes.
+///
+/// Version 2 moved subsystem state out of the flat keyspace into buckets;
+/// version 1 stores are migrated when opened.
+pub const STORAGE_FORMAT_VERSION: u32 = 2;
+
+const META_TREE: &str = "__meta";
+const FORMAT_VERSION_KEY: &str = "format_version";
+
+/// Version 1 kept each of these as one JSON map under a flat key of the same
+/// name; version 2 keeps one entry per key in the bucket of that name
+const V1_MAP_KEYS: [&str; 4] = ["peers", "offers", "transfers", "completed"];
+/// Version 1 key holding the pinned file ids as a JSON array
+const V1_PINS_KEY: &str = "pins";
+/// Version 1 keys `transfer:<file id>:chunks`, moved to the `chunks` bucket
+const V1_CHUNKS_PREFIX: &str = "transfer:";
+const V1_CHUNKS_SUFFIX: &str = ":chunks";
+
+/// Contents of a store: bucket name -> entries ordered by key
+pub type StorageSnapshot = BTreeMap<String, Vec<(String, Vec<u8>)>>;
+
+/// Bucket indexing key expiry times (`<bucket>\0<key>` -> unix millis)
+const TTL_BUCKET: &str = "__ttl";
+
+/// Writes applied together by [`Storage::apply_batch`]: either all of them
+/// become visible or none do
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    ops: Vec<(String, Option<Vec<u8>>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, key: String, value: Vec<u8>) {
+        self.ops.push((key, Some(value)));
+    }
+
+    /// Queue a value serialized as JSON
+    pub fn insert_json<T: serde::Serialize>(&mut self, key: String, value: &T) -> Result<()> {
+        let bytes = serde_json::to_vec(value).map_err(storage_error)?;
+        self.insert(key, bytes);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: String) {
+        self.ops.push((key, None));
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+/// Lazy iterator over the live entries of a bucket, ordered by key.
+///
+/// Expired entries are skipped and removed as they are encountered.
+pub struct StorageIter {
+    store: Storage,
+    entries: Box<dyn Iterator<Item = Result<(String, Vec<u8>)>>>,
+}
+
+impl Iterator for StorageIter {
+    type Item = Result<(String, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, value) = match self.entries.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.store.is_expired(&key) {
+                Ok(false) => return Some(Ok((key, value))),
+                Ok(true) => {
+                    if let Err(e) = self.store.remove(&key) {
+                        return Some(Err(e));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Key-value store shared by node subsystems.
+///
+/// Each handle points at one bucket (namespace). [`Storage::open_bucket`]
+/// gives subsystems their own keyspace so they never collide on keys.
+/// Entries may carry a TTL, after which they are treated as absent and
+/// removed lazily on access or by [`Storage::purge_expired`].
+/// Cloning a `Storage` yields another handle to the same underlying data.
+#[derive(Clone)]
+pub struct Storage {
+    bucket: String,
+    backend: Backend,
+}
+
+/// In-memory buckets: bucket name -> key -> value
+type MemoryBuckets = Arc<RwLock<HashMap<String, HashMap<String, Vec<u8>>>>>;
+
+#[derive(Clone)]
+enum Backend {
+    Memory(MemoryBuckets),
+    Disk {
+        db: sled::Db,
+        tree: sled::Tree,
+        ttl: sled::Tree,
+    },
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            bucket: String::new(),
+            backend: Backend::Memory(Arc::default()),
+        }
+    }
+}
+
+impl Storage {
+    /// Create an in-memory store. Contents are lost when the last handle is dropped.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open (or create) a disk-backed store at `path`.
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = sled::open(path).map_err(storage_error)?;
+        check_format_version(&db)?;
+
+        Ok(Self {
+            bucket: String::new(),
+            backend: Backend::Disk {
+                tree: (*db).clone(),
+                ttl: db.open_tree(TTL_BUCKET).map_err(storage_error)?,
+                db,
+            },
+        })
+    }
+
+    /// Open (or create) the bucket `name` in the same store.
+    ///
+    /// Buckets are not nested: opening a bucket from a bucket handle opens a
+    /// sibling. Names starting with `__` are reserved.
+    pub fn open_bucket(&self, name: &str) -> Result<Storage> {
+        if name.is_empty() || name.starts_with("__") || name.contains('\0') {
+            return Err(CoreLinkError::Storage(format!(
+                "invalid bucket name: {:?}",
+                name
+            )));
+        }
+        self.sibling(name)
+    }
+
+    /// Handle to another bucket, without validating the name. "" is the root bucket.
+    fn sibling(&self, name: &str) -> Result<Storage> {
+        let backend = match &self.backend {
+            Backend::Memory(buckets) => Backend::Memory(buckets.clone()),
+            Backend::Disk { db, ttl, .. } => Backend::Disk {
+                tree: if name.is_empty() {
+                    (**db).clone()
+                } else {
+                    db.open_tree(name).map_err(storage_error)?
+                },
+                ttl: ttl.clone(),
+                db: db.clone(),
+            },
+        };
+        Ok(Self {
+            bucket: name.to_string(),
+            backend,
+        })
+    }
+
+    pub fn is_persistent(&self) -> bool {
+        matches!(self.backend, Backend::Disk { .. })
+    }
+
+    pub fn insert(&self, key: String, value: Vec<u8>) -> Result<()> {
+        // A plain insert replaces any earlier expiring entry
+        self.set_expiry(&key, None)?;
+        self.raw_insert(key, value)
+    }
+
+    /// Insert an entry that expires after `ttl`
+    pub fn insert_with_ttl(&self, key: String, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let expires_at = now_millis().saturating_add(ttl.as_millis() as u64);
+        self.set_expiry(&key, Some(expires_at))?;
+        self.raw_insert(key, value)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        if self.is_expired(key)? {
+            self.remove(key)?;
+            return Ok(None);
+        }
+        self.raw_get(key)
+    }
+
+    pub fn remove(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.set_expiry(key, None)?;
+        self.raw_remove(key)
+    }
+
+    /// Every live entry in this bucket, ordered by key
+    pub fn iter(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        self.scan_prefix("").collect()
+    }
+
+    /// Lazily iterate over the live entries whose key starts with `prefix`, ordered by key
+    pub fn scan_prefix(&self, prefix: &str) -> StorageIter {
+        let entries: Box<dyn Iterator<Item = Result<(String, Vec<u8>)>>> = match &self.backend {
+            Backend::Memory(buckets) => {
+                let mut entries: Vec<_> = buckets
+                    .read()
+                    .unwrap()
+                    .get(&self.bucket)
+                    .map(|entries| {
+                        entries
+                            .iter()
+                            .filter(|(key, _)| key.starts_with(prefix))
+                            .map(|(key, value)| (key.clone(), value.clone()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                Box::new(entries.into_iter().map(Ok))
+            }
+            Backend::Disk { tree, .. } => {
+                Box::new(tree.scan_prefix(prefix.as_bytes()).map(|entry| {
+                    let (key, value) = entry.map_err(storage_error)?;
+                    Ok((String::from_utf8_lossy(&key).into_owned(), value.to_vec()))
+                }))
+            }
+        };
+
+        StorageIter {
+            store: self.clone(),
+            entries,
+        }
+    }
+
+    /// Live entries whose key starts with `prefix`, decoded as JSON and ordered by key
+    pub fn scan_prefix_json<T: serde::de::DeserializeOwned>(
+        &self,
+        prefix: &str,
+    ) -> Result<Vec<(String, T)>> {
+        self.scan_prefix(prefix)
+            .map(|entry| {
+                let (key, bytes) = entry?;
+                let value = serde_json::from_slice(&bytes).map_err(storage_error)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    /// Remove every entry whose key starts with `prefix` in one batch. Returns how many were removed.
+    pub fn remove_prefix(&self, prefix: &str) -> Result<usize> {
+        let mut batch = WriteBatch::new();
+        for entry in self.scan_prefix(prefix) {
+            batch.remove(entry?.0);
+        }
+        let removed = batch.len();
+        self.apply_batch(batch)?;
+        Ok(removed)
+    }
+
+    /// Apply every write in `batch` atomically. Written keys lose any TTL, as with [`Storage::insert`].
+    pub fn apply_batch(&self, batch: WriteBatch) -> Result<()> {
+        match &self.backend {
+            Backend::Memory(buckets) => {
+                let mut buckets = buckets.write().unwrap();
+                for (key, value) in batch.ops {
+                    if let Some(ttl) = buckets.get_mut(TTL_BUCKET) {
+                        ttl.remove(&ttl_key(&self.bucket, &key));
+                    }
+                    let entries = buckets.entry(self.bucket.clone()).or_default();
+                    match value {
+                        Some(value) => entries.insert(key, value),
+                        None => entries.remove(&key),
+                    };
+                }
+            }
+            Backend::Disk { tree, ttl, .. } => {
+                let result: TransactionResult<()> = (tree, ttl).transaction(|(tree, ttl)| {
+                    for (key, value) in &batch.ops {
+                        ttl.remove(ttl_key(&self.bucket, key).as_bytes())?;
+                        match value {
+                            Some(value) => tree.insert(key.as_bytes(), value.as_slice())?,
+                            None => tree.remove(key.as_bytes())?,
+                        };
+                    }
+                    Ok(())
+                });
+                result.map_err(|e| storage_error(format!("{:?}", e)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove every entry in this bucket
+    pub fn clear(&self) -> Result<()> {
+        let prefix = ttl_key(&self.bucket, "");
+        for (index, _) in self.ttl_entries()? {
+            if index.starts_with(&prefix) {
+                self.ttl_remove(&index)?;
+            }
+        }
+
+        match &self.backend {
+            Backend::Memory(buckets) => {
+                buckets.write().unwrap().remove(&self.bucket);
+            }
+            Backend::Disk { tree, .. } => {
+                tree.clear().map_err(storage_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Names of every bucket holding data, including "" for the root bucket
+    pub fn bucket_names(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = match &self.backend {
+            Backend::Memory(buckets) => buckets
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|(_, entries)| !entries.is_empty())
+                .map(|(name, _)| name.clone())
+                .collect(),
+            Backend::Disk { db, .. } => {
+                let mut names = vec![String::new()];
+                names.extend(
+                    db.tree_names()
+                        .iter()
+                        .map(|name| String::from_utf8_lossy(name).into_owned()),
+                );
+                names
+            }
+        };
+        names.retain(|name| !name.starts_with("__"));
+        names.sort();
+        Ok(names)
+    }
+
+    /// Copy of every permanent entry in every bucket. Entries with a TTL are
+    /// transient caches and are left out.
+    pub fn snapshot(&self) -> Result<StorageSnapshot> {
+        let expiring: HashSet<String> = self
+            .ttl_entries()?
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+
+        let mut snapshot = StorageSnapshot::new();
+        for name in self.bucket_names()? {
+            let entries: Vec<_> = self
+                .sibling(&name)?
+                .raw_iter()?
+                .into_iter()
+                .filter(|(key, _)| !expiring.contains(&ttl_key(&name, key)))
+                .collect();
+            if !entries.is_empty() {
+                snapshot.insert(name, entries);
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Replace the contents of every bucket in `snapshot` with the snapshot's entries
+    pub fn restore(&self, snapshot: &StorageSnapshot) -> Result<()> {
+        for (name, entries) in snapshot {
+            if name.starts_with("__") {
+                continue;
+            }
+            let bucket = self.sibling(name)?;
+            bucket.clear()?;
+            let mut batch = WriteBatch::new();
+            for (key, value) in entries {
+                batch.insert(key.clone(), value.clone());
+            }
+            bucket.apply_batch(batch)?;
+        }
+        self.flush()
+    }
+
+    /// Remove expired entries from every bucket. Returns how many were removed.
+    pub fn purge_expired(&self) -> Result<usize> {
+        let now = now_millis();
+        let mut purged = 0;
+
+        for (index, expires_at) in self.ttl_entries()? {
+            if expires_at > now {
+                continue;
+            }
+            if let Some((bucket, key)) = index.split_once('\0') {
+                self.sibling(bucket)?.raw_remove(key)?;
+                purged += 1;
+            }
+            self.ttl_remove(&index)?;
+        }
+
+        Ok(purged)
+    }
+
+    /// Flush pending writes to disk. No-op for in-memory stores.
+    pub fn flush(&self) -> Result<()> {
+        if let Backend::Disk { db, .. } = &self.backend {
+            db.flush().map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    /// Store a value serialized as JSON
+    pub fn insert_json<T: serde::Serialize>(&self, key: String, value: &T) -> Result<()> {
+        let bytes = serde_json::to_vec(value).map_err(storage_error)?;
+        self.insert(key, bytes)
+    }
+
+    /// Store a value serialized as JSON that expires after `ttl`
+    pub fn insert_json_with_ttl<T: serde::Serialize>(
+        &self,
+        key: String,
+        value: &T,
+        ttl: Duration,
+    ) -> Result<()> {
+        let bytes = serde_json::to_vec(value).map_err(storage_error)?;
+        self.insert_with_ttl(key, bytes, ttl)
+    }
+
+    /// Load a JSON value stored with [`Storage::insert_json`]
+    pub fn get_json<T: serde::de::DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key)? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes).map_err(storage_error)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every entry in this bucket decoded as JSON, ordered by key
+    pub fn iter_json<T: serde::de::DeserializeOwned>(&self) -> Result<Vec<(String, T)>> {
+        self.iter()?
+            .into_iter()
+            .map(|(key, bytes)| {
+                let value = serde_json::from_slice(&bytes).map_err(storage_error)?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn raw_insert(&self, key: String, value: Vec<u8>) -> Result<()> {
+        match &self.backend {
+            Backend::Memory(buckets) => {
+                buckets
+                    .write()
+                    .unwrap()
+                    .entry(self.bucket.clone())
+                    .or_default()
+                    .insert(key, value);
+            }
+            Backend::Disk { tree, .. } => {
+                tree.insert(key.as_bytes(), value).map_err(storage_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn raw_get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match &self.backend {
+            Backend::Memory(buckets) => Ok(buckets
+                .read()
+                .unwrap()
+                .get(&self.bucket)
+                .and_then(|entries| entries.get(key).cloned())),
+            Backend::Disk { tree, .. } => Ok(tree
+                .get(key.as_bytes())
+                .map_err(storage_error)?
+                .map(|v| v.to_vec())),
+        }
+    }
+
+    fn raw_remove(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match &self.backend {
+            Backend::Memory(buckets) => Ok(buckets
+                .write()
+                .unwrap()
+                .get_mut(&self.bucket)
+                .and_then(|entries| entries.remove(key))),
+            Backend::Disk { tree, .. } => Ok(tree
+                .remove(key.as_bytes())
+                .map_err(storage_error)?
+                .map(|v| v.to_vec())),
+        }
+    }
+
+    /// Every entry in this bucket ordered by key, including expired ones
+    fn raw_iter(&self) -> Result<Vec<(String, Vec<u8>)>> {
+        self.scan_prefix("").entries.collect()
+    }
+
+    fn is_expired(&self, key: &str) -> Result<bool> {
+        let index = ttl_key(&self.bucket, key);
+        let expires_at = match &self.backend {
+            Backend::Memory(buckets) => buckets
+                .read()
+                .unwrap()
+                .get(TTL_BUCKET)
+                .and_then(|ttl| ttl.get(&index).cloned()),
+            Backend::Disk { ttl, .. } => ttl
+                .get(index.as_bytes())
+                .map_err(storage_error)?
+                .map(|v| v.to_vec()),
+        };
+        Ok(expires_at.is_some_and(|bytes| decode_millis(&bytes) <= now_millis()))
+    }
+
+    fn set_expiry(&self, key: &str, expires_at: Option<u64>) -> Result<()> {
+        let index = ttl_key(&self.bucket, key);
+        match expires_at {
+            Some(millis) => match &self.backend {
+                Backend::Memory(buckets) => {
+                    buckets
+                        .write()
+                        .unwrap()
+                        .entry(TTL_BUCKET.to_string())
+                        .or_default()
+                        .insert(index, millis.to_be_bytes().to_vec());
+                }
+                Backend::Disk { ttl, .. } => {
+                    ttl.insert(index.as_bytes(), &millis.to_be_bytes())
+                        .map_err(storage_error)?;
+                }
+            },
+            None => self.ttl_remove(&index)?,
+        }
+        Ok(())
+    }
+
+    fn ttl_remove(&self, index: &str) -> Result<()> {
+        match &self.backend {
+            Backend::Memory(buckets) => {
+                if let Some(ttl) = buckets.write().unwrap().get_mut(TTL_BUCKET) {
+                    ttl.remove(index);
+                }
+            }
+            Backend::Disk { ttl, .. } => {
+                ttl.remove(index.as_bytes()).map_err(storage_error)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn ttl_entries(&self) -> Result<Vec<(String, u64)>> {
+        match &self.backend {
+            Backend::Memory(buckets) => Ok(buckets
+                .read()
+                .unwrap()
+                .get(TTL_BUCKET)
+                .map(|ttl| {
+                    ttl.iter()
+                        .map(|(index, bytes)| (index.clone(), decode_millis(bytes)))
+                        .collect()
+                })
+                .unwrap_or_default()),
+            Backend::Disk { ttl, .. } => ttl
+                .iter()
+                .map(|entry| {
+                    let (index, bytes) = entry.map_err(storage_error)?;
+                    Ok((
+                        String::from_utf8_lossy(&index).into_owned(),
+                        decode_millis(&bytes),
+                    ))
+                })
+                .collect(),
+        }
+    }
+}
+
+fn ttl_key(bucket: &str, key: &str) -> String {
+    format!("{}\0{}", bucket, key)
+}
+
+fn decode_millis(bytes: &[u8]) -> u64 {
+    bytes.try_into().map(u64::from_be_bytes).unwrap_or(0)
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Stamp a fresh database with the current format version, or verify an
+/// existing one, migrating it from version 1.
+fn check_format_version(db: &sled::Db) -> Result<()> {
+    let meta = db.open_tree(META_TREE).map_err(storage_error)?;
+
+    match meta.get(FORMAT_VERSION_KEY).map_err(storage_error)? {
+        Some(bytes) => {
+            let bytes: [u8; 4] = bytes.as_ref().try_into().map_err(|_| {
+                CoreLinkError::Storage("corrupt storage format version".to_string())
+            })?;
+            match u32::from_be_bytes(bytes) {
+                STORAGE_FORMAT_VERSION => {}
+                1 => {
+                    migrate_v1(db)?;
+                    meta.insert(FORMAT_VERSION_KEY, &STORAGE_FORMAT_VERSION.to_be_bytes())
+                        .map_err(storage_error)?;
+                    db.flush().map_err(storage_error)?;
+                }
+                version => {
+                    return Err(CoreLinkError::Storage(format!(
+                        "unsupported storage format version {} (expected {})",
+                        version, STORAGE_FORMAT_VERSION
+                    )));
+                }
+            }
+        }
+        None => {
+            meta.insert(FORMAT_VERSION_KEY, &STORAGE_FORMAT_VERSION.to_be_bytes())
+                .map_err(storage_error)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move version 1 state out of the flat keyspace into the version 2 buckets.
+///
+/// Entries are copied and flushed before the flat keys are removed, and the
+/// caller stamps the new version last, so an interrupted migration is simply
+/// run again on the next open.
+fn migrate_v1(db: &sled::Db) -> Result<()> {
+    let mut migrated = Vec::new();
+    for name in V1_MAP_KEYS {
+        let Some(bytes) = db.get(name).map_err(storage_error)? else {
+            continue;
+        };
+        let entries: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&bytes).map_err(storage_error)?;
+        let bucket = db.open_tree(name).map_err(storage_error)?;
+        for (key, value) in entries {
+            let value = serde_json::to_vec(&value).map_err(storage_error)?;
+            bucket
+                .insert(key.as_bytes(), value)
+                .map_err(storage_error)?;
+        }
+        migrated.push(name.as_bytes().to_vec());
+    }
+
+    if let Some(bytes) = db.get(V1_PINS_KEY).map_err(storage_error)? {
+        let pinned: Vec<String> = serde_json::from_slice(&bytes).map_err(storage_error)?;
+        let bucket = db.open_tree(V1_PINS_KEY).map_err(storage_error)?;
+        for file_id in pinned {
+            bucket
+                .insert(file_id.as_bytes(), Vec::new())
+                .map_err(storage_error)?;
+        }
+        migrated.push(V1_PINS_KEY.as_bytes().to_vec());
+    }
+
+    let chunks = db.open_tree("chunks").map_err(storage_error)?;
+    for entry in db.scan_prefix(V1_CHUNKS_PREFIX) {
+        let (key, value) = entry.map_err(storage_error)?;
+        let Some(file_id) = key
+            .strip_prefix(V1_CHUNKS_PREFIX.as_bytes())
+            .and_then(|rest| rest.strip_suffix(V1_CHUNKS_SUFFIX.as_bytes()))
+        else {
+            continue;
+        };
+        chunks.insert(file_id, value).map_err(storage_error)?;
+        migrated.push(key.to_vec());
+    }
+
+    db.flush().map_err(storage_error)?;
+    for key in migrated {
+        db.remove(key).map_err(storage_error)?;
+    }
+    Ok(())
+}
+
+fn storage_error(e: impl std::fmt::Display) -> CoreLinkError {
+    CoreLinkError::Storage(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_memory_storage() -> Result<()> {
+        let storage = Storage::new();
+        assert!(!storage.is_persistent());
+
+        storage.insert("key".to_string(), b"value".to_vec())?;
+        assert_eq!(storage.get("key")?, Some(b"value".to_vec()));
+
+        // Clones share the same data
+        let handle = storage.clone();
+        assert_eq!(handle.remove("key")?, Some(b"value".to_vec()));
+        assert_eq!(storage.get("key")?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_storage_survives_reopen() -> Result<()> {
+        let dir = tempdir()?;
+
+        {
+            let storage = Storage::open(dir.path())?;
+            assert!(storage.is_persistent());
+            storage.insert_json("numbers".to_string(), &vec![1u32, 2, 3])?;
+            storage.flush()?;
+        }
+
+        let storage = Storage::open(dir.path())?;
+        let numbers: Option<Vec<u32>> = storage.get_json("numbers")?;
+        assert_eq!(numbers, Some(vec![1, 2, 3]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_version_mismatch() -> Result<()> {
+        let dir = tempdir()?;
+
+        {
+            let db = sled::open(dir.path()).map_err(storage_error)?;
+            let meta = db.open_tree(META_TREE).map_err(storage_error)?;
+            meta.insert(FORMAT_VERSION_KEY, &999u32.to_be_bytes())
+                .map_err(storage_error)?;
+            db.flush().map_err(storage_error)?;
+        }
+
+        assert!(matches!(
+            Storage::open(dir.path()),
+            Err(CoreLinkError::Storage(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_v1_store_is_migrated() -> Result<()> {
+        let dir = tempdir()?;
+
+        // A store as version 1 left it: whole maps under flat keys
+        {
+            let db = sled::open(dir.path()).map_err(storage_error)?;
+            let meta = db.open_tree(META_TREE).map_err(storage_error)?;
+            meta.insert(FORMAT_VERSION_KEY, &1u32.to_be_bytes())
+                .map_err(storage_error)?;
+            let v1 = [
+                (
+                    "peers",
+                    r#"{"peer-a":{"addresses":["/ip4/10.0.0.1/tcp/4001"]}}"#,
+                ),
+                ("offers", r#"{"f1":{"name":"a.txt"},"f2":{"name":"b.txt"}}"#),
+                ("transfers", r#"{"f3":{"output_path":"/tmp/c.txt"}}"#),
+                ("completed", r#"{}"#),
+                ("pins", r#"["f1"]"#),
+                ("transfer:f3:chunks", r#"[0,2]"#),
+                ("unrelated", r#"1"#),
+            ];
+            for (key, value) in v1 {
+                db.insert(key, value.as_bytes()).map_err(storage_error)?;
+            }
+            db.flush().map_err(storage_error)?;
+        }
+
+        for _ in 0..2 {
+            let storage = Storage::open(dir.path())?;
+            let peers = storage.open_bucket("peers")?;
+            let peer: serde_json::Value = peers.get_json("peer-a")?.unwrap();
+            assert_eq!(peer["addresses"][0], "/ip4/10.0.0.1/tcp/4001");
+            let offers = storage.open_bucket("offers")?;
+            assert_eq!(
+                offers
+                    .iter()?
+                    .into_iter()
+                    .map(|(key, _)| key)
+                    .collect::<Vec<_>>(),
+                vec!["f1", "f2"]
+            );
+            assert!(storage.open_bucket("transfers")?.get("f3")?.is_some());
+            assert!(storage.open_bucket("completed")?.iter()?.is_empty());
+            assert_eq!(storage.open_bucket("pins")?.get("f1")?, Some(Vec::new()));
+            assert_eq!(
+                storage.open_bucket("chunks")?.get_json::<Vec<u32>>("f3")?,
+                Some(vec![0, 2])
+            );
+
+            // The flat keys are gone, and keys the migration does not know stay
+            assert_eq!(storage.get("offers")?, None);
+            assert_eq!(storage.get("transfer:f3:chunks")?, None);
+            assert_eq!(storage.get("unrelated")?, Some(b"1".to_vec()));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_buckets_are_isolated() -> Result<()> {
+        let dir = tempdir()?;
+
+        for storage in [Storage::new(), Storage::open(dir.path())?] {
+            let peers = storage.open_bucket("peers")?;
+            let offers = storage.open_bucket("offers")?;
+
+            peers.insert_json("a".to_string(), &1u32)?;
+            peers.insert_json("b".to_string(), &2u32)?;
+            offers.insert_json("a".to_string(), &10u32)?;
+
+            assert_eq!(peers.get_json::<u32>("a")?, Some(1));
+            assert_eq!(offers.get_json::<u32>("a")?, Some(10));
+            assert_eq!(storage.get("a")?, None);
+            assert_eq!(
+                peers.iter_json::<u32>()?,
+                vec![("a".to_string(), 1), ("b".to_string(), 2)]
+            );
+
+            // Reopening a bucket sees the same data; clearing leaves others alone
+            storage.open_bucket("peers")?.clear()?;
+            assert!(peers.iter()?.is_empty());
+            assert_eq!(offers.iter()?.len(), 1);
+        }
+
+        assert!(Storage::new().open_bucket("__meta").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ttl_entries_expire() -> Result<()> {
+        let dir = tempdir()?;
+
+        for storage in [Storage::new(), Storage::open(dir.path())?] {
+            let cache = storage.open_bucket("cache")?;
+
+            cache.insert_with_ttl("stale".to_string(), b"old".to_vec(), Duration::ZERO)?;
+            cache.insert_with_ttl(
+                "fresh".to_string(),
+                b"new".to_vec(),
+                Duration::from_secs(60),
+            )?;
+            cache.insert("forever".to_string(), b"kept".to_vec())?;
+
+            // Lazily expired on access
+            assert_eq!(cache.get("stale")?, None);
+            assert_eq!(cache.get("fresh")?, Some(b"new".to_vec()));
+            assert_eq!(cache.iter()?.len(), 2);
+
+            // Re-inserting without a TTL makes an entry permanent
+            cache.insert_with_ttl("fresh".to_string(), b"new".to_vec(), Duration::ZERO)?;
+            cache.insert("fresh".to_string(), b"again".to_vec())?;
+            assert_eq!(cache.get("fresh")?, Some(b"again".to_vec()));
+
+            // Background purge sweeps every bucket
+            let other = storage.open_bucket("other")?;
+            other.insert_with_ttl("a".to_string(), b"1".to_vec(), Duration::ZERO)?;
+            cache.insert_with_ttl("b".to_string(), b"2".to_vec(), Duration::ZERO)?;
+            assert_eq!(storage.purge_expired()?, 2);
+            assert_eq!(storage.purge_expired()?, 0);
+            assert_eq!(cache.iter()?.len(), 2);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_batches_and_prefix_scans() -> Result<()> {
+        let dir = tempdir()?;
+
+        for storage in [Storage::new(), Storage::open(dir.path())?] {
+            let files = storage.open_bucket("files")?;
+            files.insert_with_ttl("a/old".to_string(), b"0".to_vec(), Duration::ZERO)?;
+            files.insert("c/3".to_string(), b"3".to_vec())?;
+
+            let mut batch = WriteBatch::new();
+            batch.insert("a/1".to_string(), b"1".to_vec());
+            batch.insert_json("a/2".to_string(), &2u32)?;
+            batch.insert("b/1".to_string(), b"x".to_vec());
+            batch.remove("c/3".to_string());
+            files.apply_batch(batch)?;
+
+            // Expired entries are skipped, the rest come back in key order
+            let keys: Vec<String> = files
+                .scan_prefix("a/")
+                .map(|entry| entry.map(|(key, _)| key))
+                .collect::<Result<_>>()?;
+            assert_eq!(keys, vec!["a/1", "a/2"]);
+            assert_eq!(
+                files.scan_prefix_json::<u32>("a/2")?,
+                vec![("a/2".to_string(), 2)]
+            );
+            assert_eq!(files.get("c/3")?, None);
+
+            assert_eq!(files.remove_prefix("a/")?, 2);
+            assert_eq!(files.iter()?, vec![("b/1".to_string(), b"x".to_vec())]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() -> Result<()> {
+        let dir = tempdir()?;
+        let source = Storage::open(dir.path())?;
+
+        source.insert("root".to_string(), b"r".to_vec())?;
+        source
+            .open_bucket("offers")?
+            .insert("f1".to_string(), b"meta".to_vec())?;
+        source.open_bucket("cache")?.insert_with_ttl(
+            "tmp".to_string(),
+            b"x".to_vec(),
+            Duration::from_secs(60),
+        )?;
+
+        let snapshot = source.snapshot()?;
+        assert_eq!(
+            snapshot.keys().collect::<Vec<_>>(),
+            vec!["", "offers"],
+            "expiring entries are not part of a snapshot"
+        );
+
+        let target = Storage::new();
+        target
+            .open_bucket("offers")?
+            .insert("stale".to_string(), b"old".to_vec())?;
+        target.restore(&snapshot)?;
+
+        assert_eq!(target.get("root")?, Some(b"r".to_vec()));
+        assert_eq!(
+            target.open_bucket("offers")?.iter()?,
+            vec![("f1".to_string(), b"meta".to_vec())]
+        );
+
+        Ok(())
+    }
+}
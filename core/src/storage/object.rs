@@ -0,0 +1,225 @@
+use crate::Result;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Backend for storing opaque objects (chunk blocks, files) under string keys.
+///
+/// Keys may contain `/` separators, which backends are free to map onto
+/// directories or key prefixes.
+pub trait ObjectStore: Send + Sync {
+    fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    fn has(&self, key: &str) -> Result<bool>;
+
+    /// Remove an object. Returns whether it was present.
+    fn delete(&self, key: &str) -> Result<bool>;
+
+    /// List every stored key with its size in bytes
+    fn list(&self) -> Result<Vec<(String, u64)>>;
+}
+
+/// Objects kept in memory, mainly for tests
+#[derive(Default)]
+pub struct MemoryObjectStore {
+    objects: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryObjectStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ObjectStore for MemoryObjectStore {
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.objects
+            .write()
+            .unwrap()
+            .insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.objects.read().unwrap().get(key).cloned())
+    }
+
+    fn has(&self, key: &str) -> Result<bool> {
+        Ok(self.objects.read().unwrap().contains_key(key))
+    }
+
+    fn delete(&self, key: &str) -> Result<bool> {
+        Ok(self.objects.write().unwrap().remove(key).is_some())
+    }
+
+    fn list(&self) -> Result<Vec<(String, u64)>> {
+        Ok(self
+            .objects
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, data)| (key.clone(), data.len() as u64))
+            .collect())
+    }
+}
+
+/// Objects stored as files below a root directory on local disk
+#[derive(Debug, Clone)]
+pub struct DiskObjectStore {
+    root: PathBuf,
+}
+
+impl DiskObjectStore {
+    pub fn open(root: &Path) -> Result<Self> {
+        fs::create_dir_all(root)?;
+        Ok(Self {
+            root: root.to_path_buf(),
+        })
+    }
+
+    fn object_path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+
+    fn list_dir(&self, dir: &Path, objects: &mut Vec<(String, u64)>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                self.list_dir(&path, objects)?;
+            } else if path.extension() != Some(OsStr::new("tmp")) {
+                let key = path
+                    .strip_prefix(&self.root)
+                    .unwrap()
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                objects.push((key, entry.metadata()?.len()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ObjectStore for DiskObjectStore {
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.object_path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first so readers never observe a partial object
+        let tmp_path = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(self.object_path(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn has(&self, key: &str) -> Result<bool> {
+        Ok(self.object_path(key).exists())
+    }
+
+    fn delete(&self, key: &str) -> Result<bool> {
+        match fs::remove_file(self.object_path(key)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<(String, u64)>> {
+        let mut objects = Vec::new();
+        self.list_dir(&self.root, &mut objects)?;
+        Ok(objects)
+    }
+}
+
+/// Copy every object missing from `to` out of `from`. Returns the number copied.
+pub fn migrate(from: &dyn ObjectStore, to: &dyn ObjectStore) -> Result<usize> {
+    let mut copied = 0;
+
+    for (key, _) in from.list()? {
+        if to.has(&key)? {
+            continue;
+        }
+        if let Some(data) = from.get(&key)? {
+            to.put(&key, &data)?;
+            copied += 1;
+        }
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn exercise(store: &dyn ObjectStore) -> Result<()> {
+        store.put("ab/first", b"one")?;
+        store.put("cd/second", b"second")?;
+
+        assert!(store.has("ab/first")?);
+        assert_eq!(store.get("cd/second")?, Some(b"second".to_vec()));
+        assert_eq!(store.get("missing")?, None);
+
+        let mut keys = store.list()?;
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![("ab/first".to_string(), 3), ("cd/second".to_string(), 6)]
+        );
+
+        assert!(store.delete("ab/first")?);
+        assert!(!store.delete("ab/first")?);
+        assert!(!store.has("ab/first")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_object_store() -> Result<()> {
+        exercise(&MemoryObjectStore::new())
+    }
+
+    #[test]
+    fn test_disk_object_store() -> Result<()> {
+        let dir = tempdir()?;
+        exercise(&DiskObjectStore::open(dir.path())?)
+    }
+
+    #[test]
+    fn test_migrate_between_backends() -> Result<()> {
+        let dir = tempdir()?;
+        let disk = DiskObjectStore::open(dir.path())?;
+        let memory = MemoryObjectStore::new();
+
+        disk.put("ab/one", b"1")?;
+        disk.put("cd/two", b"2")?;
+        memory.put("ab/one", b"1")?;
+
+        assert_eq!(migrate(&disk, &memory)?, 1);
+        assert_eq!(memory.get("cd/two")?, Some(b"2".to_vec()));
+        assert_eq!(migrate(&disk, &memory)?, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use super::Storage;
+use crate::Result;
+use std::collections::HashSet;
+
+/// Bucket holding one (empty) entry per pinned file id
+const PINS_BUCKET: &str = "pins";
+
+/// Files that are exempt from quota eviction and garbage collection
+pub struct PinSet {
+    store: Storage,
+    pinned: HashSet<String>,
+}
+
+impl PinSet {
+    pub fn load(store: Storage) -> Result<Self> {
+        let store = store.open_bucket(PINS_BUCKET)?;
+        let pinned = store
+            .iter()?
+            .into_iter()
+            .map(|(file_id, _)| file_id)
+            .collect();
+        Ok(Self { store, pinned })
+    }
+
+    /// Pin a file. Returns false if it was already pinned.
+    pub fn pin(&mut self, file_id: &str) -> Result<bool> {
+        let added = self.pinned.insert(file_id.to_string());
+        if added {
+            self.store.insert(file_id.to_string(), Vec::new())?;
+        }
+        Ok(added)
+    }
+
+    /// Unpin a file. Returns false if it was not pinned.
+    pub fn unpin(&mut self, file_id: &str) -> Result<bool> {
+        let removed = self.pinned.remove(file_id);
+        if removed {
+            self.store.remove(file_id)?;
+        }
+        Ok(removed)
+    }
+
+    pub fn is_pinned(&self, file_id: &str) -> bool {
+        self.pinned.contains(file_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.pinned.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pins_persist() -> Result<()> {
+        let store = Storage::new();
+
+        let mut pins = PinSet::load(store.clone())?;
+        assert!(pins.pin("file-a")?);
+        assert!(!pins.pin("file-a")?);
+        assert!(pins.pin("file-b")?);
+        assert!(pins.unpin("file-b")?);
+        assert!(!pins.unpin("file-b")?);
+
+        let pins = PinSet::load(store)?;
+        assert!(pins.is_pinned("file-a"));
+        assert!(!pins.is_pinned("file-b"));
+        assert_eq!(pins.iter().count(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use super::object::ObjectStore;
+use crate::Result;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// When objects move between the hot and cold tiers
+#[derive(Debug, Clone)]
+pub struct TieringPolicy {
+    /// Hot objects not read for this long are demoted to the cold tier
+    pub cold_after: Duration,
+    /// Cold objects read at least this many times between passes are promoted
+    pub promote_after_hits: u32,
+}
+
+/// Outcome of a tiering pass
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TieringReport {
+    pub promoted: usize,
+    pub demoted: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AccessStats {
+    /// Reads since the last tiering pass
+    hits: u32,
+    last_access: Instant,
+}
+
+/// Two backends behind one store: new and frequently read objects live on the
+/// fast hot tier, objects that go unread move to the slower, cheaper cold tier.
+///
+/// Access frequency is tracked in memory, so after a restart every object
+/// counts as freshly accessed.
+pub struct TieredObjectStore {
+    hot: Arc<dyn ObjectStore>,
+    cold: Arc<dyn ObjectStore>,
+    access: RwLock<HashMap<String, AccessStats>>,
+    started: Instant,
+}
+
+impl TieredObjectStore {
+    pub fn new(hot: Arc<dyn ObjectStore>, cold: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            hot,
+            cold,
+            access: RwLock::default(),
+            started: Instant::now(),
+        }
+    }
+
+    fn record_access(&self, key: &str) {
+        let mut access = self.access.write().unwrap();
+        let stats = access.entry(key.to_string()).or_insert(AccessStats {
+            hits: 0,
+            last_access: Instant::now(),
+        });
+        stats.hits = stats.hits.saturating_add(1);
+        stats.last_access = Instant::now();
+    }
+
+    fn stats(&self, key: &str) -> AccessStats {
+        self.access
+            .read()
+            .unwrap()
+            .get(key)
+            .copied()
+            .unwrap_or(AccessStats {
+                hits: 0,
+                last_access: self.started,
+            })
+    }
+
+    /// Move objects between tiers according to `policy` and start a new hit window
+    pub fn apply_policy(&self, policy: &TieringPolicy) -> Result<TieringReport> {
+        let mut report = TieringReport::default();
+
+        for (key, _) in self.cold.list()? {
+            if self.stats(&key).hits < policy.promote_after_hits {
+                continue;
+            }
+            if let Some(data) = self.cold.get(&key)? {
+                self.hot.put(&key, &data)?;
+                self.cold.delete(&key)?;
+                report.promoted += 1;
+            }
+        }
+
+        for (key, _) in self.hot.list()? {
+            if self.stats(&key).last_access.elapsed() < policy.cold_after {
+                continue;
+            }
+            if let Some(data) = self.hot.get(&key)? {
+                self.cold.put(&key, &data)?;
+                self.hot.delete(&key)?;
+                report.demoted += 1;
+            }
+        }
+
+        for stats in self.access.write().unwrap().values_mut() {
+            stats.hits = 0;
+        }
+        Ok(report)
+    }
+}
+
+impl ObjectStore for TieredObjectStore {
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.hot.put(key, data)?;
+        self.record_access(key);
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let data = match self.hot.get(key)? {
+            Some(data) => Some(data),
+            None => self.cold.get(key)?,
+        };
+        if data.is_some() {
+            self.record_access(key);
+        }
+        Ok(data)
+    }
+
+    fn has(&self, key: &str) -> Result<bool> {
+        Ok(self.hot.has(key)? || self.cold.has(key)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<bool> {
+        self.access.write().unwrap().remove(key);
+        let hot = self.hot.delete(key)?;
+        let cold = self.cold.delete(key)?;
+        Ok(hot || cold)
+    }
+
+    fn list(&self) -> Result<Vec<(String, u64)>> {
+        let mut objects: HashMap<String, u64> = self.cold.list()?.into_iter().collect();
+        objects.extend(self.hot.list()?);
+        Ok(objects.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryObjectStore;
+
+    #[test]
+    fn test_tiering_moves_objects() -> Result<()> {
+        let hot = Arc::new(MemoryObjectStore::new());
+        let cold = Arc::new(MemoryObjectStore::new());
+        let store = TieredObjectStore::new(hot.clone(), cold.clone());
+
+        store.put("a", b"first")?;
+        store.put("b", b"second")?;
+
+        // Nothing has gone unread long enough to demote
+        let keep = TieringPolicy {
+            cold_after: Duration::from_secs(3600),
+            promote_after_hits: 2,
+        };
+        assert_eq!(store.apply_policy(&keep)?, TieringReport::default());
+
+        // Demote everything, then reads still find objects in the cold tier
+        let demote_all = TieringPolicy {
+            cold_after: Duration::ZERO,
+            ..keep.clone()
+        };
+        assert_eq!(store.apply_policy(&demote_all)?.demoted, 2);
+        assert!(hot.list()?.is_empty());
+        assert_eq!(store.get("a")?, Some(b"first".to_vec()));
+        assert_eq!(store.list()?.len(), 2);
+
+        // A frequently read cold object is promoted back
+        store.get("a")?;
+        assert_eq!(
+            store.apply_policy(&keep)?,
+            TieringReport {
+                promoted: 1,
+                demoted: 0
+            }
+        );
+        assert!(hot.has("a")?);
+        assert!(cold.has("b")?);
+
+        assert!(store.delete("b")?);
+        assert!(!store.has("b")?);
+
+        Ok(())
+    }
+}
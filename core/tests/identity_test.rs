@@ -1,4 +1,5 @@
-use corelink_core::identity::Identity;
+use corelink_core::identity::{Identity, NodeId};
+use corelink_core::{Message, MessageType};
 
 #[test]
 fn test_identity_generation() {
@@ -17,3 +18,44 @@ fn test_signature_verification() {
 
     assert_eq!(signature.to_bytes().len(), 64);
 }
+
+#[test]
+fn test_node_id_from_peer_id() {
+    let keypair = libp2p_identity::Keypair::ed25519_from_bytes([7u8; 32]).unwrap();
+    let peer_id = keypair.public().to_peer_id();
+
+    let pubkey = keypair.public().try_into_ed25519().unwrap().to_bytes();
+    let pubkey = ed25519_dalek::VerifyingKey::from_bytes(&pubkey).unwrap();
+
+    assert_eq!(NodeId::from_peer_id(&peer_id), NodeId::from_pubkey(&pubkey));
+}
+
+#[test]
+fn test_message_signature() {
+    let keypair = libp2p_identity::Keypair::ed25519_from_bytes([7u8; 32]).unwrap();
+    let peer_id = keypair.public().to_peer_id();
+    let other = libp2p_identity::Keypair::ed25519_from_bytes([8u8; 32])
+        .unwrap()
+        .public()
+        .to_peer_id();
+    let message = Message::new(
+        NodeId::from_peer_id(&other),
+        MessageType::Custom {
+            msg_type: "chat".to_string(),
+            payload: serde_json::json!({ "text": "hello" }),
+        },
+    )
+    .signed(&keypair)
+    .unwrap();
+
+    assert_eq!(message.from, NodeId::from_peer_id(&peer_id));
+    assert!(message.verify(&peer_id));
+    assert!(!message.verify(&other));
+
+    let mut tampered = message.clone();
+    tampered.msg_type = MessageType::Custom {
+        msg_type: "chat".to_string(),
+        payload: serde_json::json!({ "text": "goodbye" }),
+    };
+    assert!(!tampered.verify(&peer_id));
+}
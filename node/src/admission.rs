@@ -0,0 +1,245 @@
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::Multiaddr;
+use libp2p_identity::PeerId;
+use libp2p_swarm::ConnectionId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// Caps on how many connections the node keeps
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionLimits {
+    pub max_inbound: usize,
+    pub max_outbound: usize,
+    /// Connections allowed from a single remote IP
+    pub max_per_ip: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_inbound: 64,
+            max_outbound: 32,
+            max_per_ip: 8,
+        }
+    }
+}
+
+/// Why a connection was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdmissionError {
+    TooManyFromIp(IpAddr),
+    InboundFull,
+    OutboundFull,
+    Banned(PeerId),
+}
+
+impl fmt::Display for AdmissionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdmissionError::TooManyFromIp(ip) => write!(f, "too many connections from {}", ip),
+            AdmissionError::InboundFull => write!(f, "inbound connection limit reached"),
+            AdmissionError::OutboundFull => write!(f, "outbound connection limit reached"),
+            AdmissionError::Banned(peer) => write!(f, "{} is banned", peer),
+        }
+    }
+}
+
+impl std::error::Error for AdmissionError {}
+
+struct Connection {
+    peer: PeerId,
+    inbound: bool,
+    ip: Option<IpAddr>,
+}
+
+/// Open connections, checked against [`ConnectionLimits`] before new ones are
+/// accepted.
+///
+/// When the inbound limit is reached, the inbound connection of the
+/// worst-scoring peer is evicted to make room, as long as the peer scores
+/// below a newcomer (0).
+/// Banned peers are refused either way until their ban runs out.
+pub struct ConnectionTracker {
+    limits: ConnectionLimits,
+    connections: HashMap<ConnectionId, Connection>,
+    /// Connections being closed to make room, no longer counted
+    evicting: HashSet<ConnectionId>,
+    /// Banned peers and when their ban ends
+    bans: HashMap<PeerId, Instant>,
+}
+
+impl ConnectionTracker {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            connections: HashMap::new(),
+            evicting: HashSet::new(),
+            bans: HashMap::new(),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+
+    fn live(&self) -> impl Iterator<Item = (&ConnectionId, &Connection)> {
+        self.connections
+            .iter()
+            .filter(|(id, _)| !self.evicting.contains(id))
+    }
+
+    /// Refuse connections with `peer` until `until`
+    pub fn ban(&mut self, peer: PeerId, until: Instant) {
+        self.bans.insert(peer, until);
+    }
+
+    /// Decide on any connection with `peer`, forgetting its ban once over
+    pub fn admit_peer(&mut self, peer: &PeerId) -> Result<(), AdmissionError> {
+        match self.bans.get(peer) {
+            Some(until) if *until > Instant::now() => Err(AdmissionError::Banned(*peer)),
+            Some(_) => {
+                self.bans.remove(peer);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Decide on a new inbound connection from `remote_addr`. Returns an
+    /// inbound connection to close, and its peer, if room had to be made.
+    pub fn admit_inbound(
+        &mut self,
+        remote_addr: &Multiaddr,
+        score: impl Fn(&PeerId) -> f64,
+    ) -> Result<Option<(PeerId, ConnectionId)>, AdmissionError> {
+        if let Some(ip) = ip_of(remote_addr) {
+            if self.live().filter(|(_, c)| c.ip == Some(ip)).count() >= self.limits.max_per_ip {
+                return Err(AdmissionError::TooManyFromIp(ip));
+            }
+        }
+
+        if self.live().filter(|(_, c)| c.inbound).count() < self.limits.max_inbound {
+            return Ok(None);
+        }
+
+        let worst = self
+            .live()
+            .filter(|(_, c)| c.inbound)
+            .map(|(id, c)| (c.peer, *id, score(&c.peer)))
+            .min_by(|a, b| a.2.total_cmp(&b.2));
+        match worst {
+            Some((peer, id, score)) if score < 0.0 => {
+                self.evicting.insert(id);
+                Ok(Some((peer, id)))
+            }
+            _ => Err(AdmissionError::InboundFull),
+        }
+    }
+
+    /// Decide on dialing out
+    pub fn admit_outbound(&self) -> Result<(), AdmissionError> {
+        if self.live().filter(|(_, c)| !c.inbound).count() >= self.limits.max_outbound {
+            return Err(AdmissionError::OutboundFull);
+        }
+        Ok(())
+    }
+
+    pub fn established(
+        &mut self,
+        id: ConnectionId,
+        peer: PeerId,
+        inbound: bool,
+        remote_addr: &Multiaddr,
+    ) {
+        self.connections.insert(
+            id,
+            Connection {
+                peer,
+                inbound,
+                ip: ip_of(remote_addr),
+            },
+        );
+    }
+
+    pub fn closed(&mut self, id: ConnectionId) {
+        self.connections.remove(&id);
+        self.evicting.remove(&id);
+    }
+}
+
+fn ip_of(addr: &Multiaddr) -> Option<IpAddr> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+        Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_and_eviction() {
+        let mut tracker = ConnectionTracker::new(ConnectionLimits {
+            max_inbound: 2,
+            max_outbound: 1,
+            max_per_ip: 1,
+        });
+        let addr = |n: u8| -> Multiaddr { format!("/ip4/10.0.0.{}/tcp/4001", n).parse().unwrap() };
+        let (good, bad) = (PeerId::random(), PeerId::random());
+        let score = |peer: &PeerId| if *peer == bad { -5.0 } else { 1.0 };
+
+        assert_eq!(tracker.admit_inbound(&addr(1), score), Ok(None));
+        tracker.established(ConnectionId::new_unchecked(1), good, true, &addr(1));
+
+        // One connection per IP
+        assert!(matches!(
+            tracker.admit_inbound(&addr(1), score),
+            Err(AdmissionError::TooManyFromIp(_))
+        ));
+
+        assert_eq!(tracker.admit_inbound(&addr(2), score), Ok(None));
+        tracker.established(ConnectionId::new_unchecked(2), bad, true, &addr(2));
+
+        // At capacity, the badly scoring peer makes room; the good one never does
+        assert_eq!(
+            tracker.admit_inbound(&addr(3), score),
+            Ok(Some((bad, ConnectionId::new_unchecked(2))))
+        );
+        tracker.established(
+            ConnectionId::new_unchecked(3),
+            PeerId::random(),
+            true,
+            &addr(3),
+        );
+        assert_eq!(
+            tracker.admit_inbound(&addr(4), score),
+            Err(AdmissionError::InboundFull)
+        );
+        tracker.closed(ConnectionId::new_unchecked(2));
+
+        assert_eq!(tracker.admit_outbound(), Ok(()));
+        tracker.established(ConnectionId::new_unchecked(4), bad, false, &addr(5));
+        assert_eq!(tracker.admit_outbound(), Err(AdmissionError::OutboundFull));
+    }
+
+    #[test]
+    fn test_bans_expire() {
+        let mut tracker = ConnectionTracker::new(ConnectionLimits::default());
+        let (banned, expired) = (PeerId::random(), PeerId::random());
+        tracker.ban(banned, Instant::now() + std::time::Duration::from_secs(60));
+        tracker.ban(expired, Instant::now());
+
+        assert_eq!(
+            tracker.admit_peer(&banned),
+            Err(AdmissionError::Banned(banned))
+        );
+        assert_eq!(tracker.admit_peer(&expired), Ok(()));
+        assert_eq!(tracker.admit_peer(&PeerId::random()), Ok(()));
+    }
+}
@@ -1,54 +1,488 @@
+use crate::api_error::{self, ApiError, ProblemDetails};
+use crate::behaviour_stats::BehaviourStats;
+use crate::file_transfer::{
+    CacheStats, FileRemoval, GcReport, RecoveryReport, TransferState, TransferSummary,
+};
+use crate::health::{HealthCheck, HealthReport, HealthStatus};
+use crate::peer_registry::RttSample;
+use crate::rate_limit::{self, RateLimiter};
+use crate::replication::ReplicationHealth;
+use crate::transfer_activity::{ActivityState, TransferError};
+use crate::websocket::{self, Encoding, EventHub};
 use axum::{
-    extract::State,
-    http::{Method, StatusCode},
-    response::{IntoResponse, Json},
+    body::Body,
+    extract::{ConnectInfo, DefaultBodyLimit, Path, Query, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
     routing::{get, post},
-    Router,
+    RequestExt, Router,
 };
+use corelink_core::file::{name_matches, FileMetadata};
+use corelink_core::storage::BlockUsage;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot, watch, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::WebSocketStream;
+use tower::ServiceExt;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{debug, error, info, warn};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use utoipa_swagger_ui::SwaggerUi;
+
+/// Commands sent from API handlers to the node's main loop
+#[derive(Debug)]
+pub enum ApiCommand {
+    /// Pin or unpin a file
+    SetPinned {
+        file_id: String,
+        pinned: bool,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Run a garbage collection pass
+    CollectGarbage {
+        dry_run: bool,
+        reply: oneshot::Sender<Result<GcReport, String>>,
+    },
+    /// List a file's version history, newest first
+    FileVersions {
+        file_id: String,
+        reply: oneshot::Sender<Vec<FileVersion>>,
+    },
+    /// Replace a peer's operator tags and note
+    SetPeerTags {
+        peer_id: String,
+        tags: Vec<String>,
+        note: Option<String>,
+        reply: oneshot::Sender<Result<(), String>>,
+    },
+    /// Connect to a peer by address, remembering it if `save` is set.
+    /// Replies with the peer id once connected.
+    Dial {
+        address: Multiaddr,
+        save: bool,
+        reply: oneshot::Sender<Result<String, String>>,
+    },
+    /// Connect to a peer at the addresses the node knows for it, replying
+    /// at once if already connected
+    ConnectPeer {
+        peer_id: PeerId,
+        save: bool,
+        reply: oneshot::Sender<Result<String, String>>,
+    },
+    /// Describe one connected peer in full
+    PeerDetail {
+        peer_id: PeerId,
+        reply: oneshot::Sender<Option<PeerDetail>>,
+    },
+    /// Close every connection to a peer, banning it if `ban` is set for
+    /// `ban_secs` or the configured ban duration
+    DisconnectPeer {
+        peer_id: PeerId,
+        ban: bool,
+        ban_secs: Option<u64>,
+        reply: oneshot::Sender<PeerDisconnect>,
+    },
+    /// Ask connected peers for offered files whose name matches `query`.
+    /// Replies with how many peers were asked and a channel their answers
+    /// arrive on.
+    QueryPeers {
+        query: String,
+        limit: u32,
+        reply: oneshot::Sender<(usize, mpsc::Receiver<QueryAnswer>)>,
+    },
+    /// Sign and send an application message to a connected peer. Replies
+    /// with a receipt that resolves once the message is written or fails;
+    /// under backpressure, only once the peer's queue has room for it.
+    SendMessage {
+        peer_id: PeerId,
+        msg_type: String,
+        payload: serde_json::Value,
+        reply: oneshot::Sender<io::Result<MessageReceipt>>,
+    },
+    /// Offer a local file to connected peers
+    OfferFile {
+        path: PathBuf,
+        reply: oneshot::Sender<io::Result<FileInfo>>,
+    },
+    /// Download a file a connected peer has offered, from `peer` first if
+    /// given. A file nobody offered is asked for from `peer`, or from the
+    /// peers known to hold it.
+    Download {
+        file_id: String,
+        peer: Option<PeerId>,
+        reply: oneshot::Sender<io::Result<TransferSummary>>,
+    },
+    /// Offers waiting to be accepted or rejected
+    Offers {
+        reply: oneshot::Sender<Vec<OfferInfo>>,
+    },
+    /// Download a file waiting in manual mode from the peers offering it
+    AcceptOffer {
+        file_id: String,
+        reply: oneshot::Sender<io::Result<TransferSummary>>,
+    },
+    /// Decline a file waiting in manual mode, telling the peers offering it
+    RejectOffer {
+        file_id: String,
+        reply: oneshot::Sender<io::Result<OfferInfo>>,
+    },
+    /// Stop a running or queued download
+    CancelDownload {
+        file_id: String,
+        reply: oneshot::Sender<io::Result<()>>,
+    },
+    /// Cancel a download, withdraw an offer or delete a finished download
+    RemoveFile {
+        file_id: String,
+        reply: oneshot::Sender<io::Result<FileRemoval>>,
+    },
+    /// Describe one file in full
+    FileDetail {
+        file_id: String,
+        reply: oneshot::Sender<Option<FileDetail>>,
+    },
+    /// Report progress of one download
+    Transfer {
+        file_id: String,
+        reply: oneshot::Sender<Option<TransferSummary>>,
+    },
+    /// Re-read the config file and apply the settings that can change
+    /// while running. Replies with the changed settings that need a restart.
+    ReloadConfig {
+        reply: oneshot::Sender<Result<Vec<String>, String>>,
+    },
+    /// Report progress of every running and queued download
+    Transfers {
+        reply: oneshot::Sender<Vec<TransferSummary>>,
+    },
+    /// Report speed, state and peers of uploads and downloads in progress
+    LiveTransfers {
+        reply: oneshot::Sender<TransfersReport>,
+    },
+    /// Snapshot every counter and gauge
+    Metrics {
+        reply: oneshot::Sender<Result<NodeMetrics, String>>,
+    },
+    /// Report raw and stored block sizes
+    StorageUsage {
+        reply: oneshot::Sender<Result<BlockUsage, String>>,
+    },
+    /// Report this node, its peers and the links between them
+    Topology {
+        reply: oneshot::Sender<NetworkTopology>,
+    },
+}
+
+/// A peer's answer to a file query: the matching files it offers
+pub type QueryAnswer = (PeerId, Vec<FileMetadata>);
+
+/// An application message on its way to its peer
+#[derive(Debug)]
+pub struct MessageReceipt {
+    /// Id its `MessageSent` or `MessageFailed` event carries
+    pub message_id: u64,
+    /// Resolves once the message is written or fails for good
+    pub outcome: oneshot::Receiver<Result<(), String>>,
+}
 
 /// Shared API state
 #[derive(Clone)]
 pub struct ApiState {
     inner: Arc<RwLock<ApiStateInner>>,
+    commands: Option<mpsc::Sender<ApiCommand>>,
+    /// Where uploaded files are written before being offered
+    uploads_dir: Option<PathBuf>,
+    /// Tells this run's ETags from those of earlier runs
+    instance: u64,
+    /// Bearer token the admin endpoints require; they refuse every request
+    /// without one
+    admin_token: Option<String>,
+    /// Asks the node to shut down or restart
+    stop: Option<mpsc::Sender<StopRequest>>,
+    /// Clients of `/ws`, which answers 404 without it
+    events: Option<EventHub>,
+    /// Directories `/files/offer` may offer files from
+    share_dirs: Vec<PathBuf>,
+    /// Origins browsers may call the API from; none without any
+    cors_origins: Vec<HeaderValue>,
+    /// Longest ban a disconnect request may ask for
+    max_ban_secs: u64,
+    /// Largest body `/files/upload` accepts
+    max_upload_bytes: usize,
 }
 
 struct ApiStateInner {
+    node: NodeInfo,
     stats: NodeStats,
     peers: Vec<PeerInfo>,
     files: Vec<FileInfo>,
+    replication: Vec<ReplicationHealth>,
+    health: HealthReport,
+    recovery: RecoveryReport,
+    versions: Versions,
+}
+
+/// Resources dashboards poll, answered with an ETag
+#[derive(Debug, Clone, Copy)]
+enum Polled {
+    Stats,
+    Peers,
+    Files,
+}
+
+/// How many times each polled resource has changed
+#[derive(Debug, Clone, Copy, Default)]
+struct Versions {
+    stats: u64,
+    peers: u64,
+    files: u64,
 }
 
 impl ApiState {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(RwLock::new(ApiStateInner {
+                node: NodeInfo::default(),
                 stats: NodeStats {
                     peer_count: 0,
                     active_uploads: 0,
                     active_downloads: 0,
+                    queued_downloads: 0,
                     uptime_seconds: 0,
                     bytes_sent: 0,
                     bytes_received: 0,
+                    partition_suspected: false,
+                    websocket_clients: 0,
+                    websocket_events_dropped: 0,
                 },
                 peers: Vec::new(),
                 files: Vec::new(),
+                replication: Vec::new(),
+                health: HealthReport::default(),
+                recovery: RecoveryReport::default(),
+                versions: Versions::default(),
             })),
+            commands: None,
+            uploads_dir: None,
+            instance: rand::random(),
+            admin_token: None,
+            stop: None,
+            events: None,
+            share_dirs: Vec::new(),
+            cors_origins: Vec::new(),
+            max_ban_secs: u64::MAX,
+            max_upload_bytes: usize::MAX,
+        }
+    }
+
+    /// Route mutating requests to the node's main loop through `commands`
+    pub fn with_commands(mut self, commands: mpsc::Sender<ApiCommand>) -> Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Accept file uploads into `uploads_dir`
+    pub fn with_uploads_dir(mut self, uploads_dir: PathBuf) -> Self {
+        self.uploads_dir = Some(uploads_dir);
+        self
+    }
+
+    /// Let `/files/offer` offer files under `dirs`, and nowhere else
+    pub fn with_share_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.share_dirs = dirs;
+        self
+    }
+
+    /// Whether `path`, already canonical, lies under a share directory
+    async fn is_shared(&self, path: &std::path::Path) -> bool {
+        for dir in &self.share_dirs {
+            if let Ok(dir) = tokio::fs::canonicalize(dir).await {
+                if path.starts_with(&dir) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Refuse uploads larger than `bytes`
+    pub fn with_max_upload_bytes(mut self, bytes: u64) -> Self {
+        self.max_upload_bytes = usize::try_from(bytes).unwrap_or(usize::MAX);
+        self
+    }
+
+    /// Refuse disconnect requests asking for bans longer than `secs`
+    pub fn with_max_ban_secs(mut self, secs: u64) -> Self {
+        self.max_ban_secs = secs;
+        self
+    }
+
+    /// Let browsers on `origins` call the API. Origins that are not valid
+    /// header values are skipped.
+    pub fn with_cors_origins(mut self, origins: &[String]) -> Self {
+        self.cors_origins = origins
+            .iter()
+            .filter_map(|origin| match HeaderValue::from_str(origin) {
+                Ok(origin) => Some(origin),
+                Err(_) => {
+                    warn!("Ignoring invalid CORS origin {:?}", origin);
+                    None
+                }
+            })
+            .collect();
+        self
+    }
+
+    /// Enable the admin endpoints for requests bearing `token`
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token.filter(|token| !token.is_empty());
+        self
+    }
+
+    /// Pass shutdown and restart requests from the admin endpoints to `stop`
+    pub fn with_stop(mut self, stop: mpsc::Sender<StopRequest>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Stream the events `events` numbers to clients of `/ws`
+    pub fn with_events(mut self, events: EventHub) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Fail unless `headers` carry the admin token
+    fn authorize_admin(&self, headers: &HeaderMap) -> Result<(), ApiError> {
+        let Some(token) = &self.admin_token else {
+            return Err(ApiError::new(
+                StatusCode::FORBIDDEN,
+                "Admin endpoints are disabled; set api_admin_token to enable them",
+            )
+            .with_code("admin_disabled"));
+        };
+        let given = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match given {
+            Some(given) if same_token(given, token) => Ok(()),
+            Some(_) => Err(ApiError::unauthorized("Invalid admin token")),
+            None => Err(ApiError::unauthorized("Admin token required")),
+        }
+    }
+
+    /// Ask the node to stop, answering once the request is passed on; the
+    /// node then stops the same way as on SIGTERM
+    async fn request_stop(&self, headers: &HeaderMap, stop: StopRequest) -> ApiResult {
+        self.authorize_admin(headers)?;
+        let sender = self.stop.as_ref().ok_or_else(ApiError::unavailable)?;
+        match sender.try_send(stop) {
+            Ok(()) => {
+                info!("🛑 {:?} requested through the REST API", stop);
+                Ok((
+                    StatusCode::ACCEPTED,
+                    Json(serde_json::json!(StopResponse { action: stop })),
+                ))
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                Err(ApiError::conflict("Node is already stopping").with_code("already_stopping"))
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(ApiError::unavailable()),
         }
     }
 
+    /// Send a command to the main loop and wait for its reply.
+    ///
+    /// Fails with 503 if the node is not accepting commands.
+    async fn send_command<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> ApiCommand,
+    ) -> Result<T, ApiError> {
+        let commands = self.commands.as_ref().ok_or_else(ApiError::unavailable)?;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        commands
+            .send(command(reply_tx))
+            .await
+            .map_err(|_| ApiError::unavailable())?;
+        reply_rx.await.map_err(|_| ApiError::unavailable())
+    }
+
+    /// Download a file waiting in manual mode from the peers offering it
+    pub(crate) async fn accept_offer(&self, file_id: String) -> Result<TransferSummary, ApiError> {
+        Ok(self
+            .send_command(|reply| ApiCommand::AcceptOffer { file_id, reply })
+            .await??)
+    }
+
+    /// Decline a file waiting in manual mode
+    pub(crate) async fn reject_offer(&self, file_id: String) -> Result<OfferInfo, ApiError> {
+        Ok(self
+            .send_command(|reply| ApiCommand::RejectOffer { file_id, reply })
+            .await??)
+    }
+
+    pub async fn update_node(&self, node: NodeInfo) {
+        let mut inner = self.inner.write().await;
+        inner.node = node;
+    }
+
     pub async fn update_stats(&self, stats: NodeStats) {
         let mut inner = self.inner.write().await;
-        inner.stats = stats;
+        if inner.stats != stats {
+            inner.stats = stats;
+            inner.versions.stats += 1;
+        }
     }
 
     pub async fn update_peers(&self, peers: Vec<PeerInfo>) {
         let mut inner = self.inner.write().await;
-        inner.peers = peers;
+        if inner.peers != peers {
+            inner.peers = peers;
+            inner.versions.peers += 1;
+        }
+    }
+
+    /// Change a listed peer in place; peers not listed are left alone
+    pub async fn update_peer(&self, peer_id: &str, update: impl FnOnce(&mut PeerInfo)) {
+        let mut inner = self.inner.write().await;
+        let Some(peer) = inner.peers.iter_mut().find(|p| p.peer_id == peer_id) else {
+            return;
+        };
+        let before = peer.clone();
+        update(peer);
+        if *peer != before {
+            inner.versions.peers += 1;
+        }
+    }
+
+    /// ETag of what `resource` holds now
+    async fn etag(&self, resource: Polled) -> String {
+        let versions = self.inner.read().await.versions;
+        let version = match resource {
+            Polled::Stats => versions.stats,
+            Polled::Peers => versions.peers,
+            Polled::Files => versions.files,
+        };
+        format!("\"{:x}-{}\"", self.instance, version)
     }
 
     pub async fn add_file(&self, file: FileInfo) {
@@ -59,22 +493,87 @@ impl ApiState {
         } else {
             inner.files.push(file);
         }
+        inner.versions.files += 1;
     }
 
     pub async fn update_file_status(&self, file_id: &str, status: FileStatus) {
         let mut inner = self.inner.write().await;
         if let Some(file) = inner.files.iter_mut().find(|f| f.file_id == file_id) {
             file.status = status;
+            inner.versions.files += 1;
+        }
+    }
+
+    pub async fn update_file_pinned(&self, file_id: &str, pinned: bool) {
+        let mut inner = self.inner.write().await;
+        if let Some(file) = inner.files.iter_mut().find(|f| f.file_id == file_id) {
+            file.pinned = pinned;
+            inner.versions.files += 1;
+        }
+    }
+
+    pub async fn update_file_holders(&self, file_id: &str, holders: Vec<String>) {
+        let mut inner = self.inner.write().await;
+        if let Some(file) = inner.files.iter_mut().find(|f| f.file_id == file_id) {
+            file.holders = holders;
+            inner.versions.files += 1;
         }
     }
 
-    pub async fn update_file_progress(&self, file_id: &str, progress: f32) {
+    pub async fn update_file_progress(&self, file_id: &str, progress: f32, bytes_received: u64) {
         let mut inner = self.inner.write().await;
         if let Some(file) = inner.files.iter_mut().find(|f| f.file_id == file_id) {
             file.progress = progress;
+            file.bytes_received = bytes_received;
+            inner.versions.files += 1;
+        }
+    }
+
+    pub async fn remove_file(&self, file_id: &str) {
+        let mut inner = self.inner.write().await;
+        inner.files.retain(|f| f.file_id != file_id);
+        inner.versions.files += 1;
+    }
+
+    /// Mark a download as finished with every byte received
+    pub async fn complete_file(&self, file_id: &str, path: Option<String>) {
+        let mut inner = self.inner.write().await;
+        if let Some(file) = inner.files.iter_mut().find(|f| f.file_id == file_id) {
+            file.status = FileStatus::Complete;
+            file.progress = 1.0;
+            file.bytes_received = file.size;
+            file.path = path;
+            inner.versions.files += 1;
         }
     }
 
+    pub async fn update_replication(&self, replication: Vec<ReplicationHealth>) {
+        let mut inner = self.inner.write().await;
+        inner.replication = replication;
+    }
+
+    pub async fn update_health(&self, health: HealthReport) {
+        let mut inner = self.inner.write().await;
+        inner.health = health;
+    }
+
+    pub async fn set_recovery(&self, recovery: RecoveryReport) {
+        let mut inner = self.inner.write().await;
+        inner.recovery = recovery;
+    }
+
+    pub async fn get_recovery(&self) -> RecoveryReport {
+        self.inner.read().await.recovery.clone()
+    }
+
+    pub async fn get_health(&self) -> HealthReport {
+        self.inner.read().await.health.clone()
+    }
+
+    pub async fn get_node(&self) -> NodeInfo {
+        self.inner.read().await.node.clone()
+    }
+
     pub async fn get_stats(&self) -> NodeStats {
         self.inner.read().await.stats.clone()
     }
@@ -86,30 +585,167 @@ impl ApiState {
     pub async fn get_files(&self) -> Vec<FileInfo> {
         self.inner.read().await.files.clone()
     }
+
+    /// Peers matching `query`, sorted and paginated
+    pub async fn query_peers(&self, query: &PeersQuery) -> Page<PeerInfo> {
+        let mut peers: Vec<PeerInfo> = self
+            .inner
+            .read()
+            .await
+            .peers
+            .iter()
+            .filter(|peer| {
+                query
+                    .capability
+                    .as_ref()
+                    .is_none_or(|capability| peer.capabilities.contains(capability))
+            })
+            .cloned()
+            .collect();
+        if let Some(sort) = query.sort {
+            peers.sort_by(|a, b| match sort {
+                PeerSort::ConnectedSince => {
+                    ordered(a.connected_since.cmp(&b.connected_since), query.order)
+                }
+                PeerSort::Reputation => ordered(a.reputation.cmp(&b.reputation), query.order),
+                // Peers not yet pinged go last either way
+                PeerSort::Rtt => match (a.rtt_ms, b.rtt_ms) {
+                    (Some(a), Some(b)) => ordered(a.total_cmp(&b), query.order),
+                    (a, b) => a.is_none().cmp(&b.is_none()),
+                },
+            });
+        }
+        Page::of(peers, query.offset, query.limit)
+    }
+
+    /// Files matching `query`, sorted and paginated
+    pub async fn query_files(&self, query: &FilesQuery) -> Page<FileInfo> {
+        let mut files: Vec<FileInfo> = self
+            .inner
+            .read()
+            .await
+            .files
+            .iter()
+            .filter(|file| query.status.as_ref().is_none_or(|s| file.status == *s))
+            .filter(|file| {
+                query.peer.as_ref().is_none_or(|peer| {
+                    file.peer_id.as_ref() == Some(peer) || file.holders.contains(peer)
+                })
+            })
+            .cloned()
+            .collect();
+        if let Some(sort) = query.sort {
+            files.sort_by(|a, b| {
+                let ordering = match sort {
+                    FileSort::Name => a.name.cmp(&b.name),
+                    FileSort::Size => a.size.cmp(&b.size),
+                    FileSort::Progress => a.progress.total_cmp(&b.progress),
+                    FileSort::CreatedAt => a.created_at.cmp(&b.created_at),
+                };
+                ordered(ordering, query.order)
+            });
+        }
+        Page::of(files, query.offset, query.limit)
+    }
+
+    pub async fn get_replication(&self) -> Vec<ReplicationHealth> {
+        self.inner.read().await.replication.clone()
+    }
+}
+
+/// This node's identity and the addresses it can be reached at
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct NodeInfo {
+    pub peer_id: String,
+    pub listen_addresses: Vec<String>,
+    /// Addresses confirmed reachable from outside by AutoNAT and reported
+    /// by several peers
+    pub external_addresses: Vec<String>,
+    /// Addresses peers have seen us at, not (yet) confirmed
+    pub candidate_addresses: Vec<String>,
+    /// "public", "private" or "unknown"
+    pub nat_status: String,
 }
 
 /// Node statistics
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct NodeStats {
     pub peer_count: usize,
+    /// Files peers are fetching chunks of right now
     pub active_uploads: usize,
     pub active_downloads: usize,
+    /// Downloads waiting for a free slot
+    #[serde(default)]
+    pub queued_downloads: usize,
     pub uptime_seconds: u64,
     pub bytes_sent: u64,
     pub bytes_received: u64,
+    /// Whether the node seems cut off from much of the network
+    #[serde(default)]
+    pub partition_suspected: bool,
+    /// Clients connected to the WebSocket event feed
+    #[serde(default)]
+    pub websocket_clients: usize,
+    /// Progress events dropped for WebSocket clients that read too slowly
+    #[serde(default)]
+    pub websocket_events_dropped: u64,
 }
 
 /// Peer information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct PeerInfo {
     pub peer_id: String,
+    /// Remote addresses of the open connections
     pub addresses: Vec<String>,
+    /// Unix time the oldest open connection was established
     pub connected_since: u64,
+    /// Protocol and agent version from identify; empty until identified
     pub protocol_version: String,
+    #[serde(default)]
+    pub agent_version: String,
+    /// Addresses the peer listens on, as it reported through identify
+    #[serde(default)]
+    pub listen_addresses: Vec<String>,
+    /// Protocols the peer supports, as it reported through identify
+    #[serde(default)]
+    pub protocols: Vec<String>,
+    /// Trust score from storage challenges, from -100 to 100
+    #[serde(default)]
+    pub reputation: i32,
+    /// Smoothed ping round-trip time
+    #[serde(default)]
+    pub rtt_ms: Option<f64>,
+    /// Smoothed chunk throughput in bytes per second
+    #[serde(default)]
+    pub throughput: Option<f64>,
+    #[serde(default)]
+    pub failures: u32,
+    /// Protocol bytes exchanged with this peer
+    #[serde(default)]
+    pub bytes_sent: u64,
+    #[serde(default)]
+    pub bytes_received: u64,
+    /// Capabilities advertised in discovery, e.g. "storage"
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Space the peer advertises having left for files
+    #[serde(default)]
+    pub free_bytes: Option<u64>,
+    /// Optional protocol features advertised in discovery, e.g.
+    /// "offer-rejection"
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Operator tags, e.g. "trusted", "backup-target" or "flaky"
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub note: Option<String>,
 }
 
 /// File information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct FileInfo {
     pub file_id: String,
     pub name: String,
@@ -117,155 +753,3241 @@ pub struct FileInfo {
     pub chunks: u32,
     pub status: FileStatus,
     pub progress: f32,
+    /// Bytes downloaded so far
+    #[serde(default)]
+    pub bytes_received: u64,
     pub peer_id: Option<String>,
+    pub pinned: bool,
+    /// Peers known to hold a copy
+    #[serde(default)]
+    pub holders: Vec<String>,
+    #[serde(default)]
+    pub version: u32,
+    /// file_id of the version this one replaces
+    #[serde(default)]
+    pub previous_file_id: Option<String>,
+    /// Where a finished download was saved
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Unix time the file was first offered
+    #[serde(default)]
+    pub created_at: u64,
+}
+
+/// Everything known about one file, for detailed transfer views
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FileDetail {
+    #[serde(flatten)]
+    pub file: FileInfo,
+    pub chunk_size: u32,
+    pub mime_type: Option<String>,
+    /// Waiting for a free download slot
+    pub queued: bool,
+    pub chunks_received: u32,
+    /// Held chunks in hex, one bit per chunk with chunk 0 in the high bit
+    pub chunk_bitmap: String,
+    /// Peers the file is or was fetched from
+    pub sources: Vec<SourceInfo>,
+    /// Average download speed in bytes per second
+    pub speed: f64,
+    /// Seconds until the download completes at that speed
+    pub eta_seconds: Option<u64>,
+    /// Most recent errors, oldest first
+    pub errors: Vec<TransferError>,
+}
+
+/// A peer a download fetches chunks from
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SourceInfo {
+    pub peer_id: String,
+    /// Still fetched from, rather than a past source
+    pub active: bool,
+    pub chunks_received: u32,
+    pub bytes_received: u64,
+    pub requests_in_flight: usize,
+}
+
+/// Everything known about one connected peer
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PeerDetail {
+    #[serde(flatten)]
+    pub peer: PeerInfo,
+    /// Latest ping round trips, oldest first
+    pub rtt_history: Vec<RttSample>,
+    /// Downloads fetching chunks from the peer
+    pub transfers: Vec<PeerTransfer>,
+}
+
+/// A download a peer serves chunks for
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PeerTransfer {
+    pub file_id: String,
+    pub name: String,
+    pub state: TransferState,
+    /// Chunks the peer delivered for the download so far
+    pub chunks_received: u32,
+    pub bytes_received: u64,
+    pub requests_in_flight: usize,
+}
+
+/// A node in the network graph
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TopologyNode {
+    pub peer_id: String,
+    /// This node
+    pub local: bool,
+    /// Connected to this node; false for peers only its peers know
+    pub connected: bool,
+}
+
+/// A connection between two nodes, either way round
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TopologyLink {
+    pub source: String,
+    pub target: String,
+}
+
+/// This node, its peers and the peers they said they are connected to, for
+/// drawing the network. Refresh on `PeerConnected` and `PeerDisconnected`
+/// events.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NetworkTopology {
+    pub nodes: Vec<TopologyNode>,
+    pub links: Vec<TopologyLink>,
+}
+
+/// Counters and gauges of the node, for dashboards polling
+/// `GET /api/v1/metrics`
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NodeMetrics {
+    /// Unix time of the snapshot
+    pub timestamp: u64,
+    pub uptime_seconds: u64,
+    pub traffic: TrafficMetrics,
+    pub transfers: TransferMetrics,
+    pub cache: CacheStats,
+    pub storage: StorageMetrics,
+    pub consensus: ConsensusMetrics,
+    pub messages: MessageMetrics,
+    /// Messages by type, offers, chunks served and verification failures,
+    /// overall and per peer
+    pub behaviour: BehaviourStats,
+    pub websocket: WebSocketMetrics,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TrafficMetrics {
+    pub connected_peers: usize,
+    /// Protocol bytes exchanged with peers since the node started
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransferMetrics {
+    pub offered_files: usize,
+    /// Files peers fetched chunks of lately
+    pub active_uploads: usize,
+    pub active_downloads: usize,
+    pub queued_downloads: usize,
+    pub completed_downloads: usize,
+    pub chunk_requests_in_flight: usize,
+    /// Bytes per second over the last few seconds, across all transfers
+    pub upload_speed: f64,
+    pub download_speed: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct StorageMetrics {
+    pub blocks: usize,
+    /// Size occupied in the backend, after compression
+    pub stored_bytes: u64,
+    /// Space left on the storage volume
+    pub free_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ConsensusMetrics {
+    pub epoch: u64,
+    /// Registry updates rejected for coming from a stale epoch
+    pub stale_messages: u64,
+}
+
+/// Messages waiting to be sent to peers
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MessageMetrics {
+    /// Messages in the per-peer queues
+    pub queued: usize,
+    /// Application messages held back until their peer's queue has room
+    pub blocked: usize,
+    /// Messages dropped without being sent since the node started: to make
+    /// room, for want of it, or with a peer that disconnected
+    pub dropped: u64,
+}
+
+/// Clients of the WebSocket event feed and how well they keep up
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct WebSocketMetrics {
+    pub clients: Vec<WebSocketClient>,
+    /// Progress events dropped for slow clients since the node started
+    pub events_dropped: u64,
+    /// Clients disconnected for reading too slowly or not answering pings
+    pub clients_dropped: u64,
+}
+
+/// A connected WebSocket client
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct WebSocketClient {
+    pub address: String,
+    /// Subprotocol its events are framed in, e.g. `corelink.json`
+    pub protocol: String,
+    /// Unix time the client connected
+    pub connected_at: u64,
+    /// Events waiting to be sent, i.e. how far behind the client is
+    pub queued_events: usize,
+    pub events_sent: u64,
+    /// Progress events dropped because the client read too slowly
+    pub events_dropped: u64,
+}
+
+/// Uploads and downloads in progress
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransfersReport {
+    pub uploads: Vec<UploadInfo>,
+    pub downloads: Vec<DownloadInfo>,
+}
+
+/// A running or queued download
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct DownloadInfo {
+    #[serde(flatten)]
+    pub transfer: TransferSummary,
+    pub activity: ActivityState,
+    /// Bytes per second over the last few seconds
+    pub speed: f64,
+    /// Seconds until the download completes at that speed
+    pub eta_seconds: Option<u64>,
+    /// Peers the file is or was fetched from
+    pub peers: Vec<SourceInfo>,
+}
+
+/// A file of ours peers fetched chunks of lately
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UploadInfo {
+    pub file_id: String,
+    pub name: String,
+    pub size: u64,
+    pub activity: ActivityState,
+    /// Bytes per second over the last few seconds
+    pub speed: f64,
+    pub bytes_sent: u64,
+    pub peers: Vec<UploadPeer>,
+}
+
+/// A peer fetching chunks of one of our files
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UploadPeer {
+    pub peer_id: String,
+    pub chunks_sent: u32,
+    pub bytes_sent: u64,
+}
+
+/// One entry in a file's version history
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FileVersion {
+    pub file_id: String,
+    pub name: String,
+    pub size: u64,
+    pub version: u32,
+    pub previous_file_id: Option<String>,
+    pub created_at: u64,
 }
 
 /// File transfer status
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum FileStatus {
     Offering,
+    /// Offered by a peer, waiting to be accepted or rejected
+    Pending,
     Downloading,
     Complete,
     Failed,
 }
 
-/// Request to offer a file
-#[derive(Debug, Deserialize)]
-pub struct OfferFileRequest {
-    pub path: String,
+/// Query parameters for listing peers
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PeersQuery {
+    /// Only list peers advertising this capability
+    pub capability: Option<String>,
+    pub sort: Option<PeerSort>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
 }
 
-/// Start the REST API server
-pub async fn start_api_server(
-    addr: &str,
-    state: ApiState,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_origin(Any)
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers(Any);
-
-    // Build router
-    let app = Router::new()
-        .route("/api/health", get(health_handler))
-        .route("/api/stats", get(stats_handler))
-        .route("/api/peers", get(peers_handler))
-        .route("/api/files", get(files_handler))
-        .route("/api/files/offer", post(offer_file_handler))
-        .layer(cors)
-        .with_state(state);
+/// What to sort peers by
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerSort {
+    ConnectedSince,
+    Reputation,
+    /// Round-trip time, peers not yet pinged last
+    Rtt,
+}
 
-    info!("🌐 REST API server listening on {}", addr);
+/// Query parameters for listing files
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FilesQuery {
+    pub status: Option<FileStatus>,
+    /// Only list files offered by or held by this peer
+    pub peer: Option<String>,
+    pub sort: Option<FileSort>,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
 
-    // Start server
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+/// What to sort files by
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileSort {
+    Name,
+    Size,
+    Progress,
+    CreatedAt,
+}
 
-    Ok(())
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
 }
 
-/// Health check endpoint
-async fn health_handler() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "status": "ok",
-        "service": "corelink-node",
-        "version": env!("CARGO_PKG_VERSION"),
-    }))
+/// One page of a listing, and how many items matched in total
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub total: usize,
+    pub items: Vec<T>,
 }
 
-/// Get node statistics
-async fn stats_handler(State(state): State<ApiState>) -> impl IntoResponse {
-    let stats = state.get_stats().await;
-    Json(stats)
+impl<T> Page<T> {
+    /// Skip `offset` items and keep at most `limit` of the rest
+    fn of(items: Vec<T>, offset: Option<usize>, limit: Option<usize>) -> Self {
+        let total = items.len();
+        let items = items
+            .into_iter()
+            .skip(offset.unwrap_or_default())
+            .take(limit.unwrap_or(usize::MAX))
+            .collect();
+        Self { total, items }
+    }
+
+    /// Answer with the items, and the total in an `X-Total-Count` header
+    fn into_response(self) -> impl IntoResponse
+    where
+        T: Serialize,
+    {
+        (
+            [(TOTAL_COUNT_HEADER, self.total.to_string())],
+            Json(self.items),
+        )
+    }
 }
 
-/// Get connected peers
-async fn peers_handler(State(state): State<ApiState>) -> impl IntoResponse {
-    let peers = state.get_peers().await;
-    Json(peers)
+fn ordered(ordering: std::cmp::Ordering, order: SortOrder) -> std::cmp::Ordering {
+    match order {
+        SortOrder::Asc => ordering,
+        SortOrder::Desc => ordering.reverse(),
+    }
 }
 
-/// Get files
-async fn files_handler(State(state): State<ApiState>) -> impl IntoResponse {
-    let files = state.get_files().await;
-    Json(files)
+/// Header telling clients how many items a paginated listing holds
+const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+/// Version of the REST API and the prefix its endpoints live under.
+///
+/// A breaking change to an endpoint ships under the next version's prefix
+/// while the old version keeps being served. Responses from routes of an
+/// old version carry `Deprecation: true` and a `Link` to their successor,
+/// and the routes go away no sooner than the release after.
+const API_VERSION: &str = "1";
+const API_PREFIX: &str = "/api/v1";
+/// Response header naming the API version that answered
+const API_VERSION_HEADER: &str = "x-api-version";
+const DEPRECATION_HEADER: &str = "deprecation";
+
+/// How long `POST /api/v1/peers/connect` waits for the dial to finish
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Clients that have not finished the TLS handshake by then are dropped, so
+/// they cannot hold up shutdown
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request to connect to a peer, either at `address` or by `peer_id` at
+/// addresses the node already knows
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ConnectRequest {
+    pub address: Option<String>,
+    pub peer_id: Option<String>,
+    /// Remember the peer and redial it on restart
+    #[serde(default)]
+    pub save: bool,
 }
 
-/// Offer a file (placeholder - actual implementation will be in main.rs)
-async fn offer_file_handler(
-    State(_state): State<ApiState>,
-    Json(request): Json<OfferFileRequest>,
-) -> impl IntoResponse {
-    // This is a placeholder - the actual file offering logic needs to be coordinated
-    // with the main swarm, so this endpoint will need to send a message to the main loop
-    // For now, return a simple response
-    info!("📤 API request to offer file: {}", request.path);
+/// Outcome of a connect request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectStatus {
+    Connected,
+    Failed,
+    /// Still dialing when the request stopped waiting
+    Pending,
+}
 
-    // TODO: Implement actual file offering via channel to main loop
-    (
-        StatusCode::NOT_IMPLEMENTED,
-        Json(serde_json::json!({
-            "error": "File offering via API not yet implemented",
-            "message": "Use the CLI 'offer' command for now",
-        })),
-    )
+/// Response of `POST /api/v1/peers/connect`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConnectResponse {
+    pub status: ConnectStatus,
+    /// Peer reached, or the one dialed if known
+    pub peer_id: Option<String>,
+    pub address: Option<String>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// What a connect request dials
+enum DialTarget {
+    Address(Multiaddr),
+    Peer(PeerId),
+}
 
-    #[tokio::test]
-    async fn test_api_state() {
-        let state = ApiState::new();
+/// Results `GET /api/v1/files/search` returns unless asked for fewer
+const DEFAULT_SEARCH_LIMIT: usize = 50;
+const MAX_SEARCH_LIMIT: usize = 200;
+/// How long a search waits for peers unless told otherwise, and at most
+const DEFAULT_SEARCH_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_SEARCH_TIMEOUT: Duration = Duration::from_secs(10);
 
-        // Test stats update
-        let stats = NodeStats {
-            peer_count: 2,
-            active_uploads: 1,
-            active_downloads: 1,
-            uptime_seconds: 100,
-            bytes_sent: 1024,
-            bytes_received: 2048,
-        };
-        state.update_stats(stats.clone()).await;
+/// Query parameters for searching files
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Terms that must all appear in the file name, ignoring case
+    pub q: String,
+    /// Also ask connected peers
+    #[serde(default)]
+    pub network: bool,
+    pub limit: Option<usize>,
+    /// How long to wait for peers to answer
+    pub timeout_ms: Option<u64>,
+}
 
-        let retrieved = state.get_stats().await;
-        assert_eq!(retrieved.peer_count, 2);
-        assert_eq!(retrieved.bytes_sent, 1024);
-    }
+/// A file matching a search
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SearchHit {
+    pub file_id: String,
+    pub name: String,
+    pub size: u64,
+    pub version: u32,
+    /// This node holds the whole file
+    pub local: bool,
+    /// Peers offering the file
+    pub sources: Vec<String>,
+    /// Copies found: one per source, plus this node's
+    pub availability: usize,
+}
 
-    #[tokio::test]
-    async fn test_file_updates() {
-        let state = ApiState::new();
+/// Response of `GET /api/v1/files/search`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SearchResponse {
+    pub results: Vec<SearchHit>,
+    pub peers_queried: usize,
+    /// Peers that answered before the timeout
+    pub peers_answered: usize,
+    /// More files matched than the limit allowed
+    pub truncated: bool,
+}
 
-        let file = FileInfo {
-            file_id: "test123".to_string(),
-            name: "test.txt".to_string(),
-            size: 1024,
-            chunks: 2,
-            status: FileStatus::Downloading,
-            progress: 0.0,
-            peer_id: Some("peer1".to_string()),
-        };
+/// Query parameters for disconnecting a peer
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DisconnectQuery {
+    /// Also refuse connections with the peer for a while
+    #[serde(default)]
+    pub ban: bool,
+    /// How long to ban for, by default `ban_duration_secs` from the config;
+    /// at most `max_ban_duration_secs`
+    pub ban_secs: Option<u64>,
+}
 
-        state.add_file(file).await;
+/// What disconnecting a peer did
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PeerDisconnect {
+    /// Whether the peer had connections to close
+    pub disconnected: bool,
+    /// Unix time the ban ends, if the peer was banned
+    pub banned_until: Option<u64>,
+}
 
-        // Update progress
-        state.update_file_progress("test123", 0.5).await;
+/// Query parameters for uploading a file
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UploadQuery {
+    /// File name to save and offer the upload under
+    pub name: String,
+}
 
-        let files = state.get_files().await;
-        assert_eq!(files.len(), 1);
-        assert_eq!(files[0].progress, 0.5);
+/// Request to download a file
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct DownloadRequest {
+    /// Peer to fetch chunks from first, if it offers the file
+    #[serde(default)]
+    pub peer_id: Option<String>,
+}
 
-        // Update status
-        state
-            .update_file_status("test123", FileStatus::Complete)
-            .await;
+/// How long `POST /api/v1/messages` waits to see a message written
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
 
-        let files = state.get_files().await;
-        assert_eq!(files[0].status, FileStatus::Complete);
+/// Request to send an application message
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SendMessageRequest {
+    pub peer_id: String,
+    /// Application-defined kind of message, passed on as is
+    pub msg_type: String,
+    #[serde(default)]
+    pub payload: serde_json::Value,
+}
+
+/// How far sending a message got
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageStatus {
+    /// Waiting for a stream to the peer
+    Queued,
+    /// Written to the peer
+    Sent,
+    Failed,
+}
+
+/// Response of `POST /api/v1/messages`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SendMessageResponse {
+    /// Id the `MessageSent` or `MessageFailed` WebSocket event carries once
+    /// a queued message is delivered or given up on
+    pub message_id: u64,
+    pub peer_id: String,
+    pub msg_type: String,
+    pub status: MessageStatus,
+}
+
+/// Most operations one `POST /api/v1/files/batch` may carry
+const MAX_BATCH_OPERATIONS: usize = 500;
+
+/// Request to run several file operations in one go
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+/// One operation of a batch, named by its `op` field
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    /// Offer a file on the node's filesystem, given by absolute path
+    Offer {
+        path: String,
+    },
+    /// Start downloading a file, from `peer_id` first if given
+    Download {
+        file_id: String,
+        #[serde(default)]
+        peer_id: Option<String>,
+    },
+    /// Stop a running or queued download. Offered and finished files are
+    /// refused; remove those with `DELETE /files/{file_id}`.
+    Cancel {
+        file_id: String,
+    },
+    Pin {
+        file_id: String,
+    },
+    Unpin {
+        file_id: String,
+    },
+}
+
+/// Request to tag a peer
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PeerTagsRequest {
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+/// Query parameters for a garbage collection run
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GcQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Request to offer a file
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct OfferFileRequest {
+    pub path: String,
+}
+
+/// Whether the client's `If-None-Match` names `etag`, so it already has
+/// the current representation
+fn has_etag(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+/// `response` with an `ETag` header. Handlers take the tag before reading
+/// what they answer with, so a change in between costs the client one more
+/// full response rather than leaving it with stale data.
+fn tagged(etag: String, response: impl IntoResponse) -> Response {
+    ([(header::ETAG, etag)], response).into_response()
+}
+
+/// What handlers that can fail answer with
+type ApiResult = Result<(StatusCode, Json<serde_json::Value>), ApiError>;
+
+fn unknown_file(file_id: &str) -> ApiError {
+    ApiError::not_found(format!("Unknown file: {}", file_id)).with_code("unknown_file")
+}
+
+/// Response of `GET /api/v1/health`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthResponse {
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+    pub service: String,
+    pub version: String,
+}
+
+/// Response of `POST /api/v1/peers/{peer_id}/tags`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PeerTagsResponse {
+    pub peer_id: String,
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+}
+
+/// Response of `DELETE /api/v1/files/{file_id}`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RemoveFileResponse {
+    pub file_id: String,
+    pub removed: FileRemoval,
+}
+
+/// Outcome of one operation of a batch
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResult {
+    /// Position of the operation in the request
+    pub index: usize,
+    /// Status the operation's own endpoint would have answered with
+    pub status: u16,
+    /// That endpoint's response body
+    pub body: serde_json::Value,
+}
+
+/// Response of `POST /api/v1/files/batch`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BatchResponse {
+    pub succeeded: usize,
+    pub failed: usize,
+    /// One result per operation, in request order
+    pub results: Vec<BatchResult>,
+}
+
+/// Response of `DELETE /api/v1/peers/{peer_id}`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DisconnectResponse {
+    pub peer_id: String,
+    #[serde(flatten)]
+    pub outcome: PeerDisconnect,
+}
+
+/// Response of pinning or unpinning a file
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PinResponse {
+    pub file_id: String,
+    pub pinned: bool,
+}
+
+/// Response of `POST /api/v1/files/{file_id}/download`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DownloadResponse {
+    pub file_id: String,
+    pub transfer: TransferSummary,
+    /// Where to poll the download's progress
+    pub poll: String,
+}
+
+/// A file peers offered, waiting to be accepted or rejected
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OfferInfo {
+    pub file_id: String,
+    pub name: String,
+    pub size: u64,
+    pub chunks: u32,
+    /// Peers offering the file
+    pub peer_ids: Vec<String>,
+    /// Unix time of the first offer
+    pub offered_at: u64,
+    /// Unix time the offer is forgotten unless answered before
+    pub expires_at: u64,
+}
+
+/// Response of `POST /api/v1/config/reload`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReloadResponse {
+    pub reloaded: bool,
+    /// Changed settings that only apply after a restart
+    pub restart_required: Vec<String>,
+}
+
+/// How an admin asked the node to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StopRequest {
+    /// Stop for good
+    Shutdown,
+    /// Stop, then start again with the same arguments, rereading the config
+    Restart,
+}
+
+/// Response of `POST /api/v1/admin/shutdown` and `/api/v1/admin/restart`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StopResponse {
+    pub action: StopRequest,
+}
+
+/// OpenAPI description of the REST API, served at `/api/openapi.json`
+/// with a Swagger UI at `/api/docs`
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "CoreLink node API"),
+    paths(
+        health_handler,
+        node_handler,
+        stats_handler,
+        peers_handler,
+        topology_handler,
+        connect_handler,
+        peer_detail_handler,
+        disconnect_handler,
+        peer_tags_handler,
+        files_handler,
+        search_files_handler,
+        offer_file_handler,
+        upload_file_handler,
+        batch_handler,
+        send_message_handler,
+        file_detail_handler,
+        remove_file_handler,
+        download_handler,
+        pin_file_handler,
+        unpin_file_handler,
+        file_versions_handler,
+        offers_handler,
+        accept_offer_handler,
+        reject_offer_handler,
+        replication_handler,
+        transfers_handler,
+        transfer_handler,
+        recovery_handler,
+        storage_usage_handler,
+        metrics_handler,
+        gc_handler,
+        reload_config_handler,
+        shutdown_handler,
+        restart_handler,
+    ),
+    tags(
+        (name = "node", description = "Identity, health and configuration"),
+        (name = "peers", description = "Connected peers"),
+        (name = "files", description = "Offered and known files"),
+        (name = "transfers", description = "Uploads and downloads"),
+        (name = "storage", description = "Block storage"),
+        (name = "messages", description = "Application messages between peers"),
+        (name = "admin", description = "Node lifecycle, for orchestration tooling; needs the admin token"),
+    ),
+    modifiers(&AdminSecurity)
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token the admin endpoints take
+struct AdminSecurity;
+
+impl utoipa::Modify for AdminSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "admin_token",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+            );
+    }
+}
+
+/// Every endpoint, relative to the version prefix it is served under
+fn routes(max_upload_bytes: usize) -> Router<ApiState> {
+    Router::new()
+        .route("/health", get(health_handler))
+        .route("/node", get(node_handler))
+        .route("/stats", get(stats_handler))
+        .route("/peers", get(peers_handler))
+        .route("/peers/connect", post(connect_handler))
+        // Older name of `/peers/connect`
+        .route("/peers/dial", post(connect_handler))
+        .route(
+            "/peers/:peer_id",
+            get(peer_detail_handler).delete(disconnect_handler),
+        )
+        .route("/peers/:peer_id/tags", post(peer_tags_handler))
+        .route("/network/topology", get(topology_handler))
+        .route("/files", get(files_handler))
+        .route("/files/search", get(search_files_handler))
+        .route("/files/offer", post(offer_file_handler))
+        .route(
+            "/files/upload",
+            post(upload_file_handler).layer(DefaultBodyLimit::max(max_upload_bytes)),
+        )
+        .route("/files/batch", post(batch_handler))
+        .route(
+            "/files/:file_id",
+            get(file_detail_handler).delete(remove_file_handler),
+        )
+        .route("/files/:file_id/download", post(download_handler))
+        .route(
+            "/files/:file_id/pin",
+            post(pin_file_handler).delete(unpin_file_handler),
+        )
+        .route("/files/:file_id/versions", get(file_versions_handler))
+        .route("/offers", get(offers_handler))
+        .route("/offers/:file_id/accept", post(accept_offer_handler))
+        .route("/offers/:file_id/reject", post(reject_offer_handler))
+        .route("/replication", get(replication_handler))
+        .route("/transfers", get(transfers_handler))
+        .route("/transfers/:file_id", get(transfer_handler))
+        .route("/downloads/recovery", get(recovery_handler))
+        .route("/storage", get(storage_usage_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/storage/gc", post(gc_handler))
+        .route("/messages", post(send_message_handler))
+        .route("/config/reload", post(reload_config_handler))
+        .route("/admin/shutdown", post(shutdown_handler))
+        .route("/admin/restart", post(restart_handler))
+}
+
+/// Tag every response with the API version that served it
+async fn add_version_header(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(
+        HeaderName::from_static(API_VERSION_HEADER),
+        HeaderValue::from_static(API_VERSION),
+    );
+    response
+}
+
+/// Flag a response from a deprecated route and link to its successor
+async fn mark_deprecated(request: Request, next: Next) -> Response {
+    let successor = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        API_PREFIX,
+        request.uri()
+    );
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static(DEPRECATION_HEADER),
+        HeaderValue::from_static("true"),
+    );
+    if let Ok(link) = HeaderValue::from_str(&successor) {
+        headers.insert(header::LINK, link);
+    }
+    response
+}
+
+/// Bind the REST API to `addr` and serve it in the background, holding
+/// each client to `limiter`. With `tls`, clients connect over `https://`
+/// and `wss://` only. Once `shutdown` becomes true the server finishes
+/// in-flight requests and the returned task ends.
+pub async fn start_api_server(
+    addr: &str,
+    state: ApiState,
+    limiter: Arc<RateLimiter>,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
+    let app = router(state, limiter);
+
+    // Start server
+    let listener = TcpListener::bind(addr).await?;
+    info!("🌐 REST API server listening on {}", addr);
+    let tls = tls.map(TlsAcceptor::from);
+    Ok(tokio::spawn(async move {
+        let graceful = GracefulShutdown::new();
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            };
+            let (stream, remote) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept API connection: {}", e);
+                    continue;
+                }
+            };
+            let app = app.clone();
+            let tls = tls.clone();
+            let watcher = graceful.watcher();
+            tokio::spawn(async move {
+                let served = match tls {
+                    Some(tls) => {
+                        match time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await {
+                            Ok(Ok(stream)) => serve_connection(stream, remote, app, watcher).await,
+                            Ok(Err(e)) => {
+                                debug!("TLS handshake with {} failed: {}", remote, e);
+                                return;
+                            }
+                            Err(_) => {
+                                debug!("TLS handshake with {} timed out", remote);
+                                return;
+                            }
+                        }
+                    }
+                    None => serve_connection(stream, remote, app, watcher).await,
+                };
+                if let Err(e) = served {
+                    debug!("API connection from {} failed: {}", remote, e);
+                }
+            });
+        }
+        // Requests in flight get to finish; WebSocket clients of `/ws` close
+        // on their own
+        drop(listener);
+        graceful.shutdown().await;
+    }))
+}
+
+/// Answer requests on one connection until either side closes it, letting
+/// `/ws` take it over
+async fn serve_connection<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    stream: S,
+    remote: SocketAddr,
+    app: Router,
+    watcher: hyper_util::server::graceful::Watcher,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(remote));
+        app.clone().oneshot(request)
+    });
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+    watcher.watch(connection.into_owned()).await
+}
+
+/// The whole REST API: versioned endpoints, their deprecated aliases and
+/// the docs
+fn router(state: ApiState, limiter: Arc<RateLimiter>) -> Router {
+    // Only the configured origins may call the API from a browser
+    let cors = CorsLayer::new()
+        .allow_origin(AllowOrigin::list(state.cors_origins.clone()))
+        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::OPTIONS])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::IF_NONE_MATCH,
+        ])
+        .expose_headers([
+            HeaderName::from_static(TOTAL_COUNT_HEADER),
+            HeaderName::from_static(API_VERSION_HEADER),
+            HeaderName::from_static(DEPRECATION_HEADER),
+            header::LINK,
+            header::ETAG,
+        ]);
+
+    Router::new()
+        .nest(API_PREFIX, routes(state.max_upload_bytes))
+        // Unversioned aliases from before versioning, kept for old clients
+        .nest(
+            "/api",
+            routes(state.max_upload_bytes).layer(middleware::from_fn(mark_deprecated)),
+        )
+        .merge(SwaggerUi::new("/api/docs").url("/api/openapi.json", ApiDoc::openapi()))
+        .route("/ws", get(websocket_handler))
+        .fallback(api_error::no_route)
+        .layer(middleware::from_fn(api_error::problem_rejections))
+        .layer(middleware::from_fn_with_state(limiter, rate_limit::limit))
+        .layer(middleware::from_fn(add_version_header))
+        .layer(cors)
+        .with_state(state)
+}
+
+/// Upgrade to the WebSocket event stream, on the REST API's port
+///
+/// Takes the same `?since_seq=` and `Sec-WebSocket-Protocol` as the
+/// standalone WebSocket server.
+async fn websocket_handler(
+    State(state): State<ApiState>,
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    mut request: Request,
+) -> Result<Response, ApiError> {
+    let hub = state.events.clone().ok_or_else(|| {
+        ApiError::not_found("WebSocket events are not served").with_code("no_route")
+    })?;
+    let headers = request.headers();
+    let lists = |name: HeaderName, token: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|item| item.trim().eq_ignore_ascii_case(token))
+            })
+    };
+    if !lists(header::UPGRADE, "websocket") || !lists(header::CONNECTION, "upgrade") {
+        return Err(
+            ApiError::bad_request("Expected a WebSocket upgrade").with_code("not_websocket")
+        );
+    }
+    if !lists(header::SEC_WEBSOCKET_VERSION, "13") {
+        return Err(
+            ApiError::bad_request("Only WebSocket version 13 is supported")
+                .with_code("not_websocket"),
+        );
+    }
+    let key = headers.get(header::SEC_WEBSOCKET_KEY).ok_or_else(|| {
+        ApiError::bad_request("Missing Sec-WebSocket-Key").with_code("not_websocket")
+    })?;
+    let accept = derive_accept_key(key.as_bytes());
+    let encoding = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Encoding::negotiate);
+    let since_seq = websocket::since_seq_of(request.uri());
+
+    let upgrade = hyper::upgrade::on(&mut request);
+    tokio::spawn(async move {
+        let upgraded = match upgrade.await {
+            Ok(upgraded) => upgraded,
+            Err(e) => {
+                warn!("WebSocket upgrade from {} failed: {}", address, e);
+                return;
+            }
+        };
+        let ws_stream =
+            WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+        let encoding = encoding.unwrap_or_default();
+        if let Err(e) = hub
+            .serve(ws_stream, address, encoding, since_seq, state)
+            .await
+        {
+            error!("WebSocket connection error: {}", e);
+        }
+    });
+
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept);
+    if let Some(encoding) = encoding {
+        response = response.header(header::SEC_WEBSOCKET_PROTOCOL, encoding.protocol());
+    }
+    response.body(Body::empty()).map_err(ApiError::internal)
+}
+
+/// Health check endpoint
+///
+/// Answers 503 when any subsystem is unhealthy, so load balancers stop
+/// routing to the node; degraded nodes still answer 200.
+#[utoipa::path(
+    get,
+    path = "/api/v1/health",
+    tag = "node",
+    responses(
+        (status = 200, description = "Healthy or degraded", body = HealthResponse),
+        (status = 503, description = "A subsystem is unhealthy", body = HealthResponse),
+    )
+)]
+async fn health_handler(State(state): State<ApiState>) -> impl IntoResponse {
+    let health = state.get_health().await;
+    let code = match health.status {
+        HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Healthy | HealthStatus::Degraded => StatusCode::OK,
+    };
+    (
+        code,
+        Json(serde_json::json!(HealthResponse {
+            status: health.status,
+            checks: health.checks,
+            service: "corelink-node".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        })),
+    )
+}
+
+/// Get the node's identity and reachable addresses
+#[utoipa::path(
+    get,
+    path = "/api/v1/node",
+    tag = "node",
+    responses(
+        (status = 200, body = NodeInfo),
+    )
+)]
+async fn node_handler(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.get_node().await)
+}
+
+/// Get node statistics
+#[utoipa::path(
+    get,
+    path = "/api/v1/stats",
+    tag = "node",
+    params(("If-None-Match" = Option<String>, Header, description = "ETag of the copy the client has")),
+    responses(
+        (status = 200, body = NodeStats),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+    )
+)]
+async fn stats_handler(State(state): State<ApiState>, headers: HeaderMap) -> Response {
+    let etag = state.etag(Polled::Stats).await;
+    if has_etag(&headers, &etag) {
+        return tagged(etag, StatusCode::NOT_MODIFIED);
+    }
+    tagged(etag, Json(state.get_stats().await))
+}
+
+/// Get connected peers, optionally only those with `?capability=...`
+#[utoipa::path(
+    get,
+    path = "/api/v1/peers",
+    tag = "peers",
+    params(PeersQuery, ("If-None-Match" = Option<String>, Header, description = "ETag of the copy the client has")),
+    responses(
+        (status = 200, description = "One page of peers; X-Total-Count holds how many matched", body = Vec<PeerInfo>),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+    )
+)]
+async fn peers_handler(
+    State(state): State<ApiState>,
+    Query(query): Query<PeersQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let etag = state.etag(Polled::Peers).await;
+    if has_etag(&headers, &etag) {
+        return tagged(etag, StatusCode::NOT_MODIFIED);
+    }
+    tagged(etag, state.query_peers(&query).await.into_response())
+}
+
+/// Get this node, its peers and the links between them. Links among peers
+/// are as each peer last advertised them in discovery.
+#[utoipa::path(
+    get,
+    path = "/api/v1/network/topology",
+    tag = "peers",
+    responses(
+        (status = 200, body = NetworkTopology),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn topology_handler(State(state): State<ApiState>) -> ApiResult {
+    let topology = state
+        .send_command(|reply| ApiCommand::Topology { reply })
+        .await?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!(topology))))
+}
+
+/// Connect to a peer by address or by id, waiting up to
+/// [`CONNECT_TIMEOUT`] for the outcome. Also served as `/peers/dial`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/peers/connect",
+    tag = "peers",
+    request_body = ConnectRequest,
+    responses(
+        (status = 200, description = "Connected", body = ConnectResponse),
+        (status = 202, description = "Still dialing", body = ConnectResponse),
+        (status = 400, description = "Invalid address or peer id", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "Dialing failed; `details` holds the ConnectResponse", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn connect_handler(
+    State(state): State<ApiState>,
+    Json(request): Json<ConnectRequest>,
+) -> ApiResult {
+    let target = match (&request.address, &request.peer_id) {
+        (Some(address), None) => DialTarget::Address(
+            address
+                .parse()
+                .map_err(|e| ApiError::bad_request(format!("Invalid address: {}", e)))?,
+        ),
+        (None, Some(peer_id)) => DialTarget::Peer(
+            peer_id
+                .parse()
+                .map_err(|e| ApiError::bad_request(format!("Invalid peer id: {}", e)))?,
+        ),
+        _ => return Err(ApiError::bad_request("Give either an address or a peer id")),
+    };
+    let dialed_peer = match &target {
+        DialTarget::Address(address) => address.iter().find_map(|protocol| match protocol {
+            Protocol::P2p(peer_id) => Some(peer_id.to_string()),
+            _ => None,
+        }),
+        DialTarget::Peer(peer_id) => Some(peer_id.to_string()),
+    };
+
+    let save = request.save;
+    let dialing = state.send_command(|reply| match target {
+        DialTarget::Address(address) => ApiCommand::Dial {
+            address,
+            save,
+            reply,
+        },
+        DialTarget::Peer(peer_id) => ApiCommand::ConnectPeer {
+            peer_id,
+            save,
+            reply,
+        },
+    });
+    let (code, status, peer_id, error) = match time::timeout(CONNECT_TIMEOUT, dialing).await {
+        Ok(Ok(Ok(peer_id))) => (
+            StatusCode::OK,
+            ConnectStatus::Connected,
+            Some(peer_id),
+            None,
+        ),
+        Ok(Ok(Err(e))) => (
+            StatusCode::BAD_GATEWAY,
+            ConnectStatus::Failed,
+            dialed_peer,
+            Some(e),
+        ),
+        Ok(Err(e)) => return Err(e),
+        Err(_) => (
+            StatusCode::ACCEPTED,
+            ConnectStatus::Pending,
+            dialed_peer,
+            None,
+        ),
+    };
+    let response = serde_json::json!(ConnectResponse {
+        status,
+        peer_id,
+        address: request.address,
+    });
+    match error {
+        Some(error) => Err(ApiError::new(code, error)
+            .with_code("connect_failed")
+            .with_details(response)),
+        None => Ok((code, Json(response))),
+    }
+}
+
+/// Addresses, identify info, measurements, RTT history, transfers,
+/// reputation and tags of one connected peer
+#[utoipa::path(
+    get,
+    path = "/api/v1/peers/{peer_id}",
+    tag = "peers",
+    params(("peer_id" = String, Path, description = "Id of the peer")),
+    responses(
+        (status = 200, body = PeerDetail),
+        (status = 400, description = "Invalid peer id", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Peer not connected", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn peer_detail_handler(
+    State(state): State<ApiState>,
+    Path(peer_id): Path<String>,
+) -> ApiResult {
+    let peer: PeerId = peer_id
+        .parse()
+        .map_err(|e| ApiError::bad_request(format!("Invalid peer id: {}", e)))?;
+    let detail = state
+        .send_command(|reply| ApiCommand::PeerDetail {
+            peer_id: peer,
+            reply,
+        })
+        .await?
+        .ok_or_else(|| {
+            ApiError::not_found(format!("Not connected to {}", peer_id))
+                .with_code("peer_not_connected")
+        })?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!(detail))))
+}
+
+/// Close every connection to a peer, optionally banning it
+#[utoipa::path(
+    delete,
+    path = "/api/v1/peers/{peer_id}",
+    tag = "peers",
+    params(("peer_id" = String, Path, description = "Id of the peer"), DisconnectQuery),
+    responses(
+        (status = 200, body = DisconnectResponse),
+        (status = 400, description = "Invalid peer id or ban too long", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Peer not connected and no ban asked for", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn disconnect_handler(
+    State(state): State<ApiState>,
+    Path(peer_id): Path<String>,
+    Query(query): Query<DisconnectQuery>,
+) -> ApiResult {
+    let peer: PeerId = peer_id
+        .parse()
+        .map_err(|e| ApiError::bad_request(format!("Invalid peer id: {}", e)))?;
+    if let Some(secs) = query.ban_secs.filter(|secs| *secs > state.max_ban_secs) {
+        return Err(ApiError::bad_request(format!(
+            "ban_secs {} exceeds the maximum of {}",
+            secs, state.max_ban_secs
+        )));
+    }
+    let outcome = state
+        .send_command(|reply| ApiCommand::DisconnectPeer {
+            peer_id: peer,
+            ban: query.ban,
+            ban_secs: query.ban_secs,
+            reply,
+        })
+        .await?;
+
+    if !outcome.disconnected && outcome.banned_until.is_none() {
+        return Err(ApiError::not_found(format!("Not connected to {}", peer_id))
+            .with_code("peer_not_connected"));
+    }
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(DisconnectResponse { peer_id, outcome })),
+    ))
+}
+
+/// Replace a peer's tags and note
+#[utoipa::path(
+    post,
+    path = "/api/v1/peers/{peer_id}/tags",
+    tag = "peers",
+    params(("peer_id" = String, Path, description = "Id of the peer")),
+    request_body = PeerTagsRequest,
+    responses(
+        (status = 200, body = PeerTagsResponse),
+        (status = 400, description = "Invalid peer id", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn peer_tags_handler(
+    State(state): State<ApiState>,
+    Path(peer_id): Path<String>,
+    Json(request): Json<PeerTagsRequest>,
+) -> ApiResult {
+    state
+        .send_command(|reply| ApiCommand::SetPeerTags {
+            peer_id: peer_id.clone(),
+            tags: request.tags.clone(),
+            note: request.note.clone(),
+            reply,
+        })
+        .await?
+        .map_err(ApiError::bad_request)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(PeerTagsResponse {
+            peer_id,
+            tags: request.tags,
+            note: request.note,
+        })),
+    ))
+}
+
+/// Get files
+#[utoipa::path(
+    get,
+    path = "/api/v1/files",
+    tag = "files",
+    params(FilesQuery, ("If-None-Match" = Option<String>, Header, description = "ETag of the copy the client has")),
+    responses(
+        (status = 200, description = "One page of files; X-Total-Count holds how many matched", body = Vec<FileInfo>),
+        (status = 304, description = "Unchanged since the ETag in If-None-Match"),
+    )
+)]
+async fn files_handler(
+    State(state): State<ApiState>,
+    Query(query): Query<FilesQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let etag = state.etag(Polled::Files).await;
+    if has_etag(&headers, &etag) {
+        return tagged(etag, StatusCode::NOT_MODIFIED);
+    }
+    tagged(etag, state.query_files(&query).await.into_response())
+}
+
+/// Search the local catalog by file name and, with `network=true`, the
+/// files connected peers offer
+#[utoipa::path(
+    get,
+    path = "/api/v1/files/search",
+    tag = "files",
+    params(SearchQuery),
+    responses(
+        (status = 200, description = "Matches, most available first", body = SearchResponse),
+        (status = 400, description = "Empty query", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn search_files_handler(
+    State(state): State<ApiState>,
+    Query(query): Query<SearchQuery>,
+) -> ApiResult {
+    if query.q.trim().is_empty() {
+        return Err(ApiError::bad_request("Nothing to search for"));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+
+    let mut hits: HashMap<String, SearchHit> = HashMap::new();
+    for file in state.inner.read().await.files.iter() {
+        if !name_matches(&file.name, &query.q) {
+            continue;
+        }
+        // Files offered by peers carry the peer; our own offers do not
+        let offered_by = file
+            .peer_id
+            .clone()
+            .filter(|_| file.status == FileStatus::Offering);
+        hits.insert(
+            file.file_id.clone(),
+            SearchHit {
+                file_id: file.file_id.clone(),
+                name: file.name.clone(),
+                size: file.size,
+                version: file.version,
+                local: file.status == FileStatus::Complete
+                    || (file.status == FileStatus::Offering && offered_by.is_none()),
+                sources: offered_by.into_iter().collect(),
+                availability: 0,
+            },
+        );
+    }
+
+    let (mut peers_queried, mut peers_answered) = (0, 0);
+    if query.network {
+        let (asked, mut answers) = state
+            .send_command(|reply| ApiCommand::QueryPeers {
+                query: query.q.clone(),
+                limit: limit as u32,
+                reply,
+            })
+            .await?;
+        peers_queried = asked;
+        let timeout = query
+            .timeout_ms
+            .map_or(DEFAULT_SEARCH_TIMEOUT, Duration::from_millis)
+            .min(MAX_SEARCH_TIMEOUT);
+        let deadline = time::Instant::now() + timeout;
+        while peers_answered < peers_queried {
+            let Ok(Some((peer, files))) = time::timeout_at(deadline, answers.recv()).await else {
+                break;
+            };
+            peers_answered += 1;
+            for metadata in files {
+                let hit = hits
+                    .entry(metadata.file_id.clone())
+                    .or_insert_with(|| SearchHit {
+                        file_id: metadata.file_id.clone(),
+                        name: metadata.name.clone(),
+                        size: metadata.size,
+                        version: metadata.version,
+                        local: false,
+                        sources: Vec::new(),
+                        availability: 0,
+                    });
+                let peer = peer.to_string();
+                if !hit.sources.contains(&peer) {
+                    hit.sources.push(peer);
+                }
+            }
+        }
+    }
+
+    let mut results: Vec<SearchHit> = hits
+        .into_values()
+        .map(|mut hit| {
+            hit.availability = hit.sources.len() + usize::from(hit.local);
+            hit
+        })
+        .collect();
+    results.sort_by(|a, b| {
+        b.availability
+            .cmp(&a.availability)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    let truncated = results.len() > limit;
+    results.truncate(limit);
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(SearchResponse {
+            results,
+            peers_queried,
+            peers_answered,
+            truncated,
+        })),
+    ))
+}
+
+/// Full metadata, chunk bitmap, sources, speed and errors of one file
+#[utoipa::path(
+    get,
+    path = "/api/v1/files/{file_id}",
+    tag = "files",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    responses(
+        (status = 200, body = FileDetail),
+        (status = 404, description = "Unknown file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn file_detail_handler(
+    State(state): State<ApiState>,
+    Path(file_id): Path<String>,
+) -> ApiResult {
+    let detail = state
+        .send_command(|reply| ApiCommand::FileDetail {
+            file_id: file_id.clone(),
+            reply,
+        })
+        .await?
+        .ok_or_else(|| unknown_file(&file_id))?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!(detail))))
+}
+
+/// Cancel a download, withdraw an offer or delete a finished download,
+/// depending on where the file stands
+#[utoipa::path(
+    delete,
+    path = "/api/v1/files/{file_id}",
+    tag = "files",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    responses(
+        (status = 200, body = RemoveFileResponse),
+        (status = 404, description = "Unknown file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn remove_file_handler(
+    State(state): State<ApiState>,
+    Path(file_id): Path<String>,
+) -> ApiResult {
+    let removal = state
+        .send_command(|reply| ApiCommand::RemoveFile {
+            file_id: file_id.clone(),
+            reply,
+        })
+        .await??;
+
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(RemoveFileResponse {
+            file_id,
+            removed: removal,
+        })),
+    ))
+}
+
+/// Pin a file so it is exempt from eviction and garbage collection
+#[utoipa::path(
+    post,
+    path = "/api/v1/files/{file_id}/pin",
+    tag = "files",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    responses(
+        (status = 200, body = PinResponse),
+        (status = 400, description = "Unknown file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn pin_file_handler(State(state): State<ApiState>, Path(file_id): Path<String>) -> ApiResult {
+    set_pinned(state, file_id, true).await
+}
+
+/// Unpin a file
+#[utoipa::path(
+    delete,
+    path = "/api/v1/files/{file_id}/pin",
+    tag = "files",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    responses(
+        (status = 200, body = PinResponse),
+        (status = 400, description = "Unknown file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn unpin_file_handler(
+    State(state): State<ApiState>,
+    Path(file_id): Path<String>,
+) -> ApiResult {
+    set_pinned(state, file_id, false).await
+}
+
+async fn set_pinned(state: ApiState, file_id: String, pinned: bool) -> ApiResult {
+    state
+        .send_command(|reply| ApiCommand::SetPinned {
+            file_id: file_id.clone(),
+            pinned,
+            reply,
+        })
+        .await?
+        .map_err(ApiError::bad_request)?;
+
+    state.update_file_pinned(&file_id, pinned).await;
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(PinResponse { file_id, pinned })),
+    ))
+}
+
+/// Version history of a file, newest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/files/{file_id}/versions",
+    tag = "files",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    responses(
+        (status = 200, body = Vec<FileVersion>),
+        (status = 404, description = "Unknown file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn file_versions_handler(
+    State(state): State<ApiState>,
+    Path(file_id): Path<String>,
+) -> ApiResult {
+    let versions = state
+        .send_command(|reply| ApiCommand::FileVersions {
+            file_id: file_id.clone(),
+            reply,
+        })
+        .await?;
+
+    if versions.is_empty() {
+        return Err(unknown_file(&file_id));
+    }
+    Ok((StatusCode::OK, Json(serde_json::json!(versions))))
+}
+
+/// Run garbage collection, or just report what it would remove with `?dry_run=true`
+#[utoipa::path(
+    post,
+    path = "/api/v1/storage/gc",
+    tag = "storage",
+    params(GcQuery),
+    responses(
+        (status = 200, body = GcReport),
+        (status = 500, description = "Garbage collection failed", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn gc_handler(State(state): State<ApiState>, Query(query): Query<GcQuery>) -> ApiResult {
+    let report = state
+        .send_command(|reply| ApiCommand::CollectGarbage {
+            dry_run: query.dry_run,
+            reply,
+        })
+        .await?
+        .map_err(ApiError::internal)?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!(report))))
+}
+
+/// Raw and stored (compressed) size of the block store
+#[utoipa::path(
+    get,
+    path = "/api/v1/storage",
+    tag = "storage",
+    responses(
+        (status = 200, description = "Block count, raw bytes and stored bytes", body = Object),
+        (status = 500, description = "Usage could not be read", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn storage_usage_handler(State(state): State<ApiState>) -> ApiResult {
+    let usage = state
+        .send_command(|reply| ApiCommand::StorageUsage { reply })
+        .await?
+        .map_err(ApiError::internal)?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!(usage))))
+}
+
+/// Snapshot of traffic, transfer, cache, storage and consensus counters,
+/// for monitoring without Prometheus
+#[utoipa::path(
+    get,
+    path = "/api/v1/metrics",
+    tag = "node",
+    responses(
+        (status = 200, body = NodeMetrics),
+        (status = 500, description = "Storage could not be read", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn metrics_handler(State(state): State<ApiState>) -> ApiResult {
+    let metrics = state
+        .send_command(|reply| ApiCommand::Metrics { reply })
+        .await?
+        .map_err(ApiError::internal)?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!(metrics))))
+}
+
+/// Get replication health of offered files
+#[utoipa::path(
+    get,
+    path = "/api/v1/replication",
+    tag = "files",
+    responses(
+        (status = 200, body = Vec<ReplicationHealth>),
+    )
+)]
+async fn replication_handler(State(state): State<ApiState>) -> impl IntoResponse {
+    let replication = state.get_replication().await;
+    Json(replication)
+}
+
+/// Uploads and downloads in progress, with their recent speed, state and
+/// peers
+#[utoipa::path(
+    get,
+    path = "/api/v1/transfers",
+    tag = "transfers",
+    responses(
+        (status = 200, body = TransfersReport),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn transfers_handler(State(state): State<ApiState>) -> ApiResult {
+    let transfers = state
+        .send_command(|reply| ApiCommand::LiveTransfers { reply })
+        .await?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!(transfers))))
+}
+
+/// Progress of one download, as returned when it was started
+#[utoipa::path(
+    get,
+    path = "/api/v1/transfers/{file_id}",
+    tag = "transfers",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    responses(
+        (status = 200, body = TransferSummary),
+        (status = 404, description = "No such download", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn transfer_handler(State(state): State<ApiState>, Path(file_id): Path<String>) -> ApiResult {
+    let transfer = state
+        .send_command(|reply| ApiCommand::Transfer {
+            file_id: file_id.clone(),
+            reply,
+        })
+        .await?
+        .ok_or_else(|| {
+            ApiError::not_found(format!("No download of {}", file_id)).with_code("unknown_transfer")
+        })?;
+
+    Ok((StatusCode::OK, Json(serde_json::json!(transfer))))
+}
+
+/// What startup recovery did with downloads interrupted by the last shutdown
+#[utoipa::path(
+    get,
+    path = "/api/v1/downloads/recovery",
+    tag = "transfers",
+    responses(
+        (status = 200, body = RecoveryReport),
+    )
+)]
+async fn recovery_handler(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.get_recovery().await)
+}
+
+/// Offer a file on the node's filesystem, given by absolute path under one
+/// of the share directories
+#[utoipa::path(
+    post,
+    path = "/api/v1/files/offer",
+    tag = "files",
+    request_body = OfferFileRequest,
+    responses(
+        (status = 201, description = "Offered", body = FileInfo),
+        (status = 400, description = "Relative path or not a regular file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "File is outside the share directories or not readable", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "No such file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn offer_file_handler(
+    State(state): State<ApiState>,
+    Json(request): Json<OfferFileRequest>,
+) -> ApiResult {
+    info!("📤 API request to offer file: {}", request.path);
+
+    // The node's working directory means nothing to API clients
+    let path = PathBuf::from(&request.path);
+    if !path.is_absolute() {
+        return Err(ApiError::bad_request("path must be absolute"));
+    }
+    // Resolve links and `..` so the path cannot climb out of a share directory
+    let path = tokio::fs::canonicalize(&path).await?;
+    if !state.is_shared(&path).await {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "path is outside the share directories",
+        ));
+    }
+
+    let file = state
+        .send_command(|reply| ApiCommand::OfferFile { path, reply })
+        .await??;
+
+    Ok((StatusCode::CREATED, Json(serde_json::json!(file))))
+}
+
+/// Save the request body into the uploads directory as `name` and offer it
+#[utoipa::path(
+    post,
+    path = "/api/v1/files/upload",
+    tag = "files",
+    params(UploadQuery),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 201, description = "Saved and offered", body = FileInfo),
+        (status = 400, description = "Invalid file name or interrupted upload", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 413, description = "Larger than api_max_upload_bytes", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn upload_file_handler(
+    State(state): State<ApiState>,
+    Query(query): Query<UploadQuery>,
+    request: Request,
+) -> ApiResult {
+    let Some(uploads_dir) = &state.uploads_dir else {
+        return Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Node is not accepting uploads",
+        ));
+    };
+    // A bare file name, so uploads cannot land outside the directory
+    let name = query.name;
+    if name.starts_with('.') || std::path::Path::new(&name).file_name() != Some(name.as_ref()) {
+        return Err(ApiError::bad_request(format!(
+            "Invalid file name: {:?}",
+            name
+        )));
+    }
+
+    info!("📥 API upload of {}", name);
+    let path = uploads_dir.join(&name);
+    // Held to the route's DefaultBodyLimit, however the body is sent
+    save_upload(request.with_limited_body().into_body(), &path)
+        .await
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to save {}: {}", name, e)))?;
+
+    let file = state
+        .send_command(|reply| ApiCommand::OfferFile { path, reply })
+        .await??;
+
+    Ok((StatusCode::CREATED, Json(serde_json::json!(file))))
+}
+
+/// Stream `body` into a hidden file next to `path`, then move it into place
+/// so a failed upload never replaces an earlier one
+async fn save_upload(body: Body, path: &std::path::Path) -> io::Result<()> {
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let partial = path.with_file_name(format!(".{}.{:016x}.upload", name, rand::random::<u64>()));
+    let write = async {
+        let mut file = tokio::fs::File::create(&partial).await?;
+        let mut stream = body.into_data_stream();
+        while let Some(bytes) = stream.next().await {
+            let bytes = bytes.map_err(|e| {
+                let too_large = std::error::Error::source(&e)
+                    .is_some_and(|e| e.is::<http_body_util::LengthLimitError>());
+                if too_large {
+                    io::Error::new(io::ErrorKind::FileTooLarge, "upload is too large")
+                } else {
+                    io::Error::new(io::ErrorKind::InvalidInput, e)
+                }
+            })?;
+            file.write_all(&bytes).await?;
+        }
+        file.sync_all().await?;
+        tokio::fs::rename(&partial, path).await
+    };
+    let result = write.await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+    result
+}
+
+/// Start downloading a file offered by a connected peer. The body may name
+/// a `peer_id` to fetch from first; a file nobody offered is requested from
+/// that peer, or from the peers known to hold it. Answers with the transfer
+/// and where to poll its progress.
+#[utoipa::path(
+    post,
+    path = "/api/v1/files/{file_id}/download",
+    tag = "transfers",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    request_body = Option<DownloadRequest>,
+    responses(
+        (status = 202, description = "Download started or queued", body = DownloadResponse),
+        (status = 400, description = "Invalid peer id", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "This node does not download files", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Nobody offers or holds the file, or the peer does not", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Already downloading, or no source is connected", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn download_handler(
+    State(state): State<ApiState>,
+    Path(file_id): Path<String>,
+    request: Option<Json<DownloadRequest>>,
+) -> ApiResult {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let peer = request
+        .peer_id
+        .as_deref()
+        .map(str::parse::<PeerId>)
+        .transpose()
+        .map_err(|e| ApiError::bad_request(format!("Invalid peer id: {}", e)))?;
+
+    let transfer = state
+        .send_command(|reply| ApiCommand::Download {
+            file_id: file_id.clone(),
+            peer,
+            reply,
+        })
+        .await??;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!(DownloadResponse {
+            poll: format!("/api/v1/transfers/{}", file_id),
+            file_id,
+            transfer,
+        })),
+    ))
+}
+
+/// Files peers offered that wait to be accepted or rejected, when the node
+/// runs with `offer_mode = "manual"`
+#[utoipa::path(
+    get,
+    path = "/api/v1/offers",
+    tag = "transfers",
+    responses(
+        (status = 200, body = Vec<OfferInfo>),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn offers_handler(State(state): State<ApiState>) -> ApiResult {
+    let offers = state
+        .send_command(|reply| ApiCommand::Offers { reply })
+        .await?;
+    Ok((StatusCode::OK, Json(serde_json::json!(offers))))
+}
+
+/// Accept a waiting offer: download the file from the peers offering it
+#[utoipa::path(
+    post,
+    path = "/api/v1/offers/{file_id}/accept",
+    tag = "transfers",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    responses(
+        (status = 202, description = "Download started or queued", body = DownloadResponse),
+        (status = 404, description = "No offer of the file is waiting", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "No peer offering the file is connected", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn accept_offer_handler(
+    State(state): State<ApiState>,
+    Path(file_id): Path<String>,
+) -> ApiResult {
+    let transfer = state.accept_offer(file_id.clone()).await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!(DownloadResponse {
+            poll: format!("/api/v1/transfers/{}", file_id),
+            file_id,
+            transfer,
+        })),
+    ))
+}
+
+/// Reject a waiting offer; the peers offering the file are told
+#[utoipa::path(
+    post,
+    path = "/api/v1/offers/{file_id}/reject",
+    tag = "transfers",
+    params(("file_id" = String, Path, description = "Id of the file")),
+    responses(
+        (status = 200, description = "The offer that was rejected", body = OfferInfo),
+        (status = 404, description = "No offer of the file is waiting", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn reject_offer_handler(
+    State(state): State<ApiState>,
+    Path(file_id): Path<String>,
+) -> ApiResult {
+    let offer = state.reject_offer(file_id).await?;
+    Ok((StatusCode::OK, Json(serde_json::json!(offer))))
+}
+
+/// Run a list of offer, download, cancel, pin and unpin operations one
+/// after another. Each operation behaves as its own endpoint would, except
+/// that cancel only stops downloads, and a failed one does not stop those
+/// after it.
+#[utoipa::path(
+    post,
+    path = "/api/v1/files/batch",
+    tag = "files",
+    request_body = BatchRequest,
+    responses(
+        (status = 200, description = "Every operation ran; see each result", body = BatchResponse),
+        (status = 400, description = "No operations, or too many", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn batch_handler(
+    State(state): State<ApiState>,
+    Json(request): Json<BatchRequest>,
+) -> ApiResult {
+    let count = request.operations.len();
+    if count == 0 || count > MAX_BATCH_OPERATIONS {
+        return Err(ApiError::bad_request(format!(
+            "A batch takes 1 to {} operations, not {}",
+            MAX_BATCH_OPERATIONS, count
+        ))
+        .with_details(serde_json::json!({ "max_operations": MAX_BATCH_OPERATIONS })));
+    }
+    info!("📦 API batch of {} file operations", count);
+
+    let mut results = Vec::with_capacity(count);
+    for (index, operation) in request.operations.into_iter().enumerate() {
+        let state = State(state.clone());
+        let result = match operation {
+            BatchOperation::Offer { path } => {
+                offer_file_handler(state, Json(OfferFileRequest { path })).await
+            }
+            BatchOperation::Download { file_id, peer_id } => {
+                download_handler(
+                    state,
+                    Path(file_id),
+                    Some(Json(DownloadRequest { peer_id })),
+                )
+                .await
+            }
+            BatchOperation::Cancel { file_id } => cancel_download(state.0, file_id).await,
+            BatchOperation::Pin { file_id } => set_pinned(state.0, file_id, true).await,
+            BatchOperation::Unpin { file_id } => set_pinned(state.0, file_id, false).await,
+        };
+        let (status, body) = outcome(result);
+        results.push(BatchResult {
+            index,
+            status: status.as_u16(),
+            body,
+        });
+    }
+
+    let succeeded = results
+        .iter()
+        .filter(|result| (200..300).contains(&result.status))
+        .count();
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(BatchResponse {
+            succeeded,
+            failed: results.len() - succeeded,
+            results,
+        })),
+    ))
+}
+
+/// Stop a download, refusing files that are offered or already finished
+/// rather than withdrawing or deleting them as `DELETE /files/{file_id}` does
+async fn cancel_download(state: ApiState, file_id: String) -> ApiResult {
+    state
+        .send_command(|reply| ApiCommand::CancelDownload {
+            file_id: file_id.clone(),
+            reply,
+        })
+        .await?
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidInput => ApiError::conflict(e).with_code("not_downloading"),
+            _ => ApiError::from(e),
+        })?;
+
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(RemoveFileResponse {
+            file_id,
+            removed: FileRemoval::Cancelled,
+        })),
+    ))
+}
+
+/// Status and body a handler answered with, whether it failed or not
+fn outcome(result: ApiResult) -> (StatusCode, serde_json::Value) {
+    match result {
+        Ok((status, Json(body))) => (status, body),
+        Err(error) => (error.status(), serde_json::json!(error.problem())),
+    }
+}
+
+/// Sign an application message and send it to a connected peer, waiting
+/// briefly to see it written. Answers `queued` if it is still on its way;
+/// its outcome is then published as a `MessageSent` or `MessageFailed`
+/// WebSocket event carrying the answered `message_id`.
+#[utoipa::path(
+    post,
+    path = "/api/v1/messages",
+    tag = "messages",
+    request_body = SendMessageRequest,
+    responses(
+        (status = 200, description = "Sent", body = SendMessageResponse),
+        (status = 202, description = "Queued", body = SendMessageResponse),
+        (status = 400, description = "Invalid peer id or message type", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "Peer is not connected", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "Writing to the peer failed; `details` holds the SendMessageResponse", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands, or the peer's message queue is full", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn send_message_handler(
+    State(state): State<ApiState>,
+    Json(request): Json<SendMessageRequest>,
+) -> ApiResult {
+    let peer_id: PeerId = request
+        .peer_id
+        .parse()
+        .map_err(|e| ApiError::bad_request(format!("Invalid peer id: {}", e)))?;
+    if request.msg_type.trim().is_empty() {
+        return Err(ApiError::bad_request("msg_type must not be empty"));
+    }
+
+    let receipt = state
+        .send_command(|reply| ApiCommand::SendMessage {
+            peer_id,
+            msg_type: request.msg_type.clone(),
+            payload: request.payload,
+            reply,
+        })
+        .await?
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotConnected => ApiError::conflict(e).with_code("peer_not_connected"),
+            io::ErrorKind::WouldBlock => {
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e).with_code("queue_full")
+            }
+            _ => ApiError::from(e),
+        })?;
+    let (code, status, error) = match time::timeout(SEND_TIMEOUT, receipt.outcome).await {
+        Ok(Ok(Ok(()))) => (StatusCode::OK, MessageStatus::Sent, None),
+        Ok(Ok(Err(e))) => (StatusCode::BAD_GATEWAY, MessageStatus::Failed, Some(e)),
+        // Still waiting for a stream, or the connection went away with it
+        Ok(Err(_)) | Err(_) => (StatusCode::ACCEPTED, MessageStatus::Queued, None),
+    };
+    let response = serde_json::json!(SendMessageResponse {
+        message_id: receipt.message_id,
+        peer_id: request.peer_id,
+        msg_type: request.msg_type,
+        status,
+    });
+    match error {
+        Some(error) => Err(ApiError::new(code, error)
+            .with_code("send_failed")
+            .with_details(response)),
+        None => Ok((code, Json(response))),
+    }
+}
+
+/// Apply config file changes without restarting
+#[utoipa::path(
+    post,
+    path = "/api/v1/config/reload",
+    tag = "node",
+    responses(
+        (status = 200, body = ReloadResponse),
+        (status = 400, description = "Invalid config file", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn reload_config_handler(State(state): State<ApiState>) -> ApiResult {
+    let restart_required = state
+        .send_command(|reply| ApiCommand::ReloadConfig { reply })
+        .await?
+        .map_err(|e| ApiError::bad_request(e).with_code("invalid_config"))?;
+
+    Ok((
+        StatusCode::OK,
+        Json(serde_json::json!(ReloadResponse {
+            reloaded: true,
+            restart_required,
+        })),
+    ))
+}
+
+/// Stop the node gracefully: save state, let in-flight requests finish and
+/// close peer connections
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/shutdown",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 202, description = "The node is shutting down", body = StopResponse),
+        (status = 401, description = "Missing or wrong admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "No admin token is configured", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "The node is already stopping", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn shutdown_handler(State(state): State<ApiState>, headers: HeaderMap) -> ApiResult {
+    state.request_stop(&headers, StopRequest::Shutdown).await
+}
+
+/// Stop the node gracefully, then start it again with the same arguments
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/restart",
+    tag = "admin",
+    security(("admin_token" = [])),
+    responses(
+        (status = 202, description = "The node is restarting", body = StopResponse),
+        (status = 401, description = "Missing or wrong admin token", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "No admin token is configured", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 409, description = "The node is already stopping", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "Node is not accepting commands", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn restart_handler(State(state): State<ApiState>, headers: HeaderMap) -> ApiResult {
+    state.request_stop(&headers, StopRequest::Restart).await
+}
+
+/// Compare tokens in time independent of where they differ
+pub(crate) fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_api_state() {
+        let state = ApiState::new();
+
+        // Test stats update
+        let stats = NodeStats {
+            peer_count: 2,
+            active_uploads: 1,
+            active_downloads: 1,
+            queued_downloads: 0,
+            uptime_seconds: 100,
+            bytes_sent: 1024,
+            bytes_received: 2048,
+            partition_suspected: false,
+            websocket_clients: 0,
+            websocket_events_dropped: 0,
+        };
+        state.update_stats(stats.clone()).await;
+
+        let retrieved = state.get_stats().await;
+        assert_eq!(retrieved.peer_count, 2);
+        assert_eq!(retrieved.bytes_sent, 1024);
+    }
+
+    #[tokio::test]
+    async fn test_file_updates() {
+        let state = ApiState::new();
+
+        let file = FileInfo {
+            file_id: "test123".to_string(),
+            name: "test.txt".to_string(),
+            size: 1024,
+            chunks: 2,
+            status: FileStatus::Downloading,
+            progress: 0.0,
+            bytes_received: 0,
+            peer_id: Some("peer1".to_string()),
+            pinned: false,
+            holders: vec![],
+            version: 1,
+            previous_file_id: None,
+            path: None,
+            created_at: 0,
+        };
+
+        state.add_file(file).await;
+
+        // Update progress
+        state.update_file_progress("test123", 0.5, 512).await;
+
+        let files = state.query_files(&FilesQuery::default()).await.items;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].progress, 0.5);
+        assert_eq!(files[0].bytes_received, 512);
+
+        // Update status
+        state
+            .update_file_status("test123", FileStatus::Complete)
+            .await;
+
+        let files = state.query_files(&FilesQuery::default()).await.items;
+        assert_eq!(files[0].status, FileStatus::Complete);
+    }
+
+    #[tokio::test]
+    async fn test_pin_command_round_trip() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new().with_commands(tx);
+
+        tokio::spawn(async move {
+            if let Some(ApiCommand::SetPinned { reply, .. }) = rx.recv().await {
+                let _ = reply.send(Ok(()));
+            }
+        });
+
+        let (status, _) = outcome(set_pinned(state, "test123".to_string(), true).await);
+        assert_eq!(status, StatusCode::OK);
+
+        // Without a main loop attached the node reports itself unavailable
+        let (status, _) = outcome(set_pinned(ApiState::new(), "test123".to_string(), true).await);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_offer_errors_map_to_statuses() {
+        let shared = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        for dir in [&shared, &outside] {
+            std::fs::write(dir.path().join("notes.txt"), b"notes").unwrap();
+        }
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new()
+            .with_commands(tx)
+            .with_share_dirs(vec![shared.path().to_path_buf()]);
+        tokio::spawn(async move {
+            if let Some(ApiCommand::OfferFile { reply, .. }) = rx.recv().await {
+                let _ = reply.send(Err(io::Error::new(io::ErrorKind::NotFound, "gone")));
+            }
+        });
+
+        let offer = |path: &str| {
+            Json(OfferFileRequest {
+                path: path.to_string(),
+            })
+        };
+        let path =
+            |dir: &tempfile::TempDir, name: &str| dir.path().join(name).display().to_string();
+        let (status, _) =
+            outcome(offer_file_handler(State(state.clone()), offer("notes.txt")).await);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) =
+            outcome(offer_file_handler(State(state.clone()), offer("/no/such/file")).await);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = outcome(
+            offer_file_handler(State(state.clone()), offer(&path(&outside, "notes.txt"))).await,
+        );
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        // `..` cannot climb out of a share directory
+        let escape = format!(
+            "{}/../{}/notes.txt",
+            shared.path().display(),
+            outside.path().file_name().unwrap().to_string_lossy()
+        );
+        let (status, _) = outcome(offer_file_handler(State(state.clone()), offer(&escape)).await);
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) =
+            outcome(offer_file_handler(State(state), offer(&path(&shared, "notes.txt"))).await);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["detail"], "gone");
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_only_configured_origins() {
+        use tower::ServiceExt;
+
+        let state = ApiState::new().with_cors_origins(&["https://dashboard.example".to_string()]);
+        let app = router(state, Arc::default());
+        let preflight = |origin: &str| {
+            axum::http::Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/api/v1/files/upload?name=a.txt")
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(preflight("https://dashboard.example"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://dashboard.example"
+        );
+        let response = app
+            .oneshot(preflight("https://evil.example"))
+            .await
+            .unwrap();
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn test_batch_reports_each_operation() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let state = ApiState::new().with_commands(tx);
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                match command {
+                    ApiCommand::SetPinned { reply, .. } => {
+                        let _ = reply.send(Ok(()));
+                    }
+                    ApiCommand::CancelDownload { file_id, reply } => {
+                        let _ = reply.send(match file_id.as_str() {
+                            "offered" => Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                "Not a download in progress: offered",
+                            )),
+                            _ => Err(io::Error::new(io::ErrorKind::NotFound, "gone")),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let request: BatchRequest = serde_json::from_value(serde_json::json!({
+            "operations": [
+                { "op": "pin", "file_id": "a" },
+                { "op": "offer", "path": "relative.txt" },
+                { "op": "cancel", "file_id": "b" },
+                { "op": "unpin", "file_id": "a" },
+                { "op": "cancel", "file_id": "offered" },
+            ]
+        }))
+        .unwrap();
+        let (status, body) = outcome(batch_handler(State(state.clone()), Json(request)).await);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["succeeded"], 2);
+        assert_eq!(body["failed"], 3);
+        let statuses: Vec<_> = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, [200, 400, 404, 200, 409]);
+        assert_eq!(body["results"][2]["body"]["detail"], "gone");
+        assert_eq!(body["results"][4]["body"]["code"], "not_downloading");
+
+        let empty = BatchRequest { operations: vec![] };
+        let (status, _) = outcome(batch_handler(State(state), Json(empty)).await);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_send_message_statuses() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new().with_commands(tx);
+        tokio::spawn(async move {
+            let mut sends = 0;
+            while let Some(command) = rx.recv().await {
+                if let ApiCommand::SendMessage { reply, .. } = command {
+                    sends += 1;
+                    let (waiter, outcome) = oneshot::channel();
+                    let receipt = MessageReceipt {
+                        message_id: sends,
+                        outcome,
+                    };
+                    match sends {
+                        1 => {
+                            let _ = waiter.send(Ok(()));
+                            let _ = reply.send(Ok(receipt));
+                        }
+                        2 => {
+                            let _ = waiter.send(Err("stream reset".to_string()));
+                            let _ = reply.send(Ok(receipt));
+                        }
+                        3 => {
+                            let _ = reply.send(Err(io::Error::new(
+                                io::ErrorKind::NotConnected,
+                                "Peer is not connected",
+                            )));
+                        }
+                        _ => {
+                            let _ = reply.send(Err(io::Error::new(
+                                io::ErrorKind::WouldBlock,
+                                "Queue is full",
+                            )));
+                        }
+                    }
+                }
+            }
+        });
+
+        let peer = PeerId::random();
+        let send = |peer_id: String| {
+            Json(SendMessageRequest {
+                peer_id,
+                msg_type: "chat".to_string(),
+                payload: serde_json::json!({ "text": "hi" }),
+            })
+        };
+        let (status, body) =
+            outcome(send_message_handler(State(state.clone()), send(peer.to_string())).await);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "sent");
+        assert_eq!(body["message_id"], 1);
+        let (status, body) =
+            outcome(send_message_handler(State(state.clone()), send(peer.to_string())).await);
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["code"], "send_failed");
+        assert_eq!(body["detail"], "stream reset");
+        assert_eq!(body["details"]["status"], "failed");
+        assert_eq!(body["details"]["message_id"], 2);
+        let (status, _) =
+            outcome(send_message_handler(State(state.clone()), send(peer.to_string())).await);
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, body) =
+            outcome(send_message_handler(State(state.clone()), send(peer.to_string())).await);
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], "queue_full");
+        let (status, _) =
+            outcome(send_message_handler(State(state), send("nonsense".to_string())).await);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_offer_answers() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new().with_commands(tx);
+        let offer = OfferInfo {
+            file_id: "waiting".to_string(),
+            name: "a.txt".to_string(),
+            size: 10,
+            chunks: 1,
+            peer_ids: vec![PeerId::random().to_string()],
+            offered_at: 1000,
+            expires_at: 4600,
+        };
+        let waiting = offer.clone();
+        tokio::spawn(async move {
+            let unknown = || io::Error::new(io::ErrorKind::NotFound, "No offer is waiting");
+            while let Some(command) = rx.recv().await {
+                match command {
+                    ApiCommand::Offers { reply } => {
+                        let _ = reply.send(vec![waiting.clone()]);
+                    }
+                    ApiCommand::AcceptOffer { file_id, reply } if file_id == "waiting" => {
+                        let _ = reply.send(Ok(TransferSummary {
+                            file_id,
+                            name: "a.txt".to_string(),
+                            size: 10,
+                            state: TransferState::Downloading,
+                            chunks_received: 0,
+                            total_chunks: 1,
+                            bytes_received: 0,
+                            progress: 0.0,
+                            sources: Vec::new(),
+                        }));
+                    }
+                    ApiCommand::AcceptOffer { reply, .. } => {
+                        let _ = reply.send(Err(unknown()));
+                    }
+                    ApiCommand::RejectOffer { file_id, reply } if file_id == "waiting" => {
+                        let _ = reply.send(Ok(waiting.clone()));
+                    }
+                    ApiCommand::RejectOffer { reply, .. } => {
+                        let _ = reply.send(Err(unknown()));
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        let (status, body) = outcome(offers_handler(State(state.clone())).await);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["expires_at"], 4600);
+
+        let answer = |file_id: &str| Path(file_id.to_string());
+        let (status, body) =
+            outcome(accept_offer_handler(State(state.clone()), answer("waiting")).await);
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["poll"], "/api/v1/transfers/waiting");
+        let (status, _) =
+            outcome(accept_offer_handler(State(state.clone()), answer("other")).await);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) =
+            outcome(reject_offer_handler(State(state.clone()), answer("waiting")).await);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["peer_ids"], serde_json::json!(offer.peer_ids));
+        let (status, _) = outcome(reject_offer_handler(State(state), answer("other")).await);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_query_files() {
+        let state = ApiState::new();
+        for (file_id, size, status, peer) in [
+            ("a", 300, FileStatus::Offering, None),
+            ("b", 100, FileStatus::Downloading, Some("peer1")),
+            ("c", 200, FileStatus::Downloading, Some("peer2")),
+            ("d", 400, FileStatus::Downloading, Some("peer1")),
+        ] {
+            state
+                .add_file(FileInfo {
+                    file_id: file_id.to_string(),
+                    name: format!("{}.txt", file_id),
+                    size,
+                    chunks: 1,
+                    status,
+                    progress: 0.0,
+                    bytes_received: 0,
+                    peer_id: peer.map(str::to_string),
+                    pinned: false,
+                    holders: vec![],
+                    version: 1,
+                    previous_file_id: None,
+                    path: None,
+                    created_at: 0,
+                })
+                .await;
+        }
+        let ids = |page: Page<FileInfo>| -> Vec<String> {
+            page.items.into_iter().map(|file| file.file_id).collect()
+        };
+
+        let page = state
+            .query_files(&FilesQuery {
+                status: Some(FileStatus::Downloading),
+                sort: Some(FileSort::Size),
+                order: SortOrder::Desc,
+                limit: Some(2),
+                ..FilesQuery::default()
+            })
+            .await;
+        assert_eq!(page.total, 3);
+        assert_eq!(ids(page), vec!["d", "c"]);
+
+        let page = state
+            .query_files(&FilesQuery {
+                peer: Some("peer1".to_string()),
+                sort: Some(FileSort::Size),
+                offset: Some(1),
+                ..FilesQuery::default()
+            })
+            .await;
+        assert_eq!(page.total, 2);
+        assert_eq!(ids(page), vec!["d"]);
+    }
+
+    #[tokio::test]
+    async fn test_upload_is_saved_and_offered() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new()
+            .with_commands(tx)
+            .with_uploads_dir(dir.path().to_path_buf());
+        tokio::spawn(async move {
+            if let Some(ApiCommand::OfferFile { path, reply }) = rx.recv().await {
+                let _ = reply.send(Ok(FileInfo {
+                    file_id: "f1".to_string(),
+                    name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                    size: std::fs::metadata(&path).unwrap().len(),
+                    chunks: 1,
+                    status: FileStatus::Offering,
+                    progress: 1.0,
+                    bytes_received: 0,
+                    peer_id: None,
+                    pinned: false,
+                    holders: Vec::new(),
+                    version: 1,
+                    previous_file_id: None,
+                    path: None,
+                    created_at: 0,
+                }));
+            }
+        });
+
+        let upload = |name: &str| {
+            Query(UploadQuery {
+                name: name.to_string(),
+            })
+        };
+        let body = |bytes: &'static str| Request::new(Body::from(bytes));
+        for name in ["../escape.txt", ".hidden", "a/b.txt", ""] {
+            let (status, _) =
+                outcome(upload_file_handler(State(state.clone()), upload(name), body("x")).await);
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", name);
+        }
+
+        let (status, body) =
+            outcome(upload_file_handler(State(state), upload("notes.txt"), body("hello")).await);
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["file_id"], "f1");
+        assert_eq!(body["size"], 5);
+        assert_eq!(
+            std::fs::read(dir.path().join("notes.txt")).unwrap(),
+            b"hello"
+        );
+        // No partial files are left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_uploads_over_the_limit_are_refused() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let state = ApiState::new()
+            .with_uploads_dir(dir.path().to_path_buf())
+            .with_max_upload_bytes(4);
+        let app = router(state, Arc::default());
+        let upload = |body: Body| {
+            axum::http::Request::post("/api/v1/files/upload?name=big.bin")
+                .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 1234))))
+                .body(body)
+                .unwrap()
+        };
+
+        // Whether or not the client says how large the body is
+        let response = app
+            .clone()
+            .oneshot(upload(Body::from("hello")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let chunks = futures::stream::iter(["he", "ll", "o"].map(Ok::<_, io::Error>));
+        let response = app
+            .oneshot(upload(Body::from_stream(chunks)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Nothing is left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_download_returns_transfer_to_poll() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new().with_commands(tx);
+        let peer = PeerId::random();
+        tokio::spawn(async move {
+            if let Some(ApiCommand::Download {
+                file_id,
+                peer: preferred,
+                reply,
+            }) = rx.recv().await
+            {
+                assert_eq!(preferred, Some(peer));
+                let _ = reply.send(Ok(TransferSummary {
+                    file_id,
+                    name: "notes.txt".to_string(),
+                    size: 5,
+                    state: TransferState::Downloading,
+                    chunks_received: 0,
+                    total_chunks: 1,
+                    bytes_received: 0,
+                    progress: 0.0,
+                    sources: vec![peer.to_string()],
+                }));
+            }
+        });
+
+        let request = |peer_id: &str| {
+            Some(Json(DownloadRequest {
+                peer_id: Some(peer_id.to_string()),
+            }))
+        };
+        let (status, _) = outcome(
+            download_handler(
+                State(state.clone()),
+                Path("abc".to_string()),
+                request("not-a-peer"),
+            )
+            .await,
+        );
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = outcome(
+            download_handler(
+                State(state),
+                Path("abc".to_string()),
+                request(&peer.to_string()),
+            )
+            .await,
+        );
+        assert_eq!(status, StatusCode::ACCEPTED);
+        assert_eq!(body["poll"], "/api/v1/transfers/abc");
+        assert_eq!(body["transfer"]["state"], "downloading");
+    }
+
+    #[tokio::test]
+    async fn test_search_merges_local_and_peer_results() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new().with_commands(tx);
+        state
+            .add_file(FileInfo {
+                file_id: "local".to_string(),
+                name: "Holiday photos.zip".to_string(),
+                size: 100,
+                chunks: 1,
+                status: FileStatus::Offering,
+                progress: 1.0,
+                bytes_received: 0,
+                peer_id: None,
+                pinned: false,
+                holders: vec![],
+                version: 1,
+                previous_file_id: None,
+                path: None,
+                created_at: 0,
+            })
+            .await;
+        let peer = PeerId::random();
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                if let ApiCommand::QueryPeers { reply, .. } = command {
+                    let (answers, receiver) = mpsc::channel(2);
+                    // The second peer never answers
+                    let _ = reply.send((2, receiver));
+                    let remote = FileMetadata::new("holiday photos.zip".to_string(), 50, vec![]);
+                    let shared = FileMetadata {
+                        file_id: "local".to_string(),
+                        ..remote.clone()
+                    };
+                    let _ = answers.send((peer, vec![remote, shared])).await;
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        });
+        let search = |q: &str, network: bool| {
+            search_files_handler(
+                State(state.clone()),
+                Query(SearchQuery {
+                    q: q.to_string(),
+                    network,
+                    limit: None,
+                    timeout_ms: Some(100),
+                }),
+            )
+        };
+
+        let (status, _) = outcome(search(" ", false).await);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, body) = outcome(search("PHOTOS", false).await);
+        assert_eq!(body["results"].as_array().unwrap().len(), 1);
+        assert_eq!(body["peers_queried"], 0);
+
+        let (status, body) = outcome(search("photos", true).await);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["peers_queried"], 2);
+        assert_eq!(body["peers_answered"], 1);
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        // Held here and by the peer, so listed first
+        assert_eq!(results[0]["file_id"], "local");
+        assert_eq!(results[0]["availability"], 2);
+        assert_eq!(results[1]["local"], false);
+        assert_eq!(results[1]["sources"][0], peer.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_connect_reports_outcome() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new().with_commands(tx);
+        let peer = PeerId::random();
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                match command {
+                    ApiCommand::Dial { reply, .. } => {
+                        let _ = reply.send(Err("Connection refused".to_string()));
+                    }
+                    ApiCommand::ConnectPeer { peer_id, reply, .. } => {
+                        let _ = reply.send(Ok(peer_id.to_string()));
+                    }
+                    _ => {}
+                }
+            }
+        });
+        let connect = |address: Option<String>, peer_id: Option<String>| {
+            connect_handler(
+                State(state.clone()),
+                Json(ConnectRequest {
+                    address,
+                    peer_id,
+                    save: false,
+                }),
+            )
+        };
+
+        let (status, _) = outcome(connect(None, None).await);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let address = format!("/ip4/127.0.0.1/tcp/1/p2p/{}", peer);
+        let (status, body) = outcome(connect(Some(address), None).await);
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["code"], "connect_failed");
+        assert_eq!(body["detail"], "Connection refused");
+        assert_eq!(body["details"]["status"], "failed");
+        assert_eq!(body["details"]["peer_id"], peer.to_string());
+
+        let (status, body) = outcome(connect(None, Some(peer.to_string())).await);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "connected");
+
+        // `/peers/dial` is the same endpoint
+        let request = axum::http::Request::post("/api/v1/peers/dial")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(r#"{{"peer_id":"{}"}}"#, peer)))
+            .unwrap();
+        let response = router(state.clone(), Arc::default())
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["status"], "connected");
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_and_ban() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let state = ApiState::new().with_commands(tx).with_max_ban_secs(86400);
+        tokio::spawn(async move {
+            while let Some(command) = rx.recv().await {
+                if let ApiCommand::DisconnectPeer {
+                    ban,
+                    ban_secs,
+                    reply,
+                    ..
+                } = command
+                {
+                    let _ = reply.send(PeerDisconnect {
+                        disconnected: false,
+                        banned_until: ban.then(|| ban_secs.unwrap_or(3600)),
+                    });
+                }
+            }
+        });
+        let disconnect_for = |peer_id: String, ban: bool, ban_secs: Option<u64>| {
+            disconnect_handler(
+                State(state.clone()),
+                Path(peer_id),
+                Query(DisconnectQuery { ban, ban_secs }),
+            )
+        };
+        let disconnect = |peer_id: String, ban: bool| disconnect_for(peer_id, ban, None);
+
+        let (status, _) = outcome(disconnect("not-a-peer".to_string(), false).await);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let peer = PeerId::random().to_string();
+        let (status, _) = outcome(disconnect(peer.clone(), false).await);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Banning works whether or not the peer is connected
+        let (status, body) = outcome(disconnect(peer.clone(), true).await);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["peer_id"], peer);
+        assert_eq!(body["disconnected"], false);
+        assert_eq!(body["banned_until"], 3600);
+
+        let (status, body) = outcome(disconnect_for(peer.clone(), true, Some(86400)).await);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["banned_until"], 86400);
+        let (status, _) = outcome(disconnect_for(peer, true, Some(u64::MAX)).await);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_admin_stop_requests() {
+        use tower::ServiceExt;
+
+        let post = |state: &ApiState, uri: &str, token: Option<&str>| {
+            let mut request = axum::http::Request::post(uri);
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            router(state.clone(), Arc::default()).oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        // Without a configured token nobody gets in
+        let disabled = ApiState::new().with_admin_token(Some(String::new()));
+        let response = post(&disabled, "/api/v1/admin/shutdown", Some(""))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let (stop_tx, mut stop_rx) = mpsc::channel(1);
+        let state = ApiState::new()
+            .with_admin_token(Some("secret".to_string()))
+            .with_stop(stop_tx);
+        let response = post(&state, "/api/v1/admin/restart", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        let response = post(&state, "/api/v1/admin/restart", Some("secreT"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(stop_rx.try_recv().is_err());
+
+        let response = post(&state, "/api/v1/admin/restart", Some("secret"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        // Only the first request counts while the node stops
+        let response = post(&state, "/api/v1/admin/shutdown", Some("secret"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(stop_rx.try_recv().unwrap(), StopRequest::Restart);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_get() {
+        use tower::ServiceExt;
+
+        let state = ApiState::new();
+        let app = router(state.clone(), Arc::default());
+        let get = |uri: &str, etag: Option<&HeaderValue>| {
+            let mut request = axum::http::Request::get(uri);
+            if let Some(etag) = etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let response = get("/api/v1/stats", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let etag = response.headers()[header::ETAG].clone();
+        let response = get("/api/v1/stats", Some(&etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // Reporting the same stats again is no change
+        let mut stats = state.get_stats().await;
+        state.update_stats(stats.clone()).await;
+        let response = get("/api/v1/stats", Some(&etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        stats.peer_count = 3;
+        state.update_stats(stats).await;
+        let response = get("/api/v1/stats", Some(&etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag);
+
+        // Each resource has its own version
+        let response = get("/api/v1/files?limit=10", None).await.unwrap();
+        let etag = response.headers()[header::ETAG].clone();
+        let response = get("/api/v1/files?limit=10", Some(&etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        state.remove_file("missing").await;
+        let response = get("/api/v1/files?limit=10", Some(&etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rejections_become_problems() {
+        use tower::ServiceExt;
+
+        let app = router(ApiState::new(), Arc::default());
+        let problem = |response: axum::response::Response| async move {
+            assert_eq!(
+                response.headers()[axum::http::header::CONTENT_TYPE],
+                api_error::PROBLEM_JSON
+            );
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let request = axum::http::Request::get("/api/v1/nowhere")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(problem(response).await["code"], "no_route");
+
+        let request = axum::http::Request::post("/api/v1/files/offer")
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{not json"))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(problem(response).await["status"], 400);
+
+        // Without a main loop attached the node reports itself unavailable
+        let request = axum::http::Request::get("/api/v1/transfers")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(problem(response).await["code"], "unavailable");
+    }
+
+    #[tokio::test]
+    async fn test_versioned_routes_and_deprecated_aliases() {
+        use tower::ServiceExt;
+
+        let app = router(ApiState::new(), Arc::default());
+        let get = |uri: &str| axum::http::Request::get(uri).body(Body::empty()).unwrap();
+
+        let response = app.clone().oneshot(get("/api/v1/stats")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[API_VERSION_HEADER], API_VERSION);
+        assert!(response.headers().get(DEPRECATION_HEADER).is_none());
+
+        let response = app.oneshot(get("/api/files?limit=5")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[API_VERSION_HEADER], API_VERSION);
+        assert_eq!(response.headers()[DEPRECATION_HEADER], "true");
+        assert_eq!(
+            response.headers()[header::LINK],
+            "</api/v1/files?limit=5>; rel=\"successor-version\""
+        );
+    }
+
+    #[tokio::test]
+    async fn test_websocket_route() {
+        use futures_util::StreamExt;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let (_, hub) = EventHub::start(shutdown_tx.clone());
+        let state = ApiState::new().with_events(hub.clone());
+        let server = start_api_server(
+            &addr.to_string(),
+            state.clone(),
+            Arc::default(),
+            None,
+            shutdown,
+        )
+        .await
+        .unwrap();
+
+        // Plain requests to `/ws` are refused, as is every request to it
+        // on nodes that do not stream events
+        let get = || {
+            axum::http::Request::get("/ws")
+                .extension(ConnectInfo(addr))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = router(state.clone(), Arc::default())
+            .oneshot(get())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = router(ApiState::new(), Arc::default())
+            .oneshot(get())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let (mut client, response) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        let snapshot = client.next().await.unwrap().unwrap();
+        assert!(snapshot.to_text().unwrap().contains("Snapshot"));
+        assert_eq!(hub.metrics().clients.len(), 1);
+
+        shutdown_tx.send(true).unwrap();
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(tokio_tungstenite::tungstenite::Message::Close(_)))
+        ));
+        time::timeout(Duration::from_secs(1), server)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_openapi_covers_routes() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = spec["paths"].as_object().unwrap();
+        assert_eq!(paths.len(), 31);
+        let detail = &paths["/api/v1/files/{file_id}"];
+        assert!(detail["get"].is_object() && detail["delete"].is_object());
+        assert!(spec["components"]["schemas"]["FileDetail"].is_object());
+        let peer = &paths["/api/v1/peers/{peer_id}"];
+        assert!(peer["get"].is_object() && peer["delete"].is_object());
+        assert!(spec["components"]["schemas"]["PeerDetail"].is_object());
+        assert!(spec["components"]["schemas"]["TransfersReport"].is_object());
+        assert!(spec["components"]["schemas"]["NodeMetrics"].is_object());
+        assert!(spec["components"]["schemas"]["NetworkTopology"].is_object());
+        assert_eq!(
+            paths["/api/v1/files"]["get"]["parameters"]
+                .as_array()
+                .unwrap()
+                .len(),
+            7
+        );
     }
 }
@@ -0,0 +1,258 @@
+use axum::{
+    body,
+    extract::Request,
+    http::{header, HeaderValue, StatusCode, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use std::io;
+use tracing::{debug, warn};
+use utoipa::ToSchema;
+
+/// Media type of every error body
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Largest rejection text carried over into a problem
+const MAX_REJECTION_BYTES: usize = 64 * 1024;
+
+/// Error of a REST API request, answered as an RFC 7807 problem
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: Option<serde_json::Value>,
+}
+
+/// Body of every failed request, as described by RFC 7807
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProblemDetails {
+    /// URI naming the kind of problem
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    /// Reason phrase of `status`
+    pub title: String,
+    pub status: u16,
+    /// What went wrong with this request
+    pub detail: String,
+    /// Stable name of the problem for programs to match on, e.g. `not_found`
+    pub code: String,
+    /// More about the problem, depending on `code`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    /// Id the error was logged under on the node
+    pub trace_id: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl ToString) -> Self {
+        Self {
+            status,
+            code: default_code(status),
+            message: message.to_string(),
+            details: None,
+        }
+    }
+
+    pub fn bad_request(message: impl ToString) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    /// Answered with a `WWW-Authenticate` challenge for a bearer token
+    pub fn unauthorized(message: impl ToString) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, message)
+    }
+
+    pub fn not_found(message: impl ToString) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn conflict(message: impl ToString) -> Self {
+        Self::new(StatusCode::CONFLICT, message)
+    }
+
+    pub fn internal(message: impl ToString) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    /// The node's main loop is not taking commands, e.g. while shutting down
+    pub fn unavailable() -> Self {
+        Self::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Node is not accepting commands",
+        )
+    }
+
+    /// Name the problem more precisely than its status does
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = code;
+        self
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Log the error under a fresh trace id and describe it
+    pub fn problem(&self) -> ProblemDetails {
+        let trace_id = format!("{:016x}", rand::random::<u64>());
+        if self.status.is_server_error() {
+            warn!(%trace_id, status = %self.status, code = self.code, "API error: {}", self.message);
+        } else {
+            debug!(%trace_id, status = %self.status, code = self.code, "API error: {}", self.message);
+        }
+        ProblemDetails {
+            problem_type: format!("urn:corelink:problem:{}", self.code),
+            title: self
+                .status
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_string(),
+            status: self.status.as_u16(),
+            detail: self.message.clone(),
+            code: self.code.to_string(),
+            details: self.details.clone(),
+            trace_id,
+        }
+    }
+}
+
+/// Code of problems that did not pick their own
+fn default_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::UNPROCESSABLE_ENTITY => "invalid_body",
+        StatusCode::TOO_MANY_REQUESTS => "rate_limited",
+        StatusCode::BAD_GATEWAY => "peer_failed",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        status if status.is_server_error() => "internal",
+        _ => "error",
+    }
+}
+
+/// A failed file operation, with a status matching its kind
+impl From<io::Error> for ApiError {
+    fn from(error: io::Error) -> Self {
+        let status = match error.kind() {
+            io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            io::ErrorKind::AlreadyExists | io::ErrorKind::NotConnected => StatusCode::CONFLICT,
+            io::ErrorKind::Unsupported => StatusCode::FORBIDDEN,
+            io::ErrorKind::InvalidInput => StatusCode::BAD_REQUEST,
+            io::ErrorKind::FileTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self::new(status, error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status, Json(self.problem())).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        if self.status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+/// Middleware turning error responses that are not JSON, such as axum's
+/// plain text rejections of malformed bodies, into problems
+pub async fn problem_rejections(request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let text = body::to_bytes(body, MAX_REJECTION_BYTES)
+        .await
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .unwrap_or_default();
+    let message = match status.canonical_reason() {
+        Some(reason) if text.is_empty() => reason.to_string(),
+        _ => text,
+    };
+    let mut problem = ApiError::new(status, message).into_response();
+    // Keep headers such as `Allow` on 405s
+    for (name, value) in parts.headers {
+        if let Some(name) =
+            name.filter(|name| *name != header::CONTENT_TYPE && *name != header::CONTENT_LENGTH)
+        {
+            problem.headers_mut().insert(name, value);
+        }
+    }
+    problem
+}
+
+/// Answer requests for paths no endpoint serves
+pub async fn no_route(uri: Uri) -> ApiError {
+    ApiError::not_found(format!("No endpoint at {}", uri.path())).with_code("no_route")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_problem_response() {
+        let error = ApiError::not_found("Unknown file: abc")
+            .with_code("unknown_file")
+            .with_details(serde_json::json!({ "file_id": "abc" }));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], PROBLEM_JSON);
+
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(problem["type"], "urn:corelink:problem:unknown_file");
+        assert_eq!(problem["title"], "Not Found");
+        assert_eq!(problem["status"], 404);
+        assert_eq!(problem["detail"], "Unknown file: abc");
+        assert_eq!(problem["details"]["file_id"], "abc");
+        assert_eq!(problem["trace_id"].as_str().unwrap().len(), 16);
+    }
+
+    #[test]
+    fn test_io_errors_map_to_statuses() {
+        let error = |kind| ApiError::from(io::Error::new(kind, "boom")).status();
+        assert_eq!(error(io::ErrorKind::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(error(io::ErrorKind::AlreadyExists), StatusCode::CONFLICT);
+        assert_eq!(error(io::ErrorKind::InvalidInput), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error(io::ErrorKind::Other),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use crate::config::NodeConfig;
+use crate::file_transfer::FileTransferManager;
+use corelink_core::storage::StorageSnapshot;
+use corelink_core::{BlockStore, Storage};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use tracing::info;
+
+/// Bump when the archive layout changes
+const BACKUP_FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_ENTRY: &str = "manifest.json";
+const IDENTITY_ENTRY: &str = "identity.key";
+const CONFIG_ENTRY: &str = "config.toml";
+const STORAGE_ENTRY: &str = "storage.json";
+const BLOCKS_DIR: &str = "blocks/";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    created_at: u64,
+    blocks: usize,
+}
+
+/// Everything needed to bring a node back: identity, config, storage buckets
+/// (offers, transfers, pins, peers, ...) and the blocks of offered and pinned files.
+///
+/// Archives are tar files. Blocks are stored decrypted, so treat an archive
+/// from a node with at-rest encryption as sensitive.
+pub struct NodeBackup {
+    pub identity: Vec<u8>,
+    pub config: NodeConfig,
+    pub storage: StorageSnapshot,
+    pub blocks: Vec<Vec<u8>>,
+}
+
+impl NodeBackup {
+    /// Capture the state of a stopped node
+    pub fn capture(
+        config: &NodeConfig,
+        identity_path: &Path,
+        store: &Storage,
+        files: &FileTransferManager,
+    ) -> io::Result<Self> {
+        let mut blocks = Vec::new();
+        for hash in files.preserved_chunk_hashes() {
+            if let Some(data) = files.blocks().get(&hash).map_err(io::Error::other)? {
+                blocks.push(data);
+            }
+        }
+
+        Ok(Self {
+            identity: std::fs::read(identity_path)?,
+            config: config.clone(),
+            storage: store.snapshot().map_err(io::Error::other)?,
+            blocks,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut archive = tar::Builder::new(create_private(path)?);
+
+        let manifest = Manifest {
+            version: BACKUP_FORMAT_VERSION,
+            created_at: current_timestamp(),
+            blocks: self.blocks.len(),
+        };
+        append(
+            &mut archive,
+            MANIFEST_ENTRY,
+            &serde_json::to_vec(&manifest)?,
+        )?;
+        append(&mut archive, IDENTITY_ENTRY, &self.identity)?;
+        append(
+            &mut archive,
+            CONFIG_ENTRY,
+            self.config.to_toml()?.as_bytes(),
+        )?;
+
+        // Values are arbitrary bytes, so hex-encode them for JSON
+        let storage: BTreeMap<&String, Vec<(&String, String)>> = self
+            .storage
+            .iter()
+            .map(|(bucket, entries)| {
+                let entries = entries
+                    .iter()
+                    .map(|(key, value)| (key, hex::encode(value)))
+                    .collect();
+                (bucket, entries)
+            })
+            .collect();
+        append(&mut archive, STORAGE_ENTRY, &serde_json::to_vec(&storage)?)?;
+
+        for data in &self.blocks {
+            let hash = corelink_core::file::calculate_chunk_hash(data);
+            append(
+                &mut archive,
+                &format!("{}{}", BLOCKS_DIR, hex::encode(hash)),
+                data,
+            )?;
+        }
+
+        archive.into_inner()?.sync_all()?;
+        info!(
+            "💾 Wrote backup to {:?} ({} bucket(s), {} block(s))",
+            path,
+            self.storage.len(),
+            self.blocks.len()
+        );
+        Ok(())
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut archive = tar::Archive::new(File::open(path)?);
+        let mut manifest: Option<Manifest> = None;
+        let mut identity = None;
+        let mut config = None;
+        let mut storage = None;
+        let mut blocks = Vec::new();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+
+            match name.as_str() {
+                MANIFEST_ENTRY => manifest = Some(serde_json::from_slice(&data)?),
+                IDENTITY_ENTRY => identity = Some(data),
+                CONFIG_ENTRY => {
+                    let contents = String::from_utf8(data).map_err(invalid)?;
+                    config = Some(toml::from_str(&contents).map_err(invalid)?);
+                }
+                STORAGE_ENTRY => {
+                    let encoded: BTreeMap<String, Vec<(String, String)>> =
+                        serde_json::from_slice(&data)?;
+                    let mut snapshot = StorageSnapshot::new();
+                    for (bucket, entries) in encoded {
+                        let entries = entries
+                            .into_iter()
+                            .map(|(key, value)| Ok((key, hex::decode(value).map_err(invalid)?)))
+                            .collect::<io::Result<_>>()?;
+                        snapshot.insert(bucket, entries);
+                    }
+                    storage = Some(snapshot);
+                }
+                _ if name.starts_with(BLOCKS_DIR) => blocks.push(data),
+                _ => {}
+            }
+        }
+
+        match manifest {
+            Some(manifest) if manifest.version == BACKUP_FORMAT_VERSION => {}
+            Some(manifest) => {
+                return Err(invalid(format!(
+                    "unsupported backup format version {}",
+                    manifest.version
+                )))
+            }
+            None => return Err(invalid("not a CoreLink backup: missing manifest")),
+        }
+
+        Ok(Self {
+            identity: identity.ok_or_else(|| invalid("backup is missing the node identity"))?,
+            config: config.ok_or_else(|| invalid("backup is missing the node config"))?,
+            storage: storage.ok_or_else(|| invalid("backup is missing node state"))?,
+            blocks,
+        })
+    }
+
+    /// Write the backed up identity, state and blocks into a node's storage
+    pub fn restore(
+        &self,
+        identity_path: &Path,
+        store: &Storage,
+        blocks: &BlockStore,
+    ) -> io::Result<()> {
+        if let Some(parent) = identity_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        io::Write::write_all(&mut create_private(identity_path)?, &self.identity)?;
+        store.restore(&self.storage).map_err(io::Error::other)?;
+        for data in &self.blocks {
+            blocks.put(data).map_err(io::Error::other)?;
+        }
+
+        info!(
+            "♻️ Restored {} bucket(s) and {} block(s) from backup",
+            self.storage.len(),
+            self.blocks.len()
+        );
+        Ok(())
+    }
+}
+
+fn append(archive: &mut tar::Builder<File>, name: &str, data: &[u8]) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o600);
+    header.set_mtime(current_timestamp());
+    header.set_cksum();
+    archive.append_data(&mut header, name, data)
+}
+
+/// Create or truncate a file only its owner can read, since backups and
+/// identities hold the node's private key
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        let file = options.open(path)?;
+        // The mode only applies to new files
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        Ok(file)
+    }
+    #[cfg(not(unix))]
+    options.open(path)
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::Arc;
+    use tempfile::{tempdir, NamedTempFile};
+
+    #[test]
+    fn test_backup_round_trip() -> io::Result<()> {
+        let node_dir = tempdir()?;
+        let store = Storage::new();
+        let mut files = FileTransferManager::new(node_dir.path().to_path_buf(), store.clone())?;
+
+        let mut offered = NamedTempFile::new()?;
+        offered.write_all(b"Back me up")?;
+        offered.flush()?;
+        let metadata = files.offer_file(offered.path())?;
+        files.set_pinned(&metadata.file_id, true)?;
+
+        let identity_path = node_dir.path().join("identity.key");
+        std::fs::write(&identity_path, b"secret identity")?;
+
+        let archive = node_dir.path().join("node.tar");
+        let config = NodeConfig {
+            port: 4100,
+            ..NodeConfig::default()
+        };
+        NodeBackup::capture(&config, &identity_path, &store, &files)?.write(&archive)?;
+
+        // Restore into a fresh node
+        let restored_dir = tempdir()?;
+        let backup = NodeBackup::read(&archive)?;
+        assert_eq!(backup.config.port, 4100);
+
+        let restored_store = Storage::new();
+        let restored_blocks =
+            BlockStore::with_backend(Arc::new(corelink_core::storage::MemoryObjectStore::new()));
+        let restored_identity = restored_dir.path().join("identity.key");
+        backup.restore(&restored_identity, &restored_store, &restored_blocks)?;
+        assert_eq!(std::fs::read(&restored_identity)?, b"secret identity");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for path in [&archive, &restored_identity] {
+                assert_eq!(std::fs::metadata(path)?.permissions().mode() & 0o777, 0o600);
+            }
+        }
+
+        let mut files =
+            FileTransferManager::new(restored_dir.path().to_path_buf(), restored_store)?
+                .with_blocks(restored_blocks);
+        assert!(files.is_pinned(&metadata.file_id));
+        let chunk = files.handle_chunk_request(&metadata.file_id, 0)?.unwrap();
+        assert_eq!(chunk.data, b"Back me up");
+
+        Ok(())
+    }
+}
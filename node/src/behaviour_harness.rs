@@ -0,0 +1,268 @@
+//! Drives a [`MessagingBehaviour`] without a swarm, for unit tests. Peers
+//! connect over mock connections, connection handler events are scripted,
+//! and time only moves when a test started with tokio's clock paused
+//! advances it.
+
+use crate::messaging_behaviour::{MessagingBehaviour, MessagingBehaviourEvent};
+use crate::protocol_handler::{CoreLinkHandlerEvent, CoreLinkHandlerIn, Outgoing};
+use corelink_core::file::{FileChunk, FileMetadata};
+use corelink_core::identity::NodeId;
+use corelink_core::message::{Message, MessageType};
+use corelink_core::{Storage, TransferResponse};
+use either::Either;
+use libp2p_core::{ConnectedPoint, Endpoint, Multiaddr};
+use libp2p_identity::PeerId;
+use libp2p_request_response::{self as request_response, OutboundRequestId};
+use libp2p_swarm::behaviour::{ConnectionClosed, ConnectionEstablished};
+use libp2p_swarm::{ConnectionId, FromSwarm, NetworkBehaviour, NotifyHandler, ToSwarm};
+use std::collections::{HashMap, VecDeque};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tempfile::TempDir;
+
+pub struct Harness {
+    pub behaviour: MessagingBehaviour,
+    /// Open mock connections of each peer
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    next_connection: usize,
+    events: VecDeque<MessagingBehaviourEvent>,
+    /// Messages handed to connection handlers and not yet written
+    handed_over: VecDeque<(PeerId, ConnectionId, Outgoing)>,
+    _dir: TempDir,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        Self::with(|behaviour| behaviour)
+    }
+
+    /// A harness around the behaviour `configure` returns
+    pub fn with(configure: impl FnOnce(MessagingBehaviour) -> MessagingBehaviour) -> Self {
+        let dir = tempfile::tempdir().expect("temporary storage");
+        let behaviour = MessagingBehaviour::new(dir.path().to_path_buf(), Storage::new())
+            .expect("behaviour storage");
+        Self {
+            behaviour: configure(behaviour),
+            connections: HashMap::new(),
+            next_connection: 0,
+            events: VecDeque::new(),
+            handed_over: VecDeque::new(),
+            _dir: dir,
+        }
+    }
+
+    /// Connect a new peer over one connection, and write what the
+    /// behaviour greets it with
+    pub fn connect(&mut self) -> PeerId {
+        let peer = PeerId::random();
+        self.open_connection(peer);
+        self.write();
+        peer
+    }
+
+    /// Open another connection to `peer`
+    pub fn open_connection(&mut self, peer: PeerId) -> ConnectionId {
+        let connection_id = ConnectionId::new_unchecked(self.next_connection);
+        self.next_connection += 1;
+        let address = Multiaddr::empty();
+        self.behaviour
+            .handle_established_outbound_connection(connection_id, peer, &address, Endpoint::Dialer)
+            .unwrap_or_else(|_| panic!("Connection to {} denied", peer));
+        let connections = self.connections.entry(peer).or_default();
+        let endpoint = dialer();
+        self.behaviour
+            .on_swarm_event(FromSwarm::ConnectionEstablished(ConnectionEstablished {
+                peer_id: peer,
+                connection_id,
+                endpoint: &endpoint,
+                failed_addresses: &[],
+                other_established: connections.len(),
+            }));
+        connections.push(connection_id);
+        self.poll();
+        connection_id
+    }
+
+    /// Close every connection to `peer`
+    pub fn disconnect(&mut self, peer: PeerId) {
+        let endpoint = dialer();
+        let mut connections = self.connections.remove(&peer).unwrap_or_default();
+        while let Some(connection_id) = connections.pop() {
+            self.behaviour
+                .on_swarm_event(FromSwarm::ConnectionClosed(ConnectionClosed {
+                    peer_id: peer,
+                    connection_id,
+                    endpoint: &endpoint,
+                    remaining_established: connections.len(),
+                }));
+        }
+        self.handed_over.retain(|(to, _, _)| *to != peer);
+        self.poll();
+    }
+
+    /// Report an event from the handler of one of `peer`'s connections
+    pub fn handler_event(&mut self, peer: PeerId, event: CoreLinkHandlerEvent) {
+        let connection_id = self.connection(&peer);
+        self.handler_event_on(peer, connection_id, event);
+    }
+
+    fn handler_event_on(
+        &mut self,
+        peer: PeerId,
+        connection_id: ConnectionId,
+        event: CoreLinkHandlerEvent,
+    ) {
+        self.behaviour
+            .on_connection_handler_event(peer, connection_id, Either::Left(event));
+        self.poll();
+    }
+
+    /// Deliver a message from `peer`
+    pub fn receive(&mut self, peer: PeerId, msg_type: MessageType) {
+        let message = Message::new(NodeId::from_peer_id(&peer), msg_type);
+        self.handler_event(
+            peer,
+            CoreLinkHandlerEvent::MessageReceived(Box::new(message), 0),
+        );
+    }
+
+    /// Write the messages handed to connections, reporting each sent
+    pub fn write(&mut self) -> Vec<(PeerId, Message)> {
+        let mut written = Vec::new();
+        while let Some((peer, connection_id, (message, id))) = self.handed_over.pop_front() {
+            self.handler_event_on(
+                peer,
+                connection_id,
+                CoreLinkHandlerEvent::MessageSent(0, id),
+            );
+            written.push((peer, message));
+        }
+        written
+    }
+
+    /// Fail to write the messages handed to connections
+    pub fn fail_writes(&mut self, error: &str) -> Vec<(PeerId, Message)> {
+        let mut failed = Vec::new();
+        while let Some((peer, connection_id, (message, id))) = self.handed_over.pop_front() {
+            let event = CoreLinkHandlerEvent::SendError(error.to_string(), id);
+            self.handler_event_on(peer, connection_id, event);
+            failed.push((peer, message));
+        }
+        failed
+    }
+
+    /// Answer the file requests in flight as `peer`, with the metadata
+    /// `answer` returns for each file; None answers that it is not held
+    pub fn answer_file_requests(
+        &mut self,
+        peer: PeerId,
+        answer: impl Fn(&str) -> Option<FileMetadata>,
+    ) {
+        for (request_id, file_id) in self.behaviour.outbound_file_requests() {
+            let response =
+                answer(&file_id).map_or(TransferResponse::NotFound, TransferResponse::File);
+            self.respond(peer, request_id, response);
+        }
+        self.poll();
+    }
+
+    /// Answer the chunk requests in flight, and those they lead to, with
+    /// what `answer` returns for each chunk; None answers that the peer
+    /// does not hold it. Returns how many requests were answered.
+    pub fn answer_chunks(&mut self, answer: impl Fn(&str, u32) -> Option<FileChunk>) -> usize {
+        let mut answered = 0;
+        loop {
+            let requests = self.behaviour.outbound_chunk_requests();
+            if requests.is_empty() {
+                return answered;
+            }
+            for (request_id, peer, file_id, asked) in requests {
+                let mut chunks: Vec<FileChunk> = asked
+                    .iter()
+                    .filter_map(|&chunk_index| answer(&file_id, chunk_index))
+                    .collect();
+                let response = match (asked.len(), chunks.len()) {
+                    (_, 0) => TransferResponse::NotFound,
+                    (1, _) => TransferResponse::Chunk(chunks.remove(0)),
+                    _ => TransferResponse::Chunks(chunks),
+                };
+                self.respond(peer, request_id, response);
+                answered += 1;
+            }
+            self.poll();
+        }
+    }
+
+    /// Move tokio's paused clock forward, then let the behaviour act on it
+    pub async fn advance(&mut self, duration: Duration) {
+        tokio::time::advance(duration).await;
+        self.poll();
+    }
+
+    /// Events the behaviour emitted since the last call
+    pub fn events(&mut self) -> Vec<MessagingBehaviourEvent> {
+        self.events.drain(..).collect()
+    }
+
+    /// Poll the behaviour until it has nothing more to do, collecting its
+    /// events and the messages it hands to connections. Waits for received
+    /// chunks to be stored first.
+    pub fn poll(&mut self) {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        loop {
+            self.poll_ready(&mut cx);
+            if self.behaviour.chunk_writes_in_flight() == 0 {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn poll_ready(&mut self, cx: &mut Context) {
+        while let Poll::Ready(action) = self.behaviour.poll(cx) {
+            match action {
+                ToSwarm::GenerateEvent(event) => self.events.push_back(event),
+                ToSwarm::NotifyHandler {
+                    peer_id,
+                    handler,
+                    event: Either::Left(CoreLinkHandlerIn::Send(outgoing)),
+                } => {
+                    let connection_id = match handler {
+                        NotifyHandler::One(connection_id) => connection_id,
+                        NotifyHandler::Any => self.connection(&peer_id),
+                    };
+                    self.handed_over
+                        .push_back((peer_id, connection_id, *outgoing));
+                }
+                // Keep-alive changes, transfer requests and the like
+                _ => {}
+            }
+        }
+    }
+
+    fn respond(&mut self, peer: PeerId, request_id: OutboundRequestId, response: TransferResponse) {
+        self.behaviour
+            .inject_transfer_event(request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response {
+                    request_id,
+                    response,
+                },
+            });
+    }
+
+    fn connection(&self, peer: &PeerId) -> ConnectionId {
+        *self
+            .connections
+            .get(peer)
+            .and_then(|connections| connections.first())
+            .unwrap_or_else(|| panic!("{} is not connected", peer))
+    }
+}
+
+fn dialer() -> ConnectedPoint {
+    ConnectedPoint::Dialer {
+        address: Multiaddr::empty(),
+        role_override: Endpoint::Dialer,
+    }
+}
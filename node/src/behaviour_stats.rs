@@ -0,0 +1,110 @@
+//! Counters of what the messaging behaviour did since the node started,
+//! overall and per peer. Kept in memory only.
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+/// Messages of one type
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct MessageCounts {
+    pub queued: u64,
+    pub sent: u64,
+    /// Given up on, after retries or for want of room
+    pub failed: u64,
+}
+
+/// Files peers offered and what became of the offers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct OfferCounts {
+    /// Offers received, counting each peer offering a file
+    pub seen: u64,
+    /// Offered files downloaded, on their own or once accepted
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Offers that waited for an answer until they expired
+    pub expired: u64,
+}
+
+/// What the behaviour did with one peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PeerCounts {
+    pub messages_sent: u64,
+    pub messages_failed: u64,
+    pub chunks_served: u64,
+    /// Chunks from the peer that did not match their hash
+    pub verification_failures: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BehaviourStats {
+    /// By message type, e.g. "FileOffer"
+    pub messages: BTreeMap<String, MessageCounts>,
+    pub offers: OfferCounts,
+    pub chunks_served: u64,
+    pub verification_failures: u64,
+    /// By peer id
+    pub peers: BTreeMap<String, PeerCounts>,
+}
+
+impl BehaviourStats {
+    pub fn message_queued(&mut self, msg_type: &str) {
+        self.message(msg_type).queued += 1;
+    }
+
+    pub fn message_sent(&mut self, peer: &PeerId, msg_type: &str) {
+        self.message(msg_type).sent += 1;
+        self.peer(peer).messages_sent += 1;
+    }
+
+    pub fn message_failed(&mut self, peer: &PeerId, msg_type: &str) {
+        self.message(msg_type).failed += 1;
+        self.peer(peer).messages_failed += 1;
+    }
+
+    pub fn chunk_served(&mut self, peer: &PeerId) {
+        self.chunks_served += 1;
+        self.peer(peer).chunks_served += 1;
+    }
+
+    pub fn verification_failed(&mut self, peer: &PeerId) {
+        self.verification_failures += 1;
+        self.peer(peer).verification_failures += 1;
+    }
+
+    fn message(&mut self, msg_type: &str) -> &mut MessageCounts {
+        self.messages.entry(msg_type.to_string()).or_default()
+    }
+
+    fn peer(&mut self, peer: &PeerId) -> &mut PeerCounts {
+        self.peers.entry(peer.to_string()).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_by_type_and_peer() {
+        let mut stats = BehaviourStats::default();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        stats.message_queued("Ping");
+        stats.message_queued("Ping");
+        stats.message_sent(&first, "Ping");
+        stats.message_failed(&second, "Ping");
+        stats.chunk_served(&first);
+        stats.verification_failed(&second);
+
+        let counts = MessageCounts {
+            queued: 2,
+            sent: 1,
+            failed: 1,
+        };
+        assert_eq!(stats.messages["Ping"], counts);
+        assert_eq!(stats.chunks_served, 1);
+        assert_eq!(stats.peers[&first.to_string()].messages_sent, 1);
+        assert_eq!(stats.peers[&second.to_string()].verification_failures, 1);
+    }
+}
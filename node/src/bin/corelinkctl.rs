@@ -0,0 +1,67 @@
+//! Send console commands to a node started with `--daemon`.
+//!
+//! Usage: corelinkctl [--socket <path>] <command> [args...]
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Where a node with the default storage path listens
+const DEFAULT_SOCKET: &str = "./storage/control.sock";
+
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let socket = match args.iter().position(|arg| arg == "--socket") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            PathBuf::from(path)
+        }
+        _ => PathBuf::from(DEFAULT_SOCKET),
+    };
+    let command = if args.is_empty() {
+        "help".to_string()
+    } else {
+        args.join(" ")
+    };
+
+    match send(&socket, &command) {
+        Ok(output) => {
+            for line in output {
+                println!("{}", line);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("corelinkctl: {}: {}", socket.display(), e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &std::path::Path, command: &str) -> std::io::Result<Vec<String>> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let mut stream = UnixStream::connect(socket)?;
+    writeln!(stream, "{}", command)?;
+
+    // The response ends with an empty line
+    let mut output = Vec::new();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        output.push(line);
+    }
+    Ok(output)
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &std::path::Path, _command: &str) -> std::io::Result<Vec<String>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "control sockets need Unix domain sockets",
+    ))
+}
@@ -0,0 +1,629 @@
+use crate::api::{ApiState, DownloadInfo, FileInfo, FileStatus, NodeInfo, NodeStats, PeerInfo};
+use crate::file_transfer::FileRemoval;
+use crate::health::HealthReport;
+use crate::partition::PartitionStatus;
+use crate::replication::ReplicationHealth;
+use crate::websocket::{WsEvent, WsEventSender};
+use corelink_core::identity::NodeId;
+use corelink_core::network::{self, NetworkEvent};
+use libp2p_identity::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Something that happened in the swarm driver, for the API bridge and
+/// anyone else subscribed
+#[derive(Debug, Clone)]
+pub enum NodeEvent {
+    PeerConnected {
+        peer_id: String,
+        address: String,
+    },
+    PeerDisconnected {
+        peer_id: String,
+    },
+    /// A peer cannot understand some or all of this node's messages
+    IncompatiblePeer {
+        peer_id: String,
+        reason: String,
+    },
+    /// A peer offered a file, which is now downloading
+    FileOffered(FileInfo),
+    /// This node started offering a file
+    FileAdded(FileInfo),
+    ChunkReceived {
+        file_id: String,
+        chunk_index: u32,
+        /// Peer that sent the chunk
+        peer_id: String,
+        /// Size of this chunk in bytes
+        chunk_size: u64,
+        /// Bytes of the file received so far
+        bytes_received: u64,
+        total_bytes: u64,
+        chunks_remaining: u32,
+        progress: f32,
+        /// Bytes per second over the last few seconds
+        speed: f64,
+    },
+    /// Where a running download stands, at most once per
+    /// [`PROGRESS_INTERVAL`](crate::driver::PROGRESS_INTERVAL) and only when
+    /// it changed
+    TransferProgress(Box<DownloadInfo>),
+    TransferComplete {
+        file_id: String,
+        name: String,
+        size: u64,
+        /// Where the file was saved; None when it only lives in the block store
+        path: Option<PathBuf>,
+    },
+    TransferFailed {
+        file_id: String,
+        reason: String,
+    },
+    HoldersChanged {
+        file_id: String,
+        holders: Vec<String>,
+    },
+    /// A download was cancelled, an offer withdrawn or a file deleted
+    FileRemoved {
+        file_id: String,
+        removal: FileRemoval,
+    },
+    /// A peer declined a file this node offered it
+    OfferRejected {
+        peer_id: String,
+        file_id: String,
+    },
+    /// A peer sent an application message
+    MessageReceived {
+        peer_id: String,
+        msg_type: String,
+        payload: serde_json::Value,
+    },
+    /// An application message sent through the API was written, or failed
+    /// for good with `error`
+    MessageDelivery {
+        message_id: u64,
+        peer_id: String,
+        error: Option<String>,
+    },
+    /// Messages for `peer_id` were dropped from its queue without being sent
+    MessagesDropped {
+        peer_id: String,
+        count: usize,
+        reason: String,
+    },
+    /// A partition started or ended
+    PartitionChanged(PartitionStatus),
+    /// Periodic snapshot of the node's state
+    Status(Box<NodeStatus>),
+    /// Something went wrong talking to a peer
+    Error {
+        category: ErrorCategory,
+        peer_id: Option<String>,
+        message: String,
+    },
+    /// Free space on the storage volume fell below the health threshold, or
+    /// rose back above it
+    StorageChanged {
+        low: bool,
+        free_bytes: u64,
+        min_free_bytes: u64,
+    },
+    /// A message from `peer_id` moved the node to a newer consensus epoch
+    EpochChanged {
+        peer_id: String,
+        previous_epoch: u64,
+        epoch: u64,
+    },
+    /// A registry update from `peer_id` was dropped for its stale epoch
+    StaleEpochRejected {
+        peer_id: String,
+        msg_type: String,
+        epoch: u64,
+        current_epoch: u64,
+    },
+}
+
+/// What kind of thing went wrong in a [`NodeEvent::Error`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    /// A peer could not be reached or dropped the connection
+    Connection,
+    /// A peer misbehaved or a message could not be delivered
+    Protocol,
+    /// A peer's identity or signature did not check out
+    Auth,
+}
+
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+    pub node: NodeInfo,
+    pub stats: NodeStats,
+    pub peers: Vec<PeerInfo>,
+    pub replication: Vec<ReplicationHealth>,
+    pub health: HealthReport,
+}
+
+/// Mirror node events, and peer measurements as they change, into the REST
+/// API state and out to WebSocket clients, until the driver goes away
+pub async fn run(
+    mut events: broadcast::Receiver<NodeEvent>,
+    mut measurements: broadcast::Receiver<NetworkEvent>,
+    api: ApiState,
+    ws: WsEventSender,
+) {
+    // Measurements are kept by node id, which cannot be turned back into
+    // the peer id clients know peers by
+    let mut peer_ids: HashMap<NodeId, String> = HashMap::new();
+    let mut measuring = true;
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    track_peer(&mut peer_ids, &event);
+                    apply(event, &api, &ws).await;
+                }
+                // The next status snapshot catches the API state up
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("API bridge fell behind, missed {} event(s)", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            event = measurements.recv(), if measuring => match event {
+                Ok(NetworkEvent::PeerAdded(peer) | NetworkEvent::PeerUpdated(peer)) => {
+                    if let Some(peer_id) = peer_ids.get(&peer.node_id) {
+                        measured(peer_id, &peer, &api, &ws).await;
+                    }
+                }
+                Ok(NetworkEvent::PeerRemoved(_)) => {}
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("API bridge fell behind, missed {} peer measurement(s)", missed);
+                }
+                Err(broadcast::error::RecvError::Closed) => measuring = false,
+            },
+        }
+    }
+}
+
+/// Remember the peer ids of connected peers by their node ids
+fn track_peer(peer_ids: &mut HashMap<NodeId, String>, event: &NodeEvent) {
+    let (peer_id, connected) = match event {
+        NodeEvent::PeerConnected { peer_id, .. } => (peer_id, true),
+        NodeEvent::PeerDisconnected { peer_id } => (peer_id, false),
+        _ => return,
+    };
+    let Ok(peer) = peer_id.parse::<PeerId>() else {
+        return;
+    };
+    let node_id = NodeId::from_peer_id(&peer);
+    if connected {
+        peer_ids.insert(node_id, peer_id.clone());
+    } else {
+        peer_ids.remove(&node_id);
+    }
+}
+
+/// Publish a connected peer's new latency and bandwidth figures
+async fn measured(peer_id: &str, peer: &network::PeerInfo, api: &ApiState, ws: &WsEventSender) {
+    let rtt_ms = peer.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0);
+    broadcast_ws_event(
+        ws,
+        WsEvent::PeerMetrics {
+            peer_id: peer_id.to_string(),
+            rtt_ms,
+            throughput: peer.throughput,
+            bytes_sent: peer.bytes_sent,
+            bytes_received: peer.bytes_received,
+            timestamp: current_timestamp(),
+        },
+    );
+    api.update_peer(peer_id, |listed| {
+        listed.rtt_ms = rtt_ms;
+        listed.throughput = peer.throughput;
+        listed.failures = peer.failures;
+        listed.bytes_sent = peer.bytes_sent;
+        listed.bytes_received = peer.bytes_received;
+    })
+    .await;
+}
+
+async fn apply(event: NodeEvent, api: &ApiState, ws: &WsEventSender) {
+    let timestamp = current_timestamp();
+    match event {
+        NodeEvent::PeerConnected { peer_id, address } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::PeerConnected {
+                    peer_id,
+                    address,
+                    timestamp,
+                },
+            );
+        }
+        NodeEvent::PeerDisconnected { peer_id } => {
+            broadcast_ws_event(ws, WsEvent::PeerDisconnected { peer_id, timestamp });
+        }
+        NodeEvent::IncompatiblePeer { peer_id, reason } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::IncompatiblePeer {
+                    peer_id,
+                    reason,
+                    timestamp,
+                },
+            );
+        }
+        NodeEvent::FileOffered(file) => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::FileOffered {
+                    peer_id: file.peer_id.clone().unwrap_or_default(),
+                    file_id: file.file_id.clone(),
+                    name: file.name.clone(),
+                    size: file.size,
+                    chunks: file.chunks,
+                    pending: file.status == FileStatus::Pending,
+                    timestamp,
+                },
+            );
+            api.add_file(file).await;
+        }
+        NodeEvent::FileAdded(file) => api.add_file(file).await,
+        NodeEvent::ChunkReceived {
+            file_id,
+            chunk_index,
+            peer_id,
+            chunk_size,
+            bytes_received,
+            total_bytes,
+            chunks_remaining,
+            progress,
+            speed,
+        } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::ChunkReceived {
+                    file_id: file_id.clone(),
+                    chunk_index,
+                    peer_id,
+                    chunk_size,
+                    bytes_received,
+                    total_bytes,
+                    chunks_remaining,
+                    progress,
+                    bytes_per_second: speed,
+                    timestamp,
+                },
+            );
+            api.update_file_progress(&file_id, progress, bytes_received)
+                .await;
+        }
+        NodeEvent::TransferProgress(download) => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::TransferProgress {
+                    download: *download,
+                    timestamp,
+                },
+            );
+        }
+        NodeEvent::TransferComplete {
+            file_id,
+            name,
+            size,
+            path,
+        } => {
+            let path = path.map(|path| path.display().to_string());
+            broadcast_ws_event(
+                ws,
+                WsEvent::TransferComplete {
+                    file_id: file_id.clone(),
+                    name,
+                    size,
+                    path: path.clone(),
+                    timestamp,
+                },
+            );
+            api.complete_file(&file_id, path).await;
+        }
+        NodeEvent::TransferFailed { file_id, reason } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::TransferFailed {
+                    file_id: file_id.clone(),
+                    reason,
+                    timestamp,
+                },
+            );
+            api.update_file_status(&file_id, FileStatus::Failed).await;
+        }
+        NodeEvent::HoldersChanged { file_id, holders } => {
+            api.update_file_holders(&file_id, holders).await;
+        }
+        NodeEvent::FileRemoved { file_id, removal } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::FileRemoved {
+                    file_id: file_id.clone(),
+                    removal,
+                    timestamp,
+                },
+            );
+            api.remove_file(&file_id).await;
+        }
+        NodeEvent::OfferRejected { peer_id, file_id } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::OfferRejected {
+                    peer_id,
+                    file_id,
+                    timestamp,
+                },
+            );
+        }
+        NodeEvent::MessageReceived {
+            peer_id,
+            msg_type,
+            payload,
+        } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::MessageReceived {
+                    peer_id,
+                    msg_type,
+                    payload,
+                    timestamp,
+                },
+            );
+        }
+        NodeEvent::MessageDelivery {
+            message_id,
+            peer_id,
+            error,
+        } => {
+            let event = match error {
+                None => WsEvent::MessageSent {
+                    message_id,
+                    peer_id,
+                    timestamp,
+                },
+                Some(error) => WsEvent::MessageFailed {
+                    message_id,
+                    peer_id,
+                    error,
+                    timestamp,
+                },
+            };
+            broadcast_ws_event(ws, event);
+        }
+        NodeEvent::MessagesDropped {
+            peer_id,
+            count,
+            reason,
+        } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::MessagesDropped {
+                    peer_id,
+                    count,
+                    reason,
+                    timestamp,
+                },
+            );
+        }
+        NodeEvent::PartitionChanged(status) => {
+            let event = if status.suspected {
+                WsEvent::PartitionSuspected {
+                    unreachable_anchors: status.unreachable_anchors,
+                    connectivity: status.connectivity,
+                    timestamp,
+                }
+            } else {
+                WsEvent::PartitionHealed {
+                    connectivity: status.connectivity,
+                    timestamp,
+                }
+            };
+            broadcast_ws_event(ws, event);
+        }
+        NodeEvent::Status(status) => {
+            let NodeStatus {
+                node,
+                stats,
+                peers,
+                replication,
+                health,
+            } = *status;
+            broadcast_ws_event(
+                ws,
+                WsEvent::NodeStatus {
+                    peer_count: stats.peer_count,
+                    active_uploads: stats.active_uploads,
+                    active_downloads: stats.active_downloads,
+                    timestamp,
+                },
+            );
+            api.update_stats(stats).await;
+            api.update_node(node).await;
+            api.update_peers(peers).await;
+            api.update_replication(replication).await;
+            api.update_health(health).await;
+        }
+        NodeEvent::Error {
+            category,
+            peer_id,
+            message,
+        } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::Error {
+                    category,
+                    peer_id,
+                    message,
+                    timestamp,
+                },
+            );
+        }
+        NodeEvent::StorageChanged {
+            low,
+            free_bytes,
+            min_free_bytes,
+        } => {
+            let event = if low {
+                WsEvent::StorageLow {
+                    free_bytes,
+                    min_free_bytes,
+                    timestamp,
+                }
+            } else {
+                WsEvent::StorageRecovered {
+                    free_bytes,
+                    timestamp,
+                }
+            };
+            broadcast_ws_event(ws, event);
+        }
+        NodeEvent::EpochChanged {
+            peer_id,
+            previous_epoch,
+            epoch,
+        } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::EpochChanged {
+                    peer_id,
+                    previous_epoch,
+                    epoch,
+                    timestamp,
+                },
+            );
+        }
+        NodeEvent::StaleEpochRejected {
+            peer_id,
+            msg_type,
+            epoch,
+            current_epoch,
+        } => {
+            broadcast_ws_event(
+                ws,
+                WsEvent::StaleEpochRejected {
+                    peer_id,
+                    msg_type,
+                    epoch,
+                    current_epoch,
+                    timestamp,
+                },
+            );
+        }
+    }
+}
+
+/// Broadcast an event to all connected WebSocket clients
+fn broadcast_ws_event(tx: &WsEventSender, event: WsEvent) {
+    if let Err(_e) = tx.send(event) {
+        // No subscribers is ok, don't log error
+        // Only log if there are actual subscribers who failed to receive
+        if tx.receiver_count() > 0 {
+            warn!("Failed to broadcast WebSocket event");
+        }
+    }
+}
+
+fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use corelink_core::network::NetworkState;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_events_reach_api_and_websocket() {
+        let (events, rx) = broadcast::channel(16);
+        let (ws, mut ws_rx) = broadcast::channel(16);
+        let api = ApiState::new();
+        let network = NetworkState::new();
+        let bridge = tokio::spawn(run(rx, network.subscribe(), api.clone(), ws));
+
+        events
+            .send(NodeEvent::FileAdded(FileInfo {
+                file_id: "f1".to_string(),
+                name: "notes.txt".to_string(),
+                size: 3,
+                chunks: 1,
+                status: FileStatus::Downloading,
+                progress: 0.0,
+                bytes_received: 0,
+                peer_id: None,
+                pinned: false,
+                holders: Vec::new(),
+                version: 1,
+                previous_file_id: None,
+                path: None,
+                created_at: 0,
+            }))
+            .unwrap();
+        events
+            .send(NodeEvent::TransferFailed {
+                file_id: "f1".to_string(),
+                reason: "gone".to_string(),
+            })
+            .unwrap();
+        drop(events);
+        bridge.await.unwrap();
+
+        assert_eq!(
+            api.query_files(&Default::default()).await.items[0].status,
+            FileStatus::Failed
+        );
+        assert!(matches!(
+            ws_rx.try_recv(),
+            Ok(WsEvent::TransferFailed { reason, .. }) if reason == "gone"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_measurements_reach_websocket_as_they_change() {
+        let (events, rx) = broadcast::channel(16);
+        let (ws, mut ws_rx) = broadcast::channel(16);
+        let network = NetworkState::new();
+        let bridge = tokio::spawn(run(rx, network.subscribe(), ApiState::new(), ws));
+        let (peer, stranger) = (PeerId::random(), PeerId::random());
+
+        events
+            .send(NodeEvent::PeerConnected {
+                peer_id: peer.to_string(),
+                address: String::new(),
+            })
+            .unwrap();
+        assert!(matches!(
+            ws_rx.recv().await,
+            Ok(WsEvent::PeerConnected { .. })
+        ));
+        // Peers never reported connected are not published
+        network.record_rtt(&NodeId::from_peer_id(&stranger), Duration::from_millis(5));
+        network.record_rtt(&NodeId::from_peer_id(&peer), Duration::from_millis(20));
+        match ws_rx.recv().await {
+            Ok(WsEvent::PeerMetrics {
+                peer_id, rtt_ms, ..
+            }) => {
+                assert_eq!(peer_id, peer.to_string());
+                assert_eq!(rtt_ms, Some(20.0));
+            }
+            other => panic!("expected peer metrics, got {:?}", other),
+        }
+
+        drop(events);
+        bridge.await.unwrap();
+    }
+}
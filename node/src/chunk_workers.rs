@@ -0,0 +1,84 @@
+//! Threads that verify and store received chunks, so hashing and disk
+//! writes do not hold up the swarm. Results come back in the order the
+//! writes finish, not the order they were handed over.
+
+use crate::file_transfer::{ChunkWrite, ChunkWritten};
+use futures::channel::mpsc;
+use futures::StreamExt;
+use libp2p_identity::PeerId;
+use std::collections::VecDeque;
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use tracing::warn;
+
+/// Chunks verified and stored at once
+const WORKERS: usize = 4;
+
+pub struct ChunkWorkers {
+    jobs: std_mpsc::Sender<(PeerId, ChunkWrite)>,
+    results: mpsc::UnboundedReceiver<(PeerId, ChunkWritten)>,
+    /// Chunks stored here because no worker was left to take them
+    stored_inline: VecDeque<(PeerId, ChunkWritten)>,
+    /// Writes handed over whose results were not taken yet
+    in_flight: usize,
+}
+
+impl ChunkWorkers {
+    pub fn new() -> Self {
+        let (jobs, queue) = std_mpsc::channel::<(PeerId, ChunkWrite)>();
+        let (done, results) = mpsc::unbounded();
+        let queue = Arc::new(Mutex::new(queue));
+        for index in 0..WORKERS {
+            let queue = queue.clone();
+            let done = done.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("chunk-worker-{}", index))
+                .spawn(move || loop {
+                    // Workers stop once the pool is dropped
+                    let Ok((peer, write)) = queue.lock().expect("chunk queue").recv() else {
+                        return;
+                    };
+                    if done.unbounded_send((peer, write.run())).is_err() {
+                        return;
+                    }
+                });
+            if let Err(e) = spawned {
+                warn!("Failed to start chunk worker: {}", e);
+            }
+        }
+        Self {
+            jobs,
+            results,
+            stored_inline: VecDeque::new(),
+            in_flight: 0,
+        }
+    }
+
+    /// Verify and store a chunk `peer` sent, in the background
+    pub fn submit(&mut self, peer: PeerId, write: ChunkWrite) {
+        self.in_flight += 1;
+        if let Err(std_mpsc::SendError((peer, write))) = self.jobs.send((peer, write)) {
+            warn!("No chunk worker is running, storing the chunk inline");
+            self.stored_inline.push_back((peer, write.run()));
+        }
+    }
+
+    /// The next chunk stored, with the peer that sent it
+    pub fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<(PeerId, ChunkWritten)>> {
+        let next = match self.stored_inline.pop_front() {
+            Some(stored) => Poll::Ready(Some(stored)),
+            None => self.results.poll_next_unpin(cx),
+        };
+        if let Poll::Ready(Some(_)) = next {
+            self.in_flight -= 1;
+        }
+        next
+    }
+
+    /// Chunks handed over and not yet stored
+    #[cfg(test)]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+}
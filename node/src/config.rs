@@ -0,0 +1,689 @@
+use crate::admission::ConnectionLimits;
+use crate::file_transfer::TransferLimits;
+use crate::flood::FloodLimits;
+use crate::health::HealthConfig;
+use crate::logging::{self, LogFormat, LoggingConfig};
+use crate::messaging_behaviour::DEFAULT_OFFER_EXPIRY_SECS;
+use crate::offers::OfferMode;
+use crate::outbound_queue::QueueLimits;
+use crate::partition::PartitionConfig;
+use crate::rate_limit::RateLimitConfig;
+use crate::retry::RetryPolicy;
+use crate::role::NodeRole;
+use crate::shared_folder::DEFAULT_IGNORE;
+use corelink_core::crypto::{self, EncryptionKey};
+use corelink_core::storage::{
+    DiskObjectStore, EncryptedObjectStore, MemoryObjectStore, ObjectStore, S3Config, S3ObjectStore,
+    TieredObjectStore, TieringPolicy,
+};
+use libp2p_core::multiaddr::Protocol;
+use libp2p_core::Multiaddr;
+use libp2p_identity::PeerId;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Node configuration, loaded from a TOML file given with `--config`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NodeConfig {
+    pub port: u16,
+    /// "storage", "relay-only" or "edge"
+    pub role: NodeRole,
+    /// Free-form labels advertised to peers, e.g. a region or rack
+    pub labels: Vec<String>,
+    pub storage_path: PathBuf,
+    pub replication_factor: usize,
+    pub gc_interval_secs: u64,
+    /// How often to challenge holders to prove they still store our files
+    pub challenge_interval_secs: u64,
+    /// Caps on inbound, outbound and per-IP connections
+    pub connection_limits: ConnectionLimits,
+    /// How long peers banned through the API stay banned unless the ban
+    /// request says otherwise
+    pub ban_duration_secs: u64,
+    /// Longest ban a request through the API may ask for
+    pub max_ban_duration_secs: u64,
+    /// Caps on concurrent downloads, chunk requests in flight and cached bytes
+    pub transfer_limits: TransferLimits,
+    /// Messages waiting to be sent to each peer, and what happens to more
+    pub message_queue: QueueLimits,
+    /// How often messages that fail to be written are tried again
+    pub message_retry: RetryPolicy,
+    /// Messages each peer may send per minute, by type, before it is muted
+    pub flood_limits: FloodLimits,
+    /// "automatic" downloads files as soon as peers offer them; "manual"
+    /// holds offers until accepted or rejected through the API
+    pub offer_mode: OfferMode,
+    /// How long offers wait for an answer in manual mode
+    pub offer_expiry_secs: u64,
+    /// Find peers on the local network with mDNS; turn off where multicast
+    /// is blocked
+    pub mdns: bool,
+    /// Peers dialed on startup and redialed whenever disconnected, as
+    /// multiaddrs ending in `/p2p/<peer id>`
+    pub bootstrap_peers: Vec<String>,
+    /// Anchor peers and thresholds for detecting network partitions
+    pub partition: PartitionConfig,
+    /// Thresholds for `/api/v1/health`
+    pub health: HealthConfig,
+    /// Requests per minute each REST API client may make
+    pub api_rate_limit: RateLimitConfig,
+    /// Bearer token for the `/api/v1/admin` endpoints, which are disabled
+    /// without one; `CORELINK_ADMIN_TOKEN` takes precedence
+    pub api_admin_token: Option<String>,
+    /// Directories `POST /api/v1/files/offer` may offer files from, besides
+    /// the shared folder and the uploads directory
+    pub api_share_dirs: Vec<PathBuf>,
+    /// Origins browsers may call the REST API from, e.g.
+    /// `https://dashboard.example`; none by default
+    pub api_cors_origins: Vec<String>,
+    /// Largest file `POST /api/v1/files/upload` accepts, in bytes
+    pub api_max_upload_bytes: u64,
+    pub storage: StorageBackendConfig,
+    /// zstd level for compressing blocks at rest; 0 disables compression
+    pub compression_level: i32,
+    /// Move rarely read blocks from `storage` to a cheaper cold backend
+    pub tiering: Option<TieringConfig>,
+    /// Directory whose files are offered automatically
+    pub shared_folder: Option<PathBuf>,
+    /// Glob patterns of file names in the shared folder that are never offered
+    pub shared_ignore: Vec<String>,
+    /// Hex key file used to encrypt blocks at rest
+    pub encryption_key_file: Option<PathBuf>,
+    pub logging: LoggingConfig,
+    /// Unix socket accepting console commands; defaults to
+    /// `<storage_path>/control.sock` with `--daemon`
+    pub control_socket: Option<PathBuf>,
+    /// Certificate and key to serve the REST API and WebSocket event stream
+    /// over `https://` and `wss://`
+    pub tls: Option<TlsConfig>,
+    /// Also serve WebSocket events on their own port (node port + 4000), as
+    /// before the REST API served `/ws`
+    pub standalone_websocket: bool,
+}
+
+/// Control socket file name in the storage directory
+pub const DEFAULT_CONTROL_SOCKET: &str = "control.sock";
+
+/// Environment variable holding a passphrase to derive the at-rest encryption key from
+pub const PASSPHRASE_ENV: &str = "CORELINK_ENCRYPTION_PASSPHRASE";
+
+/// Salt for the passphrase in the storage directory
+pub const SALT_FILE: &str = "encryption.salt";
+
+/// Environment variable holding the token for the REST API's admin endpoints
+pub const ADMIN_TOKEN_ENV: &str = "CORELINK_ADMIN_TOKEN";
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            port: 4001,
+            role: NodeRole::default(),
+            labels: Vec::new(),
+            storage_path: PathBuf::from("./storage"),
+            replication_factor: 0,
+            gc_interval_secs: 3600,
+            challenge_interval_secs: 300,
+            connection_limits: ConnectionLimits::default(),
+            ban_duration_secs: 3600,
+            max_ban_duration_secs: 30 * 24 * 3600,
+            transfer_limits: TransferLimits::default(),
+            message_queue: QueueLimits::default(),
+            message_retry: RetryPolicy::default(),
+            flood_limits: FloodLimits::default(),
+            offer_mode: OfferMode::default(),
+            offer_expiry_secs: DEFAULT_OFFER_EXPIRY_SECS,
+            mdns: true,
+            bootstrap_peers: Vec::new(),
+            partition: PartitionConfig::default(),
+            health: HealthConfig::default(),
+            api_rate_limit: RateLimitConfig::default(),
+            api_admin_token: None,
+            api_share_dirs: Vec::new(),
+            api_cors_origins: Vec::new(),
+            api_max_upload_bytes: 1 << 30,
+            storage: StorageBackendConfig::default(),
+            compression_level: 3,
+            tiering: None,
+            shared_folder: None,
+            shared_ignore: DEFAULT_IGNORE.iter().map(|s| s.to_string()).collect(),
+            encryption_key_file: None,
+            logging: LoggingConfig::default(),
+            control_socket: None,
+            tls: None,
+            standalone_websocket: false,
+        }
+    }
+}
+
+/// PEM files of a certificate chain and its private key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsConfig {
+    /// Server side settings for accepting TLS connections
+    pub fn server_config(&self) -> io::Result<Arc<rustls::ServerConfig>> {
+        let context = |path: &Path| {
+            let path = path.display().to_string();
+            move |e: rustls::pki_types::pem::Error| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e))
+            }
+        };
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(context(&self.cert_path))?;
+        let key = PrivateKeyDer::from_pem_file(&self.key_path).map_err(context(&self.key_path))?;
+        let config =
+            rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Arc::new(config))
+    }
+}
+
+/// Where chunk blocks are kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case")]
+pub enum StorageBackendConfig {
+    /// Files below `path`, by default `<storage_path>/blocks` (`<storage_path>/cold` for the cold tier)
+    Disk {
+        #[serde(default)]
+        path: Option<PathBuf>,
+    },
+    /// In memory only; blocks are lost on restart
+    Memory,
+    /// An S3-compatible bucket
+    S3(S3Config),
+}
+
+impl Default for StorageBackendConfig {
+    fn default() -> Self {
+        Self::Disk { path: None }
+    }
+}
+
+/// Hot/cold tiering of blocks between `storage` and a second backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TieringConfig {
+    pub cold: StorageBackendConfig,
+    /// Demote blocks that have not been read for this long
+    #[serde(default = "default_cold_after_secs")]
+    pub cold_after_secs: u64,
+    /// Promote cold blocks read this many times within one interval
+    #[serde(default = "default_promote_after_hits")]
+    pub promote_after_hits: u32,
+    /// How often to apply the tiering policy
+    #[serde(default = "default_tiering_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_cold_after_secs() -> u64 {
+    86400
+}
+
+fn default_promote_after_hits() -> u32 {
+    3
+}
+
+fn default_tiering_interval_secs() -> u64 {
+    600
+}
+
+impl TieringConfig {
+    pub fn policy(&self) -> TieringPolicy {
+        TieringPolicy {
+            cold_after: Duration::from_secs(self.cold_after_secs),
+            promote_after_hits: self.promote_after_hits,
+        }
+    }
+}
+
+impl NodeConfig {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Load the config named by `--config` (or defaults), then apply command
+    /// line overrides, refusing settings the node cannot run with
+    pub fn from_args(args: &[String]) -> io::Result<Self> {
+        let config = match arg_value(args, "--config") {
+            Some(path) => Self::load(Path::new(path))?,
+            None => Self::default(),
+        }
+        .with_overrides(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Apply `--port` and friends on top of this config
+    pub fn with_overrides(mut self, args: &[String]) -> Self {
+        if let Some(port) = arg_value(args, "--port").and_then(|s| s.parse().ok()) {
+            self.port = port;
+        }
+        if let Some(role) = arg_value(args, "--role").and_then(|s| s.parse().ok()) {
+            self.role = role;
+        }
+        if let Some(factor) = arg_value(args, "--replication-factor").and_then(|s| s.parse().ok()) {
+            self.replication_factor = factor;
+        }
+        if let Some(secs) = arg_value(args, "--gc-interval").and_then(|s| s.parse().ok()) {
+            self.gc_interval_secs = secs;
+        }
+        if args.iter().any(|arg| arg == "--no-mdns") {
+            self.mdns = false;
+        }
+        if let Some(peers) = arg_value(args, "--bootstrap") {
+            self.bootstrap_peers = peers
+                .split(',')
+                .map(|peer| peer.trim().to_string())
+                .collect();
+        }
+        if let Some(path) = arg_value(args, "--shared") {
+            self.shared_folder = Some(PathBuf::from(path));
+        }
+        if let Some(path) = arg_value(args, "--encryption-key-file") {
+            self.encryption_key_file = Some(PathBuf::from(path));
+        }
+        if let Some(path) = arg_value(args, "--control-socket") {
+            self.control_socket = Some(PathBuf::from(path));
+        }
+        if let Some(level) = arg_value(args, "--log-level") {
+            self.logging.level = level.to_string();
+        }
+        if let Some(format) = arg_value(args, "--log-format") {
+            match format {
+                "json" => self.logging.format = LogFormat::Json,
+                "text" => self.logging.format = LogFormat::Text,
+                _ => {}
+            }
+        }
+        if let Some(path) = arg_value(args, "--log-file") {
+            self.logging.file = Some(PathBuf::from(path));
+        }
+
+        self
+    }
+
+    /// Adopt the settings of `new` that can change while the node runs:
+    /// replication factor, intervals, connection, transfer and message queue limits, message retries, flood
+    /// limits, ban duration, health thresholds and log level. Returns the other settings that differ, which only take
+    /// effect after a restart.
+    pub fn reload(&mut self, new: NodeConfig) -> io::Result<Vec<String>> {
+        let mut merged = self.clone();
+        merged.replication_factor = new.replication_factor;
+        merged.gc_interval_secs = new.gc_interval_secs;
+        merged.challenge_interval_secs = new.challenge_interval_secs;
+        merged.connection_limits = new.connection_limits.clone();
+        merged.ban_duration_secs = new.ban_duration_secs;
+        merged.transfer_limits = new.transfer_limits.clone();
+        merged.message_queue = new.message_queue.clone();
+        merged.message_retry = new.message_retry.clone();
+        merged.flood_limits = new.flood_limits.clone();
+        merged.health = new.health.clone();
+        merged.api_rate_limit = new.api_rate_limit.clone();
+        merged.logging.level = new.logging.level.clone();
+        // Nothing changes unless all of it can be applied
+        merged.validate()?;
+
+        let table = |config: &NodeConfig| match toml::Value::try_from(config) {
+            Ok(toml::Value::Table(table)) => Ok(table),
+            Ok(_) => Err(io::Error::other("config is not a table")),
+            Err(e) => Err(io::Error::other(e)),
+        };
+        let (current, wanted) = (table(&merged)?, table(&new)?);
+        let mut restart_required: Vec<String> = current
+            .keys()
+            .chain(wanted.keys())
+            .filter(|key| current.get(*key) != wanted.get(*key))
+            .cloned()
+            .collect();
+        restart_required.sort();
+        restart_required.dedup();
+
+        *self = merged;
+        Ok(restart_required)
+    }
+
+    /// Reject settings the node cannot run with
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        logging::parse_level(&self.logging.level)?;
+        for (key, secs) in [
+            ("gc_interval_secs", self.gc_interval_secs),
+            ("challenge_interval_secs", self.challenge_interval_secs),
+            (
+                "tiering.interval_secs",
+                self.tiering
+                    .as_ref()
+                    .map_or(1, |tiering| tiering.interval_secs),
+            ),
+        ] {
+            if secs == 0 {
+                return invalid(format!("{} must be at least 1", key));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> io::Result<String> {
+        toml::to_string(self).map_err(io::Error::other)
+    }
+
+    /// Token for the REST API's admin endpoints, if they are enabled
+    pub fn admin_token(&self) -> Option<String> {
+        std::env::var(ADMIN_TOKEN_ENV)
+            .ok()
+            .or_else(|| self.api_admin_token.clone())
+            .filter(|token| !token.is_empty())
+    }
+
+    /// At-rest encryption key from the key file or passphrase, if encryption is enabled
+    pub fn encryption_key(&self) -> io::Result<Option<EncryptionKey>> {
+        if let Some(path) = &self.encryption_key_file {
+            return EncryptionKey::load(path)
+                .map(Some)
+                .map_err(io::Error::other);
+        }
+        let Some(passphrase) = std::env::var(PASSPHRASE_ENV)
+            .ok()
+            .filter(|passphrase| !passphrase.is_empty())
+        else {
+            return Ok(None);
+        };
+        // The salt lives with the store it unlocks
+        std::fs::create_dir_all(&self.storage_path)?;
+        let salt = crypto::load_or_create_salt(&self.storage_path.join(SALT_FILE))
+            .map_err(io::Error::other)?;
+        EncryptionKey::from_passphrase(&passphrase, &salt)
+            .map(Some)
+            .map_err(io::Error::other)
+    }
+
+    /// Open the configured block storage backend, tiered and encrypted if configured
+    pub fn open_object_store(&self) -> io::Result<Arc<dyn ObjectStore>> {
+        match self.open_tiers()? {
+            Some(tiers) => self.with_encryption(tiers),
+            None => self.with_encryption(self.open_backend(&self.storage, "blocks")?),
+        }
+    }
+
+    /// Hot (`storage`) and cold backends combined, if tiering is configured
+    pub fn open_tiers(&self) -> io::Result<Option<Arc<TieredObjectStore>>> {
+        let Some(tiering) = &self.tiering else {
+            return Ok(None);
+        };
+        Ok(Some(Arc::new(TieredObjectStore::new(
+            self.open_backend(&self.storage, "blocks")?,
+            self.open_backend(&tiering.cold, "cold")?,
+        ))))
+    }
+
+    /// Wrap `backend` in at-rest encryption if a key is configured
+    pub fn with_encryption(
+        &self,
+        backend: Arc<dyn ObjectStore>,
+    ) -> io::Result<Arc<dyn ObjectStore>> {
+        Ok(match self.encryption_key()? {
+            Some(key) => Arc::new(EncryptedObjectStore::new(backend, &key)),
+            None => backend,
+        })
+    }
+
+    fn open_backend(
+        &self,
+        backend: &StorageBackendConfig,
+        default_dir: &str,
+    ) -> io::Result<Arc<dyn ObjectStore>> {
+        Ok(match backend {
+            StorageBackendConfig::Disk { path } => {
+                let root = path
+                    .clone()
+                    .unwrap_or_else(|| self.storage_path.join(default_dir));
+                Arc::new(DiskObjectStore::open(&root).map_err(io::Error::other)?)
+            }
+            StorageBackendConfig::Memory => Arc::new(MemoryObjectStore::new()),
+            StorageBackendConfig::S3(s3) => {
+                Arc::new(S3ObjectStore::new(s3.clone()).map_err(io::Error::other)?)
+            }
+        })
+    }
+}
+
+/// Value following `flag` on the command line
+/// Parse `addresses` as multiaddrs ending in `/p2p/<peer id>`, paired with
+/// that peer. Anything else is skipped.
+pub fn peer_addresses(addresses: &[String]) -> Vec<(PeerId, Multiaddr)> {
+    addresses
+        .iter()
+        .filter_map(|address| address.parse::<Multiaddr>().ok())
+        .filter_map(|addr| Some((peer_of(&addr)?, addr)))
+        .collect()
+}
+
+/// The peer a multiaddr's `/p2p/` component names
+pub fn peer_of(addr: &Multiaddr) -> Option<PeerId> {
+    addr.iter().find_map(|protocol| match protocol {
+        Protocol::P2p(peer) => Some(peer),
+        _ => None,
+    })
+}
+
+pub fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound_queue::OverflowPolicy;
+
+    #[test]
+    fn test_parse_s3_config() {
+        let config: NodeConfig = toml::from_str(
+            r#"
+            port = 4100
+
+            [storage]
+            backend = "s3"
+            endpoint = "http://localhost:9000"
+            bucket = "corelink"
+            access_key = "minio"
+            secret_key = "minio123"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 4100);
+        assert_eq!(config.gc_interval_secs, 3600);
+        match config.storage {
+            StorageBackendConfig::S3(s3) => {
+                assert_eq!(s3.bucket, "corelink");
+                assert_eq!(s3.region, "us-east-1");
+            }
+            other => panic!("unexpected backend: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_tiering_config() {
+        let config: NodeConfig = toml::from_str(
+            r#"
+            [tiering]
+            cold_after_secs = 60
+
+            [tiering.cold]
+            backend = "disk"
+            path = "/mnt/archive"
+            "#,
+        )
+        .unwrap();
+
+        let tiering = config.tiering.unwrap();
+        assert_eq!(tiering.policy().cold_after, Duration::from_secs(60));
+        assert_eq!(tiering.promote_after_hits, 3);
+        match tiering.cold {
+            StorageBackendConfig::Disk { path } => {
+                assert_eq!(path, Some(PathBuf::from("/mnt/archive")))
+            }
+            other => panic!("unexpected backend: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_logging_config() {
+        let config: NodeConfig = toml::from_str(
+            r#"
+            [logging]
+            level = "warn,corelink_node::messaging_behaviour=debug"
+            format = "json"
+            file = "/var/log/corelink/node.log"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert_eq!(config.logging.rotation, crate::logging::LogRotation::Daily);
+
+        let args: Vec<String> = ["corelink-node", "--log-format", "text"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(config.with_overrides(&args).logging.format, LogFormat::Text);
+    }
+
+    #[test]
+    fn test_reload_applies_live_settings() {
+        let mut config = NodeConfig::default();
+        let new: NodeConfig = toml::from_str(
+            r#"
+            port = 4100
+            gc_interval_secs = 60
+
+            [connection_limits]
+            max_inbound = 10
+
+            [transfer_limits]
+            max_concurrent_downloads = 2
+
+            [message_queue]
+            overflow = "backpressure"
+
+            [message_retry]
+            max_attempts = 1
+
+            [api_rate_limit]
+            writes_per_minute = 5
+
+            [logging]
+            level = "debug"
+            format = "json"
+            "#,
+        )
+        .unwrap();
+
+        let restart_required = config.reload(new).unwrap();
+        assert_eq!(restart_required, vec!["logging", "port"]);
+        assert_eq!(config.gc_interval_secs, 60);
+        assert_eq!(config.connection_limits.max_inbound, 10);
+        assert_eq!(config.transfer_limits.max_concurrent_downloads, 2);
+        assert_eq!(config.message_queue.overflow, OverflowPolicy::Backpressure);
+        assert_eq!(config.message_retry.max_attempts, 1);
+        assert_eq!(config.api_rate_limit.writes_per_minute, 5);
+        assert_eq!(config.logging.level, "debug");
+        // The rest keeps its running value
+        assert_eq!(config.port, 4001);
+        assert_eq!(config.logging.format, LogFormat::Text);
+    }
+
+    #[test]
+    fn test_invalid_reload_changes_nothing() {
+        let mut config = NodeConfig::default();
+        for new in [
+            r#"
+            gc_interval_secs = 60
+            [logging]
+            level = "[not a filter"
+            "#,
+            r#"
+            replication_factor = 2
+            gc_interval_secs = 0
+            "#,
+            r#"
+            challenge_interval_secs = 0
+            "#,
+        ] {
+            let error = config.reload(toml::from_str(new).unwrap()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert_eq!(config.gc_interval_secs, 3600);
+        assert_eq!(config.replication_factor, 0);
+        assert_eq!(config.logging.level, NodeConfig::default().logging.level);
+    }
+
+    #[test]
+    fn test_zero_tiering_interval_is_refused() {
+        let config: NodeConfig = toml::from_str(
+            r#"
+            [tiering]
+            interval_secs = 0
+            cold = { backend = "memory" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_command_line_overrides() {
+        let peer = PeerId::random();
+        let bootstrap = format!("/ip4/10.0.0.1/tcp/4001/p2p/{}", peer);
+        let args: Vec<String> = [
+            "corelink-node",
+            "--port",
+            "4002",
+            "--no-mdns",
+            "--bootstrap",
+            &format!("{},/ip4/10.0.0.2/tcp/4001", bootstrap),
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        let config = NodeConfig::from_args(&args).unwrap();
+        assert_eq!(config.port, 4002);
+        assert!(!config.mdns);
+        // Addresses without a peer id cannot be checked, so are skipped
+        assert_eq!(
+            peer_addresses(&config.bootstrap_peers),
+            vec![(peer, bootstrap.parse().unwrap())]
+        );
+        assert!(matches!(
+            config.storage,
+            StorageBackendConfig::Disk { path: None }
+        ));
+
+        // A zero interval would stop the node at startup
+        let args: Vec<String> = ["corelink-node", "--gc-interval", "0"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let error = NodeConfig::from_args(&args).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+}
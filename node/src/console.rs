@@ -0,0 +1,210 @@
+use crate::driver::{dial, CoreLinkBehaviour, PendingDial};
+use crate::partition::PartitionDetector;
+use crate::peer_store::PeerStore;
+use corelink_core::identity::NodeId;
+use libp2p::swarm::ConnectionId;
+use libp2p::Swarm;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Operator commands, typed on stdin or sent over the control socket
+pub struct Console<'a> {
+    pub swarm: &'a mut Swarm<CoreLinkBehaviour>,
+    pub peer_store: &'a PeerStore,
+    pub pending_dials: &'a mut HashMap<ConnectionId, PendingDial>,
+    pub partition: &'a PartitionDetector,
+    pub start_time: Instant,
+}
+
+impl Console<'_> {
+    /// Run one command line, returning what to show the operator
+    pub fn run(&mut self, cmd: &str) -> Vec<String> {
+        let mut out = Vec::new();
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
+        match parts.as_slice() {
+            ["offer"] => {
+                // Create test file if doesn't exist
+                let test_file = PathBuf::from("test.txt");
+                if !test_file.exists() {
+                    if let Err(e) = std::fs::write(&test_file, b"Hello CoreLink! This is a test file.\nChunk-based transfer protocol working!\nSHA256 verification enabled.") {
+                        out.push(format!("❌ Failed to create test.txt: {}", e));
+                        return out;
+                    }
+                    out.push("📝 Created test.txt".to_string());
+                }
+                // Offer file
+                match self.swarm.behaviour_mut().messaging.offer_file(&test_file) {
+                    Ok(metadata) => out.push(format!(
+                        "📤 Offering: {} ({} bytes, {} chunks)",
+                        metadata.name, metadata.size, metadata.total_chunks
+                    )),
+                    Err(e) => out.push(format!("❌ Failed: {}", e)),
+                }
+            }
+            ["replicate", file_id, copies] => match copies.parse() {
+                Ok(target) => {
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .messaging
+                        .set_replication_target(file_id, target)
+                    {
+                        out.push(format!("🧬 Replicating {} to {} peer(s)", file_id, target));
+                    } else {
+                        out.push(format!("❌ Not offering file: {}", file_id));
+                    }
+                }
+                Err(_) => out.push("Usage: replicate <file_id> <copies>".to_string()),
+            },
+            ["gc"] | ["gc", "--dry-run"] => {
+                let dry_run = parts.len() == 2;
+                match self
+                    .swarm
+                    .behaviour_mut()
+                    .messaging
+                    .collect_garbage(dry_run)
+                {
+                    Ok(report) => {
+                        for path in &report.partial_downloads_removed {
+                            out.push(format!("  🗑️ {}", path.display()));
+                        }
+                    }
+                    Err(e) => out.push(format!("❌ Garbage collection failed: {}", e)),
+                }
+            }
+            ["dial", address] | ["dial", address, "--save"] => match address.parse() {
+                Ok(address) => {
+                    let save = parts.len() == 3;
+                    out.push(format!("📞 Dialing {}", address));
+                    if let Err(e) = dial(self.swarm, self.pending_dials, address, save, None) {
+                        out.push(format!("❌ {}", e));
+                    }
+                }
+                Err(e) => out.push(format!("❌ Invalid address {}: {}", address, e)),
+            },
+            ["export", file_id, dest] => {
+                match self
+                    .swarm
+                    .behaviour()
+                    .messaging
+                    .export_file(file_id, Path::new(dest))
+                {
+                    Ok(()) => out.push(format!("📤 Exported {} to {}", file_id, dest)),
+                    Err(e) => out.push(format!("❌ Export failed: {}", e)),
+                }
+            }
+            ["peers"] => {
+                let behaviour = &self.swarm.behaviour().messaging;
+                let peers: Vec<_> = self.swarm.connected_peers().copied().collect();
+                out.push(format!("👥 {} connected peer(s)", peers.len()));
+                for peer_id in peers {
+                    let rtt = behaviour
+                        .network()
+                        .get_peer(&NodeId::from_peer_id(&peer_id))
+                        .and_then(|peer| peer.rtt)
+                        .map_or("?".to_string(), |rtt| format!("{}ms", rtt.as_millis()));
+                    let tags = self
+                        .peer_store
+                        .get(&peer_id)
+                        .map(|r| r.tags.join(","))
+                        .unwrap_or_default();
+                    out.push(format!(
+                        "  {}  rtt {}  reputation {}  {}",
+                        peer_id,
+                        rtt,
+                        behaviour.peer_reputation(&peer_id),
+                        tags
+                    ));
+                }
+            }
+            ["files"] => {
+                let behaviour = &self.swarm.behaviour().messaging;
+                for metadata in behaviour.offered_files() {
+                    out.push(format!(
+                        "  📤 {}  {} ({} bytes)",
+                        metadata.file_id, metadata.name, metadata.size
+                    ));
+                }
+                for (metadata, progress) in behaviour.downloads() {
+                    out.push(format!(
+                        "  📥 {}  {} ({:.1}%)",
+                        metadata.file_id, metadata.name, progress
+                    ));
+                }
+                for metadata in behaviour.queued_downloads() {
+                    out.push(format!(
+                        "  ⏸️ {}  {} (queued)",
+                        metadata.file_id, metadata.name
+                    ));
+                }
+            }
+            ["download", file_id] => {
+                match self.swarm.behaviour_mut().messaging.download(file_id, None) {
+                    Ok(metadata) => out.push(format!(
+                        "🔽 Downloading {} ({} bytes)",
+                        metadata.name, metadata.size
+                    )),
+                    Err(e) => out.push(format!("❌ Download failed: {}", e)),
+                }
+            }
+            ["cancel", file_id] => {
+                match self
+                    .swarm
+                    .behaviour_mut()
+                    .messaging
+                    .cancel_download(file_id)
+                {
+                    Ok(()) => out.push(format!("🚫 Cancelled {}", file_id)),
+                    Err(e) => out.push(format!("❌ Cancel failed: {}", e)),
+                }
+            }
+            ["status"] => {
+                let behaviour = &self.swarm.behaviour().messaging;
+                out.push(format!("🔑 Peer ID: {}", self.swarm.local_peer_id()));
+                out.push(format!(
+                    "⏱️ Uptime: {}s",
+                    self.start_time.elapsed().as_secs()
+                ));
+                out.push(format!(
+                    "👥 Peers: {}",
+                    self.swarm.connected_peers().count()
+                ));
+                out.push(format!(
+                    "📁 Offering {} file(s), downloading {}",
+                    behaviour.offered_files().len(),
+                    behaviour.downloads().len()
+                ));
+                for address in behaviour.network().confirmed_addresses() {
+                    out.push(format!("🌍 Reachable at {}", address));
+                }
+                if self.partition.status().suspected {
+                    out.push("🪓 Network partition suspected".to_string());
+                }
+            }
+            ["help"] => {
+                out.push("Commands:".to_string());
+                for line in HELP {
+                    out.push(format!("  {}", line));
+                }
+            }
+            [] => {} // Ignore empty input
+            _ => out.push(format!("Unknown: '{}'. Type 'help'", cmd)),
+        }
+        out
+    }
+}
+
+const HELP: &[&str] = &[
+    "offer                        - Share test.txt with connected peers",
+    "replicate <file_id> <copies> - Keep <copies> peer copies of an offered file",
+    "gc [--dry-run]               - Remove unreferenced blocks and partial downloads",
+    "export <file_id> <path>      - Write a stored file's contents to <path>",
+    "dial <multiaddr> [--save]    - Connect to a peer, remembering it with --save",
+    "peers                        - List connected peers with latency",
+    "files                        - List offered files and downloads in progress",
+    "download <file_id>           - Download a file offered by a connected peer",
+    "cancel <file_id>             - Cancel a download",
+    "status                       - Show node status",
+    "help                         - Show this help",
+];
@@ -0,0 +1,155 @@
+use std::io;
+use std::path::Path;
+use tokio::sync::{mpsc, oneshot};
+
+/// A console command received on the control socket, answered with the
+/// lines of output it produced
+pub struct ControlRequest {
+    pub command: String,
+    pub reply: oneshot::Sender<Vec<String>>,
+}
+
+/// Accept console commands on a Unix socket at `path`.
+///
+/// Clients send one command per line. Each response is the command's output,
+/// one line at a time, ended by an empty line.
+#[cfg(unix)]
+pub fn listen(path: &Path) -> io::Result<mpsc::Receiver<ControlRequest>> {
+    use tracing::warn;
+
+    // Left behind by a previous run that did not shut down cleanly
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = bind_private(path)?;
+
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, tx).await {
+                            warn!("Control connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Failed to accept control connection: {}", e),
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+/// Bind a socket at `path` that only this user can connect to. Anyone who
+/// can connect controls the node, so the socket is bound inside a private
+/// directory and only moved into place once its permissions are narrowed.
+#[cfg(unix)]
+fn bind_private(path: &Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let staging = parent.join(format!(".control.{:016x}", rand::random::<u64>()));
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("control.sock");
+    let bound = tokio::net::UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+    bound
+}
+
+#[cfg(not(unix))]
+pub fn listen(_path: &Path) -> io::Result<mpsc::Receiver<ControlRequest>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "control sockets need Unix domain sockets",
+    ))
+}
+
+#[cfg(unix)]
+async fn serve(
+    stream: tokio::net::UnixStream,
+    requests: mpsc::Sender<ControlRequest>,
+) -> io::Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    while let Some(command) = lines.next_line().await? {
+        let (reply, output) = oneshot::channel();
+        if requests
+            .send(ControlRequest { command, reply })
+            .await
+            .is_err()
+        {
+            break;
+        }
+        let Ok(output) = output.await else {
+            break;
+        };
+
+        let mut response = String::new();
+        for line in output.iter().filter(|line| !line.is_empty()) {
+            response.push_str(line);
+            response.push('\n');
+        }
+        response.push('\n');
+        write.write_all(response.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixStream;
+
+    #[tokio::test]
+    async fn test_control_socket_round_trip() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("control.sock");
+        let mut requests = listen(&path)?;
+
+        tokio::spawn(async move {
+            while let Some(request) = requests.recv().await {
+                let _ = request
+                    .reply
+                    .send(vec![format!("ran {}", request.command), String::new()]);
+            }
+        });
+
+        let (read, mut write) = UnixStream::connect(&path).await?.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"status\n").await?;
+        assert_eq!(lines.next_line().await?.as_deref(), Some("ran status"));
+        // Empty output lines are dropped so they cannot end the response early
+        assert_eq!(lines.next_line().await?.as_deref(), Some(""));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_control_socket_is_private() -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("control.sock");
+        let _requests = listen(&path)?;
+
+        let mode = std::fs::metadata(&path)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Nothing is left of the directory it was bound in
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        UnixStream::connect(&path).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use corelink_core::message::DiscoveryMessage;
+use corelink_core::Storage;
+use libp2p_identity::PeerId;
+use std::time::Duration;
+use tracing::warn;
+
+/// Bucket holding the last discovery message of each peer (peer id -> message)
+const DISCOVERY_BUCKET: &str = "discovery";
+
+/// How long a peer's discovery message is trusted after it was received
+const DISCOVERY_TTL: Duration = Duration::from_secs(3600);
+
+/// What peers last announced about themselves, so a peer that reconnects
+/// soon after is known before it announces itself again. Entries expire
+/// after [`DISCOVERY_TTL`].
+pub struct DiscoveryCache {
+    store: Storage,
+    ttl: Duration,
+}
+
+impl DiscoveryCache {
+    pub fn new(store: Storage) -> corelink_core::Result<Self> {
+        Ok(Self {
+            store: store.open_bucket(DISCOVERY_BUCKET)?,
+            ttl: DISCOVERY_TTL,
+        })
+    }
+
+    pub fn record(&self, peer: &PeerId, discovery: &DiscoveryMessage) {
+        if let Err(e) = self
+            .store
+            .insert_json_with_ttl(peer.to_string(), discovery, self.ttl)
+        {
+            warn!("Failed to cache discovery of {}: {}", peer, e);
+        }
+    }
+
+    /// The discovery message `peer` sent within the last [`DISCOVERY_TTL`]
+    pub fn get(&self, peer: &PeerId) -> Option<DiscoveryMessage> {
+        self.store
+            .get_json(&peer.to_string())
+            .inspect_err(|e| warn!("Failed to read cached discovery of {}: {}", peer, e))
+            .ok()
+            .flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn discovery() -> DiscoveryMessage {
+        DiscoveryMessage {
+            capabilities: vec!["storage".to_string()],
+            protocol_version: "1".to_string(),
+            labels: vec!["eu-west".to_string()],
+            peers: Vec::new(),
+            features: Vec::new(),
+            listen_addresses: Vec::new(),
+            free_bytes: Some(1024),
+        }
+    }
+
+    #[test]
+    fn test_discovery_expires() -> corelink_core::Result<()> {
+        let store = Storage::new();
+        let peer = PeerId::random();
+
+        let cache = DiscoveryCache::new(store.clone())?;
+        cache.record(&peer, &discovery());
+        let cached = DiscoveryCache::new(store.clone())?.get(&peer).unwrap();
+        assert_eq!(cached.labels, vec!["eu-west".to_string()]);
+        assert_eq!(cached.free_bytes, Some(1024));
+        assert!(cache.get(&PeerId::random()).is_none());
+
+        let cache = DiscoveryCache {
+            ttl: Duration::ZERO,
+            ..DiscoveryCache::new(store)?
+        };
+        cache.record(&peer, &discovery());
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get(&peer).is_none());
+        Ok(())
+    }
+}
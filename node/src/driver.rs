@@ -0,0 +1,1946 @@
+use crate::api::{
+    ApiCommand, ConsensusMetrics, DownloadInfo, FileDetail, FileInfo, FileStatus, FileVersion,
+    MessageMetrics, MessageReceipt, NetworkTopology, NodeInfo, NodeMetrics, NodeStats, OfferInfo,
+    PeerDetail, PeerDisconnect, PeerInfo, PeerTransfer, QueryAnswer, SourceInfo, StorageMetrics,
+    TopologyLink, TopologyNode, TrafficMetrics, TransferMetrics, TransfersReport, UploadInfo,
+    UploadPeer,
+};
+use crate::bridge::{ErrorCategory, NodeEvent, NodeStatus};
+use crate::config::{self, NodeConfig};
+use crate::console::Console;
+use crate::control::ControlRequest;
+use crate::file_transfer::{FileRemoval, TransferState, TransferSummary};
+use crate::health;
+use crate::logging::{Logging, AUDIT_TARGET};
+use crate::messaging_behaviour::{MessagingBehaviour, MessagingBehaviourEvent};
+use crate::offers::PendingOffer;
+use crate::outbound_queue::OverflowPolicy;
+use crate::partition::PartitionDetector;
+use crate::peer_registry::{PeerConnection, PeerRegistry};
+use crate::peer_store::PeerStore;
+use crate::rate_limit::RateLimiter;
+use crate::service;
+use crate::shared_folder::{SharedChange, SharedFolder};
+use crate::transfer_activity::ActivityState;
+use crate::websocket::EventHub;
+use corelink_core::file::FileMetadata;
+use corelink_core::identity::NodeId;
+use corelink_core::BlockStore;
+use futures::StreamExt;
+use libp2p::{
+    autonat, identify, mdns, ping, relay,
+    swarm::{
+        behaviour::toggle::Toggle,
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionId, DialError, SwarmEvent,
+    },
+    Multiaddr, PeerId, Swarm,
+};
+use notify::RecommendedWatcher;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{info, warn};
+
+#[derive(libp2p::swarm::NetworkBehaviour)]
+pub struct CoreLinkBehaviour {
+    pub ping: ping::Behaviour,
+    pub identify: identify::Behaviour,
+    pub autonat: autonat::Behaviour,
+    /// Relays connections for other peers on relay-only nodes
+    pub relay: Toggle<relay::Behaviour>,
+    /// Local network discovery; off when `mdns = false`
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
+    pub messaging: MessagingBehaviour,
+}
+
+/// How long to wait for peer connections to close on shutdown
+const DISCONNECT_GRACE: Duration = Duration::from_secs(5);
+
+/// How often running downloads are summarized in a
+/// [`NodeEvent::TransferProgress`]
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Owns the swarm and everything that touches it: network events, operator
+/// and API commands, and the timers driving discovery, replication and
+/// status. Reports what happens as [`NodeEvent`]s.
+pub struct SwarmDriver {
+    swarm: Swarm<CoreLinkBehaviour>,
+    config: NodeConfig,
+    /// Command line, re-read on config reloads
+    args: Vec<String>,
+    /// Lets config reloads change the log level
+    logger: Option<Logging>,
+    /// Lets config reloads change the REST API's rate limits
+    rate_limiter: Option<Arc<RateLimiter>>,
+    peer_store: PeerStore,
+    /// Open connections and identify info of connected peers
+    peers: PeerRegistry,
+    blocks: BlockStore,
+    events: broadcast::Sender<NodeEvent>,
+    commands: Option<mpsc::Receiver<ApiCommand>>,
+    control: Option<mpsc::Receiver<ControlRequest>>,
+    /// Operator commands typed on stdin; `None` once stdin is closed
+    stdin: Option<Lines<BufReader<Stdin>>>,
+    shared: Option<SharedFolder>,
+    shared_events: Option<mpsc::UnboundedReceiver<PathBuf>>,
+    _shared_watcher: Option<RecommendedWatcher>,
+    websocket: Option<JoinHandle<()>>,
+    event_hub: Option<EventHub>,
+    anchors: Vec<(PeerId, Multiaddr)>,
+    /// Static peers to stay connected to
+    bootstrap: Vec<(PeerId, Multiaddr)>,
+    partition: PartitionDetector,
+    /// Dials requested by an operator, until they connect or fail
+    pending_dials: HashMap<ConnectionId, PendingDial>,
+    /// File queries sent to peers, and where their answers go
+    queries: HashMap<u64, mpsc::Sender<QueryAnswer>>,
+    /// Application messages in flight, and who waits to hear how they went
+    message_receipts: HashMap<u64, oneshot::Sender<Result<(), String>>>,
+    /// Application messages held back under backpressure, oldest first
+    blocked_sends: VecDeque<BlockedSend>,
+    /// Downloads of files nobody offered, waiting to hear from the peers
+    /// asked for them
+    file_requests: HashMap<String, FileRequest>,
+    /// Bytes and activity of each download as last reported, to skip
+    /// progress reports that would say nothing new
+    reported_progress: HashMap<String, (u64, ActivityState)>,
+    /// Whether free space was below `health.min_free_bytes` at the last
+    /// status report
+    storage_low: bool,
+    start_time: Instant,
+    /// Flipped once the swarm has a listen address
+    listening: watch::Sender<bool>,
+    /// Service manager watchdog pings, sent from the event loop itself
+    watchdog: Option<time::Interval>,
+    gc_interval: time::Interval,
+    challenge_interval: time::Interval,
+}
+
+impl SwarmDriver {
+    pub fn new(
+        swarm: Swarm<CoreLinkBehaviour>,
+        config: NodeConfig,
+        args: Vec<String>,
+        peer_store: PeerStore,
+        blocks: BlockStore,
+        events: broadcast::Sender<NodeEvent>,
+    ) -> Self {
+        let anchors = config.partition.anchor_addresses();
+        let bootstrap = config::peer_addresses(&config.bootstrap_peers);
+        let partition = PartitionDetector::new(config.partition.clone());
+        // Scheduled garbage collection of unreferenced blocks and partial downloads
+        let gc_interval = delayed_interval(config.gc_interval_secs);
+        // Proof-of-storage challenges to holders of our files
+        let challenge_interval = delayed_interval(config.challenge_interval_secs);
+        Self {
+            swarm,
+            config,
+            args,
+            logger: None,
+            rate_limiter: None,
+            peer_store,
+            peers: PeerRegistry::new(),
+            blocks,
+            events,
+            commands: None,
+            control: None,
+            stdin: None,
+            shared: None,
+            shared_events: None,
+            _shared_watcher: None,
+            websocket: None,
+            event_hub: None,
+            anchors,
+            bootstrap,
+            partition,
+            pending_dials: HashMap::new(),
+            queries: HashMap::new(),
+            message_receipts: HashMap::new(),
+            blocked_sends: VecDeque::new(),
+            file_requests: HashMap::new(),
+            reported_progress: HashMap::new(),
+            storage_low: false,
+            start_time: Instant::now(),
+            listening: watch::channel(false).0,
+            watchdog: None,
+            gc_interval,
+            challenge_interval,
+        }
+    }
+
+    /// Apply log level changes on config reloads
+    pub fn with_logging(mut self, logger: Logging) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Apply REST API rate limit changes on config reloads
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Ping the service manager's watchdog every `period`, so it restarts
+    /// the node if this loop stalls
+    pub fn with_watchdog(mut self, period: Duration) -> Self {
+        self.watchdog = Some(time::interval(period));
+        self
+    }
+
+    /// Serve commands from the REST API
+    pub fn with_commands(mut self, commands: mpsc::Receiver<ApiCommand>) -> Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Serve console commands from the control socket
+    pub fn with_control(mut self, control: mpsc::Receiver<ControlRequest>) -> Self {
+        self.control = Some(control);
+        self
+    }
+
+    /// Read console commands from stdin
+    pub fn with_stdin(mut self) -> Self {
+        self.stdin = Some(BufReader::new(tokio::io::stdin()).lines());
+        self
+    }
+
+    /// Offer files that appear in `shared`, watching it for changes
+    pub fn with_shared_folder(mut self, shared: SharedFolder) -> io::Result<Self> {
+        let (watcher, events) = shared.watch()?;
+        self.shared = Some(shared);
+        self.shared_events = Some(events);
+        self._shared_watcher = Some(watcher);
+        Ok(self)
+    }
+
+    /// Report the standalone WebSocket server's task in health checks
+    pub fn with_websocket(mut self, server: JoinHandle<()>) -> Self {
+        self.websocket = Some(server);
+        self
+    }
+
+    /// Report WebSocket clients in stats and metrics
+    pub fn with_event_hub(mut self, hub: EventHub) -> Self {
+        self.event_hub = Some(hub);
+        self
+    }
+
+    /// Whether the swarm has a listen address yet
+    pub fn listening(&self) -> watch::Receiver<bool> {
+        self.listening.subscribe()
+    }
+
+    /// Dial bootstrap peers, remembered peers and anchors
+    pub fn dial_known_peers(&mut self) {
+        self.dial_bootstrap_peers();
+
+        // Reconnect to peers remembered from previous runs
+        for (peer_id, addr) in self.peer_store.known_addresses() {
+            info!("📒 Dialing known peer {} at {}", peer_id, addr);
+            if let Err(e) = self.swarm.dial(addr) {
+                info!("❌ Failed to dial {}: {:?}", peer_id, e);
+            }
+        }
+
+        // Anchors should always be reachable; losing them signals a partition
+        for (peer_id, addr) in &self.anchors {
+            info!("⚓ Dialing anchor peer {} at {}", peer_id, addr);
+            if let Err(e) = self.swarm.dial(addr.clone()) {
+                info!("❌ Failed to dial {}: {:?}", peer_id, e);
+            }
+        }
+    }
+
+    /// Drive the node until `shutdown` resolves
+    pub async fn run(&mut self, shutdown: impl Future<Output = ()>) {
+        // Discovery broadcast interval
+        let mut discovery_interval = time::interval(Duration::from_secs(10));
+        // Status broadcast interval (every 5 seconds)
+        let mut status_interval = time::interval(Duration::from_secs(5));
+        let mut shared_interval = time::interval(Duration::from_secs(1));
+        let mut progress_interval = time::interval(PROGRESS_INTERVAL);
+
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => {
+                    info!("🛑 Shutting down");
+                    return;
+                }
+                event = self.swarm.select_next_some() => self.handle_swarm_event(event),
+                _ = discovery_interval.tick() => self.discover(),
+                _ = status_interval.tick() => self.report_status(),
+                _ = progress_interval.tick() => self.report_progress(),
+                _ = self.gc_interval.tick() => {
+                    if let Err(e) = self.swarm.behaviour_mut().messaging.collect_garbage(false) {
+                        warn!("Scheduled garbage collection failed: {}", e);
+                    }
+                }
+                _ = tick(&mut self.watchdog) => service::notify_watchdog(),
+                _ = self.challenge_interval.tick() => {
+                    self.swarm.behaviour_mut().messaging.challenge_holders();
+                }
+                Some(path) = next_shared_event(&mut self.shared_events) => {
+                    if let Some(shared) = self.shared.as_mut() {
+                        shared.touch(&path);
+                    }
+                }
+                _ = shared_interval.tick(), if self.shared.is_some() => self.offer_shared_changes(),
+                Some(command) = next(&mut self.commands) => self.handle_command(command),
+                line = next_line(&mut self.stdin) => match line {
+                    Some(cmd) => {
+                        for line in self.console().run(&cmd) {
+                            info!("{}", line);
+                        }
+                    }
+                    // Stdin closed, e.g. when started by a service manager
+                    None => self.stdin = None,
+                },
+                Some(request) = next(&mut self.control) => {
+                    let _ = request.reply.send(self.console().run(&request.command));
+                }
+            }
+        }
+    }
+
+    /// Stop taking API commands; requests still in flight get "not accepting
+    /// commands"
+    pub fn close_commands(&mut self) {
+        self.commands = None;
+    }
+
+    /// Save transfer progress and known peers so the next run picks up
+    /// where we left off
+    pub fn flush(&self) {
+        if let Err(e) = self.swarm.behaviour().messaging.flush() {
+            warn!("Failed to save transfer state: {}", e);
+        }
+        if let Err(e) = self.peer_store.flush() {
+            warn!("Failed to save peer store: {}", e);
+        }
+    }
+
+    /// Close peer connections rather than dropping them
+    pub async fn disconnect(&mut self) {
+        let peers: Vec<_> = self.swarm.connected_peers().copied().collect();
+        for peer_id in peers {
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+        }
+        let swarm = &mut self.swarm;
+        let _ = time::timeout(DISCONNECT_GRACE, async {
+            while swarm.connected_peers().next().is_some() {
+                swarm.select_next_some().await;
+            }
+        })
+        .await;
+    }
+
+    fn console(&mut self) -> Console<'_> {
+        Console {
+            swarm: &mut self.swarm,
+            peer_store: &self.peer_store,
+            pending_dials: &mut self.pending_dials,
+            partition: &self.partition,
+            start_time: self.start_time,
+        }
+    }
+
+    fn emit(&self, event: NodeEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    fn handle_swarm_event(&mut self, event: SwarmEvent<CoreLinkBehaviourEvent>) {
+        let swarm = &mut self.swarm;
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("📍 Listening on {}", address);
+                self.listening.send_replace(true);
+            }
+            SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
+                for (peer_id, addr) in list {
+                    info!("🔍 Discovered peer: {} at {}", peer_id, addr);
+                    if let Err(e) = swarm.dial(addr.clone()) {
+                        info!("❌ Failed to dial {}: {:?}", peer_id, e);
+                    }
+                }
+            }
+            SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
+                for (peer_id, _) in list {
+                    info!("🕳️ Peer expired: {}", peer_id);
+                }
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            } => {
+                info!(
+                    "✅ Connection established with {} via {}",
+                    peer_id,
+                    endpoint.get_remote_address()
+                );
+
+                // Only addresses we dialed are worth redialing later, and
+                // manual dials only when asked to
+                let manual = self.pending_dials.remove(&connection_id);
+                let save = manual.as_ref().is_none_or(|dial| dial.save);
+                let dialable = endpoint
+                    .is_dialer()
+                    .then(|| endpoint.get_remote_address())
+                    .filter(|_| save);
+                self.peer_store.record_connection(&peer_id, dialable);
+                self.peers.connection_established(
+                    peer_id,
+                    connection_id,
+                    PeerConnection {
+                        address: endpoint.get_remote_address().clone(),
+                        established_at: current_timestamp(),
+                    },
+                );
+                if let Some(reply) = manual.and_then(|dial| dial.reply) {
+                    let _ = reply.send(Ok(peer_id.to_string()));
+                }
+
+                self.emit(NodeEvent::PeerConnected {
+                    peer_id: peer_id.to_string(),
+                    address: endpoint.get_remote_address().to_string(),
+                });
+            }
+            SwarmEvent::OutgoingConnectionError {
+                connection_id,
+                peer_id,
+                error,
+            } => {
+                // A peer answering with another identity than dialed is
+                // an authentication failure rather than a network one
+                let category = match error {
+                    DialError::WrongPeerId { .. } => ErrorCategory::Auth,
+                    _ => ErrorCategory::Connection,
+                };
+                self.emit(NodeEvent::Error {
+                    category,
+                    peer_id: peer_id.map(|peer| peer.to_string()),
+                    message: error.to_string(),
+                });
+                if let Some(dial) = self.pending_dials.remove(&connection_id) {
+                    info!("❌ Failed to connect to {}: {}", dial.target, error);
+                    if let Some(reply) = dial.reply {
+                        let _ = reply.send(Err(error.to_string()));
+                    }
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                cause,
+                ..
+            } => {
+                info!("❌ Connection closed with {}: {:?}", peer_id, cause);
+                self.peers.connection_closed(&peer_id, connection_id);
+                self.emit(NodeEvent::PeerDisconnected {
+                    peer_id: peer_id.to_string(),
+                });
+                self.release_blocked_sends();
+            }
+            SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Ping(ping::Event {
+                peer,
+                result,
+                ..
+            })) => match result {
+                Ok(rtt) => {
+                    info!("🏓 Ping to {}: {:?}", peer, rtt);
+                    self.peers.record_rtt(&peer, rtt, current_timestamp());
+                    swarm
+                        .behaviour()
+                        .messaging
+                        .network()
+                        .record_rtt(&NodeId::from_peer_id(&peer), rtt);
+                }
+                Err(e) => {
+                    info!("❌ Ping failed to {}: {:?}", peer, e);
+                    swarm.behaviour().messaging.record_peer_failure(&peer);
+                }
+            },
+            SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Identify(
+                identify::Event::Received { peer_id, info },
+            )) => {
+                info!("🆔 Identified {}: {:?}", peer_id, info.protocol_version);
+                self.peers.identified(&peer_id, &info);
+                swarm.behaviour_mut().messaging.set_peer_protocols(
+                    peer_id,
+                    info.protocol_version.clone(),
+                    &info.protocols,
+                );
+                swarm
+                    .behaviour_mut()
+                    .messaging
+                    .set_peer_addresses(peer_id, info.listen_addrs);
+                // Where the peer sees us; AutoNAT probes these to confirm them
+                swarm
+                    .behaviour()
+                    .messaging
+                    .network()
+                    .record_observed_address(
+                        &info.observed_addr.to_string(),
+                        &NodeId::from_peer_id(&peer_id),
+                    );
+            }
+            SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Autonat(
+                autonat::Event::StatusChanged { old, new },
+            )) => {
+                info!("🧭 NAT status changed from {:?} to {:?}", old, new);
+            }
+            SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Relay(event)) => {
+                info!("🔁 Relay: {:?}", event);
+            }
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                info!("🌍 Confirmed external address {}", address);
+                swarm
+                    .behaviour()
+                    .messaging
+                    .network()
+                    .confirm_external_address(&address.to_string());
+            }
+            SwarmEvent::ExternalAddrExpired { address } => {
+                info!("🌫️ External address expired: {}", address);
+                swarm
+                    .behaviour()
+                    .messaging
+                    .network()
+                    .expire_external_address(&address.to_string());
+            }
+            SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Messaging(event)) => {
+                self.handle_messaging_event(event)
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_messaging_event(&mut self, event: MessagingBehaviourEvent) {
+        match event {
+            MessagingBehaviourEvent::MessageReceived { from, message } => {
+                info!(
+                    "📬 Messaging event: MessageReceived from {}: {:?}",
+                    from, message.msg_type
+                );
+            }
+            MessagingBehaviourEvent::MessageSent { to, receipt } => {
+                info!("✅ Message sent to {}", to);
+                if let Some(receipt) = receipt {
+                    self.message_delivered(receipt, to, Ok(()));
+                }
+                self.release_blocked_sends();
+            }
+            MessagingBehaviourEvent::SendError {
+                to,
+                error,
+                receipt,
+                msg_type,
+                attempts,
+            } => {
+                info!("❌ Failed to send {} to {}: {}", msg_type, to, error);
+                self.emit(NodeEvent::Error {
+                    category: ErrorCategory::Protocol,
+                    peer_id: Some(to.to_string()),
+                    message: format!(
+                        "Failed to send {} message after {} attempts: {}",
+                        msg_type, attempts, error
+                    ),
+                });
+                if let Some(receipt) = receipt {
+                    self.message_delivered(receipt, to, Err(error));
+                }
+                self.release_blocked_sends();
+            }
+            MessagingBehaviourEvent::IncompatiblePeer { peer, reason } => {
+                self.emit(NodeEvent::IncompatiblePeer {
+                    peer_id: peer.to_string(),
+                    reason,
+                });
+            }
+            MessagingBehaviourEvent::MessagesDropped { to, count, reason } => {
+                warn!("Dropped {} message(s) to {}: {}", count, to, reason);
+                self.emit(NodeEvent::MessagesDropped {
+                    peer_id: to.to_string(),
+                    count,
+                    reason,
+                });
+            }
+            MessagingBehaviourEvent::BadSignature { from, msg_type } => {
+                self.emit(NodeEvent::Error {
+                    category: ErrorCategory::Auth,
+                    peer_id: Some(from.to_string()),
+                    message: format!("Badly signed {} message", msg_type),
+                });
+            }
+            MessagingBehaviourEvent::PeerMuted {
+                peer,
+                msg_type,
+                duration,
+            } => {
+                self.emit(NodeEvent::Error {
+                    category: ErrorCategory::Protocol,
+                    peer_id: Some(peer.to_string()),
+                    message: format!(
+                        "Muted for {}s after sending too many {} messages",
+                        duration.as_secs(),
+                        msg_type
+                    ),
+                });
+            }
+            MessagingBehaviourEvent::EpochChanged {
+                from,
+                previous,
+                epoch,
+            } => {
+                self.emit(NodeEvent::EpochChanged {
+                    peer_id: from.to_string(),
+                    previous_epoch: previous,
+                    epoch,
+                });
+            }
+            MessagingBehaviourEvent::StaleEpochRejected {
+                from,
+                msg_type,
+                epoch,
+                current,
+            } => {
+                self.emit(NodeEvent::StaleEpochRejected {
+                    peer_id: from.to_string(),
+                    msg_type,
+                    epoch,
+                    current_epoch: current,
+                });
+            }
+            MessagingBehaviourEvent::CustomMessage {
+                from,
+                msg_type,
+                payload,
+            } => {
+                info!("✉️ {} message from {}", msg_type, from);
+                self.emit(NodeEvent::MessageReceived {
+                    peer_id: from.to_string(),
+                    msg_type,
+                    payload,
+                });
+            }
+            MessagingBehaviourEvent::FileOffered {
+                peer,
+                metadata,
+                pending,
+            } => {
+                info!(
+                    "📁 File offered by {}: {} ({} bytes, {} chunks)",
+                    peer, metadata.name, metadata.size, metadata.total_chunks
+                );
+                let status = if pending {
+                    FileStatus::Pending
+                } else {
+                    FileStatus::Downloading
+                };
+                let file = self.file_info(&metadata, status, Some(peer));
+                self.emit(NodeEvent::FileOffered(file));
+            }
+            MessagingBehaviourEvent::OfferClosed { file_id, removal } => {
+                self.emit(NodeEvent::FileRemoved { file_id, removal });
+            }
+            MessagingBehaviourEvent::OfferRejected { peer, file_id } => {
+                self.emit(NodeEvent::OfferRejected {
+                    peer_id: peer.to_string(),
+                    file_id,
+                });
+            }
+            MessagingBehaviourEvent::ChunkReceived {
+                file_id,
+                chunk_index,
+                peer,
+                chunk_size,
+                bytes_received,
+                total_bytes,
+                chunks_remaining,
+                progress,
+                speed,
+            } => {
+                info!(
+                    "📦 Chunk {} received for {}: {:.1}%",
+                    chunk_index,
+                    file_id,
+                    progress * 100.0
+                );
+                self.emit(NodeEvent::ChunkReceived {
+                    file_id,
+                    chunk_index,
+                    peer_id: peer.to_string(),
+                    chunk_size,
+                    bytes_received,
+                    total_bytes,
+                    chunks_remaining,
+                    progress,
+                    speed,
+                });
+            }
+            MessagingBehaviourEvent::TransferComplete { metadata, path } => {
+                info!("✅ File transfer complete: {}", metadata.name);
+                self.emit(NodeEvent::TransferComplete {
+                    file_id: metadata.file_id,
+                    name: metadata.name,
+                    size: metadata.size,
+                    path,
+                });
+            }
+            MessagingBehaviourEvent::HoldersChanged { file_id, holders } => {
+                info!("🗂️ {} is held by {} peer(s)", file_id, holders.len());
+                self.emit(NodeEvent::HoldersChanged {
+                    file_id,
+                    holders: holders.iter().map(|p| p.to_string()).collect(),
+                });
+            }
+            MessagingBehaviourEvent::TransferFailed { file_id, reason } => {
+                info!("❌ File transfer failed {}: {}", file_id, reason);
+                self.emit(NodeEvent::TransferFailed { file_id, reason });
+            }
+            // Without mDNS, peers of peers are the only way to find more of the network
+            MessagingBehaviourEvent::PeersAdvertised { from, addresses } if !self.config.mdns => {
+                let local = *self.swarm.local_peer_id();
+                let mut by_peer: HashMap<PeerId, Vec<Multiaddr>> = HashMap::new();
+                for addr in addresses {
+                    match config::peer_of(&addr) {
+                        Some(peer) if peer != local && !self.swarm.is_connected(&peer) => {
+                            by_peer.entry(peer).or_default().push(addr)
+                        }
+                        _ => {}
+                    }
+                }
+                for (peer, addresses) in by_peer {
+                    info!("🤝 Dialing {}, learned from {}", peer, from);
+                    self.dial_peer(peer, addresses);
+                }
+            }
+            MessagingBehaviourEvent::PeersAdvertised { .. } => {}
+            MessagingBehaviourEvent::FileFound { peer, metadata } => {
+                if let Some(request) = self.file_requests.remove(&metadata.file_id) {
+                    let preferred = request.peer.or(Some(peer));
+                    let result = self.start_download(&metadata.file_id, preferred);
+                    let _ = request.reply.send(result);
+                }
+            }
+            MessagingBehaviourEvent::FileNotFound {
+                peer,
+                file_id,
+                error,
+            } => {
+                info!("{}", error);
+                let Some(request) = self.file_requests.get_mut(&file_id) else {
+                    return;
+                };
+                request.asked = request.asked.saturating_sub(1);
+                if request.asked == 0 {
+                    let request = self.file_requests.remove(&file_id).expect("just found");
+                    let message = match request.peer {
+                        Some(_) => format!("{} does not hold {}", peer, file_id),
+                        None => format!("No peer offers or holds {}", file_id),
+                    };
+                    let _ = request
+                        .reply
+                        .send(Err(io::Error::new(io::ErrorKind::NotFound, message)));
+                }
+            }
+            MessagingBehaviourEvent::QueryResults {
+                query_id,
+                peer,
+                files,
+            } => {
+                if let Some(answers) = self.queries.get(&query_id) {
+                    if let Err(TrySendError::Closed(_)) = answers.try_send((peer, files)) {
+                        // Whoever searched stopped waiting
+                        self.queries.remove(&query_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Start downloading an offered file and summarize the transfer
+    fn start_download(
+        &mut self,
+        file_id: &str,
+        peer: Option<PeerId>,
+    ) -> io::Result<TransferSummary> {
+        let messaging = &mut self.swarm.behaviour_mut().messaging;
+        let metadata = messaging.download(file_id, peer)?;
+        info!("🔽 Downloading {} ({} bytes)", metadata.name, metadata.size);
+        messaging
+            .transfer_summary(file_id)
+            .ok_or_else(|| io::Error::other(format!("Download of {} did not start", file_id)))
+    }
+
+    /// Ask `peer`, or the connected peers known to hold the file, for a file
+    /// nobody offered. The download starts once one of them has it.
+    fn request_file(
+        &mut self,
+        file_id: String,
+        peer: Option<PeerId>,
+        reply: oneshot::Sender<io::Result<TransferSummary>>,
+    ) {
+        if self.file_requests.contains_key(&file_id) {
+            let _ = reply.send(Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Already looking for {}", file_id),
+            )));
+            return;
+        }
+        let messaging = &mut self.swarm.behaviour_mut().messaging;
+        let candidates = match peer {
+            Some(peer) => vec![peer],
+            None => messaging.file_holders(&file_id),
+        };
+        let mut asked = 0;
+        let mut error = None;
+        for candidate in candidates {
+            match messaging.request_file(candidate, &file_id) {
+                Ok(()) => asked += 1,
+                Err(e) => error = Some(e),
+            }
+        }
+        if asked > 0 {
+            self.file_requests
+                .insert(file_id, FileRequest { peer, asked, reply });
+            return;
+        }
+        let error = match (peer, error) {
+            (Some(_), Some(error)) => error,
+            _ => io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No peer offers or holds {}", file_id),
+            ),
+        };
+        let _ = reply.send(Err(error));
+    }
+
+    fn file_info(
+        &self,
+        metadata: &FileMetadata,
+        status: FileStatus,
+        peer: Option<PeerId>,
+    ) -> FileInfo {
+        let messaging = &self.swarm.behaviour().messaging;
+        FileInfo {
+            file_id: metadata.file_id.clone(),
+            name: metadata.name.clone(),
+            size: metadata.size,
+            chunks: metadata.total_chunks,
+            progress: if status == FileStatus::Offering {
+                1.0
+            } else {
+                0.0
+            },
+            bytes_received: if status == FileStatus::Offering {
+                metadata.size
+            } else {
+                0
+            },
+            status,
+            peer_id: peer.map(|peer| peer.to_string()),
+            pinned: messaging.is_pinned(&metadata.file_id),
+            holders: messaging
+                .file_holders(&metadata.file_id)
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            version: metadata.version,
+            previous_file_id: metadata.previous_file_id.clone(),
+            path: None,
+            created_at: metadata.created_at,
+        }
+    }
+
+    /// Identity, measurements and operator tags of a connected peer
+    fn peer_info(&self, peer_id: &PeerId) -> PeerInfo {
+        let messaging = &self.swarm.behaviour().messaging;
+        let network = messaging.network();
+        let measured = network.get_peer(&NodeId::from_peer_id(peer_id));
+        let record = self.peer_store.get(peer_id);
+        let connected = self.peers.get(peer_id);
+        let identity = connected
+            .and_then(|c| c.identity.clone())
+            .unwrap_or_default();
+        let strings = |addresses: Vec<Multiaddr>| -> Vec<String> {
+            addresses.iter().map(|a| a.to_string()).collect()
+        };
+        PeerInfo {
+            peer_id: peer_id.to_string(),
+            addresses: strings(connected.map(|c| c.addresses()).unwrap_or_default()),
+            connected_since: connected.map_or(0, |c| c.connected_since()),
+            protocol_version: identity.protocol_version,
+            agent_version: identity.agent_version,
+            listen_addresses: match identity.listen_addresses {
+                // Peers that never identified may still have advertised some
+                addresses if addresses.is_empty() => measured
+                    .as_ref()
+                    .map(|p| p.listen_addresses.clone())
+                    .unwrap_or_default(),
+                addresses => strings(addresses),
+            },
+            protocols: identity.protocols,
+            reputation: messaging.peer_reputation(peer_id),
+            rtt_ms: measured
+                .as_ref()
+                .and_then(|p| p.rtt)
+                .map(|rtt| rtt.as_secs_f64() * 1000.0),
+            throughput: measured.as_ref().and_then(|p| p.throughput),
+            failures: measured.as_ref().map_or(0, |p| p.failures),
+            bytes_sent: measured.as_ref().map_or(0, |p| p.bytes_sent),
+            bytes_received: measured.as_ref().map_or(0, |p| p.bytes_received),
+            labels: measured
+                .as_ref()
+                .map(|p| p.labels.clone())
+                .unwrap_or_default(),
+            free_bytes: measured.as_ref().and_then(|p| p.free_bytes),
+            capabilities: measured.map(|p| p.capabilities).unwrap_or_default(),
+            features: messaging.peer_features(peer_id),
+            tags: record.map(|r| r.tags.clone()).unwrap_or_default(),
+            note: record.and_then(|r| r.note.clone()),
+        }
+    }
+
+    /// Everything known about a connected peer
+    fn peer_detail(&self, peer_id: &PeerId) -> Option<PeerDetail> {
+        let connected = self.peers.get(peer_id)?;
+        let messaging = &self.swarm.behaviour().messaging;
+        let peer = peer_id.to_string();
+        let transfers = messaging
+            .transfer_summaries()
+            .into_iter()
+            .filter(|transfer| transfer.sources.contains(&peer))
+            .map(|transfer| {
+                let delivered = messaging
+                    .transfer_activity(&transfer.file_id)
+                    .and_then(|activity| activity.source(peer_id))
+                    .copied()
+                    .unwrap_or_default();
+                PeerTransfer {
+                    requests_in_flight: messaging
+                        .requests_in_flight(&transfer.file_id)
+                        .get(peer_id)
+                        .copied()
+                        .unwrap_or_default(),
+                    file_id: transfer.file_id,
+                    name: transfer.name,
+                    state: transfer.state,
+                    chunks_received: delivered.chunks_received,
+                    bytes_received: delivered.bytes_received,
+                }
+            })
+            .collect();
+        Some(PeerDetail {
+            peer: self.peer_info(peer_id),
+            rtt_history: connected.rtt_history.iter().cloned().collect(),
+            transfers,
+        })
+    }
+
+    /// Everything known about a file this node offers, downloads or downloaded
+    fn file_detail(&self, file_id: &str) -> Option<FileDetail> {
+        let messaging = &self.swarm.behaviour().messaging;
+        let metadata = messaging.file_metadata(file_id)?;
+        let transfer = messaging.transfer_summary(file_id);
+        let state = transfer.as_ref().map(|transfer| transfer.state);
+        let status = match state {
+            Some(TransferState::Queued | TransferState::Downloading) => FileStatus::Downloading,
+            Some(TransferState::Complete) => FileStatus::Complete,
+            None => FileStatus::Offering,
+        };
+        let current_sources = messaging.download_sources(file_id);
+        let mut file = self.file_info(&metadata, status, current_sources.first().copied());
+        if let Some(transfer) = &transfer {
+            file.progress = transfer.progress;
+            file.bytes_received = transfer.bytes_received;
+        }
+        file.path = messaging
+            .completed_path(file_id)
+            .map(|path| path.display().to_string());
+
+        let activity = messaging.transfer_activity(file_id);
+        let sources = self.download_sources(file_id);
+
+        let bitmap = messaging.chunk_bitmap(file_id).unwrap_or_default();
+        let downloading = state == Some(TransferState::Downloading);
+        Some(FileDetail {
+            chunk_size: metadata.chunk_size,
+            mime_type: metadata.mime_type.clone(),
+            queued: state == Some(TransferState::Queued),
+            chunks_received: bitmap.iter().map(|byte| byte.count_ones()).sum(),
+            chunk_bitmap: hex::encode(&bitmap),
+            sources,
+            speed: activity.map_or(0.0, |activity| activity.speed()),
+            eta_seconds: activity.filter(|_| downloading).and_then(|activity| {
+                activity.eta(metadata.size.saturating_sub(file.bytes_received))
+            }),
+            errors: activity
+                .into_iter()
+                .flat_map(|activity| activity.errors())
+                .cloned()
+                .collect(),
+            file,
+        })
+    }
+
+    /// Peers a download fetches or fetched from, current ones first
+    fn download_sources(&self, file_id: &str) -> Vec<SourceInfo> {
+        let messaging = &self.swarm.behaviour().messaging;
+        let activity = messaging.transfer_activity(file_id);
+        let current_sources = messaging.download_sources(file_id);
+        let in_flight = messaging.requests_in_flight(file_id);
+        let past_sources = activity
+            .into_iter()
+            .flat_map(|activity| activity.sources())
+            .map(|(peer, _)| *peer)
+            .filter(|peer| !current_sources.contains(peer));
+        current_sources
+            .iter()
+            .copied()
+            .chain(past_sources)
+            .map(|peer| {
+                let delivered = activity
+                    .and_then(|activity| activity.source(&peer))
+                    .copied()
+                    .unwrap_or_default();
+                SourceInfo {
+                    peer_id: peer.to_string(),
+                    active: current_sources.contains(&peer),
+                    chunks_received: delivered.chunks_received,
+                    bytes_received: delivered.bytes_received,
+                    requests_in_flight: in_flight.get(&peer).copied().unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+
+    /// Tell the API and WebSocket clients how sending an application
+    /// message went
+    fn message_delivered(&mut self, message_id: u64, peer: PeerId, outcome: Result<(), String>) {
+        self.emit(NodeEvent::MessageDelivery {
+            message_id,
+            peer_id: peer.to_string(),
+            error: outcome.clone().err(),
+        });
+        if let Some(waiter) = self.message_receipts.remove(&message_id) {
+            let _ = waiter.send(outcome);
+        }
+    }
+
+    /// Queue held-back application messages whose peer has room now, in
+    /// the order they were sent. Outside backpressure none are held back.
+    fn release_blocked_sends(&mut self) {
+        let mut still_blocked = HashSet::new();
+        for send in std::mem::take(&mut self.blocked_sends) {
+            // The sender gave up waiting
+            if send.reply.is_closed() {
+                continue;
+            }
+            let messaging = &mut self.swarm.behaviour_mut().messaging;
+            if still_blocked.contains(&send.peer_id)
+                || (messaging.queue_limits().overflow == OverflowPolicy::Backpressure
+                    && !messaging.can_send(&send.peer_id))
+            {
+                still_blocked.insert(send.peer_id);
+                self.blocked_sends.push_back(send);
+                continue;
+            }
+            let result = messaging
+                .send_custom(send.peer_id, send.msg_type, send.payload)
+                .map(|message_id| {
+                    let (waiter, outcome) = oneshot::channel();
+                    self.message_receipts.insert(message_id, waiter);
+                    MessageReceipt {
+                        message_id,
+                        outcome,
+                    }
+                });
+            let _ = send.reply.send(result);
+        }
+    }
+
+    /// This node, connected peers and the peers they advertised, linked as
+    /// they are connected
+    fn topology(&self) -> NetworkTopology {
+        let local = *self.swarm.local_peer_id();
+        let connected: HashSet<PeerId> = self.swarm.connected_peers().copied().collect();
+        let mut links: BTreeSet<(PeerId, PeerId)> = BTreeSet::new();
+        let mut link = |a: PeerId, b: PeerId| {
+            links.insert((a.min(b), a.max(b)));
+        };
+        for &peer in &connected {
+            link(local, peer);
+        }
+        for (&peer, linked) in self.swarm.behaviour().messaging.peer_links() {
+            for &other in linked {
+                link(peer, other);
+            }
+        }
+
+        let mut peers: BTreeSet<PeerId> = links.iter().flat_map(|&(a, b)| [a, b]).collect();
+        peers.insert(local);
+        NetworkTopology {
+            nodes: peers
+                .into_iter()
+                .map(|peer| TopologyNode {
+                    peer_id: peer.to_string(),
+                    local: peer == local,
+                    connected: connected.contains(&peer),
+                })
+                .collect(),
+            links: links
+                .into_iter()
+                .map(|(source, target)| TopologyLink {
+                    source: source.to_string(),
+                    target: target.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Every counter and gauge but those of the block store, which take a
+    /// listing to compute
+    fn metrics(&self) -> NodeMetrics {
+        let messaging = &self.swarm.behaviour().messaging;
+        let traffic = messaging.network().traffic();
+        let (offered_files, active_downloads, queued_downloads) = messaging.transfer_counts();
+        let uploads: Vec<_> = messaging.upload_activity().collect();
+        NodeMetrics {
+            timestamp: current_timestamp(),
+            uptime_seconds: self.start_time.elapsed().as_secs(),
+            traffic: TrafficMetrics {
+                connected_peers: self.swarm.connected_peers().count(),
+                bytes_sent: traffic.sent,
+                bytes_received: traffic.received,
+            },
+            transfers: TransferMetrics {
+                offered_files,
+                active_uploads: messaging.active_uploads(),
+                active_downloads,
+                queued_downloads,
+                completed_downloads: messaging.completed_downloads_count(),
+                chunk_requests_in_flight: messaging.chunk_requests_in_flight(),
+                upload_speed: uploads.iter().map(|(_, upload)| upload.speed()).sum(),
+                download_speed: messaging
+                    .transfer_summaries()
+                    .iter()
+                    .filter_map(|transfer| messaging.transfer_activity(&transfer.file_id))
+                    .map(|activity| activity.recent_speed())
+                    .sum(),
+            },
+            cache: messaging.cache_stats(),
+            storage: StorageMetrics::default(),
+            consensus: ConsensusMetrics {
+                epoch: messaging.current_epoch(),
+                stale_messages: messaging.stale_messages(),
+            },
+            messages: MessageMetrics {
+                queued: messaging.queued_messages(),
+                blocked: self.blocked_sends.len(),
+                dropped: messaging.dropped_messages(),
+            },
+            behaviour: messaging.stats().clone(),
+            websocket: self
+                .event_hub
+                .as_ref()
+                .map(EventHub::metrics)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Summarize downloads that moved or changed state since they were
+    /// last reported
+    fn report_progress(&mut self) {
+        let downloads = self.live_downloads();
+        let mut reported = HashMap::with_capacity(downloads.len());
+        for download in downloads {
+            let file_id = download.transfer.file_id.clone();
+            let progress = (download.transfer.bytes_received, download.activity);
+            if self.reported_progress.get(&file_id) != Some(&progress) {
+                self.emit(NodeEvent::TransferProgress(Box::new(download)));
+            }
+            reported.insert(file_id, progress);
+        }
+        self.reported_progress = reported;
+    }
+
+    fn live_downloads(&self) -> Vec<DownloadInfo> {
+        let messaging = &self.swarm.behaviour().messaging;
+        messaging
+            .transfer_summaries()
+            .into_iter()
+            .map(|transfer| {
+                let activity = messaging.transfer_activity(&transfer.file_id);
+                let state = match transfer.state {
+                    TransferState::Queued => ActivityState::Paused,
+                    _ => activity.map_or(ActivityState::Active, |activity| activity.state()),
+                };
+                let remaining = transfer.size.saturating_sub(transfer.bytes_received);
+                DownloadInfo {
+                    activity: state,
+                    speed: activity.map_or(0.0, |activity| activity.recent_speed()),
+                    eta_seconds: activity
+                        .filter(|_| state == ActivityState::Active)
+                        .and_then(|activity| activity.recent_eta(remaining)),
+                    peers: self.download_sources(&transfer.file_id),
+                    transfer,
+                }
+            })
+            .collect()
+    }
+
+    /// Speed, state and peers of the uploads and downloads in progress
+    fn live_transfers(&self) -> TransfersReport {
+        let messaging = &self.swarm.behaviour().messaging;
+        let downloads = self.live_downloads();
+        let mut uploads: Vec<UploadInfo> = messaging
+            .upload_activity()
+            .filter_map(|(file_id, upload)| {
+                let metadata = messaging.file_metadata(file_id)?;
+                Some(UploadInfo {
+                    file_id: file_id.clone(),
+                    name: metadata.name,
+                    size: metadata.size,
+                    activity: upload.state(),
+                    speed: upload.speed(),
+                    bytes_sent: upload.bytes_sent(),
+                    peers: upload
+                        .peers()
+                        .map(|(peer, sent)| UploadPeer {
+                            peer_id: peer.to_string(),
+                            chunks_sent: sent.chunks_sent,
+                            bytes_sent: sent.bytes_sent,
+                        })
+                        .collect(),
+                })
+            })
+            .collect();
+        uploads.sort_by(|a, b| a.name.cmp(&b.name));
+        TransfersReport { uploads, downloads }
+    }
+
+    fn discover(&mut self) {
+        let connected_peers = self.swarm.connected_peers().count();
+        if connected_peers > 0 {
+            info!("📡 Broadcasting discovery to {} peers", connected_peers);
+            let free_bytes = fs2::available_space(&self.config.storage_path).ok();
+            let messaging = &mut self.swarm.behaviour_mut().messaging;
+            messaging.set_free_bytes(free_bytes);
+            messaging.broadcast_discovery();
+        } else {
+            info!("⏳ No peers connected yet, waiting for discovery...");
+        }
+
+        // Top up under-replicated files
+        self.swarm.behaviour_mut().messaging.replicate();
+
+        // Re-request chunks whose requests went unanswered
+        self.swarm
+            .behaviour_mut()
+            .messaging
+            .request_missing_chunks();
+
+        self.swarm.behaviour_mut().messaging.expire_offers();
+
+        self.dial_bootstrap_peers();
+    }
+
+    /// Dial the bootstrap peers we are not connected to
+    fn dial_bootstrap_peers(&mut self) {
+        for (peer_id, addr) in self.bootstrap.clone() {
+            if self.swarm.is_connected(&peer_id) {
+                continue;
+            }
+            info!("🥾 Dialing bootstrap peer {} at {}", peer_id, addr);
+            self.dial_peer(peer_id, vec![addr]);
+        }
+    }
+
+    /// Dial `peer` unless already connected or dialing it
+    fn dial_peer(&mut self, peer: PeerId, addresses: Vec<Multiaddr>) {
+        let opts = DialOpts::peer_id(peer)
+            .condition(PeerCondition::DisconnectedAndNotDialing)
+            .addresses(addresses)
+            .build();
+        if let Err(e) = self.swarm.dial(opts) {
+            if !matches!(e, DialError::DialPeerConditionFalse(_)) {
+                info!("❌ Failed to dial {}: {}", peer, e);
+            }
+        }
+    }
+
+    fn report_status(&mut self) {
+        let peer_count = self.swarm.connected_peers().count();
+
+        // Watch for a sudden loss of anchors or peers
+        let connected: HashSet<_> = self.swarm.connected_peers().copied().collect();
+        if let Some(status) = self.partition.check(&connected) {
+            if status.suspected {
+                warn!(
+                    "🪓 Network partition suspected: connectivity {:.0}%, unreachable anchors {:?}",
+                    status.connectivity * 100.0,
+                    status.unreachable_anchors
+                );
+            } else {
+                info!("🩹 Network partition healed");
+            }
+            self.emit(NodeEvent::PartitionChanged(status));
+        }
+        if self.partition.status().suspected {
+            // Keep trying the anchors so we notice when the partition heals
+            for (peer_id, addr) in self
+                .anchors
+                .iter()
+                .filter(|(peer_id, _)| !connected.contains(peer_id))
+            {
+                if let Err(e) = self.swarm.dial(addr.clone()) {
+                    info!("❌ Failed to dial anchor {}: {:?}", peer_id, e);
+                }
+            }
+        }
+
+        // Warn once when free space runs below the health threshold, and
+        // once more when it recovers
+        let min_free_bytes = self.config.health.min_free_bytes;
+        if let Ok(free_bytes) = fs2::available_space(&self.config.storage_path) {
+            let low = free_bytes < min_free_bytes;
+            if low != self.storage_low {
+                self.storage_low = low;
+                if low {
+                    warn!(
+                        "💾 Storage low: {} bytes free, want {}",
+                        free_bytes, min_free_bytes
+                    );
+                }
+                self.emit(NodeEvent::StorageChanged {
+                    low,
+                    free_bytes,
+                    min_free_bytes,
+                });
+            }
+        }
+
+        let swarm = &self.swarm;
+        let messaging = &swarm.behaviour().messaging;
+        let network = messaging.network();
+        let traffic = network.traffic();
+        let (_, active_downloads, queued_downloads) = messaging.transfer_counts();
+        let websocket = self
+            .event_hub
+            .as_ref()
+            .map(EventHub::metrics)
+            .unwrap_or_default();
+        let stats = NodeStats {
+            peer_count,
+            active_uploads: messaging.active_uploads(),
+            active_downloads,
+            queued_downloads,
+            uptime_seconds: self.start_time.elapsed().as_secs(),
+            bytes_sent: traffic.sent,
+            bytes_received: traffic.received,
+            partition_suspected: self.partition.status().suspected,
+            websocket_clients: websocket.clients.len(),
+            websocket_events_dropped: websocket.events_dropped,
+        };
+
+        let (external, candidates): (Vec<_>, Vec<_>) = network
+            .external_addresses()
+            .into_iter()
+            .partition(|entry| entry.confirmed);
+        let node = NodeInfo {
+            peer_id: swarm.local_peer_id().to_string(),
+            listen_addresses: swarm.listeners().map(|a| a.to_string()).collect(),
+            external_addresses: external.into_iter().map(|e| e.address).collect(),
+            candidate_addresses: candidates.into_iter().map(|e| e.address).collect(),
+            nat_status: match swarm.behaviour().autonat.nat_status() {
+                autonat::NatStatus::Public(_) => "public",
+                autonat::NatStatus::Private => "private",
+                autonat::NatStatus::Unknown => "unknown",
+            }
+            .to_string(),
+        };
+
+        let peers = swarm
+            .connected_peers()
+            .map(|peer_id| self.peer_info(peer_id))
+            .collect();
+
+        let health = health::check(
+            &self.config.health,
+            &health::HealthInputs {
+                listen_addresses: swarm.listeners().count(),
+                // Nodes started without servers have none to fail
+                websocket_running: self
+                    .websocket
+                    .as_ref()
+                    .is_none_or(|server| !server.is_finished()),
+                peer_count,
+                storage_path: &self.config.storage_path,
+            },
+        );
+
+        self.emit(NodeEvent::Status(Box::new(NodeStatus {
+            node,
+            stats,
+            peers,
+            replication: messaging.replication_health(),
+            health,
+        })));
+    }
+
+    fn offer_shared_changes(&mut self) {
+        let Some(shared) = self.shared.as_mut() else {
+            return;
+        };
+        let messaging = &mut self.swarm.behaviour_mut().messaging;
+        for change in shared.settled() {
+            match change {
+                SharedChange::Offer { path, replaces } => {
+                    // The new version links to the old one, which is then withdrawn
+                    match messaging.offer_file_version(&path, replaces.as_deref()) {
+                        Ok(metadata) => shared.record_offer(&path, &metadata.file_id),
+                        Err(e) => warn!("Failed to offer {}: {}", path.display(), e),
+                    }
+                    if let Some(old) = replaces {
+                        messaging.withdraw_file(&old);
+                    }
+                }
+                SharedChange::Withdraw { path, file_id } => {
+                    info!("🗑️ {} removed from the shared folder", path.display());
+                    messaging.withdraw_file(&file_id);
+                    shared.forget(&path);
+                }
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: ApiCommand) {
+        match command {
+            ApiCommand::SetPinned {
+                file_id,
+                pinned,
+                reply,
+            } => {
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .messaging
+                    .set_pinned(&file_id, pinned)
+                    .map_err(|e| e.to_string());
+                let _ = reply.send(result);
+            }
+            ApiCommand::CollectGarbage { dry_run, reply } => {
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .messaging
+                    .collect_garbage(dry_run)
+                    .map_err(|e| e.to_string());
+                let _ = reply.send(result);
+            }
+            ApiCommand::FileVersions { file_id, reply } => {
+                let versions = self
+                    .swarm
+                    .behaviour()
+                    .messaging
+                    .version_history(&file_id)
+                    .into_iter()
+                    .map(|metadata| FileVersion {
+                        file_id: metadata.file_id,
+                        name: metadata.name,
+                        size: metadata.size,
+                        version: metadata.version,
+                        previous_file_id: metadata.previous_file_id,
+                        created_at: metadata.created_at,
+                    })
+                    .collect();
+                let _ = reply.send(versions);
+            }
+            ApiCommand::SetPeerTags {
+                peer_id,
+                tags,
+                note,
+                reply,
+            } => {
+                let result = match peer_id.parse::<PeerId>() {
+                    Ok(peer) => {
+                        self.peer_store.set_tags(&peer, tags, note);
+                        let tags = self
+                            .peer_store
+                            .get(&peer)
+                            .map(|r| r.tags.clone())
+                            .unwrap_or_default();
+                        info!("🏷️ Tagged {} with {:?}", peer, tags);
+                        self.swarm
+                            .behaviour_mut()
+                            .messaging
+                            .set_peer_tags(peer, tags);
+                        Ok(())
+                    }
+                    Err(e) => Err(format!("Invalid peer id {}: {}", peer_id, e)),
+                };
+                let _ = reply.send(result);
+            }
+            ApiCommand::Dial {
+                address,
+                save,
+                reply,
+            } => {
+                info!("📞 Dialing {}", address);
+                if let Err(e) = dial(
+                    &mut self.swarm,
+                    &mut self.pending_dials,
+                    address,
+                    save,
+                    Some(reply),
+                ) {
+                    info!("❌ {}", e);
+                }
+            }
+            ApiCommand::ConnectPeer {
+                peer_id,
+                save,
+                reply,
+            } => {
+                if self.swarm.is_connected(&peer_id) {
+                    let _ = reply.send(Ok(peer_id.to_string()));
+                } else {
+                    let addresses = self
+                        .peer_store
+                        .get(&peer_id)
+                        .map(|record| {
+                            record
+                                .addresses
+                                .iter()
+                                .filter_map(|address| address.parse().ok())
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    info!("📞 Dialing {}", peer_id);
+                    if let Err(e) = dial_peer(
+                        &mut self.swarm,
+                        &mut self.pending_dials,
+                        peer_id,
+                        addresses,
+                        save,
+                        Some(reply),
+                    ) {
+                        info!("❌ {}", e);
+                    }
+                }
+            }
+            ApiCommand::PeerDetail { peer_id, reply } => {
+                let _ = reply.send(self.peer_detail(&peer_id));
+            }
+            ApiCommand::DisconnectPeer {
+                peer_id,
+                ban,
+                ban_secs,
+                reply,
+            } => {
+                let banned_until = ban.then(|| {
+                    let secs = ban_secs.unwrap_or(self.config.ban_duration_secs);
+                    self.swarm
+                        .behaviour_mut()
+                        .messaging
+                        .ban_peer(peer_id, Duration::from_secs(secs));
+                    current_timestamp().saturating_add(secs)
+                });
+                let disconnected = self.swarm.disconnect_peer_id(peer_id).is_ok();
+                info!(
+                    target: AUDIT_TARGET,
+                    peer = %peer_id,
+                    disconnected,
+                    banned_until,
+                    "🔌 Peer disconnected through the API"
+                );
+                let _ = reply.send(PeerDisconnect {
+                    disconnected,
+                    banned_until,
+                });
+            }
+            ApiCommand::QueryPeers {
+                query,
+                limit,
+                reply,
+            } => {
+                self.queries.retain(|_, answers| !answers.is_closed());
+                let query_id = rand::random();
+                let asked = self
+                    .swarm
+                    .behaviour_mut()
+                    .messaging
+                    .query_files(query_id, &query, limit);
+                let (answers, receiver) = mpsc::channel(asked.max(1));
+                if asked > 0 {
+                    self.queries.insert(query_id, answers);
+                }
+                let _ = reply.send((asked, receiver));
+            }
+            ApiCommand::SendMessage {
+                peer_id,
+                msg_type,
+                payload,
+                reply,
+            } => {
+                self.message_receipts
+                    .retain(|_, waiter| !waiter.is_closed());
+                self.blocked_sends.push_back(BlockedSend {
+                    peer_id,
+                    msg_type,
+                    payload,
+                    reply,
+                });
+                self.release_blocked_sends();
+            }
+            ApiCommand::OfferFile { path, reply } => {
+                let result = match self.swarm.behaviour_mut().messaging.offer_file(&path) {
+                    Ok(metadata) => {
+                        let file = self.file_info(&metadata, FileStatus::Offering, None);
+                        self.emit(NodeEvent::FileAdded(file.clone()));
+                        Ok(file)
+                    }
+                    Err(e) => Err(io::Error::new(
+                        e.kind(),
+                        format!("Failed to offer {}: {}", path.display(), e),
+                    )),
+                };
+                let _ = reply.send(result);
+            }
+            ApiCommand::Download {
+                file_id,
+                peer,
+                reply,
+            } => {
+                if self.swarm.behaviour().messaging.is_offered(&file_id) {
+                    let _ = reply.send(self.start_download(&file_id, peer));
+                } else {
+                    self.request_file(file_id, peer, reply);
+                }
+            }
+            ApiCommand::Offers { reply } => {
+                let offers = self
+                    .swarm
+                    .behaviour()
+                    .messaging
+                    .pending_offers()
+                    .into_iter()
+                    .map(offer_info)
+                    .collect();
+                let _ = reply.send(offers);
+            }
+            ApiCommand::AcceptOffer { file_id, reply } => {
+                let messaging = &mut self.swarm.behaviour_mut().messaging;
+                let result = messaging.accept_offer(&file_id).and_then(|metadata| {
+                    info!("🔽 Accepted {} ({} bytes)", metadata.name, metadata.size);
+                    messaging
+                        .transfer_summary(&file_id)
+                        .map(|transfer| (metadata, transfer))
+                        .ok_or_else(|| {
+                            io::Error::other(format!("Download of {} did not start", file_id))
+                        })
+                });
+                let result = result.map(|(metadata, transfer)| {
+                    let file = self.file_info(&metadata, FileStatus::Downloading, None);
+                    self.emit(NodeEvent::FileAdded(file));
+                    transfer
+                });
+                let _ = reply.send(result);
+            }
+            ApiCommand::RejectOffer { file_id, reply } => {
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .messaging
+                    .reject_offer(&file_id)
+                    .map(|offer| offer_info(&offer));
+                if result.is_ok() {
+                    self.emit(NodeEvent::FileRemoved {
+                        file_id,
+                        removal: FileRemoval::Rejected,
+                    });
+                }
+                let _ = reply.send(result);
+            }
+            ApiCommand::CancelDownload { file_id, reply } => {
+                let result = self
+                    .swarm
+                    .behaviour_mut()
+                    .messaging
+                    .cancel_download(&file_id);
+                if result.is_ok() {
+                    self.emit(NodeEvent::FileRemoved {
+                        file_id,
+                        removal: FileRemoval::Cancelled,
+                    });
+                }
+                let _ = reply.send(result);
+            }
+            ApiCommand::RemoveFile { file_id, reply } => {
+                let result = self.swarm.behaviour_mut().messaging.remove_file(&file_id);
+                if let Ok(removal) = result {
+                    self.emit(NodeEvent::FileRemoved { file_id, removal });
+                }
+                let _ = reply.send(result);
+            }
+            ApiCommand::FileDetail { file_id, reply } => {
+                let _ = reply.send(self.file_detail(&file_id));
+            }
+            ApiCommand::Transfer { file_id, reply } => {
+                let _ = reply.send(self.swarm.behaviour().messaging.transfer_summary(&file_id));
+            }
+            ApiCommand::ReloadConfig { reply } => {
+                let result = self.reload_config();
+                match &result {
+                    Ok(restart_required) if restart_required.is_empty() => {
+                        info!("🔄 Reloaded config");
+                    }
+                    Ok(restart_required) => warn!(
+                        "🔄 Reloaded config; changes to {} need a restart",
+                        restart_required.join(", ")
+                    ),
+                    Err(e) => warn!("Config reload failed: {}", e),
+                }
+                let _ = reply.send(result);
+            }
+            ApiCommand::Transfers { reply } => {
+                let _ = reply.send(self.swarm.behaviour().messaging.transfer_summaries());
+            }
+            ApiCommand::LiveTransfers { reply } => {
+                let _ = reply.send(self.live_transfers());
+            }
+            ApiCommand::Metrics { reply } => {
+                let mut metrics = self.metrics();
+                // Lists every block, so keep it off the event loop
+                let blocks = self.blocks.clone();
+                let storage_path = self.config.storage_path.clone();
+                tokio::task::spawn_blocking(move || {
+                    let result = blocks.list().map(|list| {
+                        metrics.storage = StorageMetrics {
+                            blocks: list.len(),
+                            stored_bytes: list.iter().map(|(_, size)| size).sum(),
+                            free_bytes: fs2::available_space(&storage_path).ok(),
+                        };
+                        metrics
+                    });
+                    let _ = reply.send(result.map_err(|e| e.to_string()));
+                });
+            }
+            ApiCommand::Topology { reply } => {
+                let _ = reply.send(self.topology());
+            }
+            ApiCommand::StorageUsage { reply } => {
+                // Reads every block, so keep it off the event loop
+                let blocks = self.blocks.clone();
+                tokio::task::spawn_blocking(move || {
+                    let _ = reply.send(blocks.usage().map_err(|e| e.to_string()));
+                });
+            }
+        }
+    }
+
+    /// Re-read the config file and apply the settings that can change while
+    /// running. Returns the changed settings that need a restart.
+    fn reload_config(&mut self) -> Result<Vec<String>, String> {
+        if config::arg_value(&self.args, "--config").is_none() {
+            return Err("No config file to reload; start the node with --config".to_string());
+        }
+        let new = NodeConfig::from_args(&self.args).map_err(|e| e.to_string())?;
+        let old = self.config.clone();
+        let restart_required = self.config.reload(new).map_err(|e| e.to_string())?;
+
+        if let Some(logger) = self.logger.as_ref() {
+            if self.config.logging.level != old.logging.level {
+                if let Err(e) = logger.set_level(&self.config.logging.level) {
+                    self.config = old;
+                    return Err(format!("Invalid log level: {}", e));
+                }
+            }
+        }
+        let config = &self.config;
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.set_config(config.api_rate_limit.clone());
+        }
+        if config.gc_interval_secs != old.gc_interval_secs {
+            self.gc_interval = delayed_interval(config.gc_interval_secs);
+        }
+        if config.challenge_interval_secs != old.challenge_interval_secs {
+            self.challenge_interval = delayed_interval(config.challenge_interval_secs);
+        }
+        let messaging = &mut self.swarm.behaviour_mut().messaging;
+        messaging.set_replication_factor(config.replication_factor);
+        messaging.set_connection_limits(config.connection_limits.clone());
+        messaging.set_transfer_limits(config.transfer_limits.clone());
+        messaging.set_queue_limits(config.message_queue.clone());
+        messaging.set_retry_policy(config.message_retry.clone());
+        messaging.set_flood_limits(config.flood_limits.clone());
+        self.release_blocked_sends();
+
+        Ok(restart_required)
+    }
+}
+
+fn offer_info(offer: &PendingOffer) -> OfferInfo {
+    let mut peer_ids: Vec<String> = offer.peers.iter().map(PeerId::to_string).collect();
+    peer_ids.sort();
+    OfferInfo {
+        file_id: offer.metadata.file_id.clone(),
+        name: offer.metadata.name.clone(),
+        size: offer.metadata.size,
+        chunks: offer.metadata.total_chunks,
+        peer_ids,
+        offered_at: offer.offered_at,
+        expires_at: offer.expires_at,
+    }
+}
+
+/// An application message waiting for room in its peer's queue
+struct BlockedSend {
+    peer_id: PeerId,
+    msg_type: String,
+    payload: serde_json::Value,
+    reply: oneshot::Sender<io::Result<MessageReceipt>>,
+}
+
+/// A download of a file nobody offered, waiting on the peers asked for it
+struct FileRequest {
+    /// Peer the download should fetch from first, if named
+    peer: Option<PeerId>,
+    /// Peers asked that have not answered yet
+    asked: usize,
+    reply: oneshot::Sender<io::Result<TransferSummary>>,
+}
+
+/// A dial requested by an operator
+pub struct PendingDial {
+    /// Address or peer id dialed
+    target: String,
+    /// Remember the peer's address once connected
+    save: bool,
+    reply: Option<oneshot::Sender<Result<String, String>>>,
+}
+
+/// Dial `address` for an operator; the outcome is reported to `reply` once
+/// the connection succeeds or fails. Errors if the dial cannot even start.
+pub fn dial(
+    swarm: &mut Swarm<CoreLinkBehaviour>,
+    pending_dials: &mut HashMap<ConnectionId, PendingDial>,
+    address: Multiaddr,
+    save: bool,
+    reply: Option<oneshot::Sender<Result<String, String>>>,
+) -> Result<(), String> {
+    let target = address.to_string();
+    start_dial(swarm, pending_dials, address.into(), target, save, reply)
+}
+
+/// Dial `peer_id` at `addresses` and wherever else the behaviours know it
+/// to be, like [`dial`]
+pub fn dial_peer(
+    swarm: &mut Swarm<CoreLinkBehaviour>,
+    pending_dials: &mut HashMap<ConnectionId, PendingDial>,
+    peer_id: PeerId,
+    addresses: Vec<Multiaddr>,
+    save: bool,
+    reply: Option<oneshot::Sender<Result<String, String>>>,
+) -> Result<(), String> {
+    let opts = DialOpts::peer_id(peer_id)
+        .addresses(addresses)
+        .extend_addresses_through_behaviour()
+        .build();
+    start_dial(swarm, pending_dials, opts, peer_id.to_string(), save, reply)
+}
+
+fn start_dial(
+    swarm: &mut Swarm<CoreLinkBehaviour>,
+    pending_dials: &mut HashMap<ConnectionId, PendingDial>,
+    opts: DialOpts,
+    target: String,
+    save: bool,
+    reply: Option<oneshot::Sender<Result<String, String>>>,
+) -> Result<(), String> {
+    let connection_id = opts.connection_id();
+    match swarm.dial(opts) {
+        Ok(()) => {
+            pending_dials.insert(
+                connection_id,
+                PendingDial {
+                    target,
+                    save,
+                    reply,
+                },
+            );
+            Ok(())
+        }
+        Err(e) => {
+            let error = format!("Failed to dial {}: {}", target, e);
+            if let Some(reply) = reply {
+                let _ = reply.send(Err(error.clone()));
+            }
+            Err(error)
+        }
+    }
+}
+
+/// An interval whose first tick is one period from now
+fn delayed_interval(secs: u64) -> time::Interval {
+    let period = Duration::from_secs(secs);
+    time::interval_at(time::Instant::now() + period, period)
+}
+
+/// Next item from an optional channel; never resolves without one
+async fn next<T>(rx: &mut Option<mpsc::Receiver<T>>) -> Option<T> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Next tick of an optional interval; never resolves without one
+async fn tick(interval: &mut Option<time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Next changed path reported by the shared folder watcher; never resolves without one
+async fn next_shared_event(
+    events: &mut Option<mpsc::UnboundedReceiver<PathBuf>>,
+) -> Option<PathBuf> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Next line typed on stdin, or `None` once it closes; never resolves
+/// without stdin
+async fn next_line(stdin: &mut Option<Lines<BufReader<Stdin>>>) -> Option<String> {
+    match stdin {
+        Some(lines) => lines.next_line().await.ok().flatten(),
+        None => std::future::pending().await,
+    }
+}
+
+fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
@@ -1,31 +1,269 @@
 use corelink_core::file::{
-    split_file_to_chunks, verify_chunk, write_chunk_to_file, FileChunk, FileMetadata, FileTransfer,
+    chunk_on_disk_is_valid, split_file_to_chunks, verify_chunk, write_chunk_at,
+    write_chunk_to_file, FileChunk, FileMetadata, FileTransfer,
 };
+use corelink_core::{BlockStore, PinSet, Storage};
 use libp2p_identity::PeerId;
 use lru::LruCache;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::io;
-use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
+use utoipa::ToSchema;
+
+/// Bucket holding the offer registry (file_id -> FileMetadata)
+const OFFERS_BUCKET: &str = "offers";
+/// Bucket holding in-progress downloads (file_id -> TransferRecord)
+const TRANSFERS_BUCKET: &str = "transfers";
+/// Bucket holding downloaded chunk indices of in-progress downloads (file_id -> Vec<u32>)
+const CHUNKS_BUCKET: &str = "chunks";
+/// Bucket holding finished downloads (file_id -> FileMetadata)
+const COMPLETED_BUCKET: &str = "completed";
+/// Bucket holding every file version offered or seen, withdrawn ones included (file_id -> FileMetadata)
+const VERSIONS_BUCKET: &str = "versions";
+
+/// How long a chunk request may go unanswered before the chunk is requested again
+pub(crate) const CHUNK_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Caps on the work the node takes on for downloads
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransferLimits {
+    /// Downloads fetched at once; further ones wait in a queue
+    pub max_concurrent_downloads: usize,
+    /// Chunk requests awaiting an answer, across all downloads
+    pub max_chunk_requests_in_flight: usize,
+    /// Memory used to cache chunks served to peers
+    pub max_cached_bytes: usize,
+}
+
+impl Default for TransferLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrent_downloads: 4,
+            max_chunk_requests_in_flight: 32,
+            max_cached_bytes: 8 * 1024 * 1024,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum TransferStatus {
-    ChunkReceived { progress: f32 },
+    ChunkReceived {
+        progress: f32,
+        /// Bytes of the file held so far
+        bytes_received: u64,
+        total_bytes: u64,
+        /// Chunks still to fetch
+        chunks_remaining: u32,
+    },
     TransferComplete,
-    VerificationFailed { chunk_index: u32 },
+    VerificationFailed {
+        chunk_index: u32,
+    },
+}
+
+/// A received chunk to verify and store, from
+/// [`FileTransferManager::prepare_chunk`]. Running it does the hashing and
+/// disk writes, so it can be done off the thread driving the download.
+pub struct ChunkWrite {
+    chunk: FileChunk,
+    /// Hash the file's metadata gives the chunk
+    expected: [u8; 32],
+    /// Partial download file and the chunk's offset in it, unless the block
+    /// store holds the only copy
+    file: Option<(PathBuf, u64)>,
+    blocks: BlockStore,
+}
+
+/// A chunk verified and stored, or not, for
+/// [`FileTransferManager::finish_chunk`]
+pub struct ChunkWritten {
+    pub file_id: String,
+    pub chunk_index: u32,
+    pub size: u64,
+    /// Whether the chunk matched its hash
+    result: io::Result<bool>,
+}
+
+impl ChunkWrite {
+    pub fn run(self) -> ChunkWritten {
+        let result = self.store();
+        ChunkWritten {
+            size: self.chunk.data.len() as u64,
+            file_id: self.chunk.file_id,
+            chunk_index: self.chunk.chunk_index,
+            result,
+        }
+    }
+
+    fn store(&self) -> io::Result<bool> {
+        let chunk = &self.chunk;
+        if !verify_chunk(chunk) {
+            return Ok(false);
+        }
+        if chunk.hash != self.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Chunk {} hash mismatch with metadata", chunk.chunk_index),
+            ));
+        }
+
+        // Write chunk to file, keeping a copy in the block store
+        match &self.file {
+            Some((path, offset)) => {
+                write_chunk_at(&chunk.data, *offset, path)?;
+                if let Err(e) = self.blocks.put(&chunk.data) {
+                    warn!(
+                        "Failed to store block for chunk {}: {}",
+                        chunk.chunk_index, e
+                    );
+                }
+            }
+            // The block store holds the only copy
+            None => {
+                self.blocks.put(&chunk.data).map_err(io::Error::other)?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Outcome of a garbage collection pass
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct GcReport {
+    /// When true nothing was deleted; the report lists what would have been
+    pub dry_run: bool,
+    pub blocks_removed: usize,
+    pub bytes_reclaimed: u64,
+    #[schema(value_type = Vec<String>)]
+    pub partial_downloads_removed: Vec<PathBuf>,
+}
+
+/// Use of the in-memory cache of chunks served to peers
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity_bytes: usize,
+    /// Chunk requests served from the cache since the node started
+    pub hits: u64,
+    /// Chunk requests that had to read the block store
+    pub misses: u64,
+}
+
+/// Persisted state of an in-progress download
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TransferRecord {
+    metadata: FileMetadata,
+    output_path: PathBuf,
+}
+
+/// What startup recovery found among downloads interrupted by a shutdown or crash
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct RecoveryReport {
+    /// Downloads that continue once a peer offers the file again
+    pub resumed: Vec<RecoveredDownload>,
+    /// Ids of downloads whose chunks were all on disk already
+    pub completed: Vec<String>,
+    /// Partial files no download was writing to, now deleted
+    #[schema(value_type = Vec<String>)]
+    pub removed: Vec<PathBuf>,
+}
+
+/// An interrupted download and the state of its chunks
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecoveredDownload {
+    pub file_id: String,
+    pub name: String,
+    /// Chunks verified on disk (or in the block store)
+    pub valid_chunks: u32,
+    pub total_chunks: u32,
+    /// Chunks restored from the block store or found on disk but not recorded
+    pub repaired_chunks: u32,
+    /// Chunks recorded as downloaded that failed verification
+    pub lost_chunks: u32,
+}
+
+/// Where a download stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferState {
+    /// Waiting for a free download slot
+    Queued,
+    Downloading,
+    Complete,
+}
+
+/// What removing a file did, depending on where it stood
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FileRemoval {
+    /// A running or queued download was stopped and its partial data deleted
+    Cancelled,
+    /// This node stopped offering the file, or every peer that offered it
+    /// while it waited in manual mode did
+    Withdrawn,
+    /// A finished download was deleted from storage
+    Deleted,
+    /// An offer waiting in manual mode was declined
+    Rejected,
+    /// An offer waiting in manual mode went unanswered for too long
+    Expired,
+}
+
+/// Progress of one download
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TransferSummary {
+    pub file_id: String,
+    pub name: String,
+    pub size: u64,
+    pub state: TransferState,
+    pub chunks_received: u32,
+    pub total_chunks: u32,
+    pub bytes_received: u64,
+    pub progress: f32,
+    /// Peers the file is fetched from
+    pub sources: Vec<String>,
+}
+
+/// A download waiting for a free slot
+struct QueuedDownload {
+    metadata: FileMetadata,
+    output_path: PathBuf,
+    peers: Vec<PeerId>,
 }
 
 pub struct FileTransferManager {
     active_uploads: HashMap<String, FileMetadata>,
     active_downloads: HashMap<String, FileTransfer>,
+    /// Downloads beyond `limits.max_concurrent_downloads`, oldest first
+    queued_downloads: VecDeque<QueuedDownload>,
+    completed_downloads: HashMap<String, FileMetadata>,
+    pins: PinSet,
     chunk_cache: LruCache<(String, u32), Vec<u8>>,
+    cached_bytes: usize,
+    cache_hits: u64,
+    cache_misses: u64,
+    /// When each outstanding chunk request was handed out
+    requested_chunks: HashMap<(String, u32), Instant>,
+    limits: TransferLimits,
+    blocks: BlockStore,
+    offers_db: Storage,
+    transfers_db: Storage,
+    chunks_db: Storage,
+    completed_db: Storage,
+    versions_db: Storage,
+    /// When false, file contents only live in the block store (e.g. when it is encrypted)
+    write_files: bool,
     pub storage_path: PathBuf,
 }
 
 impl FileTransferManager {
-    pub fn new(storage_path: PathBuf) -> io::Result<Self> {
+    pub fn new(storage_path: PathBuf, store: Storage) -> io::Result<Self> {
         // Create storage directories
         let uploads_path = storage_path.join("uploads");
         let downloads_path = storage_path.join("downloads");
@@ -34,47 +272,244 @@ impl FileTransferManager {
         fs::create_dir_all(&uploads_path)?;
         fs::create_dir_all(&downloads_path)?;
         fs::create_dir_all(&complete_path)?;
+        let blocks = BlockStore::open(&storage_path.join("blocks")).map_err(io::Error::other)?;
+        let pins = PinSet::load(store.clone()).map_err(io::Error::other)?;
+        let bucket = |name| store.open_bucket(name).map_err(io::Error::other);
+        let offers_db = bucket(OFFERS_BUCKET)?;
+        let transfers_db = bucket(TRANSFERS_BUCKET)?;
+        let chunks_db = bucket(CHUNKS_BUCKET)?;
+        let completed_db = bucket(COMPLETED_BUCKET)?;
+        let versions_db = bucket(VERSIONS_BUCKET)?;
 
         info!("📁 FileTransferManager initialized at: {:?}", storage_path);
         info!("   Uploads: {:?}", uploads_path);
         info!("   Downloads: {:?}", downloads_path);
         info!("   Complete: {:?}", complete_path);
 
-        Ok(Self {
+        let mut manager = Self {
             active_uploads: HashMap::new(),
             active_downloads: HashMap::new(),
-            chunk_cache: LruCache::new(NonZeroUsize::new(100).unwrap()),
+            queued_downloads: VecDeque::new(),
+            completed_downloads: HashMap::new(),
+            pins,
+            chunk_cache: LruCache::unbounded(),
+            cached_bytes: 0,
+            cache_hits: 0,
+            cache_misses: 0,
+            requested_chunks: HashMap::new(),
+            limits: TransferLimits::default(),
+            blocks,
+            offers_db,
+            transfers_db,
+            chunks_db,
+            completed_db,
+            versions_db,
+            write_files: true,
             storage_path,
-        })
+        };
+        manager.load_persisted_state();
+
+        Ok(manager)
+    }
+
+    /// Keep chunk blocks in `blocks` instead of the default on-disk store
+    pub fn with_blocks(mut self, blocks: BlockStore) -> Self {
+        self.blocks = blocks;
+        self
+    }
+
+    /// Cap concurrent downloads, chunk requests in flight and cached bytes
+    pub fn with_limits(mut self, limits: TransferLimits) -> Self {
+        self.set_limits(limits);
+        self
+    }
+
+    /// Change the transfer limits. Running downloads are kept even if there
+    /// are now more than allowed; call `start_queued_downloads` to make use
+    /// of raised limits.
+    pub fn set_limits(&mut self, limits: TransferLimits) {
+        self.limits = limits;
+        self.trim_chunk_cache();
+    }
+
+    /// Keep downloaded files only in the block store instead of writing
+    /// plaintext copies to `downloads/` and `complete/`. Use `export_file` to
+    /// read them back out.
+    pub fn without_plaintext_files(mut self) -> Self {
+        self.write_files = false;
+        self
+    }
+
+    /// Restore the offer registry and in-progress downloads from the store
+    fn load_persisted_state(&mut self) {
+        match self.offers_db.iter_json::<FileMetadata>() {
+            Ok(offers) => {
+                if !offers.is_empty() {
+                    info!("📂 Restored {} offered file(s)", offers.len());
+                }
+                self.active_uploads = offers.into_iter().collect();
+            }
+            Err(e) => warn!("Failed to load offer registry: {}", e),
+        }
+
+        match self.completed_db.iter_json::<FileMetadata>() {
+            Ok(completed) => self.completed_downloads = completed.into_iter().collect(),
+            Err(e) => warn!("Failed to load completed downloads: {}", e),
+        }
+
+        let records = match self.transfers_db.iter_json::<TransferRecord>() {
+            Ok(records) => records,
+            Err(e) => {
+                warn!("Failed to load transfer state: {}", e);
+                Vec::new()
+            }
+        };
+
+        for (file_id, record) in records {
+            let mut transfer = FileTransfer::new(record.metadata, record.output_path);
+            match self.chunks_db.get_json::<Vec<u32>>(&file_id) {
+                Ok(Some(chunks)) => {
+                    for chunk_index in chunks {
+                        transfer.mark_chunk_downloaded(chunk_index);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to load chunk state for {}: {}", file_id, e),
+            }
+
+            info!(
+                "📂 Restored download {} ({:.1}% complete)",
+                transfer.metadata.name,
+                transfer.progress * 100.0
+            );
+            self.active_downloads.insert(file_id, transfer);
+        }
+    }
+
+    fn persist_offer(&self, metadata: &FileMetadata) {
+        if let Err(e) = self
+            .offers_db
+            .insert_json(metadata.file_id.clone(), metadata)
+        {
+            warn!("Failed to persist offer {}: {}", metadata.file_id, e);
+        }
+        self.record_version(metadata);
+    }
+
+    /// Remember a file version so its history outlives the offer
+    pub fn record_version(&self, metadata: &FileMetadata) {
+        if let Err(e) = self
+            .versions_db
+            .insert_json(metadata.file_id.clone(), metadata)
+        {
+            warn!("Failed to record version of {}: {}", metadata.file_id, e);
+        }
+    }
+
+    /// A file and every version it replaced, newest first
+    pub fn version_history(&self, file_id: &str) -> Vec<FileMetadata> {
+        let mut history: Vec<FileMetadata> = Vec::new();
+        let mut next = Some(file_id.to_string());
+
+        while let Some(file_id) = next.take() {
+            // Guard against a cycle of links from a misbehaving peer
+            if history.iter().any(|m| m.file_id == file_id) {
+                break;
+            }
+            let metadata = match self.versions_db.get_json::<FileMetadata>(&file_id) {
+                Ok(Some(metadata)) => metadata,
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("Failed to load version {}: {}", file_id, e);
+                    break;
+                }
+            };
+            next = metadata.previous_file_id.clone();
+            history.push(metadata);
+        }
+
+        history
+    }
+
+    fn persist_transfer(&self, transfer: &FileTransfer) {
+        let record = TransferRecord {
+            metadata: transfer.metadata.clone(),
+            output_path: transfer.output_path.clone(),
+        };
+
+        if let Err(e) = self
+            .transfers_db
+            .insert_json(transfer.metadata.file_id.clone(), &record)
+        {
+            warn!("Failed to persist transfer state: {}", e);
+        }
+    }
+
+    fn persist_chunks(&self, transfer: &FileTransfer) {
+        let mut chunks: Vec<u32> = transfer.downloaded_chunks.iter().copied().collect();
+        chunks.sort_unstable();
+
+        if let Err(e) = self
+            .chunks_db
+            .insert_json(transfer.metadata.file_id.clone(), &chunks)
+        {
+            warn!("Failed to persist chunk state: {}", e);
+        }
+    }
+
+    fn persist_completed(&self, metadata: &FileMetadata) {
+        if let Err(e) = self
+            .completed_db
+            .insert_json(metadata.file_id.clone(), metadata)
+        {
+            warn!("Failed to persist completed download: {}", e);
+        }
+    }
+
+    fn forget_transfer(&self, file_id: &str) {
+        if let Err(e) = self
+            .transfers_db
+            .remove(file_id)
+            .and_then(|_| self.chunks_db.remove(file_id))
+        {
+            warn!("Failed to remove transfer state for {}: {}", file_id, e);
+        }
     }
 
     /// Offer a file for transfer by splitting it into chunks
     pub fn offer_file(&mut self, path: &Path) -> io::Result<FileMetadata> {
-        if !path.exists() {
+        self.offer_file_version(path, None)
+    }
+
+    /// Offer a file as the next version of `previous_file_id`, if given
+    pub fn offer_file_version(
+        &mut self,
+        path: &Path,
+        previous_file_id: Option<&str>,
+    ) -> io::Result<FileMetadata> {
+        // Missing and unreadable paths fail here with their own error kind
+        if !fs::metadata(path)?.is_file() {
             return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("File not found: {:?}", path),
+                io::ErrorKind::InvalidInput,
+                format!("Not a regular file: {:?}", path),
             ));
         }
 
         info!("📤 Offering file: {:?}", path);
 
         // Split file into chunks
-        let (metadata, chunks) = split_file_to_chunks(path, 64 * 1024)?;
-
-        // Cache all chunks for quick access
-        for chunk in chunks {
-            self.chunk_cache
-                .put((metadata.file_id.clone(), chunk.chunk_index), chunk.data);
+        let (mut metadata, chunks) = split_file_to_chunks(path, 64 * 1024)?;
+        if let Some(previous_file_id) = previous_file_id {
+            match self.version_history(previous_file_id).first() {
+                Some(previous) => metadata = metadata.with_previous_version(previous),
+                None => warn!("Unknown previous version {}", previous_file_id),
+            }
         }
 
-        // Copy file to uploads directory
-        let upload_path = self.storage_path.join("uploads").join(&metadata.name);
-        if let Err(e) = fs::copy(path, &upload_path) {
-            warn!(
-                "Failed to copy file to uploads directory: {}. File will be served from original location.",
-                e
-            );
+        // Store every chunk in the block store and cache it for quick access
+        for chunk in chunks {
+            self.blocks.put(&chunk.data).map_err(io::Error::other)?;
+            self.cache_chunk(metadata.file_id.clone(), chunk.chunk_index, chunk.data);
         }
 
         info!(
@@ -85,11 +520,96 @@ impl FileTransferManager {
         // Register as active upload
         let file_id = metadata.file_id.clone();
         self.active_uploads.insert(file_id, metadata.clone());
+        self.persist_offer(&metadata);
 
         Ok(metadata)
     }
 
-    /// Request a file for download
+    /// Stop offering a file. Its blocks are reclaimed by the next garbage collection.
+    pub fn withdraw_offer(&mut self, file_id: &str) -> bool {
+        if self.active_uploads.remove(file_id).is_none() {
+            return false;
+        }
+        if let Err(e) = self.offers_db.remove(file_id) {
+            warn!("Failed to remove offer {}: {}", file_id, e);
+        }
+        info!("🙅 Withdrew offer: {}", file_id);
+        true
+    }
+
+    /// Add another peer to fetch an in-progress download from.
+    ///
+    /// Returns false if there is no such download, it has no source yet (see
+    /// `resume_download`) or `peer` is already a source.
+    pub fn add_download_source(&mut self, file_id: &str, peer: PeerId) -> bool {
+        if let Some(queued) = self.queued_download_mut(file_id) {
+            if !queued.peers.contains(&peer) {
+                queued.peers.push(peer);
+            }
+            return true;
+        }
+        match self.active_downloads.get_mut(file_id) {
+            Some(transfer) if !transfer.peers.is_empty() && !transfer.peers.contains(&peer) => {
+                transfer.add_peer(peer);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Peers an in-progress download can be fetched from
+    pub fn download_sources(&self, file_id: &str) -> Vec<PeerId> {
+        self.active_downloads
+            .get(file_id)
+            .map(|transfer| transfer.peers.clone())
+            .unwrap_or_default()
+    }
+
+    /// Stop fetching a download from `peer`. Returns true if the download
+    /// is left with no source at all.
+    pub fn remove_download_source(&mut self, file_id: &str, peer: &PeerId) -> bool {
+        if let Some(queued) = self.queued_download_mut(file_id) {
+            queued.peers.retain(|p| p != peer);
+            return queued.peers.is_empty();
+        }
+        match self.active_downloads.get_mut(file_id) {
+            Some(transfer) => {
+                transfer.peers.retain(|p| p != peer);
+                transfer.peers.is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// Downloads with a source to fetch from. Downloads restored from a
+    /// previous run wait for a peer to offer them again and take no slot.
+    fn running_downloads(&self) -> usize {
+        self.active_downloads
+            .values()
+            .filter(|transfer| !transfer.peers.is_empty())
+            .count()
+    }
+
+    fn queued_download_mut(&mut self, file_id: &str) -> Option<&mut QueuedDownload> {
+        self.queued_downloads
+            .iter_mut()
+            .find(|queued| queued.metadata.file_id == file_id)
+    }
+
+    /// Whether a download is waiting for a free slot
+    pub fn is_queued(&self, file_id: &str) -> bool {
+        self.queued_downloads
+            .iter()
+            .any(|queued| queued.metadata.file_id == file_id)
+    }
+
+    /// Downloads waiting for a free slot, oldest first
+    pub fn queued_downloads(&self) -> impl Iterator<Item = &FileMetadata> {
+        self.queued_downloads.iter().map(|queued| &queued.metadata)
+    }
+
+    /// Request a file for download. If `max_concurrent_downloads` are
+    /// already running, the download is queued instead; see `is_queued`.
     pub fn request_file(
         &mut self,
         metadata: FileMetadata,
@@ -99,13 +619,27 @@ impl FileTransferManager {
         let file_id = metadata.file_id.clone();
 
         // Check if already downloading
-        if self.active_downloads.contains_key(&file_id) {
+        if self.active_downloads.contains_key(&file_id) || self.is_queued(&file_id) {
             return Err(io::Error::new(
                 io::ErrorKind::AlreadyExists,
                 format!("Already downloading file: {}", file_id),
             ));
         }
 
+        let running = self.running_downloads();
+        if running >= self.limits.max_concurrent_downloads {
+            info!(
+                "⏸️ Queued download of {}: {} downloads already running",
+                metadata.name, running
+            );
+            self.queued_downloads.push_back(QueuedDownload {
+                metadata,
+                output_path,
+                peers: vec![peer],
+            });
+            return Ok(file_id);
+        }
+
         info!("📥 Requesting file: {} from peer {}", metadata.name, peer);
 
         // Create FileTransfer to track progress
@@ -113,8 +647,40 @@ impl FileTransferManager {
         transfer.add_peer(peer);
 
         // Pre-allocate file with correct size
-        if let Err(e) = fs::File::create(&output_path).and_then(|f| f.set_len(metadata.size)) {
-            warn!("Failed to pre-allocate download file: {}", e);
+        if self.write_files {
+            if let Err(e) = fs::File::create(&output_path).and_then(|f| f.set_len(metadata.size)) {
+                warn!("Failed to pre-allocate download file: {}", e);
+            }
+        }
+
+        // Chunks we already hold (e.g. shared with another file) need not be fetched again
+        let mut reused = 0;
+        for (chunk_index, hash) in metadata.chunk_hashes.iter().enumerate() {
+            if !self.write_files {
+                if self.blocks.has(hash) {
+                    transfer.mark_chunk_downloaded(chunk_index as u32);
+                    reused += 1;
+                }
+                continue;
+            }
+
+            let data = match self.blocks.get_chunk(hash, metadata.chunk_size as usize) {
+                Ok(Some(data)) => data,
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("Ignoring unreadable block for chunk {}: {}", chunk_index, e);
+                    continue;
+                }
+            };
+
+            let chunk = FileChunk::new(file_id.clone(), chunk_index as u32, data);
+            if write_chunk_to_file(&chunk, &metadata, &output_path).is_ok() {
+                transfer.mark_chunk_downloaded(chunk_index as u32);
+                reused += 1;
+            }
+        }
+        if reused > 0 {
+            info!("♻️ Reused {} locally stored chunk(s)", reused);
         }
 
         info!(
@@ -122,24 +688,370 @@ impl FileTransferManager {
             transfer.missing_chunks.len()
         );
 
+        let complete = transfer.is_complete();
+        self.persist_transfer(&transfer);
         self.active_downloads.insert(file_id.clone(), transfer);
 
+        if complete {
+            self.complete_download(&file_id);
+        } else if reused > 0 {
+            self.persist_chunks(&self.active_downloads[&file_id]);
+        }
+
         Ok(file_id)
     }
 
+    /// Start queued downloads while there are free slots, returning their ids.
+    /// A download whose chunks were all held locally is complete on return.
+    pub fn start_queued_downloads(&mut self) -> Vec<String> {
+        let mut started = Vec::new();
+        while self.running_downloads() < self.limits.max_concurrent_downloads {
+            let Some(queued) = self.queued_downloads.pop_front() else {
+                break;
+            };
+            let Some((&first, others)) = queued.peers.split_first() else {
+                continue;
+            };
+            match self.request_file(queued.metadata, queued.output_path, first) {
+                Ok(file_id) => {
+                    for &peer in others {
+                        self.add_download_source(&file_id, peer);
+                    }
+                    started.push(file_id);
+                }
+                Err(e) => warn!("❌ Failed to start queued download: {}", e),
+            }
+        }
+        started
+    }
+
+    pub fn blocks(&self) -> &BlockStore {
+        &self.blocks
+    }
+
+    /// Metadata of a finished download
+    pub fn completed_download(&self, file_id: &str) -> Option<&FileMetadata> {
+        self.completed_downloads.get(file_id)
+    }
+
+    /// Forget a finished download and delete its saved copy. Its blocks are
+    /// reclaimed by the next garbage collection.
+    pub fn delete_completed(&mut self, file_id: &str) -> io::Result<()> {
+        let path = self.completed_path(file_id);
+        if self.completed_downloads.remove(file_id).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No completed download: {}", file_id),
+            ));
+        }
+        if let Err(e) = self.completed_db.remove(file_id) {
+            warn!("Failed to remove completed download {}: {}", file_id, e);
+        }
+        self.pins.unpin(file_id).map_err(io::Error::other)?;
+        if let Some(path) = path {
+            fs::remove_file(&path)?;
+            debug!("Deleted completed file: {:?}", path);
+        }
+        info!("🗑️ Deleted completed download: {}", file_id);
+        Ok(())
+    }
+
+    /// Where a finished download was saved, unless it only lives in the block store
+    pub fn completed_path(&self, file_id: &str) -> Option<PathBuf> {
+        let metadata = self.completed_downloads.get(file_id)?;
+        let path = self.storage_path.join("complete").join(&metadata.name);
+        (self.write_files && path.exists()).then_some(path)
+    }
+
+    /// Chunk hashes of every offered or pinned file
+    pub fn preserved_chunk_hashes(&self) -> HashSet<[u8; 32]> {
+        let pinned = self.pins.iter().filter_map(|file_id| {
+            self.completed_downloads
+                .get(file_id)
+                .or_else(|| self.active_downloads.get(file_id).map(|t| &t.metadata))
+        });
+        self.active_uploads
+            .values()
+            .chain(pinned)
+            .flat_map(|metadata| metadata.chunk_hashes.iter().copied())
+            .collect()
+    }
+
+    /// Metadata of every file this node holds in full (offered or downloaded)
+    pub fn held_files(&self) -> impl Iterator<Item = &FileMetadata> {
+        self.active_uploads
+            .values()
+            .chain(self.completed_downloads.values())
+    }
+
+    /// Read a chunk of a held file, from the block store or the completed file on disk
+    pub fn read_chunk(&self, file_id: &str, chunk_index: u32) -> io::Result<Option<Vec<u8>>> {
+        let Some(metadata) = self
+            .active_uploads
+            .get(file_id)
+            .or_else(|| self.completed_downloads.get(file_id))
+        else {
+            return Ok(None);
+        };
+        let Some(hash) = metadata.chunk_hashes.get(chunk_index as usize) else {
+            return Ok(None);
+        };
+
+        if let Some(data) = self
+            .blocks
+            .get_chunk(hash, metadata.chunk_size as usize)
+            .map_err(io::Error::other)?
+        {
+            return Ok(Some(data));
+        }
+
+        // Unpinned blocks of completed downloads may have been collected
+        let path = self.storage_path.join("complete").join(&metadata.name);
+        if !self.completed_downloads.contains_key(file_id)
+            || !chunk_on_disk_is_valid(metadata, chunk_index, &path)?
+        {
+            return Ok(None);
+        }
+
+        let offset = chunk_index as u64 * metadata.chunk_size as u64;
+        let len = (metadata.size - offset).min(metadata.chunk_size as u64);
+        let mut file = fs::File::open(&path)?;
+        io::Seek::seek(&mut file, io::SeekFrom::Start(offset))?;
+        let mut data = vec![0u8; len as usize];
+        io::Read::read_exact(&mut file, &mut data)?;
+        Ok(Some(data))
+    }
+
+    /// Metadata of a file this node is offering
+    pub fn offered_file(&self, file_id: &str) -> Option<&FileMetadata> {
+        self.active_uploads.get(file_id)
+    }
+
+    /// Metadata of every file this node is offering
+    pub fn offered_files(&self) -> impl Iterator<Item = &FileMetadata> {
+        self.active_uploads.values()
+    }
+
+    /// Metadata of a file this node offers, downloads, queued or finished
+    pub fn file_metadata(&self, file_id: &str) -> Option<&FileMetadata> {
+        self.active_uploads
+            .get(file_id)
+            .or_else(|| self.active_downloads.get(file_id).map(|t| &t.metadata))
+            .or_else(|| self.queued_downloads().find(|m| m.file_id == file_id))
+            .or_else(|| self.completed_downloads.get(file_id))
+    }
+
+    /// Which chunks of a file are held, one bit per chunk with chunk 0 in
+    /// the high bit of the first byte
+    pub fn chunk_bitmap(&self, file_id: &str) -> Option<Vec<u8>> {
+        let metadata = self.file_metadata(file_id)?;
+        let mut bitmap = vec![0u8; metadata.total_chunks.div_ceil(8) as usize];
+        for chunk_index in 0..metadata.total_chunks {
+            let held = match self.active_downloads.get(file_id) {
+                Some(transfer) => transfer.downloaded_chunks.contains(&chunk_index),
+                None => !self.is_queued(file_id),
+            };
+            if held {
+                bitmap[chunk_index as usize / 8] |= 0x80 >> (chunk_index % 8);
+            }
+        }
+        Some(bitmap)
+    }
+
+    /// Downloads still in progress
+    pub fn downloads(&self) -> impl Iterator<Item = &FileTransfer> {
+        self.active_downloads.values()
+    }
+
+    /// Whether a download for this file is still in progress
+    pub fn is_downloading(&self, file_id: &str) -> bool {
+        self.active_downloads.contains_key(file_id)
+    }
+
+    /// Attach a source peer to a download restored from a previous run.
+    ///
+    /// Returns false if there is no such download or it already has a source.
+    pub fn resume_download(&mut self, file_id: &str, peer: PeerId) -> bool {
+        match self.active_downloads.get_mut(file_id) {
+            Some(transfer) if transfer.peers.is_empty() => {
+                transfer.add_peer(peer);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Check downloads restored from a previous run against what is on
+    /// disk, since a crash may have interrupted chunk writes or left chunks
+    /// written but not recorded. Downloads found complete are finished and
+    /// partial files with no download state are deleted. Call once at
+    /// startup, before any download resumes.
+    pub fn recover_downloads(&mut self) -> RecoveryReport {
+        let mut report = RecoveryReport::default();
+
+        let file_ids: Vec<String> = self.active_downloads.keys().cloned().collect();
+        for file_id in file_ids {
+            let (repaired, lost) = self.verify_chunks(&file_id);
+            let transfer = &self.active_downloads[&file_id];
+            if transfer.is_complete() {
+                info!("🩺 {} was already complete on disk", transfer.metadata.name);
+                report.completed.push(file_id.clone());
+                self.complete_download(&file_id);
+                continue;
+            }
+
+            info!(
+                "🩺 {}: {}/{} chunk(s) valid, {} repaired, {} to fetch again",
+                transfer.metadata.name,
+                transfer.downloaded_chunks.len(),
+                transfer.metadata.total_chunks,
+                repaired,
+                lost
+            );
+            report.resumed.push(RecoveredDownload {
+                file_id,
+                name: transfer.metadata.name.clone(),
+                valid_chunks: transfer.downloaded_chunks.len() as u32,
+                total_chunks: transfer.metadata.total_chunks,
+                repaired_chunks: repaired,
+                lost_chunks: lost,
+            });
+        }
+
+        match self.remove_orphaned_downloads() {
+            Ok(removed) => report.removed = removed,
+            Err(e) => warn!("Failed to clean up partial downloads: {}", e),
+        }
+
+        if !report.resumed.is_empty() || !report.completed.is_empty() || !report.removed.is_empty()
+        {
+            info!(
+                "🩺 Download recovery: {} to resume, {} complete, {} orphaned file(s) removed",
+                report.resumed.len(),
+                report.completed.len(),
+                report.removed.len()
+            );
+        }
+        report
+    }
+
+    /// Bring a download's chunk state in line with the chunks actually held.
+    /// Chunks torn on disk are repaired from the block store when possible
+    /// and otherwise fetched again. Returns how many chunks were repaired or
+    /// picked up, and how many recorded chunks were lost.
+    fn verify_chunks(&mut self, file_id: &str) -> (u32, u32) {
+        let Some(transfer) = self.active_downloads.get(file_id) else {
+            return (0, 0);
+        };
+
+        // The partial file itself may be gone; chunks can still come from blocks
+        if self.write_files && !transfer.output_path.exists() {
+            if let Err(e) = fs::File::create(&transfer.output_path)
+                .and_then(|f| f.set_len(transfer.metadata.size))
+            {
+                warn!("Failed to recreate {:?}: {}", transfer.output_path, e);
+            }
+        }
+
+        let mut held = Vec::new();
+        let mut rewritten = Vec::new();
+        for chunk_index in 0..transfer.metadata.total_chunks {
+            let hash = &transfer.metadata.chunk_hashes[chunk_index as usize];
+            if !self.write_files {
+                if self.blocks.has(hash) {
+                    held.push(chunk_index);
+                }
+                continue;
+            }
+
+            match chunk_on_disk_is_valid(&transfer.metadata, chunk_index, &transfer.output_path) {
+                Ok(true) => {
+                    held.push(chunk_index);
+                    continue;
+                }
+                Ok(false) => {}
+                Err(e) => debug!("Failed to check chunk {}: {}", chunk_index, e),
+            }
+
+            if let Ok(Some(data)) = self
+                .blocks
+                .get_chunk(hash, transfer.metadata.chunk_size as usize)
+            {
+                let chunk = FileChunk::new(file_id.to_string(), chunk_index, data);
+                if write_chunk_to_file(&chunk, &transfer.metadata, &transfer.output_path).is_ok() {
+                    held.push(chunk_index);
+                    rewritten.push(chunk_index);
+                }
+            }
+        }
+
+        let transfer = self.active_downloads.get_mut(file_id).unwrap();
+        let recorded = transfer.downloaded_chunks.clone();
+        let lost: Vec<u32> = recorded
+            .iter()
+            .filter(|chunk_index| !held.contains(chunk_index))
+            .copied()
+            .collect();
+        for &chunk_index in &lost {
+            transfer.mark_chunk_missing(chunk_index);
+        }
+        let mut repaired = 0;
+        for &chunk_index in &held {
+            if !recorded.contains(&chunk_index) {
+                transfer.mark_chunk_downloaded(chunk_index);
+                repaired += 1;
+            } else if rewritten.contains(&chunk_index) {
+                repaired += 1;
+            }
+        }
+
+        if !lost.is_empty() {
+            warn!(
+                "🩹 {} chunk(s) of {} were lost in a crash and will be fetched again",
+                lost.len(),
+                file_id
+            );
+        }
+        self.persist_chunks(&self.active_downloads[file_id]);
+        (repaired, lost.len() as u32)
+    }
+
+    /// Delete files in `downloads/` that no download is writing to
+    fn remove_orphaned_downloads(&self) -> io::Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for entry in fs::read_dir(self.storage_path.join("downloads"))? {
+            let entry = entry?;
+            let path = entry.path();
+            let in_use = self
+                .active_downloads
+                .values()
+                .any(|transfer| same_file(&transfer.output_path, &path));
+            if !entry.file_type()?.is_file() || in_use {
+                continue;
+            }
+            fs::remove_file(&path)?;
+            info!("🗑️ Removed orphaned partial download {:?}", path);
+            removed.push(path);
+        }
+        Ok(removed)
+    }
+
     /// Handle a chunk request and return the chunk if available
     pub fn handle_chunk_request(
         &mut self,
         file_id: &str,
         chunk_index: u32,
     ) -> io::Result<Option<FileChunk>> {
-        // Check if we're offering this file
+        // Serve files we offer, plus chunks of downloads we already hold
         let metadata = match self.active_uploads.get(file_id) {
             Some(m) => m,
-            None => {
-                debug!("Chunk request for unknown file: {}", file_id);
-                return Ok(None);
-            }
+            None => match self.active_downloads.get(file_id) {
+                Some(t) if t.downloaded_chunks.contains(&chunk_index) => &t.metadata,
+                _ => {
+                    debug!("Chunk request for unknown file: {}", file_id);
+                    return Ok(None);
+                }
+            },
         };
 
         // Validate chunk index
@@ -153,61 +1065,101 @@ impl FileTransferManager {
 
         // Check cache first
         if let Some(data) = self.chunk_cache.get(&(file_id.to_string(), chunk_index)) {
+            self.cache_hits += 1;
             debug!("📦 Serving chunk {} from cache", chunk_index);
             let chunk = FileChunk::new(file_id.to_string(), chunk_index, data.clone());
             return Ok(Some(chunk));
         }
 
-        // Load from file
-        let file_path = self.storage_path.join("uploads").join(&metadata.name);
-        if !file_path.exists() {
-            error!("Upload file not found: {:?}", file_path);
-            return Ok(None);
-        }
-
-        // Read chunk from file
-        let offset = chunk_index as u64 * metadata.chunk_size as u64;
-        let chunk_size = if chunk_index == metadata.total_chunks - 1 {
-            // Last chunk might be smaller
-            (metadata.size - offset) as usize
-        } else {
-            metadata.chunk_size as usize
+        // Load from the block store
+        self.cache_misses += 1;
+        let hash = metadata.chunk_hashes[chunk_index as usize];
+        let buffer = match self
+            .blocks
+            .get_chunk(&hash, metadata.chunk_size as usize)
+            .map_err(io::Error::other)?
+        {
+            Some(data) => data,
+            None => {
+                error!("Block for chunk {} of {} not found", chunk_index, file_id);
+                return Ok(None);
+            }
         };
 
-        let mut file = fs::File::open(&file_path)?;
-        use std::io::{Read, Seek, SeekFrom};
-        file.seek(SeekFrom::Start(offset))?;
-        let mut buffer = vec![0u8; chunk_size];
-        file.read_exact(&mut buffer)?;
-
         let chunk = FileChunk::new(file_id.to_string(), chunk_index, buffer.clone());
 
         // Cache for future requests
-        self.chunk_cache
-            .put((file_id.to_string(), chunk_index), buffer);
+        self.cache_chunk(file_id.to_string(), chunk_index, buffer);
 
-        debug!("📦 Serving chunk {} from file", chunk_index);
+        debug!("📦 Serving chunk {} from block store", chunk_index);
         Ok(Some(chunk))
     }
 
+    /// Keep a chunk in memory, evicting the least recently served ones to stay
+    /// within `max_cached_bytes`
+    fn cache_chunk(&mut self, file_id: String, chunk_index: u32, data: Vec<u8>) {
+        self.cached_bytes += data.len();
+        if let Some(old) = self.chunk_cache.put((file_id, chunk_index), data) {
+            self.cached_bytes -= old.len();
+        }
+        self.trim_chunk_cache();
+    }
+
+    fn trim_chunk_cache(&mut self) {
+        while self.cached_bytes > self.limits.max_cached_bytes {
+            let Some((_, data)) = self.chunk_cache.pop_lru() else {
+                break;
+            };
+            self.cached_bytes -= data.len();
+        }
+    }
+
     /// Handle a received chunk and write it to the download file
     pub fn handle_chunk_received(&mut self, chunk: FileChunk) -> io::Result<TransferStatus> {
-        let file_id = chunk.file_id.clone();
-        let chunk_index = chunk.chunk_index;
+        let write = self.prepare_chunk(chunk)?;
+        self.finish_chunk(write.run())
+    }
 
-        // Get the active download
-        let transfer = match self.active_downloads.get_mut(&file_id) {
-            Some(t) => t,
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::NotFound,
-                    format!("No active download for file: {}", file_id),
-                ));
-            }
+    /// Check a received chunk belongs to an active download, ready to be
+    /// verified and stored. The chunk counts as requested until it is
+    /// finished, so it is not asked for again meanwhile.
+    pub fn prepare_chunk(&mut self, chunk: FileChunk) -> io::Result<ChunkWrite> {
+        let key = (chunk.file_id.clone(), chunk.chunk_index);
+        let Some(transfer) = self.active_downloads.get(&chunk.file_id) else {
+            self.requested_chunks.remove(&key);
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No active download for file: {}", chunk.file_id),
+            ));
+        };
+        let metadata = &transfer.metadata;
+        let Some(&expected) = metadata.chunk_hashes.get(chunk.chunk_index as usize) else {
+            self.requested_chunks.remove(&key);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid chunk index {}", chunk.chunk_index),
+            ));
         };
+        let offset = chunk.chunk_index as u64 * metadata.chunk_size as u64;
+        let file = self
+            .write_files
+            .then(|| (transfer.output_path.clone(), offset));
+        Ok(ChunkWrite {
+            chunk,
+            expected,
+            file,
+            blocks: self.blocks.clone(),
+        })
+    }
+
+    /// Record a chunk verified and stored by running its [`ChunkWrite`]
+    pub fn finish_chunk(&mut self, written: ChunkWritten) -> io::Result<TransferStatus> {
+        let file_id = written.file_id;
+        let chunk_index = written.chunk_index;
+        self.requested_chunks
+            .remove(&(file_id.clone(), chunk_index));
 
-        // Verify chunk
-        if !verify_chunk(&chunk) {
+        if !written.result? {
             error!(
                 "❌ Chunk verification failed: {} index {}",
                 file_id, chunk_index
@@ -215,11 +1167,21 @@ impl FileTransferManager {
             return Ok(TransferStatus::VerificationFailed { chunk_index });
         }
 
-        // Write chunk to file
-        write_chunk_to_file(&chunk, &transfer.metadata, &transfer.output_path)?;
+        // The download may have ended while the chunk was being written
+        let transfer = match self.active_downloads.get_mut(&file_id) {
+            Some(t) => t,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No active download for file: {}", file_id),
+                ));
+            }
+        };
 
         // Update transfer state
         transfer.mark_chunk_downloaded(chunk_index);
+        let transfer = &self.active_downloads[&file_id];
+        self.persist_chunks(transfer);
 
         let progress = transfer.progress;
         debug!(
@@ -231,60 +1193,321 @@ impl FileTransferManager {
 
         // Check if transfer is complete
         if transfer.is_complete() {
-            info!("✅ Transfer complete: {}", file_id);
+            self.complete_download(&file_id);
+            return Ok(TransferStatus::TransferComplete);
+        }
 
-            // Move to complete directory
-            let final_path = self
-                .storage_path
-                .join("complete")
-                .join(&transfer.metadata.name);
+        Ok(TransferStatus::ChunkReceived {
+            progress,
+            bytes_received: downloaded_bytes(transfer),
+            total_bytes: transfer.metadata.size,
+            chunks_remaining: transfer.metadata.total_chunks
+                - transfer.downloaded_chunks.len() as u32,
+        })
+    }
 
-            if let Err(e) = fs::rename(&transfer.output_path, &final_path) {
-                warn!("Failed to move completed file: {}", e);
-            } else {
-                info!("📁 File saved to: {:?}", final_path);
-            }
+    /// Move a fully downloaded file into the complete directory and stop tracking it
+    fn complete_download(&mut self, file_id: &str) {
+        let Some(transfer) = self.active_downloads.remove(file_id) else {
+            return;
+        };
+        info!("✅ Transfer complete: {}", file_id);
+        self.forget_chunk_requests(file_id);
 
-            // Remove from active downloads
-            self.active_downloads.remove(&file_id);
+        // Move to complete directory
+        let final_path = self
+            .storage_path
+            .join("complete")
+            .join(&transfer.metadata.name);
 
-            return Ok(TransferStatus::TransferComplete);
+        if !self.write_files {
+            info!("🔐 File kept in block store: {}", transfer.metadata.name);
+        } else if let Err(e) = fs::rename(&transfer.output_path, &final_path) {
+            warn!("Failed to move completed file: {}", e);
+        } else {
+            info!("📁 File saved to: {:?}", final_path);
         }
 
-        Ok(TransferStatus::ChunkReceived { progress })
+        self.forget_transfer(file_id);
+        self.persist_completed(&transfer.metadata);
+        self.completed_downloads
+            .insert(file_id.to_string(), transfer.metadata);
     }
 
-    /// Get the next batch of chunks to request for a file
-    pub fn get_next_chunks_to_request(&self, file_id: &str, batch_size: usize) -> Vec<u32> {
-        if let Some(transfer) = self.active_downloads.get(file_id) {
-            transfer
-                .missing_chunks
-                .iter()
-                .take(batch_size)
-                .copied()
-                .collect()
+    /// Reassemble an offered or downloaded file from the block store into `dest`
+    pub fn export_file(&self, file_id: &str, dest: &Path) -> io::Result<()> {
+        let metadata = self
+            .active_uploads
+            .get(file_id)
+            .or_else(|| self.completed_downloads.get(file_id))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Unknown file: {}", file_id),
+                )
+            })?;
+
+        let mut file = fs::File::create(dest)?;
+        for (chunk_index, hash) in metadata.chunk_hashes.iter().enumerate() {
+            let data = self
+                .blocks
+                .get(hash)
+                .map_err(io::Error::other)?
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Block for chunk {} of {} is missing", chunk_index, file_id),
+                    )
+                })?;
+            io::Write::write_all(&mut file, &data)?;
+        }
+        file.sync_all()?;
+
+        info!("📤 Exported {} to {:?}", metadata.name, dest);
+        Ok(())
+    }
+
+    /// Whether this node knows a file as an offer, a download, or a completed download
+    pub fn knows_file(&self, file_id: &str) -> bool {
+        self.active_uploads.contains_key(file_id)
+            || self.active_downloads.contains_key(file_id)
+            || self.completed_downloads.contains_key(file_id)
+    }
+
+    /// Pin or unpin a file, exempting it from eviction and garbage collection
+    pub fn set_pinned(&mut self, file_id: &str, pinned: bool) -> io::Result<()> {
+        if !self.knows_file(file_id) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Unknown file: {}", file_id),
+            ));
+        }
+
+        let changed = if pinned {
+            self.pins.pin(file_id)
         } else {
-            Vec::new()
+            self.pins.unpin(file_id)
+        }
+        .map_err(io::Error::other)?;
+
+        if changed {
+            info!(
+                "📌 {} {}",
+                if pinned { "Pinned" } else { "Unpinned" },
+                file_id
+            );
+        }
+        Ok(())
+    }
+
+    pub fn is_pinned(&self, file_id: &str) -> bool {
+        self.pins.is_pinned(file_id)
+    }
+
+    /// Persist the progress of every active download and write it to disk
+    pub fn flush(&self) -> io::Result<()> {
+        for transfer in self.active_downloads.values() {
+            self.persist_chunks(transfer);
+        }
+        // Buckets share one database, so this flushes all of them
+        self.transfers_db.flush().map_err(io::Error::other)
+    }
+
+    /// Remove blocks not referenced by any offered, downloading or pinned file,
+    /// along with partial download files that no active download owns.
+    pub fn collect_garbage(&mut self, dry_run: bool) -> io::Result<GcReport> {
+        let mut report = GcReport {
+            dry_run,
+            ..Default::default()
+        };
+
+        let pinned = self.pins.iter().filter_map(|file_id| {
+            self.active_uploads
+                .get(file_id)
+                .or_else(|| self.completed_downloads.get(file_id))
+        });
+        // Without plaintext copies, completed downloads exist only as blocks
+        let completed = self
+            .completed_downloads
+            .values()
+            .filter(|_| !self.write_files);
+        let referenced: HashSet<[u8; 32]> = self
+            .active_uploads
+            .values()
+            .chain(self.active_downloads.values().map(|t| &t.metadata))
+            .chain(pinned)
+            .chain(completed)
+            .flat_map(|metadata| metadata.chunk_hashes.iter().copied())
+            .collect();
+
+        for (hash, size) in self.blocks.list().map_err(io::Error::other)? {
+            if referenced.contains(&hash) {
+                continue;
+            }
+            if !dry_run {
+                self.blocks.delete(&hash).map_err(io::Error::other)?;
+            }
+            report.blocks_removed += 1;
+            report.bytes_reclaimed += size;
+        }
+
+        let active_paths: HashSet<&PathBuf> = self
+            .active_downloads
+            .values()
+            .map(|t| &t.output_path)
+            .collect();
+        for entry in fs::read_dir(self.storage_path.join("downloads"))? {
+            let entry = entry?;
+            let path = entry.path();
+            if !entry.file_type()?.is_file() || active_paths.iter().any(|p| same_file(p, &path)) {
+                continue;
+            }
+            report.bytes_reclaimed += entry.metadata()?.len();
+            if !dry_run {
+                fs::remove_file(&path)?;
+            }
+            report.partial_downloads_removed.push(path);
+        }
+
+        info!(
+            "🧹 GC{}: {} block(s), {} partial download(s), {} bytes",
+            if dry_run { " (dry run)" } else { "" },
+            report.blocks_removed,
+            report.partial_downloads_removed.len(),
+            report.bytes_reclaimed
+        );
+        Ok(report)
+    }
+
+    /// Get the next batch of chunks to request for a file, and count them as
+    /// in flight until they arrive or time out. Chunks already in flight are
+    /// skipped, and fewer than `batch_size` are returned when that would
+    /// exceed `max_chunk_requests_in_flight`.
+    pub fn get_next_chunks_to_request(&mut self, file_id: &str, batch_size: usize) -> Vec<u32> {
+        self.requested_chunks
+            .retain(|_, sent_at| sent_at.elapsed() < CHUNK_REQUEST_TIMEOUT);
+        let room = self
+            .limits
+            .max_chunk_requests_in_flight
+            .saturating_sub(self.requested_chunks.len());
+
+        let Some(transfer) = self.active_downloads.get(file_id) else {
+            return Vec::new();
+        };
+        let chunks: Vec<u32> = transfer
+            .missing_chunks
+            .iter()
+            .filter(|&&chunk_index| {
+                !self
+                    .requested_chunks
+                    .contains_key(&(file_id.to_string(), chunk_index))
+            })
+            .take(batch_size.min(room))
+            .copied()
+            .collect();
+
+        let now = Instant::now();
+        for &chunk_index in &chunks {
+            self.requested_chunks
+                .insert((file_id.to_string(), chunk_index), now);
+        }
+        chunks
+    }
+
+    /// Chunks asked for in a request that failed, to be asked for again
+    /// right away rather than once the request would have timed out
+    pub fn release_chunk_requests(&mut self, file_id: &str, chunk_indices: &[u32]) {
+        for &chunk_index in chunk_indices {
+            self.requested_chunks
+                .remove(&(file_id.to_string(), chunk_index));
         }
     }
 
+    /// Chunk requests still awaiting an answer
+    pub fn chunk_requests_in_flight(&self) -> usize {
+        self.requested_chunks.len()
+    }
+
+    fn forget_chunk_requests(&mut self, file_id: &str) {
+        self.requested_chunks.retain(|(id, _), _| id != file_id);
+    }
+
     /// Get active downloads count
-    #[allow(dead_code)]
     pub fn active_downloads_count(&self) -> usize {
         self.active_downloads.len()
     }
 
+    pub fn completed_downloads_count(&self) -> usize {
+        self.completed_downloads.len()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.chunk_cache.len(),
+            bytes: self.cached_bytes,
+            capacity_bytes: self.limits.max_cached_bytes,
+            hits: self.cache_hits,
+            misses: self.cache_misses,
+        }
+    }
+
     /// Get active uploads count
-    #[allow(dead_code)]
     pub fn active_uploads_count(&self) -> usize {
         self.active_uploads.len()
     }
 
+    /// Running downloads by name, then queued ones in the order they will start
+    pub fn transfer_summaries(&self) -> Vec<TransferSummary> {
+        let mut running: Vec<TransferSummary> = self
+            .active_downloads
+            .values()
+            .map(running_summary)
+            .collect();
+        running.sort_by(|a, b| a.name.cmp(&b.name));
+        running
+            .into_iter()
+            .chain(self.queued_downloads.iter().map(queued_summary))
+            .collect()
+    }
+
+    /// Progress of a running, queued or finished download
+    pub fn transfer_summary(&self, file_id: &str) -> Option<TransferSummary> {
+        if let Some(transfer) = self.active_downloads.get(file_id) {
+            return Some(running_summary(transfer));
+        }
+        if let Some(queued) = self
+            .queued_downloads
+            .iter()
+            .find(|queued| queued.metadata.file_id == file_id)
+        {
+            return Some(queued_summary(queued));
+        }
+        let metadata = self.completed_downloads.get(file_id)?;
+        Some(TransferSummary {
+            file_id: metadata.file_id.clone(),
+            name: metadata.name.clone(),
+            size: metadata.size,
+            state: TransferState::Complete,
+            chunks_received: metadata.total_chunks,
+            total_chunks: metadata.total_chunks,
+            bytes_received: metadata.size,
+            progress: 1.0,
+            sources: Vec::new(),
+        })
+    }
+
     /// Cancel a download
-    #[allow(dead_code)]
     pub fn cancel_download(&mut self, file_id: &str) -> io::Result<()> {
+        if self.is_queued(file_id) {
+            info!("🚫 Cancelled queued download: {}", file_id);
+            self.queued_downloads
+                .retain(|queued| queued.metadata.file_id != file_id);
+            return Ok(());
+        }
         if let Some(transfer) = self.active_downloads.remove(file_id) {
             info!("🚫 Cancelled download: {}", file_id);
+            self.forget_transfer(file_id);
+            self.forget_chunk_requests(file_id);
 
             // Optionally delete partial file
             if transfer.output_path.exists() {
@@ -302,16 +1525,72 @@ impl FileTransferManager {
     }
 }
 
+/// Compare paths that may differ only in relative vs. absolute form
+fn running_summary(transfer: &FileTransfer) -> TransferSummary {
+    TransferSummary {
+        file_id: transfer.metadata.file_id.clone(),
+        name: transfer.metadata.name.clone(),
+        size: transfer.metadata.size,
+        state: TransferState::Downloading,
+        chunks_received: transfer.downloaded_chunks.len() as u32,
+        total_chunks: transfer.metadata.total_chunks,
+        bytes_received: downloaded_bytes(transfer),
+        progress: transfer.progress,
+        sources: transfer.peers.iter().map(|p| p.to_string()).collect(),
+    }
+}
+
+fn queued_summary(queued: &QueuedDownload) -> TransferSummary {
+    TransferSummary {
+        file_id: queued.metadata.file_id.clone(),
+        name: queued.metadata.name.clone(),
+        size: queued.metadata.size,
+        state: TransferState::Queued,
+        chunks_received: 0,
+        total_chunks: queued.metadata.total_chunks,
+        bytes_received: 0,
+        progress: 0.0,
+        sources: queued.peers.iter().map(|p| p.to_string()).collect(),
+    }
+}
+
+/// Bytes of the file covered by a download's chunks so far
+fn downloaded_bytes(transfer: &FileTransfer) -> u64 {
+    let metadata = &transfer.metadata;
+    transfer
+        .downloaded_chunks
+        .iter()
+        .map(|&chunk_index| {
+            let offset = chunk_index as u64 * metadata.chunk_size as u64;
+            metadata
+                .size
+                .saturating_sub(offset)
+                .min(metadata.chunk_size as u64)
+        })
+        .sum()
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use corelink_core::crypto::EncryptionKey;
+    use corelink_core::storage::{EncryptedObjectStore, MemoryObjectStore};
     use std::io::{Read, Write};
+    use std::sync::Arc;
     use tempfile::{tempdir, NamedTempFile};
 
     #[test]
     fn test_offer_file() -> io::Result<()> {
         let storage_dir = tempdir()?;
-        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf())?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
 
         // Create test file
         let mut temp_file = NamedTempFile::new()?;
@@ -330,10 +1609,39 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_reoffer_links_versions() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
+
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(b"first draft")?;
+        let v1 = manager.offer_file(temp_file.path())?;
+        assert_eq!(v1.version, 1);
+
+        temp_file.write_all(b", revised")?;
+        let v2 = manager.offer_file_version(temp_file.path(), Some(&v1.file_id))?;
+        assert_eq!(v2.version, 2);
+        assert_eq!(v2.previous_file_id.as_deref(), Some(v1.file_id.as_str()));
+
+        // History survives withdrawing the old version
+        assert!(manager.withdraw_offer(&v1.file_id));
+        let history: Vec<u32> = manager
+            .version_history(&v2.file_id)
+            .iter()
+            .map(|m| m.version)
+            .collect();
+        assert_eq!(history, vec![2, 1]);
+
+        Ok(())
+    }
+
     #[test]
     fn test_chunk_request() -> io::Result<()> {
         let storage_dir = tempdir()?;
-        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf())?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
 
         // Create and offer test file
         let mut temp_file = NamedTempFile::new()?;
@@ -360,9 +1668,35 @@ mod tests {
     }
 
     #[test]
+    fn test_chunk_stays_requested_while_written() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(b"written elsewhere")?;
+        temp_file.flush()?;
+        let (metadata, mut chunks) = split_file_to_chunks(temp_file.path(), 64 * 1024)?;
+        let output_path = storage_dir.path().join("downloads").join("elsewhere.txt");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+
+        assert_eq!(manager.get_next_chunks_to_request(&file_id, 4), [0]);
+        let write = manager.prepare_chunk(chunks.remove(0))?;
+        // Not asked for again while it is being written
+        assert!(manager.get_next_chunks_to_request(&file_id, 4).is_empty());
+        assert!(matches!(
+            manager.finish_chunk(write.run())?,
+            TransferStatus::TransferComplete
+        ));
+        assert_eq!(manager.chunk_requests_in_flight(), 0);
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::manual_range_contains, clippy::unneeded_struct_pattern)]
     fn test_chunk_received() -> io::Result<()> {
         let storage_dir = tempdir()?;
-        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf())?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
 
         // Create test file and split into chunks
         let mut temp_file = NamedTempFile::new()?;
@@ -381,8 +1715,16 @@ mod tests {
         for chunk in chunks {
             let status = manager.handle_chunk_received(chunk)?;
             match status {
-                TransferStatus::ChunkReceived { progress, .. } => {
+                TransferStatus::ChunkReceived {
+                    progress,
+                    bytes_received,
+                    total_bytes,
+                    chunks_remaining,
+                } => {
                     assert!(progress >= 0.0 && progress <= 1.0);
+                    assert!(bytes_received <= test_data.len() as u64);
+                    assert_eq!(total_bytes, test_data.len() as u64);
+                    assert!(chunks_remaining > 0);
                 }
                 TransferStatus::TransferComplete { .. } => {
                     // Expected for last chunk
@@ -408,10 +1750,64 @@ mod tests {
     }
 
     #[test]
+    fn test_released_chunks_are_requested_again() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
+
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(&vec![7u8; 200 * 1024])?;
+        temp_file.flush()?;
+        let (metadata, _) = split_file_to_chunks(temp_file.path(), 64 * 1024)?;
+        let output_path = storage_dir.path().join("downloads").join("test.dat");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+
+        assert_eq!(manager.get_next_chunks_to_request(&file_id, 2), [0, 1]);
+        assert_eq!(manager.get_next_chunks_to_request(&file_id, 2), [2, 3]);
+        manager.release_chunk_requests(&file_id, &[0, 1]);
+        assert_eq!(manager.chunk_requests_in_flight(), 2);
+        assert_eq!(manager.get_next_chunks_to_request(&file_id, 2), [0, 1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_bitmap() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
+
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(&(0..40).collect::<Vec<u8>>())?;
+        temp_file.flush()?;
+        let (metadata, mut chunks) = split_file_to_chunks(temp_file.path(), 4)?;
+        assert_eq!(metadata.total_chunks, 10);
+
+        let output_path = storage_dir.path().join("downloads").join("bits.dat");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+        assert_eq!(manager.chunk_bitmap(&file_id), Some(vec![0, 0]));
+
+        manager.handle_chunk_received(chunks.remove(9))?;
+        manager.handle_chunk_received(chunks.remove(0))?;
+        assert_eq!(manager.chunk_bitmap(&file_id), Some(vec![0x80, 0x40]));
+
+        for chunk in chunks {
+            manager.handle_chunk_received(chunk)?;
+        }
+        assert_eq!(manager.chunk_bitmap(&file_id), Some(vec![0xff, 0xc0]));
+        assert_eq!(manager.chunk_bitmap("unknown"), None);
+
+        Ok(())
+    }
+
+    #[test]
+    #[allow(clippy::unneeded_struct_pattern)]
     fn test_full_transfer_lifecycle() -> io::Result<()> {
         let storage_dir = tempdir()?;
-        let mut uploader = FileTransferManager::new(storage_dir.path().join("uploader"))?;
-        let mut downloader = FileTransferManager::new(storage_dir.path().join("downloader"))?;
+        let mut uploader =
+            FileTransferManager::new(storage_dir.path().join("uploader"), Storage::new())?;
+        let mut downloader =
+            FileTransferManager::new(storage_dir.path().join("downloader"), Storage::new())?;
 
         // Create test file with multiple chunks
         let mut temp_file = NamedTempFile::new()?;
@@ -473,10 +1869,320 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_state_restored_from_store() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let store = Storage::new();
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), store.clone())?;
+
+        // Offer one file and partially download another
+        let mut offered = NamedTempFile::new()?;
+        offered.write_all(b"Offered file")?;
+        offered.flush()?;
+        let offer = manager.offer_file(offered.path())?;
+
+        let mut remote = NamedTempFile::new()?;
+        let remote_data: Vec<u8> = (0..200_000).map(|i| (i % 256) as u8).collect();
+        remote.write_all(&remote_data)?;
+        remote.flush()?;
+        let (metadata, chunks) = split_file_to_chunks(remote.path(), 64 * 1024)?;
+
+        let output_path = storage_dir.path().join("downloads").join("remote.dat");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+        manager.handle_chunk_received(chunks[0].clone())?;
+        drop(manager);
+
+        // A new manager over the same store picks up where the old one left off
+        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf(), store)?;
+        assert_eq!(manager.active_uploads_count(), 1);
+        assert!(manager.handle_chunk_request(&offer.file_id, 0)?.is_some());
+
+        assert_eq!(manager.active_downloads_count(), 1);
+        assert_eq!(
+            manager.get_next_chunks_to_request(&file_id, 5),
+            vec![1, 2, 3]
+        );
+
+        let peer = PeerId::random();
+        assert!(manager.resume_download(&file_id, peer));
+        assert!(!manager.resume_download(&file_id, peer));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_download_reuses_stored_blocks() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
+
+        // Offering a file puts its chunks in the block store
+        let mut temp_file = NamedTempFile::new()?;
+        let test_data = b"Content shared between two files";
+        temp_file.write_all(test_data)?;
+        temp_file.flush()?;
+        manager.offer_file(temp_file.path())?;
+
+        // Another file with identical content needs no chunks from the network
+        let (mut metadata, _) = split_file_to_chunks(temp_file.path(), 64 * 1024)?;
+        metadata.name = "copy.txt".to_string();
+        let output_path = storage_dir.path().join("downloads").join("copy.txt");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+
+        assert!(!manager.is_downloading(&file_id));
+        let final_path = storage_dir.path().join("complete").join("copy.txt");
+        assert_eq!(fs::read(final_path)?, test_data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_chunks_recovered_after_crash() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let store = Storage::new();
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), store.clone())?;
+
+        let mut remote = NamedTempFile::new()?;
+        let remote_data: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        remote.write_all(&remote_data)?;
+        remote.flush()?;
+        let (metadata, chunks) = split_file_to_chunks(remote.path(), 64 * 1024)?;
+
+        let output_path = storage_dir.path().join("downloads").join("torn.dat");
+        let file_id =
+            manager.request_file(metadata.clone(), output_path.clone(), PeerId::random())?;
+        manager.handle_chunk_received(chunks[0].clone())?;
+        manager.handle_chunk_received(chunks[1].clone())?;
+
+        // Tear both chunks on disk; only chunk 1 loses its block as well
+        let mut file = fs::OpenOptions::new().write(true).open(&output_path)?;
+        for chunk_index in 0..2u64 {
+            io::Seek::seek(&mut file, io::SeekFrom::Start(chunk_index * 64 * 1024 + 10))?;
+            file.write_all(&[0xff; 100])?;
+        }
+        manager
+            .blocks
+            .delete(&metadata.chunk_hashes[1])
+            .map_err(io::Error::other)?;
+        drop(manager);
+
+        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf(), store)?;
+        let report = manager.recover_downloads();
+        assert_eq!(report.resumed.len(), 1);
+        assert_eq!(report.resumed[0].valid_chunks, 1);
+        assert_eq!(report.resumed[0].repaired_chunks, 1);
+        assert_eq!(report.resumed[0].lost_chunks, 1);
+        assert!(manager.resume_download(&file_id, PeerId::random()));
+        assert_eq!(
+            manager.get_next_chunks_to_request(&file_id, 5),
+            vec![1, 2, 3]
+        );
+        assert!(chunk_on_disk_is_valid(&metadata, 0, &output_path)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_chunk_after_blocks_collected() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
+
+        let mut remote = NamedTempFile::new()?;
+        remote.write_all(b"Held in complete/")?;
+        remote.flush()?;
+        let (metadata, chunks) = split_file_to_chunks(remote.path(), 64 * 1024)?;
+
+        let output_path = storage_dir.path().join("downloads").join("held.txt");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+        for chunk in chunks {
+            manager.handle_chunk_received(chunk)?;
+        }
+        assert_eq!(manager.held_files().count(), 1);
+
+        // The unpinned download's blocks go, but the completed file still answers
+        manager.collect_garbage(false)?;
+        assert!(manager.blocks.list().map_err(io::Error::other)?.is_empty());
+        assert_eq!(
+            manager.read_chunk(&file_id, 0)?,
+            Some(b"Held in complete/".to_vec())
+        );
+        assert_eq!(manager.read_chunk(&file_id, 1)?, None);
+        assert_eq!(manager.read_chunk("unknown", 0)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pin_completed_download() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let store = Storage::new();
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), store.clone())?;
+
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(b"Pin me")?;
+        temp_file.flush()?;
+        let (metadata, chunks) = split_file_to_chunks(temp_file.path(), 64 * 1024)?;
+
+        assert!(manager.set_pinned(&metadata.file_id, true).is_err());
+
+        let output_path = storage_dir.path().join("downloads").join("pin.dat");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+        for chunk in chunks {
+            manager.handle_chunk_received(chunk)?;
+        }
+
+        // Completed downloads stay known and can be pinned across restarts
+        manager.set_pinned(&file_id, true)?;
+        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf(), store)?;
+        assert!(manager.is_pinned(&file_id));
+
+        manager.set_pinned(&file_id, false)?;
+        assert!(!manager.is_pinned(&file_id));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_completed_download() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let store = Storage::new();
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), store.clone())?;
+
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(b"Delete me")?;
+        temp_file.flush()?;
+        let (metadata, chunks) = split_file_to_chunks(temp_file.path(), 64 * 1024)?;
+
+        let output_path = storage_dir.path().join("downloads").join("delete.dat");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+        for chunk in chunks {
+            manager.handle_chunk_received(chunk)?;
+        }
+        manager.set_pinned(&file_id, true)?;
+        let saved = manager.completed_path(&file_id).expect("saved to disk");
+
+        manager.delete_completed(&file_id)?;
+        assert!(!saved.exists());
+        assert!(!manager.knows_file(&file_id));
+        assert!(!manager.is_pinned(&file_id));
+
+        // Stays gone across restarts
+        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf(), store)?;
+        assert!(!manager.knows_file(&file_id));
+        assert_eq!(
+            manager.delete_completed(&file_id).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_download_has_no_plaintext_copy() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let blocks = BlockStore::with_backend(Arc::new(EncryptedObjectStore::new(
+            Arc::new(MemoryObjectStore::new()),
+            &EncryptionKey::generate(),
+        )));
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?
+                .with_blocks(blocks)
+                .without_plaintext_files();
+
+        let mut temp_file = NamedTempFile::new()?;
+        temp_file.write_all(b"Top secret contents")?;
+        temp_file.flush()?;
+        let (metadata, chunks) = split_file_to_chunks(temp_file.path(), 64 * 1024)?;
+
+        let output_path = storage_dir.path().join("downloads").join("secret.txt");
+        let file_id = manager.request_file(metadata, output_path.clone(), PeerId::random())?;
+        for chunk in chunks {
+            manager.handle_chunk_received(chunk)?;
+        }
+
+        assert!(!output_path.exists());
+        assert!(!storage_dir
+            .path()
+            .join("complete")
+            .join("secret.txt")
+            .exists());
+
+        // Completed downloads survive GC since the blocks are the only copy
+        manager.collect_garbage(false)?;
+
+        let exported = storage_dir.path().join("exported.txt");
+        manager.export_file(&file_id, &exported)?;
+        assert_eq!(fs::read(&exported)?, b"Top secret contents");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_garbage() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
+
+        // An offered file keeps its blocks
+        let mut offered = NamedTempFile::new()?;
+        offered.write_all(b"Offered and referenced")?;
+        offered.flush()?;
+        manager.offer_file(offered.path())?;
+
+        // Completed downloads are collectable unless pinned
+        let mut pinned = NamedTempFile::new()?;
+        pinned.write_all(b"Downloaded and pinned")?;
+        pinned.flush()?;
+        let mut unpinned = NamedTempFile::new()?;
+        unpinned.write_all(b"Downloaded and forgotten")?;
+        unpinned.flush()?;
+
+        for (i, file) in [&pinned, &unpinned].into_iter().enumerate() {
+            let (metadata, chunks) = split_file_to_chunks(file.path(), 64 * 1024)?;
+            let output_path = storage_dir
+                .path()
+                .join("downloads")
+                .join(format!("{i}.dat"));
+            let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+            for chunk in chunks {
+                manager.handle_chunk_received(chunk)?;
+            }
+            if i == 0 {
+                manager.set_pinned(&file_id, true)?;
+            }
+        }
+
+        // An abandoned partial download
+        let abandoned = storage_dir.path().join("downloads").join("abandoned.dat");
+        fs::write(&abandoned, b"partial")?;
+
+        let report = manager.collect_garbage(true)?;
+        assert_eq!(report.blocks_removed, 1);
+        assert_eq!(report.partial_downloads_removed, vec![abandoned.clone()]);
+        assert!(abandoned.exists());
+
+        let report = manager.collect_garbage(false)?;
+        assert_eq!(report.blocks_removed, 1);
+        assert!(!abandoned.exists());
+        assert_eq!(manager.blocks.list().map_err(io::Error::other)?.len(), 2);
+
+        let report = manager.collect_garbage(false)?;
+        assert_eq!(report.blocks_removed, 0);
+        assert!(report.partial_downloads_removed.is_empty());
+
+        Ok(())
+    }
+
     #[test]
     fn test_cancel_download() -> io::Result<()> {
         let storage_dir = tempdir()?;
-        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf())?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?;
 
         // Create and request test file
         let mut temp_file = NamedTempFile::new()?;
@@ -499,4 +2205,142 @@ mod tests {
 
         Ok(())
     }
+
+    fn metadata_for(dir: &Path, name: &str, contents: &[u8]) -> io::Result<FileMetadata> {
+        let path = dir.join(name);
+        fs::write(&path, contents)?;
+        Ok(split_file_to_chunks(&path, 64 * 1024)?.0)
+    }
+
+    #[test]
+    fn test_downloads_beyond_limit_are_queued() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let source_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?
+                .with_limits(TransferLimits {
+                    max_concurrent_downloads: 1,
+                    ..TransferLimits::default()
+                });
+        let downloads = storage_dir.path().join("downloads");
+        let first = metadata_for(source_dir.path(), "first.txt", b"first")?;
+        let second = metadata_for(source_dir.path(), "second.txt", b"second")?;
+
+        let first_id =
+            manager.request_file(first, downloads.join("first.txt"), PeerId::random())?;
+        let second_id =
+            manager.request_file(second, downloads.join("second.txt"), PeerId::random())?;
+        assert!(manager.is_downloading(&first_id));
+        assert!(manager.is_queued(&second_id));
+        let summaries = manager.transfer_summaries();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].state, TransferState::Downloading);
+        assert_eq!(summaries[1].state, TransferState::Queued);
+        assert!(manager.start_queued_downloads().is_empty());
+
+        // A free slot lets the queued download start
+        manager.cancel_download(&first_id)?;
+        assert_eq!(manager.start_queued_downloads(), vec![second_id.clone()]);
+        assert!(manager.is_downloading(&second_id));
+        assert!(!manager.is_queued(&second_id));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_requests_in_flight_are_capped() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let source_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?
+                .with_limits(TransferLimits {
+                    max_chunk_requests_in_flight: 3,
+                    ..TransferLimits::default()
+                });
+        let metadata = metadata_for(source_dir.path(), "big.bin", &[1; 5 * 64 * 1024])?;
+        let output_path = storage_dir.path().join("downloads").join("big.bin");
+        let file_id = manager.request_file(metadata, output_path, PeerId::random())?;
+
+        assert_eq!(
+            manager.get_next_chunks_to_request(&file_id, 5),
+            vec![0, 1, 2]
+        );
+        // Nothing more until a request is answered
+        assert!(manager.get_next_chunks_to_request(&file_id, 5).is_empty());
+
+        let chunk = FileChunk::new(file_id.clone(), 0, vec![1; 64 * 1024]);
+        manager.handle_chunk_received(chunk)?;
+        assert_eq!(manager.get_next_chunks_to_request(&file_id, 5), vec![3]);
+        assert_eq!(manager.chunk_requests_in_flight(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_cache_stays_within_byte_limit() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), Storage::new())?
+                .with_limits(TransferLimits {
+                    max_cached_bytes: 2 * 64 * 1024,
+                    ..TransferLimits::default()
+                });
+        let path = storage_dir.path().join("big.bin");
+        fs::write(&path, vec![2; 4 * 64 * 1024])?;
+
+        let metadata = manager.offer_file(&path)?;
+        assert_eq!(manager.cached_bytes, 2 * 64 * 1024);
+        assert_eq!(manager.chunk_cache.len(), 2);
+
+        // Evicted chunks are still served from the block store
+        let chunk = manager.handle_chunk_request(&metadata.file_id, 0)?.unwrap();
+        assert_eq!(chunk.data, vec![2; 64 * 1024]);
+        assert_eq!(manager.cached_bytes, 2 * 64 * 1024);
+
+        // ...and cached again
+        manager.handle_chunk_request(&metadata.file_id, 0)?.unwrap();
+        let stats = manager.cache_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.entries, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_downloads_after_crash() -> io::Result<()> {
+        let storage_dir = tempdir()?;
+        let source_dir = tempdir()?;
+        let store = Storage::new();
+        let mut manager =
+            FileTransferManager::new(storage_dir.path().to_path_buf(), store.clone())?;
+        let downloads = storage_dir.path().join("downloads");
+
+        // Chunk 1 reached the disk but the crash came before it was recorded
+        let data: Vec<u8> = (0..3 * 64 * 1024).map(|i| (i % 199) as u8).collect();
+        let path = source_dir.path().join("done.bin");
+        fs::write(&path, &data)?;
+        let (done, chunks) = split_file_to_chunks(&path, 64 * 1024)?;
+        let done_id =
+            manager.request_file(done.clone(), downloads.join("done.bin"), PeerId::random())?;
+        manager.handle_chunk_received(chunks[0].clone())?;
+        manager.handle_chunk_received(chunks[2].clone())?;
+        write_chunk_to_file(&chunks[1], &done, &downloads.join("done.bin"))?;
+
+        let partial = metadata_for(source_dir.path(), "partial.bin", &[5; 2 * 64 * 1024])?;
+        let partial_id =
+            manager.request_file(partial, downloads.join("partial.bin"), PeerId::random())?;
+        fs::write(downloads.join("stray.bin"), b"left over")?;
+        drop(manager);
+
+        let mut manager = FileTransferManager::new(storage_dir.path().to_path_buf(), store)?;
+        let report = manager.recover_downloads();
+        assert_eq!(report.completed, vec![done_id.clone()]);
+        assert!(!manager.is_downloading(&done_id));
+        let completed = fs::read(storage_dir.path().join("complete/done.bin"))?;
+        assert_eq!(completed, data);
+
+        assert_eq!(report.resumed.len(), 1);
+        assert_eq!(report.resumed[0].file_id, partial_id);
+        assert_eq!(report.resumed[0].valid_chunks, 0);
+        assert_eq!(report.removed, vec![downloads.join("stray.bin")]);
+        assert!(downloads.join("partial.bin").exists());
+        Ok(())
+    }
 }
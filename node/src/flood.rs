@@ -0,0 +1,158 @@
+use libp2p_identity::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+/// Messages and transfer requests each peer may send per minute, by type,
+/// in bursts of up to a minute's worth. A peer over a limit is muted: what
+/// it sends is ignored for a while and it loses reputation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FloodLimits {
+    /// Messages of any one type without a limit of its own; 0 lifts it
+    pub messages_per_minute: u32,
+    /// Limits by type, e.g. "FileOffer" or "ChunkRequest"; 0 lifts one
+    pub per_type: BTreeMap<String, u32>,
+    /// How long a peer stays muted
+    pub mute_secs: u64,
+    /// Reputation a peer loses each time it is muted
+    pub penalty: i32,
+}
+
+impl Default for FloodLimits {
+    fn default() -> Self {
+        Self {
+            messages_per_minute: 600,
+            per_type: BTreeMap::from([
+                ("FileOffer".to_string(), 120),
+                // Downloads ask for chunks in quick succession
+                ("ChunkRequest".to_string(), 6000),
+            ]),
+            mute_secs: 60,
+            penalty: 10,
+        }
+    }
+}
+
+impl FloodLimits {
+    fn per_minute(&self, msg_type: &str) -> u32 {
+        self.per_type
+            .get(msg_type)
+            .copied()
+            .unwrap_or(self.messages_per_minute)
+    }
+}
+
+/// What to do with a message from a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allowed,
+    /// The peer is muted; ignore the message
+    Muted,
+    /// The message put the peer over its limit, and it is muted from now
+    Exceeded,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets per peer and message type, and the peers muted
+pub struct FloodGuard {
+    limits: FloodLimits,
+    buckets: HashMap<(PeerId, String), Bucket>,
+    /// When each muted peer is heard again
+    muted: HashMap<PeerId, Instant>,
+}
+
+impl FloodGuard {
+    pub fn new(limits: FloodLimits) -> Self {
+        Self {
+            limits,
+            buckets: HashMap::new(),
+            muted: HashMap::new(),
+        }
+    }
+
+    /// Change the limits. Muted peers stay muted for as long as they were.
+    pub fn set_limits(&mut self, limits: FloodLimits) {
+        self.limits = limits;
+        self.buckets.clear();
+    }
+
+    pub fn limits(&self) -> &FloodLimits {
+        &self.limits
+    }
+
+    /// Count a message of `msg_type` from `peer` received at `now`
+    pub fn check(&mut self, peer: PeerId, msg_type: &str, now: Instant) -> Admission {
+        match self.muted.get(&peer) {
+            Some(&until) if now < until => return Admission::Muted,
+            Some(_) => {
+                self.muted.remove(&peer);
+            }
+            None => {}
+        }
+        let per_minute = self.limits.per_minute(msg_type) as f64;
+        if per_minute == 0.0 {
+            return Admission::Allowed;
+        }
+
+        let bucket = self
+            .buckets
+            .entry((peer, msg_type.to_string()))
+            .or_insert(Bucket {
+                tokens: per_minute,
+                updated: now,
+            });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_minute / 60.0).min(per_minute);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Admission::Allowed;
+        }
+
+        self.muted
+            .insert(peer, now + Duration::from_secs(self.limits.mute_secs));
+        // It starts over with a full allowance once heard again
+        self.buckets.retain(|(muted, _), _| *muted != peer);
+        Admission::Exceeded
+    }
+
+    /// Forget the message counts of a peer that disconnected. A mute
+    /// outlasts the connection, so reconnecting does not lift it.
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.buckets.retain(|(counted, _), _| counted != peer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flooding_peer_is_muted_for_a_while() {
+        let mut guard = FloodGuard::new(FloodLimits {
+            messages_per_minute: 2,
+            per_type: BTreeMap::from([("Ping".to_string(), 0)]),
+            mute_secs: 10,
+            penalty: 10,
+        });
+        let (flooder, other) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        assert_eq!(guard.check(flooder, "FileOffer", now), Admission::Allowed);
+        assert_eq!(guard.check(flooder, "FileOffer", now), Admission::Allowed);
+        assert_eq!(guard.check(flooder, "FileOffer", now), Admission::Exceeded);
+        // Muted for everything, unlimited types included
+        assert_eq!(guard.check(flooder, "Ping", now), Admission::Muted);
+        assert_eq!(guard.check(other, "FileOffer", now), Admission::Allowed);
+
+        let later = now + Duration::from_secs(11);
+        guard.forget(&flooder);
+        assert_eq!(guard.check(flooder, "FileOffer", later), Admission::Allowed);
+        assert_eq!(guard.check(flooder, "Ping", later), Admission::Allowed);
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use utoipa::ToSchema;
+
+/// Thresholds for the health checks behind `/api/v1/health`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Fewer connected peers than this is degraded
+    pub min_peers: usize,
+    /// Less free space than this on the storage volume is degraded
+    pub min_free_bytes: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            min_peers: 1,
+            min_free_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Outcome of one check, or of all of them. Ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: HealthStatus,
+    pub detail: String,
+}
+
+/// Every subsystem's health, and the worst of them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl Default for HealthReport {
+    /// Nothing has been checked yet, so nothing is known to work
+    fn default() -> Self {
+        Self {
+            status: HealthStatus::Unhealthy,
+            checks: Vec::new(),
+        }
+    }
+}
+
+/// What the node's main loop knows about its subsystems
+pub struct HealthInputs<'a> {
+    pub listen_addresses: usize,
+    pub websocket_running: bool,
+    pub peer_count: usize,
+    pub storage_path: &'a Path,
+}
+
+/// Run every check. Touches the storage directory, so call it off the
+/// request path.
+pub fn check(config: &HealthConfig, inputs: &HealthInputs) -> HealthReport {
+    let checks = vec![
+        if inputs.listen_addresses > 0 {
+            healthy(
+                "swarm",
+                format!("listening on {} address(es)", inputs.listen_addresses),
+            )
+        } else {
+            result("swarm", HealthStatus::Unhealthy, "not listening")
+        },
+        if inputs.websocket_running {
+            healthy("websocket", "running")
+        } else {
+            result("websocket", HealthStatus::Unhealthy, "server stopped")
+        },
+        match probe_writable(inputs.storage_path) {
+            Ok(()) => healthy("storage", "writable"),
+            Err(e) => result("storage", HealthStatus::Unhealthy, e.to_string()),
+        },
+        match fs2::available_space(inputs.storage_path) {
+            Ok(free) if free < config.min_free_bytes => result(
+                "disk",
+                HealthStatus::Degraded,
+                format!("{} bytes free, want {}", free, config.min_free_bytes),
+            ),
+            Ok(free) => healthy("disk", format!("{} bytes free", free)),
+            Err(e) => result("disk", HealthStatus::Unhealthy, e.to_string()),
+        },
+        if inputs.peer_count < config.min_peers {
+            result(
+                "peers",
+                HealthStatus::Degraded,
+                format!("{} connected, want {}", inputs.peer_count, config.min_peers),
+            )
+        } else {
+            healthy("peers", format!("{} connected", inputs.peer_count))
+        },
+    ];
+
+    HealthReport {
+        status: checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(HealthStatus::Healthy),
+        checks,
+    }
+}
+
+fn healthy(name: &str, detail: impl Into<String>) -> HealthCheck {
+    result(name, HealthStatus::Healthy, detail)
+}
+
+fn result(name: &str, status: HealthStatus, detail: impl Into<String>) -> HealthCheck {
+    HealthCheck {
+        name: name.to_string(),
+        status,
+        detail: detail.into(),
+    }
+}
+
+fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(".health");
+    std::fs::write(&probe, b"ok")?;
+    std::fs::remove_file(probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worst_check_decides() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut inputs = HealthInputs {
+            listen_addresses: 1,
+            websocket_running: true,
+            peer_count: 3,
+            storage_path: dir.path(),
+        };
+        let config = HealthConfig {
+            min_peers: 2,
+            min_free_bytes: 0,
+        };
+        assert_eq!(check(&config, &inputs).status, HealthStatus::Healthy);
+
+        inputs.peer_count = 1;
+        let report = check(&config, &inputs);
+        assert_eq!(report.status, HealthStatus::Degraded);
+        assert_eq!(report.checks.len(), 5);
+
+        let missing = dir.path().join("missing");
+        inputs.storage_path = &missing;
+        let report = check(&config, &inputs);
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        let storage = report.checks.iter().find(|c| c.name == "storage").unwrap();
+        assert_eq!(storage.status, HealthStatus::Unhealthy);
+    }
+}
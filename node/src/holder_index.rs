@@ -0,0 +1,99 @@
+use corelink_core::Storage;
+use libp2p_identity::PeerId;
+use std::collections::{BTreeSet, HashMap};
+use tracing::warn;
+
+/// Bucket holding the known holders of each file (file_id -> sorted peer ids)
+const HOLDERS_BUCKET: &str = "holders";
+
+/// Index of which peers hold which files, fed by offers and transfer completions
+pub struct HolderIndex {
+    store: Storage,
+    holders: HashMap<String, BTreeSet<String>>,
+}
+
+impl HolderIndex {
+    pub fn new(store: Storage) -> corelink_core::Result<Self> {
+        let store = store.open_bucket(HOLDERS_BUCKET)?;
+        let holders = match store.iter_json() {
+            Ok(holders) => holders.into_iter().collect(),
+            Err(e) => {
+                warn!("Failed to load holder index: {}", e);
+                HashMap::new()
+            }
+        };
+
+        Ok(Self { store, holders })
+    }
+
+    /// Record that `peer` holds `file_id`. Returns false if it was already known.
+    pub fn add_holder(&mut self, file_id: &str, peer: &PeerId) -> bool {
+        let holders = self.holders.entry(file_id.to_string()).or_default();
+        let added = holders.insert(peer.to_string());
+        if added {
+            self.persist(file_id);
+        }
+        added
+    }
+
+    /// Record that `peer` no longer holds `file_id`
+    pub fn remove_holder(&mut self, file_id: &str, peer: &PeerId) -> bool {
+        let removed = self
+            .holders
+            .get_mut(file_id)
+            .is_some_and(|holders| holders.remove(&peer.to_string()));
+        if removed {
+            self.persist(file_id);
+        }
+        removed
+    }
+
+    /// Known holders of a file
+    pub fn holders(&self, file_id: &str) -> Vec<PeerId> {
+        self.holders
+            .get(file_id)
+            .map(|holders| holders.iter().filter_map(|p| p.parse().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    fn persist(&mut self, file_id: &str) {
+        let result = match self.holders.get(file_id) {
+            Some(holders) if !holders.is_empty() => {
+                self.store.insert_json(file_id.to_string(), holders)
+            }
+            _ => {
+                self.holders.remove(file_id);
+                self.store.remove(file_id).map(|_| ())
+            }
+        };
+        if let Err(e) = result {
+            warn!("Failed to persist holders of {}: {}", file_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holders_persist() -> corelink_core::Result<()> {
+        let store = Storage::new();
+        let (a, b) = (PeerId::random(), PeerId::random());
+
+        let mut index = HolderIndex::new(store.clone())?;
+        assert!(index.add_holder("file", &a));
+        assert!(!index.add_holder("file", &a));
+        assert!(index.add_holder("file", &b));
+        assert!(index.remove_holder("file", &b));
+        assert!(!index.remove_holder("file", &b));
+        assert!(index.add_holder("other", &b));
+
+        let index = HolderIndex::new(store)?;
+        assert_eq!(index.holders("file"), vec![a]);
+        assert_eq!(index.holders("other"), vec![b]);
+        assert!(index.holders("missing").is_empty());
+
+        Ok(())
+    }
+}
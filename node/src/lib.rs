@@ -0,0 +1,55 @@
+//! A CoreLink peer-to-peer file sharing node, for running inside another
+//! application. Configure one with [`NodeBuilder`] and control it through
+//! the returned [`NodeHandle`].
+
+mod admission;
+mod api;
+mod api_error;
+mod backup;
+#[cfg(test)]
+mod behaviour_harness;
+mod behaviour_stats;
+mod bridge;
+mod chunk_workers;
+pub mod config;
+mod console;
+mod control;
+mod discovery_cache;
+mod driver;
+mod file_transfer;
+mod flood;
+pub mod health;
+mod holder_index;
+pub mod logging;
+mod maintenance;
+mod messaging_behaviour;
+mod node;
+mod offers;
+mod outbound_queue;
+pub mod partition;
+mod peer_registry;
+mod peer_store;
+mod peer_versions;
+mod protocol_handler;
+mod rate_limit;
+pub mod replication;
+mod reputation;
+mod retry;
+pub mod role;
+pub mod service;
+mod shared_folder;
+mod supervisor;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+mod transfer_activity;
+mod websocket;
+
+pub use api::{
+    FileDetail, FileInfo, FileStatus, NodeInfo, NodeStats, PeerInfo, SourceInfo, StopRequest,
+};
+pub use backup::NodeBackup;
+pub use bridge::{ErrorCategory, NodeEvent, NodeStatus};
+pub use config::NodeConfig;
+pub use file_transfer::{FileRemoval, TransferState, TransferSummary};
+pub use node::{write_backup, NodeBuilder, NodeHandle};
+pub use transfer_activity::TransferError;
@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::Level;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+/// Where logs go and how they look
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Default level plus per-module overrides, e.g.
+    /// `info,corelink_node::messaging_behaviour=debug`
+    pub level: String,
+    pub format: LogFormat,
+    /// Also log to this file, rotated according to `rotation`
+    pub file: Option<PathBuf>,
+    pub rotation: LogRotation,
+    /// Also log operator actions, and only those, to this file, rotated
+    /// like `file`
+    pub audit_file: Option<PathBuf>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            file: None,
+            rotation: LogRotation::Daily,
+            audit_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// How often a new log file is started; old files are kept with a date suffix
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+/// Target of operator actions such as disconnecting or banning a peer,
+/// written to [`LoggingConfig::audit_file`] as well
+pub const AUDIT_TARGET: &str = "audit";
+
+type Layers = Vec<Box<dyn Layer<Registry> + Send + Sync>>;
+
+/// The installed subscriber. Keep it alive for as long as the node runs, or
+/// buffered file logs are lost.
+pub struct Logging {
+    filter: reload::Handle<EnvFilter, Layered<Layers, Registry>>,
+    _guards: Vec<WorkerGuard>,
+}
+
+impl Logging {
+    /// Replace the level directives, e.g. on a config reload
+    pub fn set_level(&self, level: &str) -> io::Result<()> {
+        let filter = parse_level(level)?;
+        self.filter.reload(filter).map_err(io::Error::other)
+    }
+}
+
+pub(crate) fn parse_level(level: &str) -> io::Result<EnvFilter> {
+    EnvFilter::try_new(level).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Install the global subscriber
+pub fn init(config: &LoggingConfig) -> io::Result<Logging> {
+    let (filter, handle) = reload::Layer::new(parse_level(&config.level)?);
+
+    let mut layers: Layers = vec![format_layer(config.format, io::stdout, true)];
+    let mut guards = Vec::new();
+    if let Some(path) = &config.file {
+        let (writer, guard) = file_writer(path, config.rotation)?;
+        layers.push(format_layer(config.format, writer, false));
+        guards.push(guard);
+    }
+    if let Some(path) = &config.audit_file {
+        let (writer, guard) = file_writer(path, config.rotation)?;
+        let audit = Targets::new().with_target(AUDIT_TARGET, Level::TRACE);
+        layers.push(
+            format_layer(config.format, writer, false)
+                .with_filter(audit)
+                .boxed(),
+        );
+        guards.push(guard);
+    }
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .with(filter)
+        .try_init()
+        .map_err(io::Error::other)?;
+    Ok(Logging {
+        filter: handle,
+        _guards: guards,
+    })
+}
+
+/// Non-blocking writer to `path`, rotated every `rotation`
+fn file_writer(path: &Path, rotation: LogRotation) -> io::Result<(NonBlocking, WorkerGuard)> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "log file has no name"))?;
+    let appender = RollingFileAppender::builder()
+        .rotation(rotation.into())
+        .filename_prefix(name)
+        .build(dir)
+        .map_err(io::Error::other)?;
+    Ok(tracing_appender::non_blocking(appender))
+}
+
+fn format_layer<W>(
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
@@ -1,349 +1,221 @@
-mod api;
-mod file_transfer;
-mod messaging_behaviour;
-mod protocol_handler;
-mod websocket;
-
-use api::{start_api_server, ApiState, FileInfo, FileStatus, NodeStats, PeerInfo};
-use futures::StreamExt;
-use libp2p::{
-    identify, identity, mdns, noise, ping, swarm::SwarmEvent, tcp, yamux, Multiaddr, SwarmBuilder,
-};
+use corelink_core::crypto::EncryptionKey;
+use corelink_core::storage;
+use corelink_node::config::{self, NodeConfig, DEFAULT_CONTROL_SOCKET};
+use corelink_node::{logging, service, NodeBackup, NodeBuilder, NodeHandle, StopRequest};
 use std::error::Error;
-use std::path::PathBuf;
-use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::time;
-use tracing::{info, Level};
-use websocket::{start_websocket_server, WsEvent, WsEventSender};
-
-use messaging_behaviour::{MessagingBehaviour, MessagingBehaviourEvent};
-
-#[derive(libp2p::swarm::NetworkBehaviour)]
-struct CoreLinkBehaviour {
-    ping: ping::Behaviour,
-    identify: identify::Behaviour,
-    mdns: mdns::tokio::Behaviour,
-    messaging: MessagingBehaviour,
-}
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Tracing setup
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
-
-    // Parse command line arguments
+    // Load configuration, with command line overrides
     let args: Vec<String> = std::env::args().collect();
-    let port: u16 = args
-        .iter()
-        .position(|arg| arg == "--port")
-        .and_then(|i| args.get(i + 1))
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(4001);
-
-    info!("🚀 Starting CoreLink node on port {}", port);
-
-    // Create identity
-    let local_key = identity::Keypair::generate_ed25519();
-    let local_peer_id = local_key.public().to_peer_id();
-
-    info!("🔑 Peer ID: {}", local_peer_id);
+    let mut config = NodeConfig::from_args(&args)?;
+
+    // A backup brings its own config unless one is given explicitly
+    let restore = match config::arg_value(&args, "--restore") {
+        Some(archive) => {
+            let backup = NodeBackup::read(Path::new(archive))?;
+            if config::arg_value(&args, "--config").is_none() {
+                config = backup.config.clone().with_overrides(&args);
+            }
+            Some(backup)
+        }
+        None => None,
+    };
+    config.validate()?;
+
+    // Tracing setup; dropping it flushes file logs on exit
+    let logger = logging::init(&config.logging)?;
+
+    // Create a new at-rest encryption key and exit
+    if let Some(path) = config::arg_value(&args, "--generate-encryption-key") {
+        EncryptionKey::generate().save(Path::new(path))?;
+        info!("🔑 Wrote new encryption key to {}", path);
+        return Ok(());
+    }
 
-    // Create swarm
-    let mut swarm = SwarmBuilder::with_existing_identity(local_key)
-        .with_tokio()
-        .with_tcp(
-            tcp::Config::default(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
-        .with_behaviour(
-            |key| -> Result<CoreLinkBehaviour, Box<dyn Error + Send + Sync>> {
-                let peer_id = key.public().to_peer_id();
-                Ok(CoreLinkBehaviour {
-                    ping: ping::Behaviour::new(ping::Config::new()),
-                    identify: identify::Behaviour::new(identify::Config::new(
-                        "/corelink/1.0.0".to_string(),
-                        key.public(),
-                    )),
-                    mdns: mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)?,
-                    messaging: MessagingBehaviour::new()?,
-                })
-            },
-        )?
-        .with_swarm_config(|c| {
-            c.with_idle_connection_timeout(Duration::from_secs(60))
-                .with_per_connection_event_buffer_size(64)
-        })
-        .build();
+    // Copy all blocks into another backend and exit
+    if let Some(target) = config::arg_value(&args, "--migrate-storage-to") {
+        let target = NodeConfig::load(Path::new(target))?;
+        info!(
+            "🚚 Migrating blocks from {:?} to {:?}",
+            config.storage, target.storage
+        );
+        let copied = storage::migrate(
+            config.open_object_store()?.as_ref(),
+            target.open_object_store()?.as_ref(),
+        )?;
+        info!("✅ Migrated {} block(s)", copied);
+        return Ok(());
+    }
 
-    // Listen on all interfaces
-    let listen_addr: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", port).parse()?;
-    swarm.listen_on(listen_addr.clone())?;
+    // Package identity, config, state and preserved blocks into an archive and exit
+    if let Some(archive) = config::arg_value(&args, "--backup") {
+        corelink_node::write_backup(&config, Path::new(archive))?;
+        return Ok(());
+    }
 
-    info!("👂 Listening on {}", listen_addr);
+    let port = config.port;
+
+    // Interactive commands on stdin, unless running as a background service,
+    // where a control socket takes their place
+    let daemon = args.iter().any(|arg| arg == "--daemon");
+    let default_socket = (daemon && config.control_socket.is_none())
+        .then(|| config.storage_path.join(DEFAULT_CONTROL_SOCKET));
+    let mut builder = NodeBuilder::new(config)
+        .with_args(args.clone())
+        .with_logging(logger);
+    if let Some(path) = default_socket {
+        builder = builder.with_control_socket(path);
+    }
+    if let Some(backup) = restore {
+        builder = builder.with_restore(backup);
+    }
+    if !daemon {
+        builder = builder.with_console();
+    }
+    if let Some(period) = service::watchdog_interval() {
+        builder = builder.with_watchdog(period);
+    }
+    let node = builder.start().await?;
 
-    // Start WebSocket server (derive port from node port: 4001 -> 8001, 4002 -> 8002, etc.)
-    let ws_port = port + 4000;
-    let ws_addr = format!("127.0.0.1:{}", ws_port);
-    let ws_tx = start_websocket_server(&ws_addr)
-        .await
-        .expect("Failed to start WebSocket server");
-    info!("🌐 WebSocket server ready at ws://{}", ws_addr);
+    // Let systemd and friends know once peers and clients can reach us
+    node.ready().await;
+    service::notify_ready(&format!("Listening on port {}", port));
 
-    // Create API state and start REST API server (derive port from node port: 4001 -> 7001, 4002 -> 7002, etc.)
-    let api_state = ApiState::new();
-    let api_state_clone = api_state.clone();
-    let api_port = port + 3000;
-    let api_addr = format!("127.0.0.1:{}", api_port);
-    let api_addr_clone = api_addr.clone();
+    let stop = run_until_shutdown(&node).await;
+    match stop {
+        StopRequest::Shutdown => service::notify_stopping(),
+        StopRequest::Restart => service::notify_restarting(),
+    }
+    node.shutdown().await;
+    if stop == StopRequest::Restart {
+        return Err(restart(&args).into());
+    }
+    Ok(())
+}
 
-    tokio::spawn(async move {
-        if let Err(e) = start_api_server(&api_addr_clone, api_state_clone).await {
-            tracing::error!("API server error: {}", e);
+/// Wait for a shutdown signal or an admin's request through the API,
+/// reloading the config file on every SIGHUP
+async fn run_until_shutdown(node: &NodeHandle) -> StopRequest {
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            tracing::warn!("Failed to listen for SIGHUP: {}", e);
+            None
         }
-    });
-    info!("🌐 REST API server ready at http://{}", api_addr);
-
-    // Track start time for uptime calculation
-    let start_time = std::time::Instant::now();
-
-    // Setup stdin for interactive commands
-    let stdin = BufReader::new(tokio::io::stdin());
-    let mut lines = stdin.lines();
-    info!("💡 Commands: 'offer' to share test.txt, 'help' for more");
-
-    // Discovery broadcast interval
-    let mut discovery_interval = time::interval(Duration::from_secs(10));
-
-    // Status broadcast interval (every 5 seconds)
-    let mut status_interval = time::interval(Duration::from_secs(5));
+    };
 
     loop {
-        tokio::select! {
-            event = swarm.select_next_some() => {
-                match event {
-                    SwarmEvent::NewListenAddr { address, .. } => {
-                        info!("📍 Listening on {}", address);
-                    }
-                    SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
-                        for (peer_id, addr) in list {
-                            info!("🔍 Discovered peer: {} at {}", peer_id, addr);
-                            if let Err(e) = swarm.dial(addr.clone()) {
-                                info!("❌ Failed to dial {}: {:?}", peer_id, e);
-                            }
-                        }
-                    }
-                    SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
-                        for (peer_id, _) in list {
-                            info!("🕳️ Peer expired: {}", peer_id);
-                        }
-                    }
-                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
-                        info!("✅ Connection established with {} via {}", peer_id, endpoint.get_remote_address());
-
-                        // Broadcast to WebSocket clients
-                        broadcast_ws_event(&ws_tx, WsEvent::PeerConnected {
-                            peer_id: peer_id.to_string(),
-                            address: endpoint.get_remote_address().to_string(),
-                            timestamp: current_timestamp(),
-                        });
-                    }
-                    SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
-                        info!("❌ Connection closed with {}: {:?}", peer_id, cause);
-
-                        // Broadcast to WebSocket clients
-                        broadcast_ws_event(&ws_tx, WsEvent::PeerDisconnected {
-                            peer_id: peer_id.to_string(),
-                            timestamp: current_timestamp(),
-                        });
-                    }
-                    SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Ping(ping::Event { peer, result, .. })) => {
-                        match result {
-                            Ok(rtt) => info!("🏓 Ping to {}: {:?}", peer, rtt),
-                            Err(e) => info!("❌ Ping failed to {}: {:?}", peer, e),
-                        }
-                    }
-                    SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Identify(identify::Event::Received { peer_id, info })) => {
-                        info!("🆔 Identified {}: {:?}", peer_id, info.protocol_version);
-                    }
-                    SwarmEvent::Behaviour(CoreLinkBehaviourEvent::Messaging(event)) => {
-                        match event {
-                            MessagingBehaviourEvent::MessageReceived { from, message } => {
-                                info!("📬 Messaging event: MessageReceived from {}: {:?}", from, message.msg_type);
-                            }
-                            MessagingBehaviourEvent::MessageSent { to } => {
-                                info!("✅ Message sent to {}", to);
-                            }
-                            MessagingBehaviourEvent::SendError { to, error } => {
-                                info!("❌ Failed to send message to {}: {}", to, error);
-                            }
-                            MessagingBehaviourEvent::FileOffered { peer, metadata } => {
-                                info!(
-                                    "📁 File offered by {}: {} ({} bytes, {} chunks)",
-                                    peer, metadata.name, metadata.size, metadata.total_chunks
-                                );
-
-                                // Broadcast to WebSocket clients
-                                broadcast_ws_event(&ws_tx, WsEvent::FileOffered {
-                                    peer_id: peer.to_string(),
-                                    file_id: metadata.file_id.clone(),
-                                    name: metadata.name.clone(),
-                                    size: metadata.size,
-                                    chunks: metadata.total_chunks,
-                                    timestamp: current_timestamp(),
-                                });
-
-                                // Update API state
-                                api_state.add_file(FileInfo {
-                                    file_id: metadata.file_id.clone(),
-                                    name: metadata.name.clone(),
-                                    size: metadata.size,
-                                    chunks: metadata.total_chunks,
-                                    status: FileStatus::Downloading,
-                                    progress: 0.0,
-                                    peer_id: Some(peer.to_string()),
-                                }).await;
-                            }
-                            MessagingBehaviourEvent::ChunkReceived { file_id, progress } => {
-                                info!("📦 Chunk received for {}: {:.1}%", file_id, progress * 100.0);
-
-                                // Broadcast to WebSocket clients
-                                broadcast_ws_event(&ws_tx, WsEvent::ChunkReceived {
-                                    file_id: file_id.clone(),
-                                    chunk_index: 0, // TODO: track actual chunk index
-                                    progress,
-                                    timestamp: current_timestamp(),
-                                });
-
-                                // Update API state progress
-                                api_state.update_file_progress(&file_id, progress).await;
-                            }
-                            MessagingBehaviourEvent::TransferComplete { file_id } => {
-                                info!("✅ File transfer complete: {}", file_id);
-
-                                // Broadcast to WebSocket clients
-                                // TODO: Get actual name and size from file_manager
-                                broadcast_ws_event(&ws_tx, WsEvent::TransferComplete {
-                                    file_id: file_id.clone(),
-                                    name: "unknown".to_string(),
-                                    size: 0,
-                                    timestamp: current_timestamp(),
-                                });
-
-                                // Update API state
-                                api_state.update_file_status(&file_id, FileStatus::Complete).await;
-                                api_state.update_file_progress(&file_id, 1.0).await;
-                            }
-                            MessagingBehaviourEvent::TransferFailed { file_id, reason } => {
-                                info!("❌ File transfer failed {}: {}", file_id, reason);
-
-                                // Broadcast to WebSocket clients
-                                broadcast_ws_event(&ws_tx, WsEvent::TransferFailed {
-                                    file_id: file_id.clone(),
-                                    reason: reason.clone(),
-                                    timestamp: current_timestamp(),
-                                });
-
-                                // Update API state
-                                api_state.update_file_status(&file_id, FileStatus::Failed).await;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            _ = discovery_interval.tick() => {
-                let connected_peers: Vec<_> = swarm.connected_peers().cloned().collect();
-                if !connected_peers.is_empty() {
-                    info!("📡 Broadcasting discovery to {} peers", connected_peers.len());
-                    swarm.behaviour_mut().messaging.broadcast_discovery();
-                } else {
-                    info!("⏳ No peers connected yet, waiting for discovery...");
-                }
+        #[cfg(unix)]
+        let reload = async {
+            match hangup.as_mut() {
+                Some(hangup) => hangup.recv().await,
+                None => std::future::pending().await,
             }
-            _ = status_interval.tick() => {
-                // Update stats every 5 seconds
-                let peer_count = swarm.connected_peers().count();
-                let uptime_seconds = start_time.elapsed().as_secs();
-
-                // Broadcast to WebSocket clients
-                broadcast_ws_event(&ws_tx, WsEvent::NodeStatus {
-                    peer_count,
-                    active_uploads: 0, // TODO: get from file_manager
-                    active_downloads: 0, // TODO: get from file_manager
-                    timestamp: current_timestamp(),
-                });
+        };
+        #[cfg(not(unix))]
+        let reload = std::future::pending::<Option<()>>();
 
-                // Update REST API state
-                api_state.update_stats(NodeStats {
-                    peer_count,
-                    active_uploads: 0, // TODO: get from file_manager
-                    active_downloads: 0, // TODO: get from file_manager
-                    uptime_seconds,
-                    bytes_sent: 0, // TODO: track bytes
-                    bytes_received: 0, // TODO: track bytes
-                }).await;
-
-                // Update peer list in API
-                let peers: Vec<PeerInfo> = swarm.connected_peers()
-                    .map(|peer_id| PeerInfo {
-                        peer_id: peer_id.to_string(),
-                        addresses: vec![], // TODO: get actual addresses
-                        connected_since: current_timestamp(), // TODO: track actual connection time
-                        protocol_version: "corelink/1.0.0".to_string(),
-                    })
-                    .collect();
-                api_state.update_peers(peers).await;
-            }
-            line = lines.next_line() => {
-                if let Ok(Some(cmd)) = line {
-                    match cmd.trim() {
-                        "offer" => {
-                            // Create test file if doesn't exist
-                            let test_file = PathBuf::from("test.txt");
-                            if !test_file.exists() {
-                                std::fs::write(&test_file, b"Hello CoreLink! This is a test file.\nChunk-based transfer protocol working!\nSHA256 verification enabled.")?;
-                                info!("📝 Created test.txt");
-                            }
-                            // Offer file
-                            match swarm.behaviour_mut().messaging.offer_file(&test_file) {
-                                Ok(metadata) => {
-                                    info!("📤 Offering: {} ({} bytes, {} chunks)",
-                                          metadata.name, metadata.size, metadata.total_chunks);
-                                }
-                                Err(e) => info!("❌ Failed: {}", e),
-                            }
-                        }
-                        "help" => {
-                            info!("Commands:");
-                            info!("  offer - Share test.txt with connected peers");
-                            info!("  help  - Show this help");
-                        }
-                        "" => {} // Ignore empty input
-                        _ => info!("Unknown: '{}'. Type 'help'", cmd),
-                    }
-                }
+        tokio::select! {
+            _ = &mut shutdown => return StopRequest::Shutdown,
+            stop = node.stop_requested() => return stop,
+            Some(()) = reload => {
+                info!("🔄 SIGHUP received, reloading config");
+                // The driver logs the outcome
+                let _ = node.reload_config().await;
             }
         }
     }
 }
 
-/// Broadcast an event to all connected WebSocket clients
-fn broadcast_ws_event(tx: &WsEventSender, event: WsEvent) {
-    if let Err(_e) = tx.send(event) {
-        // No subscribers is ok, don't log error
-        // Only log if there are actual subscribers who failed to receive
-        if tx.receiver_count() > 0 {
-            tracing::warn!("Failed to broadcast WebSocket event");
+/// Replace this process with a new run of the same binary and arguments, so
+/// the config file, and a replaced binary, are read afresh. Returns only if
+/// that fails.
+fn restart(args: &[String]) -> io::Error {
+    info!("🔁 Restarting");
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return e,
+    };
+    // The backup was restored on the first run
+    let mut rest = args.iter().skip(1);
+    let mut args = Vec::new();
+    while let Some(arg) = rest.next() {
+        if arg == "--restore" {
+            rest.next();
+        } else {
+            args.push(arg);
         }
     }
+
+    let mut command = Command::new(exe);
+    command.args(args);
+    #[cfg(unix)]
+    {
+        // Keeps the process id, so service managers see the same node
+        use std::os::unix::process::CommandExt;
+        command.exec()
+    }
+    #[cfg(not(unix))]
+    match command.spawn() {
+        Ok(_) => std::process::exit(0),
+        Err(e) => e,
+    }
 }
 
-/// Get current Unix timestamp
-fn current_timestamp() -> u64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs()
+/// Resolves on Ctrl+C, SIGTERM on Unix, or on Windows when the console is
+/// closed or the system shuts down, as when a service wrapper stops the node
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(windows)]
+    let terminate = async {
+        use tokio::signal::windows::{ctrl_break, ctrl_close, ctrl_shutdown};
+        match (ctrl_close(), ctrl_shutdown(), ctrl_break()) {
+            (Ok(mut close), Ok(mut shutdown), Ok(mut brk)) => {
+                tokio::select! {
+                    _ = close.recv() => {}
+                    _ = shutdown.recv() => {}
+                    _ = brk.recv() => {}
+                }
+            }
+            _ => {
+                tracing::warn!("Failed to listen for console close events");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(any(unix, windows)))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
@@ -0,0 +1,50 @@
+use crate::config::TieringConfig;
+use corelink_core::storage::TieredObjectStore;
+use corelink_core::Storage;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+use tracing::{debug, info, warn};
+
+/// Storage housekeeping that does not involve the swarm: sweeping expired
+/// entries and moving blocks between hot and cold tiers. Runs forever.
+pub async fn run(store: Storage, tiering: Option<(Arc<TieredObjectStore>, TieringConfig)>) {
+    // Background sweep of expired storage entries
+    let mut expiry_interval = time::interval(Duration::from_secs(60));
+
+    // Hot/cold block tiering; the branch is disabled when tiering is not configured
+    let period = Duration::from_secs(
+        tiering
+            .as_ref()
+            .map_or(3600, |(_, tiering)| tiering.interval_secs),
+    );
+    let mut tiering_interval = time::interval_at(time::Instant::now() + period, period);
+
+    loop {
+        tokio::select! {
+            _ = expiry_interval.tick() => {
+                let store = store.clone();
+                match tokio::task::spawn_blocking(move || store.purge_expired()).await {
+                    Ok(Ok(0)) => {}
+                    Ok(Ok(purged)) => debug!("⌛ Purged {} expired storage entries", purged),
+                    Ok(Err(e)) => warn!("Failed to purge expired storage entries: {}", e),
+                    Err(e) => warn!("Expiry task panicked: {}", e),
+                }
+            }
+            _ = tiering_interval.tick(), if tiering.is_some() => {
+                let Some((tiers, tiering)) = tiering.clone() else {
+                    continue;
+                };
+                let policy = tiering.policy();
+                match tokio::task::spawn_blocking(move || tiers.apply_policy(&policy)).await {
+                    Ok(Ok(report)) => info!(
+                        "🧊 Tiering: {} block(s) promoted, {} demoted",
+                        report.promoted, report.demoted
+                    ),
+                    Ok(Err(e)) => warn!("Tiering pass failed: {}", e),
+                    Err(e) => warn!("Tiering task panicked: {}", e),
+                }
+            }
+        }
+    }
+}
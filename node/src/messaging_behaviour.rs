@@ -1,19 +1,92 @@
-use crate::file_transfer::{FileTransferManager, TransferStatus};
-use crate::protocol_handler::{CoreLinkHandler, CoreLinkHandlerEvent};
-use corelink_core::file::FileMetadata;
+use crate::admission::{ConnectionLimits, ConnectionTracker};
+use crate::behaviour_stats::BehaviourStats;
+use crate::chunk_workers::ChunkWorkers;
+use crate::discovery_cache::DiscoveryCache;
+use crate::file_transfer::{
+    CacheStats, ChunkWritten, FileRemoval, FileTransferManager, GcReport, RecoveryReport,
+    TransferLimits, TransferStatus, TransferSummary, CHUNK_REQUEST_TIMEOUT,
+};
+use crate::flood::{Admission, FloodGuard, FloodLimits};
+use crate::holder_index::HolderIndex;
+use crate::offers::{OfferMode, PendingOffer, PendingOffers};
+use crate::outbound_queue::{OutboundQueues, OverflowPolicy, QueueLimits, Queued};
+use crate::peer_store::{BACKUP_TARGET_TAG, FLAKY_TAG, TRUSTED_TAG};
+use crate::peer_versions::PeerVersions;
+use crate::protocol_handler::{CoreLinkHandler, CoreLinkHandlerEvent, CoreLinkHandlerIn, Outgoing};
+use crate::replication::{ReplicationHealth, ReplicationManager};
+use crate::reputation::ReputationTracker;
+use crate::retry::{PendingSends, Retry, RetryPolicy};
+use crate::role::NodeRole;
+use crate::transfer_activity::{ActivityState, TransferActivity, UploadActivity};
+use corelink_core::consensus::Consensus;
+use corelink_core::file::{name_matches, storage_proof, FileChunk, FileMetadata};
 use corelink_core::identity::NodeId;
-use corelink_core::message::{DiscoveryMessage, Message, MessageType};
-use libp2p_core::{Endpoint, Multiaddr};
-use libp2p_identity::PeerId;
+use corelink_core::message::{
+    CatalogEntry, DiscoveryMessage, Message, MessageType, CHUNK_BATCH_FEATURE, FEATURES,
+    FILE_CATALOG_FEATURE,
+};
+use corelink_core::network::{self, NetworkState};
+use corelink_core::{
+    BlockStore, Storage, TransferCodec, TransferRequest, TransferResponse, MAX_CHUNK_BATCH,
+    MESSAGE_PROTOCOL, PROTOCOL_VERSION, TRANSFER_PROTOCOL,
+};
+use either::Either;
+use libp2p_core::{multiaddr::Protocol, Endpoint, Multiaddr};
+use libp2p_identity::{Keypair, PeerId};
+use libp2p_request_response::{self as request_response, OutboundRequestId, ProtocolSupport};
 use libp2p_swarm::{
-    ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, NotifyHandler, THandler,
-    THandlerInEvent, THandlerOutEvent, ToSwarm,
+    CloseConnection, ConnectionDenied, ConnectionHandler, ConnectionHandlerSelect, ConnectionId,
+    FromSwarm, NetworkBehaviour, NotifyHandler, StreamProtocol, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm,
 };
-use std::collections::{HashMap, VecDeque};
+use rand::Rng;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::task::{Context, Poll};
-use tracing::{error, info, warn};
+use std::time::{Duration, Instant};
+use tokio::time::Sleep;
+use tracing::{debug, error, info, warn};
+
+/// Capability of peers that accept file replicas
+pub const STORAGE_CAPABILITY: &str = "storage";
+/// Capability of peers that run compute tasks
+pub const COMPUTE_CAPABILITY: &str = "compute";
+/// Capability of peers that relay connections for others
+pub const RELAY_CAPABILITY: &str = "relay";
+
+/// How long a holder has to answer a storage challenge
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Largest slice of a chunk a storage challenge covers
+const CHALLENGE_MAX_SLICE: u32 = 4096;
+/// Smallest slice of a chunk a storage challenge may cover, unless it covers
+/// the whole chunk; proofs of shorter slices would give their bytes away
+const CHALLENGE_MIN_SLICE: u32 = 32;
+/// Reputation change for a valid storage proof
+const PROOF_REWARD: i32 = 1;
+/// Reputation change for a wrong, missing or late storage proof
+const PROOF_PENALTY: i32 = -20;
+/// Bans whose end the clock cannot represent last this long instead
+const LONGEST_BAN: Duration = Duration::from_secs(100 * 365 * 24 * 3600);
+
+/// How often connections are told whether transfers keep them open
+const KEEP_ALIVE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most files one answer to a file query lists
+pub const MAX_QUERY_RESULTS: u32 = 100;
+
+/// How long offers wait for an answer in manual mode, unless configured
+pub const DEFAULT_OFFER_EXPIRY_SECS: u64 = 3600;
+
+/// A storage challenge awaiting its proof
+struct PendingChallenge {
+    peer: PeerId,
+    file_id: String,
+    expected: [u8; 32],
+    sent_at: Instant,
+}
 
 #[derive(Debug)]
 pub enum MessagingBehaviourEvent {
@@ -21,139 +94,2213 @@ pub enum MessagingBehaviourEvent {
         from: PeerId,
         message: Message,
     },
+    /// A message was written to `to`; `receipt` is set for messages sent
+    /// with [`MessagingBehaviour::send_custom`]
     MessageSent {
         to: PeerId,
+        receipt: Option<u64>,
     },
+    /// A message to `to` will not be sent: it failed its last attempt, or
+    /// could not be queued
     SendError {
         to: PeerId,
         error: String,
+        receipt: Option<u64>,
+        /// Name of the message's type, e.g. "Custom"
+        msg_type: String,
+        attempts: u32,
+    },
+    /// A peer cannot understand some or all of our messages, which are
+    /// not sent to it; reported once per peer and reason
+    IncompatiblePeer {
+        peer: PeerId,
+        reason: String,
+    },
+    /// Messages for `to` were dropped from its queue without being sent
+    MessagesDropped {
+        to: PeerId,
+        count: usize,
+        reason: String,
+    },
+    /// A peer sent an application message whose signature does not match it
+    BadSignature {
+        from: PeerId,
+        msg_type: String,
+    },
+    /// A peer sent more messages of `msg_type` than allowed, so what it
+    /// sends is ignored for `duration`
+    PeerMuted {
+        peer: PeerId,
+        msg_type: String,
+        duration: Duration,
+    },
+    /// A message from `from` moved the node to a newer consensus epoch
+    EpochChanged {
+        from: PeerId,
+        previous: u64,
+        epoch: u64,
+    },
+    /// A registry update from `from` was dropped for carrying an older epoch
+    StaleEpochRejected {
+        from: PeerId,
+        msg_type: String,
+        epoch: u64,
+        current: u64,
+    },
+    /// A peer sent a correctly signed application message
+    CustomMessage {
+        from: PeerId,
+        msg_type: String,
+        payload: serde_json::Value,
     },
     // File transfer events
     FileOffered {
         peer: PeerId,
         metadata: FileMetadata,
+        /// Waiting to be accepted or rejected rather than downloading
+        pending: bool,
+    },
+    /// An offer waiting in manual mode went unanswered, or every peer that
+    /// made it withdrew it
+    OfferClosed {
+        file_id: String,
+        removal: FileRemoval,
+    },
+    /// A peer declined a file this node offered it
+    OfferRejected {
+        peer: PeerId,
+        file_id: String,
     },
     ChunkReceived {
         file_id: String,
+        chunk_index: u32,
+        /// Peer that sent the chunk
+        peer: PeerId,
+        /// Size of this chunk in bytes
+        chunk_size: u64,
+        /// Bytes of the file received so far
+        bytes_received: u64,
+        total_bytes: u64,
+        chunks_remaining: u32,
         progress: f32,
+        /// Bytes per second over the last few seconds
+        speed: f64,
     },
     TransferComplete {
-        file_id: String,
+        metadata: FileMetadata,
+        /// Where the file was saved; None when it only lives in the block store
+        path: Option<PathBuf>,
     },
     TransferFailed {
         file_id: String,
         reason: String,
     },
+    /// The set of peers known to hold a file changed
+    HoldersChanged {
+        file_id: String,
+        holders: Vec<PeerId>,
+    },
+    /// A peer shared the addresses of peers it is connected to
+    PeersAdvertised {
+        from: PeerId,
+        addresses: Vec<Multiaddr>,
+    },
+    /// A peer answered a file query with the matching files it offers
+    QueryResults {
+        query_id: u64,
+        peer: PeerId,
+        files: Vec<FileMetadata>,
+    },
+    /// A peer asked for a file by id holds it, and now counts as offering it
+    FileFound {
+        peer: PeerId,
+        metadata: FileMetadata,
+    },
+    /// A peer asked for a file by id does not hold it, or did not answer
+    FileNotFound {
+        peer: PeerId,
+        file_id: String,
+        error: String,
+    },
 }
 
+/// Sends fire-and-forget messages over the CoreLink protocol, and requests
+/// chunks and files over the request-response transfer protocol
 pub struct MessagingBehaviour {
     connected_peers: HashMap<PeerId, Vec<ConnectionId>>,
-    pending_handler_messages: VecDeque<(PeerId, Message)>,
+    /// Whether each connection can carry messages, once its handler knows
+    messaging_streams: HashMap<ConnectionId, bool>,
+    /// Messages waiting to be handed to each peer's connection
+    outbound: OutboundQueues,
+    /// Messages not yet written, retried when writing them fails
+    sends: PendingSends,
+    /// Wakes the behaviour when the next retry is due
+    retry_timer: Option<Pin<Box<Sleep>>>,
+    stats: BehaviourStats,
     pending_events: VecDeque<MessagingBehaviourEvent>,
     file_manager: FileTransferManager,
+    /// Verify and store received chunks off the swarm's thread
+    chunk_workers: ChunkWorkers,
+    consensus: Consensus,
+    /// Registry updates rejected for coming from a stale epoch
+    stale_messages: u64,
+    replication: ReplicationManager,
+    replication_factor: usize,
+    holders: HolderIndex,
+    /// What peers last announced, to know a reconnecting peer right away
+    discoveries: DiscoveryCache,
+    reputation: ReputationTracker,
+    challenges: HashMap<[u8; 32], PendingChallenge>,
+    network: NetworkState,
+    /// When each outstanding chunk request was sent, and to whom
+    chunk_requests: HashMap<(String, u32), (PeerId, Instant)>,
+    /// Chunk and file requests, answered on their own streams
+    transfers: request_response::Behaviour<TransferCodec>,
+    /// Chunks each transfer request in flight asked for
+    outbound_chunks: HashMap<OutboundRequestId, (String, Vec<u32>)>,
+    /// File each file request in flight asked for, and the Merkle root
+    /// its catalog entry gave if it was listed in one
+    outbound_files: HashMap<OutboundRequestId, (String, Option<[u8; 32]>)>,
+    /// Peers sent our catalog since they connected
+    catalogs_sent: HashSet<PeerId>,
+    /// Peers each connected peer last said it is connected to
+    peer_links: HashMap<PeerId, HashSet<PeerId>>,
+    /// Messages and transfer requests each peer sent recently
+    flood: FloodGuard,
+    connections: ConnectionTracker,
+    /// Files offered by peers, and which peers offered them
+    remote_offers: HashMap<String, (FileMetadata, HashSet<PeerId>)>,
+    offer_mode: OfferMode,
+    /// Offers waiting to be accepted or rejected in manual mode
+    pending_offers: PendingOffers,
+    /// Operator tags steering source and replica selection
+    peer_tags: HashMap<PeerId, Vec<String>>,
+    /// Sources asked for when a download was started, tried before any other
+    preferred_sources: HashMap<String, PeerId>,
+    /// Sources, speed and errors of downloads, until the file is removed
+    transfer_activity: HashMap<String, TransferActivity>,
+    /// Peers served chunks of our files and how fast, per file
+    upload_activity: HashMap<String, UploadActivity>,
+    /// Peers evicted to make room for better ones, waiting to be disconnected
+    pending_disconnects: VecDeque<(PeerId, ConnectionId)>,
+    /// Peers transfers or queued messages are under way with, whose
+    /// connections stay open while quiet
+    kept_alive: HashSet<PeerId>,
+    keep_alive_checked: Option<Instant>,
+    /// Keep-alive changes waiting to be told to connections
+    keep_alive_updates: VecDeque<(PeerId, ConnectionId, bool)>,
+    role: NodeRole,
+    /// Operator labels advertised in discovery
+    labels: Vec<String>,
+    /// Listen addresses of connected peers, shared in discovery
+    peer_addresses: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Addresses this node listens on, and external ones peers confirmed
+    local_addresses: Vec<Multiaddr>,
+    /// Space left for files, advertised in discovery
+    free_bytes: Option<u64>,
+    /// Protocol versions and features of connected peers
+    versions: PeerVersions,
+    /// Key application messages are signed with
+    identity: Option<Keypair>,
+    /// Receipt of the next application message sent
+    next_receipt: u64,
 }
 
 impl MessagingBehaviour {
-    pub fn new() -> io::Result<Self> {
-        let file_manager = FileTransferManager::new(PathBuf::from("./storage"))?;
+    pub fn new(storage_path: PathBuf, store: Storage) -> io::Result<Self> {
+        let file_manager = FileTransferManager::new(storage_path, store.clone())?;
+        let holders = HolderIndex::new(store.clone()).map_err(io::Error::other)?;
+        let discoveries = DiscoveryCache::new(store).map_err(io::Error::other)?;
         Ok(Self {
             connected_peers: HashMap::new(),
-            pending_handler_messages: VecDeque::new(),
+            messaging_streams: HashMap::new(),
+            outbound: OutboundQueues::new(QueueLimits::default()),
+            sends: PendingSends::new(RetryPolicy::default()),
+            retry_timer: None,
+            stats: BehaviourStats::default(),
             pending_events: VecDeque::new(),
             file_manager,
+            chunk_workers: ChunkWorkers::new(),
+            consensus: Consensus::new(),
+            stale_messages: 0,
+            replication: ReplicationManager::new(),
+            replication_factor: 0,
+            holders,
+            discoveries,
+            reputation: ReputationTracker::new(),
+            challenges: HashMap::new(),
+            network: NetworkState::new(),
+            chunk_requests: HashMap::new(),
+            transfers: request_response::Behaviour::with_codec(
+                TransferCodec,
+                [(TRANSFER_PROTOCOL, ProtocolSupport::Full)],
+                request_response::Config::default().with_request_timeout(CHUNK_REQUEST_TIMEOUT),
+            ),
+            outbound_chunks: HashMap::new(),
+            outbound_files: HashMap::new(),
+            catalogs_sent: HashSet::new(),
+            peer_links: HashMap::new(),
+            flood: FloodGuard::new(FloodLimits::default()),
+            connections: ConnectionTracker::new(ConnectionLimits::default()),
+            remote_offers: HashMap::new(),
+            offer_mode: OfferMode::default(),
+            pending_offers: PendingOffers::new(DEFAULT_OFFER_EXPIRY_SECS),
+            peer_tags: HashMap::new(),
+            preferred_sources: HashMap::new(),
+            transfer_activity: HashMap::new(),
+            upload_activity: HashMap::new(),
+            pending_disconnects: VecDeque::new(),
+            kept_alive: HashSet::new(),
+            keep_alive_checked: None,
+            keep_alive_updates: VecDeque::new(),
+            role: NodeRole::default(),
+            labels: Vec::new(),
+            peer_addresses: HashMap::new(),
+            local_addresses: Vec::new(),
+            free_bytes: None,
+            versions: PeerVersions::new(),
+            identity: None,
+            next_receipt: 0,
         })
     }
 
+    /// Default number of peer copies to maintain for files this node offers
+    pub fn with_replication_factor(mut self, replication_factor: usize) -> Self {
+        self.replication_factor = replication_factor;
+        self
+    }
+
+    /// Limit how many connections are accepted and made
+    pub fn with_connection_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.connections = ConnectionTracker::new(limits);
+        self
+    }
+
+    /// Limit concurrent downloads, chunk requests in flight and cached bytes
+    pub fn with_transfer_limits(mut self, limits: TransferLimits) -> Self {
+        self.file_manager = self.file_manager.with_limits(limits);
+        self
+    }
+
+    /// Limit how many messages wait to be sent to each peer
+    pub fn with_queue_limits(mut self, limits: QueueLimits) -> Self {
+        self.outbound = OutboundQueues::new(limits);
+        self
+    }
+
+    /// Retry messages that fail to be written following `policy`
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.sends = PendingSends::new(policy);
+        self
+    }
+
+    /// Mute peers that send more messages of a type than `limits` allow
+    pub fn with_flood_limits(mut self, limits: FloodLimits) -> Self {
+        self.flood = FloodGuard::new(limits);
+        self
+    }
+
+    /// Download offered files on their own, or hold offers for `expiry_secs`
+    /// until they are accepted or rejected
+    pub fn with_offer_mode(mut self, mode: OfferMode, expiry_secs: u64) -> Self {
+        self.offer_mode = mode;
+        self.pending_offers = PendingOffers::new(expiry_secs);
+        self
+    }
+
+    /// Advertise `role`'s capabilities and follow its rules for downloads
+    pub fn with_role(mut self, role: NodeRole) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Operator labels to advertise in discovery
+    pub fn with_labels(mut self, labels: Vec<String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Sign application messages as `keypair`, normally the node's own
+    pub fn with_identity(mut self, keypair: Keypair) -> Self {
+        self.identity = Some(keypair);
+        self
+    }
+
+    /// Refuse connections with `peer` for `duration`; existing ones are
+    /// left to the caller to close
+    pub fn ban_peer(&mut self, peer: PeerId, duration: Duration) {
+        // A ban too long for the clock lasts as long as it can
+        let until = Instant::now()
+            .checked_add(duration)
+            .unwrap_or_else(|| Instant::now() + LONGEST_BAN);
+        self.connections.ban(peer, until);
+    }
+
+    /// Record where a connected peer listens, as it reported through identify
+    pub fn set_peer_addresses(&mut self, peer: PeerId, addresses: Vec<Multiaddr>) {
+        if self.connected_peers.contains_key(&peer) {
+            self.peer_addresses.insert(peer, addresses);
+        }
+    }
+
+    /// Record the protocol version and protocols a connected peer reported
+    /// through identify, reporting it if it cannot understand our messages
+    pub fn set_peer_protocols(
+        &mut self,
+        peer: PeerId,
+        protocol_version: String,
+        protocols: &[StreamProtocol],
+    ) {
+        if !self.connected_peers.contains_key(&peer) {
+            return;
+        }
+        self.versions.identified(peer, protocol_version, protocols);
+        if let Some(reason) = self.versions.missing_protocol(&peer, &MESSAGE_PROTOCOL) {
+            self.report_incompatible(peer, reason);
+        }
+    }
+
+    /// Features a connected peer advertised in discovery
+    pub fn peer_features(&self, peer: &PeerId) -> Vec<String> {
+        let mut features: Vec<String> = self
+            .versions
+            .get(peer)
+            .and_then(|version| version.features.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        features.sort();
+        features
+    }
+
+    /// Tell the driver, once, that `peer` cannot take some of our messages
+    fn report_incompatible(&mut self, peer: PeerId, reason: String) {
+        if self.versions.first_report(peer, &reason) {
+            warn!("⚠️ {} is incompatible: {}", peer, reason);
+            self.pending_events
+                .push_back(MessagingBehaviourEvent::IncompatiblePeer { peer, reason });
+        }
+    }
+
+    /// Change the replication factor for files offered from now on
+    pub fn set_replication_factor(&mut self, replication_factor: usize) {
+        self.replication_factor = replication_factor;
+    }
+
+    /// Change the connection limits. Existing connections are kept, even if
+    /// they now exceed the limits.
+    pub fn set_connection_limits(&mut self, limits: ConnectionLimits) {
+        self.connections.set_limits(limits);
+    }
+
+    /// Change the transfer limits, starting queued downloads if they were raised
+    pub fn set_transfer_limits(&mut self, limits: TransferLimits) {
+        self.file_manager.set_limits(limits);
+        self.start_queued_downloads();
+        self.request_missing_chunks();
+    }
+
+    /// Change the per-peer message queue limits
+    pub fn set_queue_limits(&mut self, limits: QueueLimits) {
+        self.outbound.set_limits(limits);
+    }
+
+    /// Change how failed messages are retried
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.sends.set_policy(policy);
+    }
+
+    /// Change how many messages of each type peers may send
+    pub fn set_flood_limits(&mut self, limits: FloodLimits) {
+        self.flood.set_limits(limits);
+    }
+
+    /// Keep chunk blocks in `blocks` instead of the default on-disk store
+    pub fn with_blocks(mut self, blocks: BlockStore) -> Self {
+        self.file_manager = self.file_manager.with_blocks(blocks);
+        self
+    }
+
+    /// Keep downloaded files only in the block store
+    pub fn without_plaintext_files(mut self) -> Self {
+        self.file_manager = self.file_manager.without_plaintext_files();
+        self
+    }
+
+    /// Known peers and their measured latency, throughput and failures
+    pub fn network(&self) -> &NetworkState {
+        &self.network
+    }
+
+    /// Connected peers that advertise `capability`
+    pub fn find_peers_with(&self, capability: &str) -> Vec<PeerId> {
+        let capable = self.network.find_peers_with(capability);
+        self.connected_peers
+            .keys()
+            .filter(|peer| capable.contains(&NodeId::from_peer_id(peer)))
+            .copied()
+            .collect()
+    }
+
+    /// Count a failed interaction against a peer's ranking
+    pub fn record_peer_failure(&self, peer: &PeerId) {
+        self.network.record_failure(&NodeId::from_peer_id(peer));
+    }
+
+    /// Whether to act on a message of `msg_type` from `peer`, muting and
+    /// penalizing the peer once it sends too many
+    fn admit(&mut self, peer: PeerId, msg_type: &str) -> bool {
+        match self.flood.check(peer, msg_type, now()) {
+            Admission::Allowed => true,
+            Admission::Muted => {
+                debug!("Ignoring {} from muted peer {}", msg_type, peer);
+                false
+            }
+            Admission::Exceeded => {
+                let limits = self.flood.limits();
+                let duration = Duration::from_secs(limits.mute_secs);
+                let score = self.reputation.adjust(&peer, -limits.penalty);
+                warn!(
+                    "🔇 Muting {} for {:?} after too many {} messages (reputation {})",
+                    peer, duration, msg_type, score
+                );
+                self.pending_events
+                    .push_back(MessagingBehaviourEvent::PeerMuted {
+                        peer,
+                        msg_type: msg_type.to_string(),
+                        duration,
+                    });
+                false
+            }
+        }
+    }
+
+    /// Request the next batch of missing chunks from the best-ranked source
+    fn request_chunks(&mut self, file_id: &str) {
+        let mut sources = self.file_manager.download_sources(file_id);
+        // Sources that disconnected may come back, but cannot be asked now
+        sources.retain(|peer| self.connected_peers.contains_key(peer));
+        // Peers that cannot answer transfer requests are no use as sources
+        for peer in sources.clone() {
+            if let Some(reason) = self.versions.missing_protocol(&peer, &TRANSFER_PROTOCOL) {
+                sources.retain(|source| *source != peer);
+                self.report_incompatible(peer, reason);
+            }
+        }
+        let node_ids: Vec<NodeId> = sources.iter().map(NodeId::from_peer_id).collect();
+        let mut ranked: Vec<PeerId> = self
+            .network
+            .rank_peers(&node_ids)
+            .into_iter()
+            .filter_map(|id| {
+                sources
+                    .iter()
+                    .find(|peer| NodeId::from_peer_id(peer) == id)
+                    .copied()
+            })
+            .collect();
+        // A source picked for this download, then operator tags, override measurements
+        let preferred = self.preferred_sources.get(file_id);
+        ranked.sort_by_key(|peer| {
+            if preferred == Some(peer) {
+                0
+            } else if self.has_tag(peer, TRUSTED_TAG) {
+                1
+            } else if self.has_tag(peer, FLAKY_TAG) {
+                3
+            } else {
+                2
+            }
+        });
+        let Some(peer) = ranked.first().copied() else {
+            return;
+        };
+        self.transfer_activity
+            .entry(file_id.to_string())
+            .or_default();
+
+        // Peers that answer batches get a whole batch in one round trip
+        let batching = self.versions.supports(&peer, CHUNK_BATCH_FEATURE);
+        let batch_size = if batching { MAX_CHUNK_BATCH } else { 5 };
+        let chunk_indices = self
+            .file_manager
+            .get_next_chunks_to_request(file_id, batch_size);
+        if chunk_indices.is_empty() {
+            return;
+        }
+
+        for &chunk_index in &chunk_indices {
+            // Only requests that went unanswered are handed out again
+            if let Some((previous, _)) = self
+                .chunk_requests
+                .insert((file_id.to_string(), chunk_index), (peer, now()))
+            {
+                self.record_transfer_error(
+                    file_id,
+                    Some(previous),
+                    format!("Request for chunk {} timed out", chunk_index),
+                );
+            }
+        }
+
+        if batching {
+            info!(
+                "📦 Requesting chunks {:?} of {} from {}",
+                chunk_indices, file_id, peer
+            );
+            let request_id = self.transfers.send_request(
+                &peer,
+                TransferRequest::ChunkBatch {
+                    file_id: file_id.to_string(),
+                    chunk_indices: chunk_indices.clone(),
+                },
+            );
+            self.outbound_chunks
+                .insert(request_id, (file_id.to_string(), chunk_indices));
+            return;
+        }
+        for chunk_index in chunk_indices {
+            info!(
+                "📦 Requesting chunk {} of {} from {}",
+                chunk_index, file_id, peer
+            );
+            let request_id = self.transfers.send_request(
+                &peer,
+                TransferRequest::Chunk {
+                    file_id: file_id.to_string(),
+                    chunk_index,
+                },
+            );
+            self.outbound_chunks
+                .insert(request_id, (file_id.to_string(), vec![chunk_index]));
+        }
+    }
+
+    /// Request missing chunks of every running download, as far as the limit
+    /// on requests in flight allows. Requests that went unanswered for too
+    /// long are sent again.
+    pub fn request_missing_chunks(&mut self) {
+        let file_ids: Vec<String> = self
+            .file_manager
+            .downloads()
+            .filter(|transfer| !transfer.peers.is_empty())
+            .map(|transfer| transfer.metadata.file_id.clone())
+            .collect();
+        for file_id in file_ids {
+            self.request_chunks(&file_id);
+        }
+    }
+
+    /// Carry on the downloads `peer` was a source of, now that it
+    /// disconnected, from other connected peers that offer or hold the file
+    fn migrate_downloads(&mut self, peer: PeerId) {
+        let file_ids: Vec<String> = self
+            .file_manager
+            .downloads()
+            .filter(|transfer| transfer.peers.contains(&peer))
+            .map(|transfer| transfer.metadata.file_id.clone())
+            .collect();
+        for file_id in file_ids {
+            let offering = self
+                .remote_offers
+                .get(&file_id)
+                .map(|(_, peers)| peers.iter().copied().collect::<Vec<_>>())
+                .unwrap_or_default();
+            for other in offering.into_iter().chain(self.holders.holders(&file_id)) {
+                if other != peer && self.connected_peers.contains_key(&other) {
+                    self.file_manager.add_download_source(&file_id, other);
+                }
+            }
+            let sources: Vec<PeerId> = self
+                .file_manager
+                .download_sources(&file_id)
+                .into_iter()
+                .filter(|source| self.connected_peers.contains_key(source))
+                .collect();
+            if sources.is_empty() {
+                warn!("⏸️ {} lost its last connected source {}", file_id, peer);
+                self.record_transfer_error(
+                    &file_id,
+                    Some(peer),
+                    "Source disconnected, waiting for another peer with the file".to_string(),
+                );
+                continue;
+            }
+            info!(
+                "🔀 Moving download of {} from {} to {:?}",
+                file_id, peer, sources
+            );
+            if self.preferred_sources.get(&file_id) == Some(&peer) {
+                self.preferred_sources.remove(&file_id);
+            }
+            self.request_chunks(&file_id);
+        }
+    }
+
+    /// Start queued downloads that now fit within the transfer limits
+    fn start_queued_downloads(&mut self) {
+        for file_id in self.file_manager.start_queued_downloads() {
+            info!("▶️ Starting queued download: {}", file_id);
+            self.download_started(file_id);
+        }
+    }
+
+    /// Fetch a download that just started, or report it complete if it was
+    /// assembled from local blocks
+    fn download_started(&mut self, file_id: String) {
+        if self.file_manager.is_downloading(&file_id) {
+            // Request first batch of chunks
+            self.request_chunks(&file_id);
+        } else {
+            info!("♻️ {} assembled from local blocks", file_id);
+            self.forget_chunk_requests(&file_id);
+            self.report_complete(&file_id);
+        }
+    }
+
+    /// Tell the node a download finished, with its metadata and final path
+    fn report_complete(&mut self, file_id: &str) {
+        let Some(metadata) = self.file_manager.completed_download(file_id).cloned() else {
+            warn!("No metadata for completed download {}", file_id);
+            return;
+        };
+        let path = self.file_manager.completed_path(file_id);
+        self.pending_events
+            .push_back(MessagingBehaviourEvent::TransferComplete { metadata, path });
+    }
+
+    /// Start or resume downloading a file offered by `peer`, or add `peer`
+    /// as another source if the download is already running or queued
+    fn start_download(&mut self, metadata: &FileMetadata, peer_id: PeerId) {
+        let file_id = metadata.file_id.clone();
+        let output_path = self
+            .file_manager
+            .storage_path
+            .join("downloads")
+            .join(&metadata.name);
+
+        let started = if self.file_manager.add_download_source(&file_id, peer_id) {
+            // Chunk requests go to whichever source ranks best
+            info!("➕ {} is another source for {}", peer_id, metadata.name);
+            Ok(None)
+        } else if self.file_manager.resume_download(&file_id, peer_id) {
+            info!("⏯️ Resuming download: {}", metadata.name);
+            Ok(Some(file_id.clone()))
+        } else {
+            self.file_manager
+                .request_file(metadata.clone(), output_path, peer_id)
+                .inspect(|_| info!("🔽 Downloading: {}", metadata.name))
+                .map(Some)
+        };
+
+        match started {
+            Ok(None) => {}
+            // Started once a running download finishes
+            Ok(Some(_)) if self.file_manager.is_queued(&file_id) => {}
+            Ok(Some(file_id)) => self.download_started(file_id),
+            Err(e) => {
+                warn!("❌ Failed to start download: {}", e);
+            }
+        }
+    }
+
+    /// Download a file offered by connected peers, e.g. after cancelling it.
+    /// Chunks come from `preferred` while it has them, if given.
+    pub fn download(
+        &mut self,
+        file_id: &str,
+        preferred: Option<PeerId>,
+    ) -> io::Result<FileMetadata> {
+        if !self.role.accepts_downloads() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("A {} node does not download files", self.role),
+            ));
+        }
+        if self.file_manager.is_downloading(file_id) || self.file_manager.is_queued(file_id) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Already downloading {}", file_id),
+            ));
+        }
+        let Some((metadata, peers)) = self.remote_offers.get(file_id) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No peer has offered {}", file_id),
+            ));
+        };
+        let metadata = metadata.clone();
+        let sources: Vec<PeerId> = peers
+            .iter()
+            .filter(|peer| self.connected_peers.contains_key(peer))
+            .copied()
+            .collect();
+        if sources.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("No connected peer offers {}", file_id),
+            ));
+        }
+        if let Some(peer) = preferred {
+            if !sources.contains(&peer) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not a connected source of {}", peer, file_id),
+                ));
+            }
+            self.preferred_sources.insert(file_id.to_string(), peer);
+        }
+
+        self.pending_offers.take(file_id);
+        for peer in sources {
+            self.start_download(&metadata, peer);
+        }
+        Ok(metadata)
+    }
+
+    /// Download a file offered in manual mode from the peers offering it
+    pub fn accept_offer(&mut self, file_id: &str) -> io::Result<FileMetadata> {
+        let Some(offer) = self.pending_offers.take(file_id) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No offer of {} is waiting for an answer", file_id),
+            ));
+        };
+        self.download(file_id, None)
+            .inspect(|_| self.stats.offers.accepted += 1)
+            .inspect_err(|_| self.pending_offers.restore(offer))
+    }
+
+    /// Decline a file offered in manual mode, telling the peers offering it
+    pub fn reject_offer(&mut self, file_id: &str) -> io::Result<PendingOffer> {
+        let Some(offer) = self.pending_offers.take(file_id) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No offer of {} is waiting for an answer", file_id),
+            ));
+        };
+        info!("🙅 Rejecting {} ({})", offer.metadata.name, file_id);
+        self.stats.offers.rejected += 1;
+        for peer in &offer.peers {
+            let rejected = self.new_message(MessageType::OfferRejected {
+                file_id: file_id.to_string(),
+            });
+            self.send_message(*peer, rejected);
+        }
+        Ok(offer)
+    }
+
+    /// Offers waiting to be accepted or rejected, oldest first
+    pub fn pending_offers(&self) -> Vec<&PendingOffer> {
+        self.pending_offers.list()
+    }
+
+    /// Forget offers that waited too long for an answer
+    pub fn expire_offers(&mut self) {
+        for offer in self.pending_offers.expire(current_timestamp()) {
+            info!("⌛ Offer of {} expired", offer.metadata.name);
+            self.stats.offers.expired += 1;
+            self.pending_events
+                .push_back(MessagingBehaviourEvent::OfferClosed {
+                    file_id: offer.metadata.file_id,
+                    removal: FileRemoval::Expired,
+                });
+        }
+    }
+
+    /// Progress of a running, queued or finished download
+    pub fn transfer_summary(&self, file_id: &str) -> Option<TransferSummary> {
+        self.file_manager.transfer_summary(file_id)
+    }
+
+    /// Stop a download and delete what was fetched so far. Offered and
+    /// finished files are left alone.
+    pub fn cancel_download(&mut self, file_id: &str) -> io::Result<()> {
+        if !self.file_manager.is_downloading(file_id)
+            && !self.file_manager.is_queued(file_id)
+            && self.file_manager.file_metadata(file_id).is_some()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Not a download in progress: {}", file_id),
+            ));
+        }
+        self.file_manager.cancel_download(file_id)?;
+        self.forget_chunk_requests(file_id);
+        self.transfer_activity.remove(file_id);
+        self.start_queued_downloads();
+        self.request_missing_chunks();
+        Ok(())
+    }
+
+    /// Cancel, withdraw or delete a file, whichever fits where it stands
+    pub fn remove_file(&mut self, file_id: &str) -> io::Result<FileRemoval> {
+        if self.file_manager.is_downloading(file_id) || self.file_manager.is_queued(file_id) {
+            self.cancel_download(file_id)?;
+            Ok(FileRemoval::Cancelled)
+        } else if self.withdraw_file(file_id) {
+            Ok(FileRemoval::Withdrawn)
+        } else {
+            self.file_manager.delete_completed(file_id)?;
+            self.transfer_activity.remove(file_id);
+            Ok(FileRemoval::Deleted)
+        }
+    }
+
+    /// Metadata of a file this node offers, downloads or downloaded
+    pub fn file_metadata(&self, file_id: &str) -> Option<FileMetadata> {
+        self.file_manager.file_metadata(file_id).cloned()
+    }
+
+    /// Which chunks of a file are held, one bit per chunk
+    pub fn chunk_bitmap(&self, file_id: &str) -> Option<Vec<u8>> {
+        self.file_manager.chunk_bitmap(file_id)
+    }
+
+    /// Where a finished download was saved
+    pub fn completed_path(&self, file_id: &str) -> Option<PathBuf> {
+        self.file_manager.completed_path(file_id)
+    }
+
+    /// Sources, speed and errors of a download
+    pub fn transfer_activity(&self, file_id: &str) -> Option<&TransferActivity> {
+        self.transfer_activity.get(file_id)
+    }
+
+    /// Files peers fetched chunks of lately, and who fetched how much
+    pub fn upload_activity(&self) -> impl Iterator<Item = (&String, &UploadActivity)> {
+        self.upload_activity
+            .iter()
+            .filter(|(_, upload)| !upload.is_expired())
+    }
+
+    /// Files peers are fetching chunks of right now
+    pub fn active_uploads(&self) -> usize {
+        self.upload_activity()
+            .filter(|(_, upload)| upload.state() == ActivityState::Active)
+            .count()
+    }
+
+    /// Outstanding chunk requests of a download, per peer
+    pub fn requests_in_flight(&self, file_id: &str) -> HashMap<PeerId, usize> {
+        let mut in_flight = HashMap::new();
+        for ((id, _), (peer, _)) in &self.chunk_requests {
+            if id == file_id {
+                *in_flight.entry(*peer).or_default() += 1;
+            }
+        }
+        in_flight
+    }
+
+    /// Peers a download currently fetches from
+    pub fn download_sources(&self, file_id: &str) -> Vec<PeerId> {
+        self.file_manager.download_sources(file_id)
+    }
+
+    fn record_transfer_error(&mut self, file_id: &str, peer: Option<PeerId>, message: String) {
+        self.transfer_activity
+            .entry(file_id.to_string())
+            .or_default()
+            .record_error(peer, message);
+    }
+
+    /// Remember why a download failed and tell the node
+    fn transfer_failed(&mut self, file_id: String, peer: Option<PeerId>, reason: String) {
+        self.record_transfer_error(&file_id, peer, reason.clone());
+        self.pending_events
+            .push_back(MessagingBehaviourEvent::TransferFailed { file_id, reason });
+    }
+
+    /// Files this node offers
+    pub fn offered_files(&self) -> Vec<FileMetadata> {
+        self.file_manager.offered_files().cloned().collect()
+    }
+
+    /// Files being downloaded, with their progress in percent
+    pub fn downloads(&self) -> Vec<(FileMetadata, f32)> {
+        self.file_manager
+            .downloads()
+            .map(|transfer| (transfer.metadata.clone(), transfer.progress))
+            .collect()
+    }
+
+    /// Downloads waiting for a running one to finish, oldest first
+    pub fn queued_downloads(&self) -> Vec<FileMetadata> {
+        self.file_manager.queued_downloads().cloned().collect()
+    }
+
+    /// Files being offered, downloaded and waiting to download
+    pub fn transfer_counts(&self) -> (usize, usize, usize) {
+        (
+            self.file_manager.active_uploads_count(),
+            self.file_manager.active_downloads_count(),
+            self.file_manager.queued_downloads().count(),
+        )
+    }
+
+    pub fn completed_downloads_count(&self) -> usize {
+        self.file_manager.completed_downloads_count()
+    }
+
+    pub fn chunk_requests_in_flight(&self) -> usize {
+        self.file_manager.chunk_requests_in_flight()
+    }
+
+    /// Counters of messages, offers and chunks since the node started
+    pub fn stats(&self) -> &BehaviourStats {
+        &self.stats
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.file_manager.cache_stats()
+    }
+
+    pub fn current_epoch(&self) -> u64 {
+        self.consensus.current_epoch()
+    }
+
+    /// Registry updates rejected so far for coming from a stale epoch
+    pub fn stale_messages(&self) -> u64 {
+        self.stale_messages
+    }
+
+    /// Progress of every running and queued download
+    pub fn transfer_summaries(&self) -> Vec<TransferSummary> {
+        self.file_manager.transfer_summaries()
+    }
+
+    /// Replace the operator tags of a peer
+    pub fn set_peer_tags(&mut self, peer: PeerId, tags: Vec<String>) {
+        if tags.is_empty() {
+            self.peer_tags.remove(&peer);
+        } else {
+            self.peer_tags.insert(peer, tags);
+        }
+    }
+
+    fn has_tag(&self, peer: &PeerId, tag: &str) -> bool {
+        self.peer_tags
+            .get(peer)
+            .is_some_and(|tags| tags.iter().any(|t| t == tag))
+    }
+
+    /// Forget outstanding chunk requests and the preferred source of a
+    /// finished or abandoned download
+    fn forget_chunk_requests(&mut self, file_id: &str) {
+        self.chunk_requests.retain(|(id, _), _| id != file_id);
+        self.outbound_chunks.retain(|_, (id, _)| id != file_id);
+        self.preferred_sources.remove(file_id);
+    }
+
+    /// Build an outgoing message from this node, stamped with the current
+    /// consensus epoch
+    fn new_message(&self, msg_type: MessageType) -> Message {
+        let from = match &self.identity {
+            Some(identity) => NodeId::from_peer_id(&identity.public().to_peer_id()),
+            // Without an identity, as in tests, messages come from nobody
+            None => {
+                NodeId::from_pubkey(&ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap())
+            }
+        };
+        Message::new(from, msg_type).with_epoch(self.consensus.current_epoch())
+    }
+
     pub fn send_message(&mut self, peer: PeerId, message: Message) {
         info!("Queueing message to peer: {}", peer);
-        self.pending_handler_messages.push_back((peer, message));
+        self.enqueue(peer, message, None);
+    }
+
+    /// Queue a message for a connected peer and track it until it is
+    /// written, dropping it or the peer's oldest one if its queue is full
+    fn enqueue(&mut self, peer: PeerId, message: Message, receipt: Option<u64>) {
+        if !self.connected_peers.contains_key(&peer) {
+            debug!("Not queueing a message to disconnected peer {}", peer);
+            return;
+        }
+        if let Some(reason) = self.versions.incompatibility(&peer, &message.msg_type) {
+            debug!(
+                "Not sending {} to {}: {}",
+                message.msg_type.name(),
+                peer,
+                reason
+            );
+            if let Some(receipt) = receipt {
+                self.pending_events
+                    .push_back(MessagingBehaviourEvent::SendError {
+                        to: peer,
+                        error: reason.clone(),
+                        receipt: Some(receipt),
+                        msg_type: message.msg_type.name().to_string(),
+                        attempts: 0,
+                    });
+            }
+            self.report_incompatible(peer, reason);
+            return;
+        }
+        self.stats.message_queued(message.msg_type.name());
+        let id = self.sends.track(peer, message.clone(), receipt);
+        self.queue(peer, (message, id));
+    }
+
+    /// Queue a tracked message, first sent or retried
+    fn queue(&mut self, peer: PeerId, outgoing: Outgoing) {
+        let (dropped, reason) = match self.outbound.push(peer, outgoing) {
+            Queued::Accepted => return,
+            Queued::Evicted(dropped) => (dropped, "Dropped to make room in the peer's queue"),
+            Queued::Full(dropped) => (dropped, "The peer's queue is full"),
+        };
+        warn!("Dropping a message to {}: {}", peer, reason);
+        self.messages_dropped(peer, vec![dropped.1], reason);
+    }
+
+    /// Stop tracking messages to `peer` that will not be sent, failing
+    /// their receipts
+    fn messages_dropped(&mut self, peer: PeerId, dropped: Vec<u64>, reason: &str) {
+        if dropped.is_empty() {
+            return;
+        }
+        for send in dropped.iter().filter_map(|&id| self.sends.remove(id)) {
+            self.stats
+                .message_failed(&peer, send.message.msg_type.name());
+            if send.receipt.is_some() {
+                self.pending_events
+                    .push_back(MessagingBehaviourEvent::SendError {
+                        to: peer,
+                        error: reason.to_string(),
+                        receipt: send.receipt,
+                        msg_type: send.message.msg_type.name().to_string(),
+                        attempts: send.attempts,
+                    });
+            }
+        }
+        self.pending_events
+            .push_back(MessagingBehaviourEvent::MessagesDropped {
+                to: peer,
+                count: dropped.len(),
+                reason: reason.to_string(),
+            });
+    }
+
+    /// Writing a message to `peer` failed: try it again after a while, or
+    /// report it failed for good once out of attempts
+    fn send_failed(&mut self, peer: PeerId, id: u64, error: String) {
+        match self.sends.failed(id, now()) {
+            Some(Retry::After(backoff)) => {
+                debug!(
+                    "Retrying a message to {} in {:?} after: {}",
+                    peer, backoff, error
+                );
+            }
+            Some(Retry::GaveUp(send)) => {
+                self.stats
+                    .message_failed(&peer, send.message.msg_type.name());
+                self.pending_events
+                    .push_back(MessagingBehaviourEvent::SendError {
+                        to: peer,
+                        error,
+                        receipt: send.receipt,
+                        msg_type: send.message.msg_type.name().to_string(),
+                        attempts: send.attempts,
+                    });
+            }
+            None => {}
+        }
     }
 
+    /// Tell the connections of peers that transfers or queued messages
+    /// started or stopped with whether to stay open while quiet. Uploads
+    /// keep a peer's connections open until they stall.
+    fn update_keep_alive(&mut self) {
+        if self
+            .keep_alive_checked
+            .is_some_and(|checked| now() - checked < KEEP_ALIVE_CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.keep_alive_checked = Some(now());
+
+        let mut busy: HashSet<PeerId> = self
+            .file_manager
+            .downloads()
+            .flat_map(|transfer| transfer.peers.iter().copied())
+            .collect();
+        busy.extend(
+            self.upload_activity
+                .values()
+                .filter(|upload| upload.state() == ActivityState::Active)
+                .flat_map(|upload| upload.peers().map(|(peer, _)| *peer)),
+        );
+        busy.extend(self.sends.peers());
+        busy.retain(|peer| self.connected_peers.contains_key(peer));
+
+        for peer in busy.symmetric_difference(&self.kept_alive) {
+            let keep_alive = busy.contains(peer);
+            debug!("Keep-alive of {} is now {}", peer, keep_alive);
+            for connection in self.connected_peers.get(peer).into_iter().flatten() {
+                self.keep_alive_updates
+                    .push_back((*peer, *connection, keep_alive));
+            }
+        }
+        self.kept_alive = busy;
+    }
+
+    /// Connection to write messages for `peer` on: one with a negotiated
+    /// messaging stream, else one not known to be unable to open any. Sends
+    /// that fail on one connection are retried on the next best.
+    fn message_connection(&self, peer: &PeerId) -> Option<ConnectionId> {
+        let connections = self.connected_peers.get(peer)?;
+        let usable = |connection: &&ConnectionId| self.messaging_streams.get(connection).copied();
+        connections
+            .iter()
+            .find(|connection| usable(connection) == Some(true))
+            .or_else(|| {
+                connections
+                    .iter()
+                    .find(|connection| usable(connection).is_none())
+            })
+            .or_else(|| connections.first())
+            .copied()
+    }
+
+    /// Queue the messages whose retry is due, and arrange to be woken up
+    /// when the next one is
+    fn poll_retries(&mut self, cx: &mut Context) {
+        while let Some(at) = self.sends.next_retry() {
+            let at = tokio::time::Instant::from_std(at);
+            let timer = match &mut self.retry_timer {
+                Some(timer) if timer.deadline() == at => timer,
+                timer => timer.insert(Box::pin(tokio::time::sleep_until(at))),
+            };
+            if timer.as_mut().poll(cx).is_pending() {
+                return;
+            }
+            self.retry_timer = None;
+            for (id, peer, message) in self.sends.due(now()) {
+                info!("🔁 Retrying {} to {}", message.msg_type.name(), peer);
+                self.queue(peer, (message, id));
+            }
+        }
+        self.retry_timer = None;
+    }
+
+    /// Whether an application message for `peer` can be queued now. Always
+    /// true under [`OverflowPolicy::DropOldest`], which makes room.
+    pub fn can_send(&self, peer: &PeerId) -> bool {
+        self.outbound.limits().overflow == OverflowPolicy::DropOldest
+            || self.outbound.has_room(peer)
+    }
+
+    pub fn queue_limits(&self) -> &QueueLimits {
+        self.outbound.limits()
+    }
+
+    /// Messages waiting to be sent, across all peers
+    pub fn queued_messages(&self) -> usize {
+        self.outbound.queued()
+    }
+
+    /// Messages dropped without being sent, since the node started
+    pub fn dropped_messages(&self) -> u64 {
+        self.outbound.dropped()
+    }
+
+    /// Sign and queue an application message to a connected peer. Returns
+    /// the receipt its `MessageSent` or `SendError` event will carry, or
+    /// `WouldBlock` if the peer's queue is full and may not be made room in.
+    pub fn send_custom(
+        &mut self,
+        peer: PeerId,
+        msg_type: String,
+        payload: serde_json::Value,
+    ) -> io::Result<u64> {
+        if !self.connected_peers.contains_key(&peer) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("Peer {} is not connected", peer),
+            ));
+        }
+        if !self.can_send(&peer) {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("Queue of messages to {} is full", peer),
+            ));
+        }
+        let Some(identity) = &self.identity else {
+            return Err(io::Error::other(
+                "Node has no identity to sign messages with",
+            ));
+        };
+        let message = self
+            .new_message(MessageType::Custom { msg_type, payload })
+            .signed(identity)
+            .map_err(|e| io::Error::other(format!("Failed to sign message: {}", e)))?;
+
+        let receipt = self.next_receipt;
+        self.next_receipt += 1;
+        info!("Queueing application message to peer: {}", peer);
+        self.enqueue(peer, message, Some(receipt));
+        Ok(receipt)
+    }
+
+    /// Dialable addresses of connected peers, each ending in its `/p2p/` id
+    fn advertised_addresses(&self) -> Vec<String> {
+        self.peer_addresses
+            .iter()
+            .flat_map(|(peer, addresses)| {
+                addresses
+                    .iter()
+                    .filter(|addr| !is_unspecified(addr))
+                    .map(move |addr| match addr.iter().last() {
+                        Some(Protocol::P2p(_)) => addr.to_string(),
+                        _ => addr.clone().with(Protocol::P2p(*peer)).to_string(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Update the space left for files, advertised in the next discovery
+    pub fn set_free_bytes(&mut self, free_bytes: Option<u64>) {
+        self.free_bytes = free_bytes;
+    }
+
+    /// Addresses this node can be dialed at, ending in its `/p2p/` id
+    fn own_addresses(&self) -> Vec<String> {
+        let peer = self
+            .identity
+            .as_ref()
+            .map(|identity| identity.public().to_peer_id());
+        self.local_addresses
+            .iter()
+            .filter(|addr| !is_unspecified(addr))
+            .map(|addr| match (peer, addr.iter().last()) {
+                (Some(peer), Some(protocol)) if !matches!(protocol, Protocol::P2p(_)) => {
+                    addr.clone().with(Protocol::P2p(peer)).to_string()
+                }
+                _ => addr.to_string(),
+            })
+            .collect()
+    }
+
+    /// Tell connected peers what this node is: its role's capabilities,
+    /// labels, features, addresses and free space, and the peers it knows
     pub fn broadcast_discovery(&mut self) {
         let peers: Vec<PeerId> = self.connected_peers.keys().copied().collect();
         info!("📡 Broadcasting discovery to {} peers", peers.len());
 
+        let discovery_msg = self.discovery_message();
+        for peer in peers {
+            self.send_message(peer, discovery_msg.clone());
+        }
+    }
+
+    fn discovery_message(&self) -> Message {
         let discovery_data = DiscoveryMessage {
-            capabilities: vec!["storage".to_string(), "compute".to_string()],
-            protocol_version: "1.0.0".to_string(),
+            capabilities: self.role.capabilities(),
+            protocol_version: PROTOCOL_VERSION.to_string(),
+            labels: self.labels.clone(),
+            peers: self.advertised_addresses(),
+            features: FEATURES.iter().map(|feature| feature.to_string()).collect(),
+            listen_addresses: self.own_addresses(),
+            free_bytes: self.free_bytes,
+        };
+        self.new_message(MessageType::Discovery(discovery_data))
+    }
+
+    /// Ask every connected peer for offered files matching `query`.
+    /// Returns how many were asked; answers arrive as
+    /// [`MessagingBehaviourEvent::QueryResults`].
+    pub fn query_files(&mut self, query_id: u64, query: &str, limit: u32) -> usize {
+        let peers: Vec<PeerId> = self.connected_peers.keys().copied().collect();
+        let message = self.new_message(MessageType::FileQuery {
+            query_id,
+            query: query.to_string(),
+            limit,
+        });
+        for peer in &peers {
+            self.send_message(*peer, message.clone());
+        }
+        peers.len()
+    }
+
+    /// Offer a file for transfer to the network
+    pub fn offer_file(&mut self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = self.file_manager.offer_file(path)?;
+        Ok(self.announce_offer(metadata))
+    }
+
+    /// Offer a changed file as the next version of `previous_file_id`
+    pub fn offer_file_version(
+        &mut self,
+        path: &Path,
+        previous_file_id: Option<&str>,
+    ) -> io::Result<FileMetadata> {
+        let metadata = self
+            .file_manager
+            .offer_file_version(path, previous_file_id)?;
+        Ok(self.announce_offer(metadata))
+    }
+
+    fn announce_offer(&mut self, metadata: FileMetadata) -> FileMetadata {
+        info!(
+            "📤 Offering file: {} ({} bytes, {} chunks)",
+            metadata.name, metadata.size, metadata.total_chunks
+        );
+
+        // Broadcast file offer to all connected peers
+        let peers: Vec<PeerId> = self.connected_peers.keys().copied().collect();
+
+        for peer in peers {
+            let offer_msg = self.new_message(MessageType::FileOffer(metadata.clone()));
+            self.send_message(peer, offer_msg);
+            self.replication.mark_pending(&metadata.file_id, peer);
+        }
+
+        self.replication
+            .set_target(&metadata.file_id, self.replication_factor);
+
+        metadata
+    }
+
+    /// A file and every earlier version known locally, newest first
+    pub fn version_history(&self, file_id: &str) -> Vec<FileMetadata> {
+        self.file_manager.version_history(file_id)
+    }
+
+    /// Stop offering a file and tell connected peers it is gone
+    pub fn withdraw_file(&mut self, file_id: &str) -> bool {
+        if !self.file_manager.withdraw_offer(file_id) {
+            return false;
+        }
+        self.replication.remove_file(file_id);
+
+        let peers: Vec<PeerId> = self.connected_peers.keys().copied().collect();
+        for peer in peers {
+            let withdraw_msg = self.new_message(MessageType::FileWithdraw {
+                file_id: file_id.to_string(),
+            });
+            self.send_message(peer, withdraw_msg);
+        }
+        true
+    }
+
+    /// Set how many peer copies of an offered file to maintain (0 disables replication)
+    pub fn set_replication_target(&mut self, file_id: &str, target: usize) -> bool {
+        if self.file_manager.offered_file(file_id).is_none() {
+            return false;
+        }
+        self.replication.set_target(file_id, target);
+        true
+    }
+
+    /// Push offers for under-replicated files to connected peers that lack a copy
+    pub fn replicate(&mut self) {
+        // Backup targets first, and never flaky peers
+        let mut candidates = self.find_peers_with(STORAGE_CAPABILITY);
+        candidates.retain(|peer| !self.has_tag(peer, FLAKY_TAG));
+        candidates.sort_by_key(|peer| {
+            if self.has_tag(peer, BACKUP_TARGET_TAG) {
+                0
+            } else if self.has_tag(peer, TRUSTED_TAG) {
+                1
+            } else {
+                2
+            }
+        });
+
+        for (file_id, peer) in self.replication.plan_offers(&candidates) {
+            let Some(metadata) = self.file_manager.offered_file(&file_id).cloned() else {
+                self.replication.remove_file(&file_id);
+                continue;
+            };
+
+            let node_id = NodeId::from_peer_id(&peer);
+            let free_bytes = self
+                .network
+                .get_peer(&node_id)
+                .and_then(|info| info.free_bytes);
+            if free_bytes.is_some_and(|free| free < metadata.size) {
+                debug!("{} has no room for {}", peer, metadata.name);
+                self.replication.clear_pending(&file_id, &peer);
+                continue;
+            }
+
+            info!("🧬 Replicating {} to {}", metadata.name, peer);
+            let offer_msg = self.new_message(MessageType::FileOffer(metadata));
+            self.send_message(peer, offer_msg);
+        }
+    }
+
+    pub fn replication_health(&self) -> Vec<ReplicationHealth> {
+        self.replication.health()
+    }
+
+    /// Save transfer state so downloads resume after a restart
+    pub fn flush(&self) -> io::Result<()> {
+        self.file_manager.flush()
+    }
+
+    /// Pin or unpin a known file
+    pub fn set_pinned(&mut self, file_id: &str, pinned: bool) -> io::Result<()> {
+        self.file_manager.set_pinned(file_id, pinned)
+    }
+
+    pub fn is_pinned(&self, file_id: &str) -> bool {
+        self.file_manager.is_pinned(file_id)
+    }
+
+    /// Verify downloads interrupted by the last shutdown, finishing or
+    /// cleaning them up where possible
+    pub fn recover_downloads(&mut self) -> RecoveryReport {
+        self.file_manager.recover_downloads()
+    }
+
+    /// Remove unreferenced blocks and abandoned partial downloads
+    pub fn collect_garbage(&mut self, dry_run: bool) -> io::Result<GcReport> {
+        self.file_manager.collect_garbage(dry_run)
+    }
+
+    /// Peers known to hold a copy of a file
+    pub fn file_holders(&self, file_id: &str) -> Vec<PeerId> {
+        self.holders.holders(file_id)
+    }
+
+    /// Whether a peer offered the file, or answered a search or file
+    /// request with it, so it can be downloaded
+    pub fn is_offered(&self, file_id: &str) -> bool {
+        self.remote_offers.contains_key(file_id)
+    }
+
+    /// Ask `peer` for the metadata of a file by id, to download it without
+    /// waiting for an offer. The answer comes as `FileFound` or
+    /// `FileNotFound`.
+    pub fn request_file(&mut self, peer: PeerId, file_id: &str) -> io::Result<()> {
+        if !self.connected_peers.contains_key(&peer) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("Peer {} is not connected", peer),
+            ));
+        }
+        if let Some(reason) = self.versions.missing_protocol(&peer, &TRANSFER_PROTOCOL) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, reason));
+        }
+        info!("🔎 Asking {} for {}", peer, file_id);
+        let request_id = self.transfers.send_request(
+            &peer,
+            TransferRequest::File {
+                file_id: file_id.to_string(),
+            },
+        );
+        self.outbound_files
+            .insert(request_id, (file_id.to_string(), None));
+        Ok(())
+    }
+
+    /// Record what `peer` announced about itself in the network state
+    fn apply_discovery(&self, peer: &PeerId, discovery: &DiscoveryMessage) {
+        let node_id = NodeId::from_peer_id(peer);
+        self.network
+            .set_capabilities(&node_id, discovery.capabilities.clone());
+        self.network.set_labels(&node_id, discovery.labels.clone());
+        self.network
+            .set_listen_addresses(&node_id, discovery.listen_addresses.clone());
+        self.network.set_free_bytes(&node_id, discovery.free_bytes);
+    }
+
+    /// Send `peer` the catalog of files we offer, once per connection and
+    /// only if it understands catalogs
+    fn send_catalog(&mut self, peer: PeerId) {
+        if !self.versions.supports(&peer, FILE_CATALOG_FEATURE) || !self.catalogs_sent.insert(peer)
+        {
+            return;
+        }
+        let entries: Vec<CatalogEntry> = self
+            .file_manager
+            .offered_files()
+            .map(CatalogEntry::from)
+            .collect();
+        if entries.is_empty() {
+            return;
+        }
+        info!("📚 Sending {} a catalog of {} files", peer, entries.len());
+        let message = self.new_message(MessageType::FileCatalog(entries));
+        self.send_message(peer, message);
+    }
+
+    /// Take the files in `peer`'s catalog as offers. Files whose metadata
+    /// is already known count at once; the rest are fetched from the peer.
+    fn catalog_received(&mut self, peer: PeerId, entries: &[CatalogEntry]) {
+        info!("📚 {} offers {} files", peer, entries.len());
+        for entry in entries {
+            let known = self
+                .remote_offers
+                .get(&entry.file_id)
+                .map(|(metadata, peers)| (metadata.clone(), peers.contains(&peer)));
+            match known {
+                Some((_, true)) => {}
+                Some((metadata, false)) if metadata.merkle_root() == entry.root => {
+                    self.offer_received(peer, &metadata);
+                }
+                _ if self.file_manager.offered_file(&entry.file_id).is_some() => {}
+                _ => {
+                    if self
+                        .versions
+                        .missing_protocol(&peer, &TRANSFER_PROTOCOL)
+                        .is_some()
+                    {
+                        return;
+                    }
+                    let request_id = self.transfers.send_request(
+                        &peer,
+                        TransferRequest::File {
+                            file_id: entry.file_id.clone(),
+                        },
+                    );
+                    self.outbound_files
+                        .insert(request_id, (entry.file_id.clone(), Some(entry.root)));
+                }
+            }
+        }
+    }
+
+    /// A peer offered a file, by message or in its catalog: remember it
+    /// and download it or hold the offer, as the offer mode says
+    fn offer_received(&mut self, peer_id: PeerId, metadata: &FileMetadata) {
+        info!(
+            "📁 File offered by {}: {} ({} bytes)",
+            peer_id, metadata.name, metadata.size
+        );
+
+        self.file_manager.record_version(metadata);
+
+        self.remote_offers
+            .entry(metadata.file_id.clone())
+            .or_insert_with(|| (metadata.clone(), HashSet::new()))
+            .1
+            .insert(peer_id);
+
+        // Download right away, unless offers wait for an answer and this one
+        // is not a new source of a running download
+        let file_id = &metadata.file_id;
+        let new = !self.file_manager.is_downloading(file_id)
+            && !self.file_manager.is_queued(file_id)
+            && self.file_manager.completed_download(file_id).is_none();
+        let pending = self.role.accepts_downloads() && self.offer_mode == OfferMode::Manual && new;
+        self.stats.offers.seen += 1;
+        if pending {
+            self.pending_offers
+                .offered(metadata, peer_id, current_timestamp());
+        } else if self.role.accepts_downloads() {
+            if new {
+                self.stats.offers.accepted += 1;
+            }
+            self.start_download(metadata, peer_id);
+        }
+
+        self.pending_events
+            .push_back(MessagingBehaviourEvent::FileOffered {
+                peer: peer_id,
+                metadata: metadata.clone(),
+                pending,
+            });
+        self.record_file_holder(&metadata.file_id, peer_id);
+    }
+
+    /// A peer listed a file in its catalog and sent its metadata: take it
+    /// as an offer if it matches the entry
+    fn catalog_file_answered(
+        &mut self,
+        peer: PeerId,
+        file_id: String,
+        root: [u8; 32],
+        response: TransferResponse,
+    ) {
+        match response {
+            TransferResponse::File(metadata)
+                if metadata.file_id == file_id && metadata.merkle_root() == root =>
+            {
+                self.offer_received(peer, &metadata);
+            }
+            response => warn!(
+                "{} answered for {} from its catalog with {:?}",
+                peer, file_id, response
+            ),
+        }
+    }
+
+    /// Remember the answer to a file request as an offer from `peer`
+    fn file_answered(&mut self, peer: PeerId, file_id: String, response: TransferResponse) {
+        let metadata = match response {
+            TransferResponse::File(metadata) if metadata.file_id == file_id => metadata,
+            TransferResponse::NotFound => {
+                self.pending_events
+                    .push_back(MessagingBehaviourEvent::FileNotFound {
+                        peer,
+                        error: format!("{} does not hold {}", peer, file_id),
+                        file_id,
+                    });
+                return;
+            }
+            response => {
+                warn!("Unexpected answer from {}: {:?}", peer, response);
+                self.pending_events
+                    .push_back(MessagingBehaviourEvent::FileNotFound {
+                        peer,
+                        error: format!("{} answered with something else", peer),
+                        file_id,
+                    });
+                return;
+            }
         };
+        info!("📁 {} holds {} ({})", peer, metadata.name, file_id);
+        self.file_manager.record_version(&metadata);
+        self.remote_offers
+            .entry(file_id.clone())
+            .or_insert_with(|| (metadata.clone(), HashSet::new()))
+            .1
+            .insert(peer);
+        self.record_file_holder(&file_id, peer);
+        self.pending_events
+            .push_back(MessagingBehaviourEvent::FileFound { peer, metadata });
+    }
+
+    fn record_file_holder(&mut self, file_id: &str, peer: PeerId) {
+        if self.holders.add_holder(file_id, &peer) {
+            self.pending_events
+                .push_back(MessagingBehaviourEvent::HoldersChanged {
+                    file_id: file_id.to_string(),
+                    holders: self.holders.holders(file_id),
+                });
+        }
+    }
+
+    /// Peers each connected peer advertised being connected to
+    pub fn peer_links(&self) -> &HashMap<PeerId, HashSet<PeerId>> {
+        &self.peer_links
+    }
+
+    /// Reputation score of a peer
+    pub fn peer_reputation(&self, peer: &PeerId) -> i32 {
+        self.reputation.score(peer)
+    }
+
+    /// Challenge a connected holder of each file we hold to prove it still
+    /// stores the file, and penalize holders that never answered earlier ones.
+    pub fn challenge_holders(&mut self) {
+        let expired: Vec<[u8; 32]> = self
+            .challenges
+            .iter()
+            .filter(|(_, c)| now() - c.sent_at > CHALLENGE_TIMEOUT)
+            .map(|(nonce, _)| *nonce)
+            .collect();
+        for nonce in expired {
+            let challenge = self.challenges.remove(&nonce).unwrap();
+            self.storage_challenge_failed(challenge.peer, &challenge.file_id, "no answer");
+        }
+
+        let mut rng = rand::thread_rng();
+        let files: Vec<FileMetadata> = self.file_manager.held_files().cloned().collect();
+        for metadata in files {
+            let candidates: Vec<PeerId> = self
+                .holders
+                .holders(&metadata.file_id)
+                .into_iter()
+                .filter(|peer| self.connected_peers.contains_key(peer))
+                .filter(|peer| {
+                    !self
+                        .challenges
+                        .values()
+                        .any(|c| c.peer == *peer && c.file_id == metadata.file_id)
+                })
+                .collect();
+            if candidates.is_empty() || metadata.total_chunks == 0 {
+                continue;
+            }
+            let peer = candidates[rng.gen_range(0..candidates.len())];
+
+            let chunk_index = rng.gen_range(0..metadata.total_chunks);
+            let data = match self.file_manager.read_chunk(&metadata.file_id, chunk_index) {
+                Ok(Some(data)) if !data.is_empty() => data,
+                _ => continue,
+            };
+            let size = data.len() as u32;
+            let offset = rng.gen_range(0..=size - size.min(CHALLENGE_MIN_SLICE));
+            let length = (size - offset).min(CHALLENGE_MAX_SLICE);
+            let nonce: [u8; 32] = rng.gen();
+            let Some(expected) = storage_proof(&nonce, &data, offset, length) else {
+                continue;
+            };
+
+            self.challenges.insert(
+                nonce,
+                PendingChallenge {
+                    peer,
+                    file_id: metadata.file_id.clone(),
+                    expected,
+                    sent_at: now(),
+                },
+            );
+            let challenge = self.new_message(MessageType::StorageChallenge {
+                file_id: metadata.file_id.clone(),
+                chunk_index,
+                offset,
+                length,
+                nonce,
+            });
+            self.send_message(peer, challenge);
+            info!(
+                "🎲 Challenging {} to prove it holds {}",
+                peer, metadata.name
+            );
+        }
+    }
+
+    fn storage_challenge_failed(&mut self, peer: PeerId, file_id: &str, reason: &str) {
+        let score = self.reputation.adjust(&peer, PROOF_PENALTY);
+        self.record_peer_failure(&peer);
+        warn!(
+            "🕵️ {} failed storage challenge for {} ({}), reputation {}",
+            peer, file_id, reason, score
+        );
+
+        // Stop counting it as a holder so replication finds another copy
+        self.replication.remove_holder(file_id, &peer);
+        if self.holders.remove_holder(file_id, &peer) {
+            self.pending_events
+                .push_back(MessagingBehaviourEvent::HoldersChanged {
+                    file_id: file_id.to_string(),
+                    holders: self.holders.holders(file_id),
+                });
+        }
+    }
+
+    /// Hand a chunk `peer_id` answered a request with to be verified and
+    /// stored; [`Self::chunk_stored`] moves the download on once it is
+    fn chunk_received(&mut self, peer_id: PeerId, chunk: FileChunk) {
+        let file_id = chunk.file_id.clone();
+        let chunk_index = chunk.chunk_index;
+        let chunk_size = chunk.data.len() as u64;
+        if let Some((requested_from, sent_at)) = self
+            .chunk_requests
+            .remove(&(file_id.clone(), chunk.chunk_index))
+        {
+            if requested_from == peer_id {
+                self.network.record_transfer(
+                    &NodeId::from_peer_id(&peer_id),
+                    chunk.data.len(),
+                    now() - sent_at,
+                );
+            }
+        }
+
+        match self.file_manager.prepare_chunk(chunk) {
+            Ok(write) => self.chunk_workers.submit(peer_id, write),
+            Err(e) => self.chunk_handled(peer_id, file_id, chunk_index, chunk_size, Err(e)),
+        }
+    }
+
+    /// Record a chunk from `peer_id` the workers verified and stored
+    fn chunk_stored(&mut self, peer_id: PeerId, written: ChunkWritten) {
+        let (file_id, chunk_index, chunk_size) =
+            (written.file_id.clone(), written.chunk_index, written.size);
+        // Chunks of downloads that ended while they were written are moot
+        if !self.file_manager.is_downloading(&file_id) {
+            debug!(
+                "Dropping chunk {} of {}, which is no longer downloading",
+                chunk_index, file_id
+            );
+            return;
+        }
+        let status = self.file_manager.finish_chunk(written);
+        self.chunk_handled(peer_id, file_id, chunk_index, chunk_size, status);
+    }
+
+    /// Move a download on after one of its chunks was handled
+    fn chunk_handled(
+        &mut self,
+        peer_id: PeerId,
+        file_id: String,
+        chunk_index: u32,
+        chunk_size: u64,
+        status: io::Result<TransferStatus>,
+    ) {
+        if let Ok(TransferStatus::ChunkReceived { .. } | TransferStatus::TransferComplete) = status
+        {
+            let activity = self.transfer_activity.entry(file_id.clone()).or_default();
+            activity.record_chunk(peer_id, chunk_size);
+            if let Ok(TransferStatus::TransferComplete) = status {
+                activity.finish();
+            }
+        }
+        match status {
+            Ok(TransferStatus::ChunkReceived {
+                progress,
+                bytes_received,
+                total_bytes,
+                chunks_remaining,
+            }) => {
+                info!(
+                    "📦 Chunk received for {}: {:.1}%",
+                    file_id,
+                    progress * 100.0
+                );
+                self.pending_events
+                    .push_back(MessagingBehaviourEvent::ChunkReceived {
+                        file_id: file_id.clone(),
+                        chunk_index,
+                        peer: peer_id,
+                        chunk_size,
+                        bytes_received,
+                        total_bytes,
+                        chunks_remaining,
+                        progress,
+                        speed: self
+                            .transfer_activity
+                            .get(&file_id)
+                            .map_or(0.0, |activity| activity.recent_speed()),
+                    });
+
+                // Request next batch of chunks
+                self.request_missing_chunks();
+            }
+            Ok(TransferStatus::TransferComplete) => {
+                info!("✅ Transfer complete: {}", file_id);
+                self.forget_chunk_requests(&file_id);
+                self.start_queued_downloads();
+                self.request_missing_chunks();
+                self.report_complete(&file_id);
+
+                // Send completion acknowledgment
+                let complete_msg = self.new_message(MessageType::TransferComplete {
+                    file_id,
+                    success: true,
+                });
+                self.send_message(peer_id, complete_msg);
+            }
+            Ok(TransferStatus::VerificationFailed { chunk_index }) => {
+                error!(
+                    "❌ Chunk verification failed: {} chunk {}",
+                    file_id, chunk_index
+                );
+                self.record_peer_failure(&peer_id);
+                self.stats.verification_failed(&peer_id);
+                self.transfer_failed(
+                    file_id.clone(),
+                    Some(peer_id),
+                    format!("Chunk {} verification failed", chunk_index),
+                );
+
+                // Send cancellation message
+                let cancel_msg = self.new_message(MessageType::TransferCancel {
+                    file_id: file_id.clone(),
+                    reason: format!("Chunk {} verification failed", chunk_index),
+                });
+                self.send_message(peer_id, cancel_msg);
+            }
+            Err(e) => {
+                error!("Failed to handle chunk: {}", e);
+                self.transfer_failed(file_id, Some(peer_id), e.to_string());
+            }
+        }
+    }
 
-        // Dummy NodeId - ideally this would be the real node's ID
-        let dummy_pubkey = ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
-
-        let discovery_msg = Message {
-            msg_type: MessageType::Discovery(discovery_data),
-            from: NodeId::from_pubkey(&dummy_pubkey),
-            to: None,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-            signature: vec![],
-        };
+    /// Read a chunk `peer_id` asked for, if we hold it
+    fn serve_chunk(
+        &mut self,
+        peer_id: PeerId,
+        file_id: &str,
+        chunk_index: u32,
+    ) -> Option<FileChunk> {
+        match self.file_manager.handle_chunk_request(file_id, chunk_index) {
+            Ok(Some(chunk)) => {
+                self.upload_activity
+                    .retain(|_, upload| !upload.is_expired());
+                self.upload_activity
+                    .entry(file_id.to_string())
+                    .or_default()
+                    .record_chunk(peer_id, chunk.data.len() as u64);
+                self.network
+                    .record_sent(&NodeId::from_peer_id(&peer_id), chunk.data.len());
+                self.stats.chunk_served(&peer_id);
+                Some(chunk)
+            }
+            Ok(None) => {
+                warn!("Chunk {} not found for file {}", chunk_index, file_id);
+                None
+            }
+            Err(e) => {
+                error!("Failed to handle chunk request for {}: {}", file_id, e);
+                None
+            }
+        }
+    }
 
-        for peer in peers {
-            self.send_message(peer, discovery_msg.clone());
+    /// Answer a peer's request for chunks or a file we hold
+    fn serve_transfer(&mut self, peer_id: PeerId, request: TransferRequest) -> TransferResponse {
+        match request {
+            TransferRequest::Chunk {
+                file_id,
+                chunk_index,
+            } => self
+                .serve_chunk(peer_id, &file_id, chunk_index)
+                .map_or(TransferResponse::NotFound, TransferResponse::Chunk),
+            // Chunks we do not hold are left out; the requester asks elsewhere
+            TransferRequest::ChunkBatch {
+                file_id,
+                chunk_indices,
+            } => TransferResponse::Chunks(
+                chunk_indices
+                    .into_iter()
+                    .take(MAX_CHUNK_BATCH)
+                    .filter_map(|chunk_index| self.serve_chunk(peer_id, &file_id, chunk_index))
+                    .collect(),
+            ),
+            TransferRequest::File { file_id } => self
+                .file_manager
+                .offered_file(&file_id)
+                .or_else(|| self.file_manager.completed_download(&file_id))
+                .cloned()
+                .map_or(TransferResponse::NotFound, TransferResponse::File),
         }
     }
 
-    /// Offer a file for transfer to the network
-    pub fn offer_file(&mut self, path: &Path) -> io::Result<FileMetadata> {
-        let metadata = self.file_manager.offer_file(path)?;
-        info!(
-            "📤 Offering file: {} ({} bytes, {} chunks)",
-            metadata.name, metadata.size, metadata.total_chunks
+    /// Store the chunks `peer` answered a request for `asked` with, in the
+    /// order they came. Chunks it did not have are asked for again once the
+    /// request times out.
+    fn chunks_answered(
+        &mut self,
+        peer: PeerId,
+        file_id: &str,
+        asked: &[u32],
+        chunks: Vec<FileChunk>,
+    ) {
+        let mut missing: HashSet<u32> = asked.iter().copied().collect();
+        for chunk in chunks {
+            if chunk.file_id != file_id || !missing.remove(&chunk.chunk_index) {
+                warn!(
+                    "Unexpected chunk {} of {} from {}",
+                    chunk.chunk_index, chunk.file_id, peer
+                );
+                continue;
+            }
+            // A bad chunk ends the download; the rest of the batch is moot
+            if !self.file_manager.is_downloading(file_id) {
+                return;
+            }
+            self.network
+                .record_received(&NodeId::from_peer_id(&peer), chunk.data.len());
+            self.chunk_received(peer, chunk);
+        }
+        if missing.is_empty() || !self.file_manager.is_downloading(file_id) {
+            return;
+        }
+        let mut missing: Vec<u32> = missing.into_iter().collect();
+        missing.sort_unstable();
+        for &chunk_index in &missing {
+            self.chunk_requests
+                .remove(&(file_id.to_string(), chunk_index));
+        }
+        self.record_transfer_error(
+            file_id,
+            Some(peer),
+            format!("{} does not have chunks {:?}", peer, missing),
         );
+    }
 
-        // Broadcast file offer to all connected peers
-        let peers: Vec<PeerId> = self.connected_peers.keys().copied().collect();
-        let dummy_pubkey = ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
-
-        for peer in peers {
-            let offer_msg = Message {
-                msg_type: MessageType::FileOffer(metadata.clone()),
-                from: NodeId::from_pubkey(&dummy_pubkey),
-                to: None,
-                timestamp: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
-                signature: vec![],
-            };
-            self.send_message(peer, offer_msg);
+    fn on_transfer_event(
+        &mut self,
+        event: request_response::Event<TransferRequest, TransferResponse>,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                let msg_type = match request {
+                    TransferRequest::Chunk { .. } | TransferRequest::ChunkBatch { .. } => {
+                        "ChunkRequest"
+                    }
+                    TransferRequest::File { .. } => "FileRequest",
+                };
+                // Left unanswered, so the peer gives up on it
+                if !self.admit(peer, msg_type) {
+                    return;
+                }
+                let response = self.serve_transfer(peer, request);
+                if self.transfers.send_response(channel, response).is_err() {
+                    debug!("{} went away before its request was answered", peer);
+                }
+            }
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } if self.outbound_files.contains_key(&request_id) => {
+                match self.outbound_files.remove(&request_id) {
+                    Some((file_id, Some(root))) => {
+                        self.catalog_file_answered(peer, file_id, root, response);
+                    }
+                    Some((file_id, None)) => self.file_answered(peer, file_id, response),
+                    None => {}
+                }
+            }
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => match (self.outbound_chunks.remove(&request_id), response) {
+                (Some((file_id, asked)), TransferResponse::Chunk(chunk)) => {
+                    self.chunks_answered(peer, &file_id, &asked, vec![chunk]);
+                }
+                (Some((file_id, asked)), TransferResponse::Chunks(chunks)) => {
+                    self.chunks_answered(peer, &file_id, &asked, chunks);
+                }
+                (Some((file_id, asked)), TransferResponse::NotFound) => {
+                    self.chunks_answered(peer, &file_id, &asked, Vec::new());
+                }
+                (_, response) => warn!("Unexpected answer from {}: {:?}", peer, response),
+            },
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                if let Some((file_id, root)) = self.outbound_files.remove(&request_id) {
+                    if root.is_some() {
+                        debug!("Could not fetch {} from {}: {}", file_id, peer, error);
+                        return;
+                    }
+                    self.pending_events
+                        .push_back(MessagingBehaviourEvent::FileNotFound {
+                            peer,
+                            error: format!("Request for {} failed: {}", file_id, error),
+                            file_id,
+                        });
+                    return;
+                }
+                let Some((file_id, asked)) = self.outbound_chunks.remove(&request_id) else {
+                    return;
+                };
+                for &chunk_index in &asked {
+                    self.chunk_requests.remove(&(file_id.clone(), chunk_index));
+                }
+                self.file_manager.release_chunk_requests(&file_id, &asked);
+                let connection_lost =
+                    matches!(error, request_response::OutboundFailure::ConnectionClosed);
+                let msg_type = if asked.len() == 1 {
+                    "ChunkRequest"
+                } else {
+                    "ChunkRequestBatch"
+                };
+                let error = format!("Request for chunks {:?} failed: {}", asked, error);
+                self.record_transfer_error(&file_id, Some(peer), error.clone());
+                self.record_peer_failure(&peer);
+                self.pending_events
+                    .push_back(MessagingBehaviourEvent::SendError {
+                        to: peer,
+                        error,
+                        receipt: None,
+                        msg_type: msg_type.to_string(),
+                        attempts: 1,
+                    });
+                // Ask again at once, on another connection or from another
+                // source if the peer is gone
+                if connection_lost {
+                    self.request_chunks(&file_id);
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("Failed to answer a request from {}: {}", peer, error);
+            }
+            request_response::Event::ResponseSent { .. } => {}
         }
+    }
 
-        Ok(metadata)
+    /// Write an offered or downloaded file's contents to `dest`
+    pub fn export_file(&self, file_id: &str, dest: &Path) -> io::Result<()> {
+        self.file_manager.export_file(file_id, dest)
     }
 }
 
 impl NetworkBehaviour for MessagingBehaviour {
-    type ConnectionHandler = CoreLinkHandler;
+    type ConnectionHandler = ConnectionHandlerSelect<
+        CoreLinkHandler,
+        THandler<request_response::Behaviour<TransferCodec>>,
+    >;
     type ToSwarm = MessagingBehaviourEvent;
 
     fn handle_established_inbound_connection(
         &mut self,
-        _connection_id: ConnectionId,
-        _peer: PeerId,
-        _local_addr: &Multiaddr,
-        _remote_addr: &Multiaddr,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
     ) -> Result<THandler<Self>, ConnectionDenied> {
-        info!("🔵 Creating handler for inbound connection");
-        Ok(CoreLinkHandler::new())
+        if let Err(e) = self.connections.admit_peer(&peer) {
+            warn!("⛔ Refusing inbound connection from {}: {}", peer, e);
+            return Err(ConnectionDenied::new(e));
+        }
+        let network = &self.network;
+        let reputation = &self.reputation;
+        let score = |peer: &PeerId| admission_score(network, reputation, peer);
+
+        match self.connections.admit_inbound(remote_addr, score) {
+            Ok(evict) => {
+                if let Some((evicted, connection)) = evict {
+                    info!("👢 Evicting {} to make room for {}", evicted, peer);
+                    self.pending_disconnects.push_back((evicted, connection));
+                }
+                info!("🔵 Creating handler for inbound connection");
+                let transfers = self.transfers.handle_established_inbound_connection(
+                    connection_id,
+                    peer,
+                    local_addr,
+                    remote_addr,
+                )?;
+                Ok(CoreLinkHandler::new().select(transfers))
+            }
+            Err(e) => {
+                warn!("⛔ Refusing inbound connection from {}: {}", peer, e);
+                Err(ConnectionDenied::new(e))
+            }
+        }
     }
 
     fn handle_established_outbound_connection(
         &mut self,
-        _connection_id: ConnectionId,
-        _peer: PeerId,
-        _addr: &Multiaddr,
-        _role_override: Endpoint,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
     ) -> Result<THandler<Self>, ConnectionDenied> {
+        if let Err(e) = self
+            .connections
+            .admit_peer(&peer)
+            .and_then(|()| self.connections.admit_outbound())
+        {
+            warn!("⛔ Dropping outbound connection to {}: {}", peer, e);
+            return Err(ConnectionDenied::new(e));
+        }
         info!("🔴 Creating handler for outbound connection");
-        Ok(CoreLinkHandler::new())
+        let transfers = self.transfers.handle_established_outbound_connection(
+            connection_id,
+            peer,
+            addr,
+            role_override,
+        )?;
+        Ok(CoreLinkHandler::new().select(transfers))
     }
 
     fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.transfers.on_swarm_event(event);
         if let FromSwarm::ConnectionEstablished(e) = event {
             info!(
                 "Established {} connection with {}",
@@ -169,274 +2316,1016 @@ impl NetworkBehaviour for MessagingBehaviour {
                 .entry(e.peer_id)
                 .or_default()
                 .push(e.connection_id);
+            if self.kept_alive.contains(&e.peer_id) {
+                self.keep_alive_updates
+                    .push_back((e.peer_id, e.connection_id, true));
+            }
+            self.connections.established(
+                e.connection_id,
+                e.peer_id,
+                !e.endpoint.is_dialer(),
+                e.endpoint.get_remote_address(),
+            );
+
+            let mut peer = network::PeerInfo::new(
+                NodeId::from_peer_id(&e.peer_id),
+                e.endpoint.get_remote_address().to_string(),
+            );
+            peer.last_seen = current_timestamp();
+            self.network.add_peer(peer);
+            // Until it announces itself again, trust what it said last time
+            if let Some(discovery) = self.discoveries.get(&e.peer_id) {
+                self.apply_discovery(&e.peer_id, &discovery);
+            }
+
+            // Tell a new peer about us right away, so it learns whether we
+            // understand catalogs and sends its own
+            if e.other_established == 0 {
+                let discovery = self.discovery_message();
+                self.send_message(e.peer_id, discovery);
+            }
         } else if let FromSwarm::ConnectionClosed(e) = event {
+            self.connections.closed(e.connection_id);
+            self.messaging_streams.remove(&e.connection_id);
             if let Some(conns) = self.connected_peers.get_mut(&e.peer_id) {
                 conns.retain(|id| id != &e.connection_id);
-                if conns.is_empty() {
+                let last = conns.is_empty();
+                // What the connection was writing is lost; try it again on
+                // another one, or give up on everything with the last
+                self.outbound.connection_closed(&e.peer_id, last);
+                if last {
+                    // Queued messages are tracked too, with those in flight
+                    let dropped = self.sends.to_peer(&e.peer_id);
+                    self.messages_dropped(e.peer_id, dropped, "The peer disconnected");
+                } else {
+                    for id in self.sends.written_on(e.connection_id) {
+                        self.send_failed(e.peer_id, id, "The connection closed".to_string());
+                    }
+                }
+                if last {
                     self.connected_peers.remove(&e.peer_id);
+                    self.kept_alive.remove(&e.peer_id);
+                    self.catalogs_sent.remove(&e.peer_id);
+                    self.peer_links.remove(&e.peer_id);
+                    self.flood.forget(&e.peer_id);
+                    self.peer_addresses.remove(&e.peer_id);
+                    self.versions.disconnected(&e.peer_id);
+                    self.replication.peer_disconnected(&e.peer_id);
+                    self.network.remove_peer(&NodeId::from_peer_id(&e.peer_id));
+                    self.migrate_downloads(e.peer_id);
                     info!("All connections closed with {}", e.peer_id);
                 }
             }
+        } else if let FromSwarm::NewListenAddr(e) = event {
+            if !self.local_addresses.contains(e.addr) {
+                self.local_addresses.push(e.addr.clone());
+            }
+        } else if let FromSwarm::ExternalAddrConfirmed(e) = event {
+            if !self.local_addresses.contains(e.addr) {
+                self.local_addresses.push(e.addr.clone());
+            }
+        } else if let FromSwarm::ExpiredListenAddr(e) = event {
+            self.local_addresses.retain(|addr| addr != e.addr);
+        } else if let FromSwarm::ExternalAddrExpired(e) = event {
+            self.local_addresses.retain(|addr| addr != e.addr);
         }
     }
 
     fn on_connection_handler_event(
         &mut self,
         peer_id: PeerId,
-        _connection_id: ConnectionId,
+        connection_id: ConnectionId,
         event: THandlerOutEvent<Self>,
     ) {
+        let event = match event {
+            Either::Left(event) => event,
+            Either::Right(event) => {
+                self.transfers
+                    .on_connection_handler_event(peer_id, connection_id, event);
+                return;
+            }
+        };
         match event {
-            CoreLinkHandlerEvent::MessageReceived(msg) => {
+            CoreLinkHandlerEvent::MessageReceived(msg, bytes) => {
                 info!("📨 Received message from {}: {:?}", peer_id, msg.msg_type);
+                self.network
+                    .record_received(&NodeId::from_peer_id(&peer_id), bytes);
+                if !self.admit(peer_id, msg.msg_type.name()) {
+                    return;
+                }
 
-                // Handle file transfer messages
-                match &msg.msg_type {
-                    MessageType::FileOffer(metadata) => {
+                // Drop registry updates from stale epochs (e.g. a deposed leader)
+                let previous = self.consensus.current_epoch();
+                match self
+                    .consensus
+                    .observe_epoch(NodeId::from_peer_id(&peer_id), msg.epoch)
+                {
+                    Err(e) if msg.msg_type.affects_registry() => {
+                        self.stale_messages += 1;
+                        warn!("🧟 Rejecting {:?} from {}: {}", msg.msg_type, peer_id, e);
+                        self.pending_events.push_back(
+                            MessagingBehaviourEvent::StaleEpochRejected {
+                                from: peer_id,
+                                msg_type: msg.msg_type.name().to_string(),
+                                epoch: msg.epoch.unwrap_or_default(),
+                                current: previous,
+                            },
+                        );
+                        return;
+                    }
+                    Ok(()) if self.consensus.current_epoch() != previous => {
                         info!(
-                            "📁 File offered by {}: {} ({} bytes)",
-                            peer_id, metadata.name, metadata.size
+                            "🗳️ Epoch {} -> {} after message from {}",
+                            previous,
+                            self.consensus.current_epoch(),
+                            peer_id
                         );
+                        self.pending_events
+                            .push_back(MessagingBehaviourEvent::EpochChanged {
+                                from: peer_id,
+                                previous,
+                                epoch: self.consensus.current_epoch(),
+                            });
+                    }
+                    _ => {}
+                }
 
-                        // Auto-start download
-                        let file_id = metadata.file_id.clone();
-                        let output_path = self
-                            .file_manager
-                            .storage_path
-                            .join("downloads")
-                            .join(&metadata.name);
+                if let MessageType::Discovery(discovery) = &msg.msg_type {
+                    self.versions.discovered(peer_id, &discovery.features);
+                    self.send_catalog(peer_id);
+                    self.apply_discovery(&peer_id, discovery);
+                    self.discoveries.record(&peer_id, discovery);
+                    let addresses: Vec<Multiaddr> = discovery
+                        .peers
+                        .iter()
+                        .filter_map(|addr| addr.parse().ok())
+                        .collect();
+                    let linked = addresses
+                        .iter()
+                        .filter_map(|addr: &Multiaddr| {
+                            addr.iter().find_map(|protocol| match protocol {
+                                Protocol::P2p(peer) => Some(peer),
+                                _ => None,
+                            })
+                        })
+                        .filter(|peer| *peer != peer_id)
+                        .collect();
+                    self.peer_links.insert(peer_id, linked);
+                    if !addresses.is_empty() {
+                        self.pending_events
+                            .push_back(MessagingBehaviourEvent::PeersAdvertised {
+                                from: peer_id,
+                                addresses,
+                            });
+                    }
+                }
 
-                        match self
-                            .file_manager
-                            .request_file(metadata.clone(), output_path, peer_id)
-                        {
-                            Ok(_) => {
-                                info!("🔽 Auto-downloading: {}", metadata.name);
-
-                                // Request first batch of chunks
-                                let chunks_to_request =
-                                    self.file_manager.get_next_chunks_to_request(&file_id, 5);
-
-                                if !chunks_to_request.is_empty() {
-                                    let dummy_pubkey =
-                                        ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32])
-                                            .unwrap();
-
-                                    for chunk_index in chunks_to_request {
-                                        let chunk_request_msg = Message {
-                                            msg_type: MessageType::ChunkRequest {
-                                                file_id: file_id.clone(),
-                                                chunk_index,
-                                            },
-                                            from: NodeId::from_pubkey(&dummy_pubkey),
-                                            to: None,
-                                            timestamp: std::time::SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .unwrap()
-                                                .as_secs(),
-                                            signature: vec![],
-                                        };
-                                        self.send_message(peer_id, chunk_request_msg);
-                                        info!("📦 Requesting chunk {} of {}", chunk_index, file_id);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                warn!("❌ Failed to start auto-download: {}", e);
+                // Handle file transfer messages
+                match &msg.msg_type {
+                    MessageType::FileOffer(metadata) => self.offer_received(peer_id, metadata),
+                    MessageType::TransferComplete { file_id, success } => {
+                        // A peer finished fetching one of our files and now holds a copy
+                        if *success {
+                            info!("🧬 {} now holds {}", peer_id, file_id);
+                            self.replication.record_holder(file_id, peer_id);
+                            self.record_file_holder(file_id, peer_id);
+                        } else {
+                            self.replication.clear_pending(file_id, &peer_id);
+                        }
+                    }
+                    MessageType::FileWithdraw { file_id } => {
+                        info!("🙅 {} withdrew its offer of {}", peer_id, file_id);
+                        if let Some((_, peers)) = self.remote_offers.get_mut(file_id) {
+                            peers.remove(&peer_id);
+                        }
+                        if self.pending_offers.withdrawn(file_id, &peer_id) {
+                            self.pending_events
+                                .push_back(MessagingBehaviourEvent::OfferClosed {
+                                    file_id: file_id.clone(),
+                                    removal: FileRemoval::Withdrawn,
+                                });
+                        }
+                        if self.file_manager.remove_download_source(file_id, &peer_id) {
+                            if let Err(e) = self.file_manager.cancel_download(file_id) {
+                                warn!("Failed to cancel download {}: {}", file_id, e);
                             }
+                            self.forget_chunk_requests(file_id);
+                            self.transfer_activity.remove(file_id);
+                            self.start_queued_downloads();
+                            self.pending_events.push_back(
+                                MessagingBehaviourEvent::TransferFailed {
+                                    file_id: file_id.clone(),
+                                    reason: "Offer withdrawn by peer".to_string(),
+                                },
+                            );
                         }
-
+                    }
+                    MessageType::OfferRejected { file_id } => {
+                        info!("🙅 {} declined {}", peer_id, file_id);
+                        self.replication.clear_pending(file_id, &peer_id);
                         self.pending_events
-                            .push_back(MessagingBehaviourEvent::FileOffered {
+                            .push_back(MessagingBehaviourEvent::OfferRejected {
                                 peer: peer_id,
-                                metadata: metadata.clone(),
+                                file_id: file_id.clone(),
                             });
                     }
-                    MessageType::ChunkRequest {
+                    MessageType::TransferCancel { file_id, reason } => {
+                        warn!(
+                            "🚫 {} cancelled transfer of {}: {}",
+                            peer_id, file_id, reason
+                        );
+                        self.replication.clear_pending(file_id, &peer_id);
+                    }
+                    MessageType::StorageChallenge {
                         file_id,
                         chunk_index,
+                        offset,
+                        length,
+                        nonce,
                     } => {
-                        // Handle chunk request - serve the chunk
-                        match self
-                            .file_manager
-                            .handle_chunk_request(file_id, *chunk_index)
-                        {
-                            Ok(Some(chunk)) => {
-                                let dummy_pubkey =
-                                    ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
-                                let chunk_msg = Message {
-                                    msg_type: MessageType::ChunkData(chunk),
-                                    from: NodeId::from_pubkey(&dummy_pubkey),
-                                    to: None,
-                                    timestamp: std::time::SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .unwrap()
-                                        .as_secs(),
-                                    signature: vec![],
-                                };
-                                self.send_message(peer_id, chunk_msg);
-                            }
-                            Ok(None) => {
-                                warn!("Chunk {} not found for file {}", chunk_index, file_id);
+                        // Only those who gave us a file may ask us to prove we
+                        // hold it
+                        let offered_by_challenger = self
+                            .remote_offers
+                            .get(file_id)
+                            .is_some_and(|(_, peers)| peers.contains(&peer_id));
+                        if !offered_by_challenger {
+                            warn!(
+                                "Ignoring storage challenge from {} for {}, which it never offered",
+                                peer_id, file_id
+                            );
+                            return;
+                        }
+                        let proof = match self.file_manager.read_chunk(file_id, *chunk_index) {
+                            Ok(Some(data)) if !challenge_span_allowed(&data, *offset, *length) => {
+                                warn!(
+                                    "Ignoring storage challenge from {} over {} byte(s) of {}",
+                                    peer_id, length, file_id
+                                );
+                                return;
                             }
+                            Ok(Some(data)) => storage_proof(nonce, &data, *offset, *length),
+                            Ok(None) => None,
                             Err(e) => {
-                                error!("Failed to handle chunk request for {}: {}", file_id, e);
+                                warn!("Failed to read chunk for storage challenge: {}", e);
+                                None
                             }
-                        }
+                        };
+                        let reply = self.new_message(MessageType::StorageProof {
+                            file_id: file_id.clone(),
+                            nonce: *nonce,
+                            proof,
+                        });
+                        self.send_message(peer_id, reply);
                     }
-                    MessageType::ChunkData(chunk) => {
-                        // Handle received chunk
-                        let file_id = chunk.file_id.clone();
-                        match self.file_manager.handle_chunk_received(chunk.clone()) {
-                            Ok(TransferStatus::ChunkReceived { progress }) => {
+                    MessageType::StorageProof {
+                        file_id,
+                        nonce,
+                        proof,
+                    } => match self.challenges.get(nonce) {
+                        Some(challenge) if challenge.peer == peer_id => {
+                            let challenge = self.challenges.remove(nonce).unwrap();
+                            if *proof == Some(challenge.expected) {
+                                let score = self.reputation.adjust(&peer_id, PROOF_REWARD);
                                 info!(
-                                    "📦 Chunk received for {}: {:.1}%",
-                                    file_id,
-                                    progress * 100.0
-                                );
-                                self.pending_events.push_back(
-                                    MessagingBehaviourEvent::ChunkReceived {
-                                        file_id: file_id.clone(),
-                                        progress,
-                                    },
-                                );
-
-                                // Request next batch of chunks
-                                let chunks_to_request =
-                                    self.file_manager.get_next_chunks_to_request(&file_id, 5);
-                                if !chunks_to_request.is_empty() {
-                                    let dummy_pubkey =
-                                        ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32])
-                                            .unwrap();
-                                    for chunk_index in chunks_to_request {
-                                        let request_msg = Message {
-                                            msg_type: MessageType::ChunkRequest {
-                                                file_id: file_id.clone(),
-                                                chunk_index,
-                                            },
-                                            from: NodeId::from_pubkey(&dummy_pubkey),
-                                            to: None,
-                                            timestamp: std::time::SystemTime::now()
-                                                .duration_since(std::time::UNIX_EPOCH)
-                                                .unwrap()
-                                                .as_secs(),
-                                            signature: vec![],
-                                        };
-                                        self.send_message(peer_id, request_msg);
-                                    }
-                                }
-                            }
-                            Ok(TransferStatus::TransferComplete) => {
-                                info!("✅ Transfer complete: {}", file_id);
-                                self.pending_events.push_back(
-                                    MessagingBehaviourEvent::TransferComplete {
-                                        file_id: file_id.clone(),
-                                    },
-                                );
-
-                                // Send completion acknowledgment
-                                let dummy_pubkey =
-                                    ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
-                                let complete_msg = Message {
-                                    msg_type: MessageType::TransferComplete {
-                                        file_id,
-                                        success: true,
-                                    },
-                                    from: NodeId::from_pubkey(&dummy_pubkey),
-                                    to: None,
-                                    timestamp: std::time::SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .unwrap()
-                                        .as_secs(),
-                                    signature: vec![],
-                                };
-                                self.send_message(peer_id, complete_msg);
-                            }
-                            Ok(TransferStatus::VerificationFailed { chunk_index }) => {
-                                error!(
-                                    "❌ Chunk verification failed: {} chunk {}",
-                                    file_id, chunk_index
-                                );
-                                self.pending_events.push_back(
-                                    MessagingBehaviourEvent::TransferFailed {
-                                        file_id: file_id.clone(),
-                                        reason: format!(
-                                            "Chunk {} verification failed",
-                                            chunk_index
-                                        ),
-                                    },
-                                );
-
-                                // Send cancellation message
-                                let dummy_pubkey =
-                                    ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
-                                let cancel_msg = Message {
-                                    msg_type: MessageType::TransferCancel {
-                                        file_id: file_id.clone(),
-                                        reason: format!(
-                                            "Chunk {} verification failed",
-                                            chunk_index
-                                        ),
-                                    },
-                                    from: NodeId::from_pubkey(&dummy_pubkey),
-                                    to: None,
-                                    timestamp: std::time::SystemTime::now()
-                                        .duration_since(std::time::UNIX_EPOCH)
-                                        .unwrap()
-                                        .as_secs(),
-                                    signature: vec![],
-                                };
-                                self.send_message(peer_id, cancel_msg);
-                            }
-                            Err(e) => {
-                                error!("Failed to handle chunk: {}", e);
-                                self.pending_events.push_back(
-                                    MessagingBehaviourEvent::TransferFailed {
-                                        file_id,
-                                        reason: e.to_string(),
-                                    },
+                                    "✅ {} proved it holds {} (reputation {})",
+                                    peer_id, file_id, score
                                 );
+                            } else if proof.is_none() {
+                                self.storage_challenge_failed(peer_id, file_id, "data not held");
+                            } else {
+                                self.storage_challenge_failed(peer_id, file_id, "wrong proof");
                             }
                         }
+                        _ => warn!("Unexpected storage proof from {}", peer_id),
+                    },
+                    MessageType::FileQuery {
+                        query_id,
+                        query,
+                        limit,
+                    } => {
+                        let files: Vec<FileMetadata> = self
+                            .file_manager
+                            .offered_files()
+                            .filter(|metadata| name_matches(&metadata.name, query))
+                            .take((*limit).min(MAX_QUERY_RESULTS) as usize)
+                            .cloned()
+                            .collect();
+                        info!(
+                            "🔎 {} searched for {:?}, {} matches",
+                            peer_id,
+                            query,
+                            files.len()
+                        );
+                        let reply = self.new_message(MessageType::FileQueryResults {
+                            query_id: *query_id,
+                            files,
+                        });
+                        self.send_message(peer_id, reply);
+                    }
+                    MessageType::FileCatalog(entries) => self.catalog_received(peer_id, entries),
+                    MessageType::FileQueryResults { query_id, files } => {
+                        // Remembered as offers, so a search result can be downloaded
+                        for metadata in files {
+                            self.remote_offers
+                                .entry(metadata.file_id.clone())
+                                .or_insert_with(|| (metadata.clone(), HashSet::new()))
+                                .1
+                                .insert(peer_id);
+                        }
+                        self.pending_events
+                            .push_back(MessagingBehaviourEvent::QueryResults {
+                                query_id: *query_id,
+                                peer: peer_id,
+                                files: files.clone(),
+                            });
+                    }
+                    MessageType::Custom { msg_type, payload } => {
+                        if msg.verify(&peer_id) {
+                            self.pending_events
+                                .push_back(MessagingBehaviourEvent::CustomMessage {
+                                    from: peer_id,
+                                    msg_type: msg_type.clone(),
+                                    payload: payload.clone(),
+                                });
+                        } else {
+                            warn!(
+                                "🔏 Dropping badly signed {} message from {}",
+                                msg_type, peer_id
+                            );
+                            self.pending_events
+                                .push_back(MessagingBehaviourEvent::BadSignature {
+                                    from: peer_id,
+                                    msg_type: msg_type.clone(),
+                                });
+                        }
                     }
                     _ => {
                         // Other message types - emit as generic MessageReceived
                         self.pending_events
                             .push_back(MessagingBehaviourEvent::MessageReceived {
                                 from: peer_id,
-                                message: msg,
+                                message: *msg,
                             });
                     }
                 }
             }
-            CoreLinkHandlerEvent::MessageSent => {
+            CoreLinkHandlerEvent::MessageSent(bytes, id) => {
                 info!("✅ Message sent to {}", peer_id);
+                self.outbound.settled(&peer_id);
+                self.network
+                    .record_sent(&NodeId::from_peer_id(&peer_id), bytes);
+                let send = self.sends.remove(id);
+                if let Some(send) = &send {
+                    self.stats
+                        .message_sent(&peer_id, send.message.msg_type.name());
+                }
                 self.pending_events
-                    .push_back(MessagingBehaviourEvent::MessageSent { to: peer_id });
+                    .push_back(MessagingBehaviourEvent::MessageSent {
+                        to: peer_id,
+                        receipt: send.and_then(|send| send.receipt),
+                    });
+            }
+            CoreLinkHandlerEvent::MessagingStream(usable) => {
+                if !usable {
+                    warn!(
+                        "Connection {} to {} cannot carry messages",
+                        connection_id, peer_id
+                    );
+                }
+                self.messaging_streams.insert(connection_id, usable);
             }
-            CoreLinkHandlerEvent::SendError(error) => {
+            CoreLinkHandlerEvent::SendError(error, id) => {
                 info!("❌ Failed to send message to {}: {}", peer_id, error);
-                self.pending_events
-                    .push_back(MessagingBehaviourEvent::SendError { to: peer_id, error });
+                self.outbound.settled(&peer_id);
+                self.record_peer_failure(&peer_id);
+                self.send_failed(peer_id, id, error);
             }
         }
     }
 
-    fn poll(&mut self, _cx: &mut Context) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+    fn poll(&mut self, cx: &mut Context) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        // Serve and collect transfer requests, which may queue events below
+        while let Poll::Ready(action) = self.transfers.poll(cx) {
+            match action {
+                ToSwarm::GenerateEvent(event) => self.on_transfer_event(event),
+                action => {
+                    return Poll::Ready(
+                        action
+                            .map_in(Either::Right)
+                            .map_out(|_| unreachable!("transfer events are handled above")),
+                    )
+                }
+            }
+        }
+
+        // Move downloads on with the chunks the workers stored
+        while let Poll::Ready(Some((peer, written))) = self.chunk_workers.poll_next(cx) {
+            self.chunk_stored(peer, written);
+        }
+
+        // Queue due retries, which may drop messages and queue events below
+        self.poll_retries(cx);
+        self.update_keep_alive();
+
         // First emit any pending events to the swarm
         if let Some(event) = self.pending_events.pop_front() {
             return Poll::Ready(ToSwarm::GenerateEvent(event));
         }
 
+        if let Some((peer_id, connection)) = self.pending_disconnects.pop_front() {
+            return Poll::Ready(ToSwarm::CloseConnection {
+                peer_id,
+                connection: CloseConnection::One(connection),
+            });
+        }
+
+        if let Some((peer_id, connection, keep_alive)) = self.keep_alive_updates.pop_front() {
+            return Poll::Ready(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(connection),
+                event: Either::Left(CoreLinkHandlerIn::KeepAlive(keep_alive)),
+            });
+        }
+
         // Then handle sending messages to handlers
-        if let Some((peer, message)) = self.pending_handler_messages.pop_front() {
+        if let Some((peer, outgoing)) = self.outbound.next() {
+            // Messages are written on one known connection, so those it
+            // loses when it closes can be retried
+            let connection = self.message_connection(&peer);
+            if let Some(connection) = connection {
+                self.sends.handed_over(outgoing.1, connection);
+            }
             return Poll::Ready(ToSwarm::NotifyHandler {
                 peer_id: peer,
-                handler: NotifyHandler::Any,
-                event: message,
+                handler: connection.map_or(NotifyHandler::Any, NotifyHandler::One),
+                event: Either::Left(CoreLinkHandlerIn::Send(Box::new(outgoing))),
             });
         }
 
         Poll::Pending
     }
 }
+
+/// Hooks for [`crate::behaviour_harness`] into what the swarm would
+/// otherwise hide
+#[cfg(test)]
+impl MessagingBehaviour {
+    /// Received chunks still being verified and stored
+    pub(crate) fn chunk_writes_in_flight(&self) -> usize {
+        self.chunk_workers.in_flight()
+    }
+
+    /// Chunk requests in flight: each request, the peer asked, the file and
+    /// the chunks asked for
+    pub(crate) fn outbound_chunk_requests(
+        &self,
+    ) -> Vec<(OutboundRequestId, PeerId, String, Vec<u32>)> {
+        self.outbound_chunks
+            .iter()
+            .filter_map(|(&request_id, (file_id, asked))| {
+                let (peer, _) = self
+                    .chunk_requests
+                    .get(&(file_id.clone(), *asked.first()?))?;
+                Some((request_id, *peer, file_id.clone(), asked.clone()))
+            })
+            .collect()
+    }
+
+    /// File requests in flight, with the file each asked for
+    pub(crate) fn outbound_file_requests(&self) -> Vec<(OutboundRequestId, String)> {
+        self.outbound_files
+            .iter()
+            .map(|(&request_id, (file_id, _))| (request_id, file_id.clone()))
+            .collect()
+    }
+
+    /// Handle a transfer protocol event as if the swarm had delivered it
+    pub(crate) fn inject_transfer_event(
+        &mut self,
+        event: request_response::Event<TransferRequest, TransferResponse>,
+    ) {
+        self.on_transfer_event(event);
+    }
+}
+
+/// How much a peer is worth keeping connected: a point per 10 reputation,
+/// less up to 10 for failing its requests. Latency and throughput are left
+/// out, so healthy but distant peers score like newcomers (0).
+fn admission_score(network: &NetworkState, reputation: &ReputationTracker, peer: &PeerId) -> f64 {
+    let failure_rate = network
+        .get_peer(&NodeId::from_peer_id(peer))
+        .map_or(0.0, |info| info.failure_rate());
+    reputation.score(peer) as f64 / 10.0 - failure_rate * 10.0
+}
+
+/// Whether `addr` is a wildcard no peer can dial
+fn is_unspecified(addr: &Multiaddr) -> bool {
+    addr.iter().any(|protocol| match protocol {
+        Protocol::Ip4(ip) => ip.is_unspecified(),
+        Protocol::Ip6(ip) => ip.is_unspecified(),
+        _ => false,
+    })
+}
+
+/// Whether a challenge may cover `length` bytes of `data` from `offset`:
+/// at least [`CHALLENGE_MIN_SLICE`] bytes, or the whole of a shorter chunk
+fn challenge_span_allowed(data: &[u8], offset: u32, length: u32) -> bool {
+    length >= CHALLENGE_MIN_SLICE || (offset == 0 && length as usize == data.len())
+}
+
+/// The time on tokio's clock, which tests can pause and move forward
+fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+
+fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::behaviour_harness::Harness;
+    use crate::behaviour_stats::MessageCounts;
+    use corelink_core::file::split_file_to_chunks;
+
+    /// A file of three chunks, and its chunks
+    fn file() -> (FileMetadata, Vec<FileChunk>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"hello world!").unwrap();
+        split_file_to_chunks(&path, 4).unwrap()
+    }
+
+    fn ping() -> Message {
+        Message::new(NodeId::from_peer_id(&PeerId::random()), MessageType::Ping)
+    }
+
+    #[test]
+    fn test_offered_file_downloads() {
+        let mut harness = Harness::new();
+        let peer = harness.connect();
+        let (metadata, chunks) = file();
+
+        harness.receive(peer, MessageType::FileOffer(metadata.clone()));
+        assert!(harness.answer_chunks(|_, index| chunks.get(index as usize).cloned()) >= 3);
+
+        let events = harness.events();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            MessagingBehaviourEvent::FileOffered { pending: false, .. }
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            MessagingBehaviourEvent::TransferComplete { metadata: done, .. }
+                if done.file_id == metadata.file_id
+        )));
+        let written = harness.write();
+        assert!(written.iter().any(|(to, message)| *to == peer
+            && matches!(
+                message.msg_type,
+                MessageType::TransferComplete { success: true, .. }
+            )));
+        assert_eq!(harness.behaviour.stats().offers.accepted, 1);
+    }
+
+    #[test]
+    fn test_bad_chunk_fails_download() {
+        let mut harness = Harness::new();
+        let peer = harness.connect();
+        let (metadata, chunks) = file();
+
+        harness.receive(peer, MessageType::FileOffer(metadata.clone()));
+        // The middle chunk comes back tampered with, once
+        let tampered = std::cell::Cell::new(false);
+        harness.answer_chunks(|_, index| {
+            let chunk = chunks.get(index as usize)?.clone();
+            if index != 1 || tampered.replace(true) {
+                return Some(chunk);
+            }
+            Some(FileChunk {
+                data: b"evil".to_vec(),
+                ..chunk
+            })
+        });
+
+        assert!(harness.events().iter().any(|event| matches!(
+            event,
+            MessagingBehaviourEvent::TransferFailed { file_id, .. } if *file_id == metadata.file_id
+        )));
+        assert!(harness
+            .write()
+            .iter()
+            .any(|(_, message)| matches!(message.msg_type, MessageType::TransferCancel { .. })));
+        let stats = harness.behaviour.stats();
+        assert_eq!(stats.verification_failures, 1);
+        assert_eq!(stats.peers[&peer.to_string()].verification_failures, 1);
+    }
+
+    #[test]
+    fn test_full_queue_rejects_messages() {
+        let mut harness = Harness::with(|behaviour| {
+            behaviour.with_queue_limits(QueueLimits {
+                max_queued_per_peer: 2,
+                overflow: OverflowPolicy::Reject,
+            })
+        });
+        let peer = harness.connect();
+        for _ in 0..3 {
+            harness.behaviour.send_message(peer, ping());
+        }
+        harness.poll();
+
+        assert!(harness.events().iter().any(|event| matches!(
+            event,
+            MessagingBehaviourEvent::MessagesDropped { count: 1, .. }
+        )));
+        assert_eq!(harness.write().len(), 2);
+        let counts = MessageCounts {
+            queued: 3,
+            sent: 2,
+            failed: 1,
+        };
+        assert_eq!(harness.behaviour.stats().messages["Ping"], counts);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_send_retried_after_backoff() {
+        let mut harness = Harness::new();
+        let peer = harness.connect();
+        harness.behaviour.send_message(peer, ping());
+        harness.poll();
+
+        assert_eq!(harness.fail_writes("Stream reset").len(), 1);
+        assert!(harness.write().is_empty());
+        harness
+            .advance(Duration::from_millis(
+                RetryPolicy::default().initial_backoff_ms,
+            ))
+            .await;
+        assert_eq!(harness.write().len(), 1);
+        assert!(harness.events().iter().any(|event| matches!(
+            event,
+            MessagingBehaviourEvent::MessageSent { to, .. } if *to == peer
+        )));
+    }
+
+    #[test]
+    fn test_discovery_advertises_and_records_node_info() {
+        let mut harness = Harness::new();
+        let peer = harness.connect();
+        let linked = PeerId::random();
+        harness.receive(
+            peer,
+            MessageType::Discovery(DiscoveryMessage {
+                capabilities: vec![STORAGE_CAPABILITY.to_string()],
+                protocol_version: PROTOCOL_VERSION.to_string(),
+                labels: Vec::new(),
+                peers: vec![format!("/ip4/10.0.0.8/tcp/4001/p2p/{}", linked)],
+                features: Vec::new(),
+                listen_addresses: vec!["/ip4/10.0.0.7/tcp/4001".to_string()],
+                free_bytes: Some(1 << 30),
+            }),
+        );
+        let advertised = harness
+            .behaviour
+            .network()
+            .get_peer(&NodeId::from_peer_id(&peer))
+            .unwrap();
+        assert_eq!(advertised.listen_addresses, ["/ip4/10.0.0.7/tcp/4001"]);
+        assert_eq!(advertised.free_bytes, Some(1 << 30));
+        assert_eq!(
+            harness.behaviour.peer_links()[&peer],
+            HashSet::from([linked])
+        );
+
+        harness.behaviour.set_free_bytes(Some(512));
+        harness.behaviour.broadcast_discovery();
+        harness.poll();
+        let written = harness.write();
+        let Some(MessageType::Discovery(discovery)) = written.first().map(|(_, m)| &m.msg_type)
+        else {
+            panic!("Expected a discovery message");
+        };
+        assert_eq!(discovery.protocol_version, PROTOCOL_VERSION);
+        assert_eq!(discovery.capabilities, NodeRole::default().capabilities());
+        assert_eq!(discovery.free_bytes, Some(512));
+
+        harness.disconnect(peer);
+        assert!(harness.behaviour.peer_links().is_empty());
+    }
+
+    #[test]
+    fn test_cancel_leaves_offered_files_alone() {
+        let mut harness = Harness::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"hello world!").unwrap();
+        let metadata = harness.behaviour.offer_file(&path).unwrap();
+
+        let error = harness
+            .behaviour
+            .cancel_download(&metadata.file_id)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(harness.behaviour.file_metadata(&metadata.file_id).is_some());
+        assert_eq!(
+            harness
+                .behaviour
+                .cancel_download("unknown")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_only_fetched_files_count_as_uploads() {
+        let mut harness = Harness::new();
+        let dir = tempfile::tempdir().unwrap();
+        let mut offered = Vec::new();
+        for name in ["a.txt", "b.txt"] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            offered.push(harness.behaviour.offer_file(&path).unwrap());
+        }
+        assert_eq!(harness.behaviour.active_uploads(), 0);
+
+        let peer = harness.connect();
+        let response = harness.behaviour.serve_transfer(
+            peer,
+            TransferRequest::Chunk {
+                file_id: offered[0].file_id.clone(),
+                chunk_index: 0,
+            },
+        );
+        assert!(matches!(response, TransferResponse::Chunk(_)));
+        assert_eq!(harness.behaviour.active_uploads(), 1);
+    }
+
+    #[test]
+    fn test_catalog_sent_to_peers_that_understand_it() {
+        let mut harness = Harness::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, b"hello world!").unwrap();
+        let metadata = harness.behaviour.offer_file(&path).unwrap();
+
+        let peer = harness.connect();
+        harness.receive(
+            peer,
+            MessageType::Discovery(DiscoveryMessage {
+                capabilities: Vec::new(),
+                protocol_version: PROTOCOL_VERSION.to_string(),
+                labels: Vec::new(),
+                peers: Vec::new(),
+                features: vec![FILE_CATALOG_FEATURE.to_string()],
+                listen_addresses: Vec::new(),
+                free_bytes: None,
+            }),
+        );
+        let written = harness.write();
+        let catalog = written
+            .iter()
+            .find_map(|(_, message)| match &message.msg_type {
+                MessageType::FileCatalog(entries) => Some(entries.clone()),
+                _ => None,
+            });
+        assert_eq!(catalog, Some(vec![CatalogEntry::from(&metadata)]));
+    }
+
+    #[test]
+    fn test_catalog_entries_become_offers() {
+        let mut harness = Harness::new();
+        let peer = harness.connect();
+        let (metadata, _) = file();
+        let mut tampered = metadata.clone();
+        tampered.file_id = "tampered".to_string();
+        let entries = vec![
+            CatalogEntry::from(&metadata),
+            CatalogEntry {
+                root: [0; 32],
+                ..CatalogEntry::from(&tampered)
+            },
+        ];
+
+        harness.receive(peer, MessageType::FileCatalog(entries));
+        harness.answer_file_requests(peer, |file_id| {
+            [&metadata, &tampered]
+                .into_iter()
+                .find(|m| m.file_id == file_id)
+                .cloned()
+        });
+
+        let offered: Vec<String> = harness
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                MessagingBehaviourEvent::FileOffered { metadata, .. } => Some(metadata.file_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(offered, [metadata.file_id.as_str()]);
+        assert!(harness.behaviour.is_offered(&metadata.file_id));
+        assert!(!harness.behaviour.is_offered("tampered"));
+    }
+
+    #[test]
+    fn test_download_moves_to_another_source() {
+        let mut harness = Harness::new();
+        let (first, second) = (harness.connect(), harness.connect());
+        let (metadata, chunks) = file();
+
+        harness.receive(first, MessageType::FileOffer(metadata.clone()));
+        harness.receive(second, MessageType::FileOffer(metadata.clone()));
+        harness.disconnect(first);
+        harness.answer_chunks(|_, index| chunks.get(index as usize).cloned());
+
+        assert!(harness
+            .events()
+            .iter()
+            .any(|event| matches!(event, MessagingBehaviourEvent::TransferComplete { .. })));
+        assert!(harness.write().iter().all(|(to, _)| *to == second));
+    }
+
+    #[test]
+    fn test_flooding_peer_is_muted_and_penalized() {
+        let mut harness = Harness::with(|behaviour| {
+            behaviour.with_flood_limits(FloodLimits {
+                messages_per_minute: 2,
+                ..FloodLimits::default()
+            })
+        });
+        let peer = harness.connect();
+        let (metadata, _) = file();
+
+        for _ in 0..3 {
+            harness.receive(peer, MessageType::Ping);
+        }
+        // Ignored while muted, whatever the type
+        harness.receive(peer, MessageType::FileOffer(metadata.clone()));
+
+        let events = harness.events();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            MessagingBehaviourEvent::PeerMuted { msg_type, .. } if msg_type == "Ping"
+        )));
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, MessagingBehaviourEvent::FileOffered { .. })));
+        assert!(!harness.behaviour.is_offered(&metadata.file_id));
+        assert_eq!(
+            harness.behaviour.peer_reputation(&peer),
+            -FloodLimits::default().penalty
+        );
+    }
+
+    #[test]
+    fn test_disconnected_peers_leave_the_network_state() {
+        let mut harness = Harness::new();
+        let peer = harness.connect();
+        let node_id = NodeId::from_peer_id(&peer);
+        harness
+            .behaviour
+            .network()
+            .record_rtt(&node_id, Duration::from_millis(20));
+        let mut events = harness.behaviour.network().subscribe();
+
+        harness.disconnect(peer);
+        assert!(harness.behaviour.network().get_peer(&node_id).is_none());
+        assert!(matches!(
+            events.try_recv(),
+            Ok(corelink_core::network::NetworkEvent::PeerRemoved(removed)) if removed == node_id
+        ));
+    }
+
+    #[test]
+    fn test_reconnecting_peers_are_known_from_their_last_discovery() {
+        let mut harness = Harness::new();
+        let peer = harness.connect();
+        let node_id = NodeId::from_peer_id(&peer);
+        harness.receive(
+            peer,
+            MessageType::Discovery(DiscoveryMessage {
+                capabilities: vec!["storage".to_string()],
+                protocol_version: PROTOCOL_VERSION.to_string(),
+                labels: vec!["eu-west".to_string()],
+                peers: Vec::new(),
+                features: Vec::new(),
+                listen_addresses: Vec::new(),
+                free_bytes: Some(2048),
+            }),
+        );
+        harness.disconnect(peer);
+
+        harness.open_connection(peer);
+        let known = harness.behaviour.network().get_peer(&node_id).unwrap();
+        assert_eq!(known.labels, vec!["eu-west".to_string()]);
+        assert_eq!(known.capabilities, vec!["storage".to_string()]);
+        assert_eq!(known.free_bytes, Some(2048));
+    }
+
+    #[test]
+    fn test_slow_peers_are_not_evicted() {
+        let network = NetworkState::new();
+        let mut reputation = ReputationTracker::new();
+        let (slow, failing) = (PeerId::random(), PeerId::random());
+        for peer in [slow, failing] {
+            let node_id = NodeId::from_peer_id(&peer);
+            network.add_peer(corelink_core::network::PeerInfo::new(
+                node_id,
+                String::new(),
+            ));
+            network.record_rtt(&node_id, Duration::from_millis(800));
+        }
+        network.record_failure(&NodeId::from_peer_id(&failing));
+
+        assert_eq!(admission_score(&network, &reputation, &slow), 0.0);
+        assert!(admission_score(&network, &reputation, &failing) < 0.0);
+        reputation.adjust(&slow, -20);
+        assert!(admission_score(&network, &reputation, &slow) < 0.0);
+    }
+
+    #[test]
+    fn test_storage_challenge_round_trip() {
+        let mut harness = Harness::new();
+        let holder = harness.connect();
+        let stranger = harness.connect();
+        let (metadata, chunks) = file();
+        harness.receive(holder, MessageType::FileOffer(metadata.clone()));
+        harness.answer_chunks(|_, index| chunks.get(index as usize).cloned());
+        harness.write();
+
+        // The holder that offered the file is challenged over a whole chunk,
+        // since its chunks are shorter than the smallest slice
+        harness.behaviour.challenge_holders();
+        harness.poll();
+        let challenge = harness
+            .write()
+            .into_iter()
+            .find_map(|(to, message)| match message.msg_type {
+                MessageType::StorageChallenge { .. } if to == holder => Some(message.msg_type),
+                _ => None,
+            })
+            .expect("challenge sent");
+        let MessageType::StorageChallenge {
+            chunk_index,
+            offset,
+            length,
+            nonce,
+            ..
+        } = challenge.clone()
+        else {
+            unreachable!()
+        };
+        assert_eq!((offset, length), (0, 4));
+
+        // Answered with our own copy, which the challenger accepts
+        harness.receive(holder, challenge.clone());
+        let proof = harness
+            .write()
+            .into_iter()
+            .find_map(|(to, message)| match message.msg_type {
+                MessageType::StorageProof { proof, .. } if to == holder => proof,
+                _ => None,
+            })
+            .expect("proof sent");
+        let data = &chunks[chunk_index as usize].data;
+        assert_eq!(Some(proof), storage_proof(&nonce, data, 0, 4));
+        let reputation = harness.behaviour.peer_reputation(&holder);
+        harness.receive(
+            holder,
+            MessageType::StorageProof {
+                file_id: metadata.file_id.clone(),
+                nonce,
+                proof: Some(proof),
+            },
+        );
+        assert_eq!(
+            harness.behaviour.peer_reputation(&holder),
+            reputation + PROOF_REWARD
+        );
+
+        // Peers that never offered the file, and spans short enough to give
+        // bytes away, go unanswered
+        harness.receive(stranger, challenge);
+        harness.receive(
+            holder,
+            MessageType::StorageChallenge {
+                file_id: metadata.file_id.clone(),
+                chunk_index,
+                offset: 1,
+                length: 1,
+                nonce,
+            },
+        );
+        assert!(!harness
+            .write()
+            .iter()
+            .any(|(_, message)| matches!(message.msg_type, MessageType::StorageProof { .. })));
+    }
+}
@@ -0,0 +1,727 @@
+use crate::api::{start_api_server, ApiCommand, ApiState, FileDetail, FileInfo, StopRequest};
+use crate::backup::NodeBackup;
+use crate::bridge::{self, NodeEvent};
+use crate::config::NodeConfig;
+use crate::driver::{CoreLinkBehaviour, SwarmDriver};
+use crate::file_transfer::{FileRemoval, FileTransferManager, TransferSummary};
+use crate::logging::Logging;
+use crate::maintenance;
+use crate::messaging_behaviour::MessagingBehaviour;
+use crate::peer_store::PeerStore;
+use crate::rate_limit::RateLimiter;
+use crate::shared_folder::SharedFolder;
+use crate::supervisor::supervise;
+use crate::websocket::{start_websocket_server, EventHub};
+use corelink_core::storage::TieredObjectStore;
+use corelink_core::{BlockStore, Storage, PROTOCOL_VERSION};
+use libp2p::{
+    autonat, identify, identity, mdns, noise, ping, relay, tcp, yamux, Multiaddr, PeerId,
+    SwarmBuilder,
+};
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{info, warn};
+
+/// How long to wait for the API and WebSocket servers to close on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Node events buffered for slow subscribers before they start missing some
+const EVENT_BUFFER: usize = 256;
+
+/// Configures and starts a node
+pub struct NodeBuilder {
+    config: NodeConfig,
+    args: Vec<String>,
+    logger: Option<Logging>,
+    restore: Option<NodeBackup>,
+    console: bool,
+    control_socket: Option<PathBuf>,
+    servers: bool,
+    watchdog: Option<Duration>,
+}
+
+impl NodeBuilder {
+    pub fn new(config: NodeConfig) -> Self {
+        Self {
+            control_socket: config.control_socket.clone(),
+            config,
+            args: Vec::new(),
+            logger: None,
+            restore: None,
+            console: false,
+            servers: true,
+            watchdog: None,
+        }
+    }
+
+    /// Command line the config came from; config reloads re-read it
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Let config reloads change the log level
+    pub fn with_logging(mut self, logger: Logging) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    /// Restore identity, state and blocks from a backup before starting
+    pub fn with_restore(mut self, backup: NodeBackup) -> Self {
+        self.restore = Some(backup);
+        self
+    }
+
+    /// Read operator commands from stdin
+    pub fn with_console(mut self) -> Self {
+        self.console = true;
+        self
+    }
+
+    /// Serve console commands on a local socket, for corelinkctl
+    pub fn with_control_socket(mut self, path: PathBuf) -> Self {
+        self.control_socket = Some(path);
+        self
+    }
+
+    /// Skip the REST API and WebSocket servers; the node is then driven
+    /// only through its [`NodeHandle`]
+    pub fn without_servers(mut self) -> Self {
+        self.servers = false;
+        self
+    }
+
+    /// Ping the service manager's watchdog every `period` while the event
+    /// loop is responsive
+    pub fn with_watchdog(mut self, period: Duration) -> Self {
+        self.watchdog = Some(period);
+        self
+    }
+
+    /// Open storage, join the network and start the API and WebSocket
+    /// servers, unless [`without_servers`](Self::without_servers). The node
+    /// runs in the background until [`NodeHandle::shutdown`].
+    pub async fn start(self) -> io::Result<NodeHandle> {
+        let NodeBuilder {
+            config,
+            args,
+            logger,
+            restore,
+            console,
+            control_socket,
+            servers,
+            watchdog,
+        } = self;
+        let port = config.port;
+
+        let (store, tiers, blocks) = open_storage(&config)?;
+        let identity_path = config.storage_path.join("identity.key");
+        if let Some(backup) = restore {
+            backup.restore(&identity_path, &store, &blocks)?;
+        }
+
+        // Load or create the node identity
+        let local_key = load_or_create_identity(&identity_path)?;
+        let peer_id = local_key.public().to_peer_id();
+
+        info!("🚀 Starting CoreLink {} node on port {}", config.role, port);
+        info!("🔑 Peer ID: {}", peer_id);
+
+        let encrypted = config.encryption_key()?.is_some();
+        if encrypted {
+            info!("🔐 Encrypting blocks at rest");
+        }
+        let peer_store = PeerStore::new(store.clone()).map_err(io::Error::other)?;
+
+        // Create swarm
+        let mut swarm = SwarmBuilder::with_existing_identity(local_key)
+            .with_tokio()
+            .with_tcp(
+                tcp::Config::default(),
+                noise::Config::new,
+                yamux::Config::default,
+            )
+            .map_err(io::Error::other)?
+            .with_behaviour(
+                |key| -> Result<CoreLinkBehaviour, Box<dyn Error + Send + Sync>> {
+                    let peer_id = key.public().to_peer_id();
+                    let mut messaging =
+                        MessagingBehaviour::new(config.storage_path.clone(), store.clone())?
+                            .with_blocks(blocks.clone())
+                            .with_replication_factor(config.replication_factor)
+                            .with_connection_limits(config.connection_limits.clone())
+                            .with_transfer_limits(config.transfer_limits.clone())
+                            .with_queue_limits(config.message_queue.clone())
+                            .with_retry_policy(config.message_retry.clone())
+                            .with_flood_limits(config.flood_limits.clone())
+                            .with_offer_mode(config.offer_mode, config.offer_expiry_secs)
+                            .with_role(config.role)
+                            .with_labels(config.labels.clone())
+                            .with_identity(key.clone());
+                    if encrypted {
+                        // Never leave plaintext copies of downloads on disk
+                        messaging = messaging.without_plaintext_files();
+                    }
+
+                    Ok(CoreLinkBehaviour {
+                        ping: ping::Behaviour::new(ping::Config::new()),
+                        identify: identify::Behaviour::new(identify::Config::new(
+                            PROTOCOL_VERSION.to_string(),
+                            key.public(),
+                        )),
+                        autonat: autonat::Behaviour::new(peer_id, autonat::Config::default()),
+                        relay: config
+                            .role
+                            .is_relay()
+                            .then(|| relay::Behaviour::new(peer_id, relay::Config::default()))
+                            .into(),
+                        mdns: config
+                            .mdns
+                            .then(|| mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id))
+                            .transpose()?
+                            .into(),
+                        messaging,
+                    })
+                },
+            )
+            .map_err(io::Error::other)?
+            .with_swarm_config(|c| {
+                c.with_idle_connection_timeout(Duration::from_secs(60))
+                    .with_per_connection_event_buffer_size(64)
+            })
+            .build();
+
+        // Listen on all interfaces
+        let listen_addr: Multiaddr = format!("/ip4/0.0.0.0/tcp/{}", port)
+            .parse()
+            .map_err(io::Error::other)?;
+        swarm
+            .listen_on(listen_addr.clone())
+            .map_err(io::Error::other)?;
+
+        info!("👂 Listening on {}", listen_addr);
+        if !config.mdns {
+            info!("🔕 mDNS disabled, finding peers through bootstrap peers only");
+        }
+
+        // Partial downloads left by a crash are checked before any resumes
+        let recovery = swarm.behaviour_mut().messaging.recover_downloads();
+
+        // Operator tags steer which peers we download from and replicate to
+        for (peer_id, tags) in peer_store.all_tags() {
+            swarm.behaviour_mut().messaging.set_peer_tags(peer_id, tags);
+        }
+
+        // Flipped to true to stop the API and WebSocket servers on shutdown
+        let (servers_tx, servers_rx) = watch::channel(false);
+        let (commands, api_commands) = mpsc::channel::<ApiCommand>(32);
+        let (stop_tx, stop_rx) = mpsc::channel(1);
+        let uploads_dir = config.storage_path.join("uploads");
+        let share_dirs = config
+            .api_share_dirs
+            .iter()
+            .chain(&config.shared_folder)
+            .chain([&uploads_dir])
+            .cloned()
+            .collect();
+        let api_state = ApiState::new()
+            .with_commands(commands.clone())
+            .with_uploads_dir(uploads_dir)
+            .with_share_dirs(share_dirs)
+            .with_cors_origins(&config.api_cors_origins)
+            .with_max_ban_secs(config.max_ban_duration_secs)
+            .with_max_upload_bytes(config.api_max_upload_bytes)
+            .with_admin_token(config.admin_token())
+            .with_stop(stop_tx);
+        api_state.set_recovery(recovery).await;
+        // Tokens other than the admin's are unverified, so clients bearing
+        // them are told apart by IP
+        let rate_limiter = Arc::new(
+            RateLimiter::new(config.api_rate_limit.clone())
+                .with_trusted_token(config.admin_token()),
+        );
+        let (ws_tx, event_hub, ws_server) = if servers {
+            let (ws_tx, event_hub) = EventHub::start(servers_tx.clone());
+            let api_state = api_state.clone().with_events(event_hub.clone());
+            let tls = config
+                .tls
+                .as_ref()
+                .map(|tls| tls.server_config())
+                .transpose()?;
+            let (http, ws) = if tls.is_some() {
+                ("https", "wss")
+            } else {
+                ("http", "ws")
+            };
+
+            // Start REST API server (derive port from node port: 4001 -> 7001, 4002 -> 7002, etc.)
+            let api_addr = format!("127.0.0.1:{}", port + 3000);
+            start_api_server(
+                &api_addr,
+                api_state.clone(),
+                rate_limiter.clone(),
+                tls.clone(),
+                servers_rx,
+            )
+            .await
+            .map_err(|e| io::Error::other(format!("Failed to start REST API server: {}", e)))?;
+            info!("🌐 REST API server ready at {}://{}", http, api_addr);
+            info!("🌐 WebSocket events at {}://{}/ws", ws, api_addr);
+
+            // Legacy WebSocket server (derive port from node port: 4001 -> 8001, 4002 -> 8002, etc.)
+            let ws_server = if config.standalone_websocket {
+                let ws_addr = format!("127.0.0.1:{}", port + 4000);
+                let ws_server = start_websocket_server(&ws_addr, event_hub.clone(), api_state, tls)
+                    .await
+                    .map_err(|e| {
+                        io::Error::other(format!("Failed to start WebSocket server: {}", e))
+                    })?;
+                info!("🌐 WebSocket server ready at {}://{}", ws, ws_addr);
+                Some(ws_server)
+            } else {
+                None
+            };
+            (ws_tx, Some(event_hub), ws_server)
+        } else {
+            (broadcast::channel(1).0, None, None)
+        };
+
+        // Mirror what the swarm driver reports into the API and WebSocket clients
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let bridge_events = events.clone();
+        let network = swarm.behaviour().messaging.network().clone();
+        let bridge = supervise("api-bridge", move || {
+            bridge::run(
+                bridge_events.subscribe(),
+                network.subscribe(),
+                api_state.clone(),
+                ws_tx.clone(),
+            )
+        });
+
+        // Expiry sweeps and tiering touch only storage, so they run on their own
+        let tiering = tiers.zip(config.tiering.clone());
+        let maintenance_store = store.clone();
+        let maintenance = supervise("maintenance", move || {
+            maintenance::run(maintenance_store.clone(), tiering.clone())
+        });
+
+        let mut driver = SwarmDriver::new(
+            swarm,
+            config.clone(),
+            args,
+            peer_store,
+            blocks,
+            events.clone(),
+        )
+        .with_commands(api_commands)
+        .with_rate_limiter(rate_limiter);
+        if let Some(event_hub) = event_hub {
+            driver = driver.with_event_hub(event_hub);
+        }
+        if let Some(ws_server) = ws_server {
+            driver = driver.with_websocket(ws_server);
+        }
+        if let Some(logger) = logger {
+            driver = driver.with_logging(logger);
+        }
+        if let Some(period) = watchdog {
+            driver = driver.with_watchdog(period);
+        }
+        let listening = driver.listening();
+        driver.dial_known_peers();
+
+        if console {
+            driver = driver.with_stdin();
+            info!("💡 Commands: 'offer' to share test.txt, 'help' for more");
+        }
+
+        // The same commands over a local socket, for corelinkctl
+        if let Some(path) = &control_socket {
+            driver = driver.with_control(crate::control::listen(path)?);
+            info!("🎛️ Control socket at {}", path.display());
+        }
+
+        // Folder whose files are offered automatically
+        if let Some(path) = &config.shared_folder {
+            let mut shared = SharedFolder::new(path.clone(), &config.shared_ignore, store.clone())?;
+            shared.scan()?;
+            info!("📂 Sharing files in {}", shared.root().display());
+            driver = driver.with_shared_folder(shared)?;
+        }
+
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let (stopping_tx, stopping) = watch::channel(None);
+        let task = tokio::spawn(run(
+            driver,
+            shutdown_rx,
+            stop_rx,
+            stopping_tx,
+            servers_tx,
+            control_socket,
+            vec![bridge, maintenance],
+        ));
+
+        Ok(NodeHandle {
+            peer_id,
+            listening,
+            stopping,
+            commands,
+            events,
+            shutdown,
+            task,
+        })
+    }
+}
+
+/// A running node. Dropping it leaves the node running in the background.
+pub struct NodeHandle {
+    peer_id: PeerId,
+    /// Whether the swarm has a listen address yet
+    listening: watch::Receiver<bool>,
+    /// Set once an admin asks through the API for the node to stop
+    stopping: watch::Receiver<Option<StopRequest>>,
+    commands: mpsc::Sender<ApiCommand>,
+    events: broadcast::Sender<NodeEvent>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl NodeHandle {
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Wait until the swarm is listening. The API and WebSocket servers
+    /// already are once [`NodeBuilder::start`] returns.
+    pub async fn ready(&self) {
+        let _ = self
+            .listening
+            .clone()
+            .wait_for(|listening| *listening)
+            .await;
+    }
+
+    /// Connect to the peer at `address`, returning its id once connected
+    pub async fn dial(&self, address: Multiaddr) -> io::Result<PeerId> {
+        self.command(|reply| ApiCommand::Dial {
+            address,
+            save: false,
+            reply,
+        })
+        .await?
+        .map_err(io::Error::other)?
+        .parse()
+        .map_err(io::Error::other)
+    }
+
+    /// Offer the file at `path` to the network
+    pub async fn offer_file(&self, path: impl Into<PathBuf>) -> io::Result<FileInfo> {
+        let path = path.into();
+        self.command(|reply| ApiCommand::OfferFile { path, reply })
+            .await?
+    }
+
+    /// Start downloading a file some peer has offered
+    pub async fn download(&self, file_id: &str) -> io::Result<TransferSummary> {
+        self.download_from(file_id, None).await
+    }
+
+    /// Start downloading a file, fetching chunks from `peer` first if it
+    /// offers the file
+    pub async fn download_from(
+        &self,
+        file_id: &str,
+        peer: Option<PeerId>,
+    ) -> io::Result<TransferSummary> {
+        let file_id = file_id.to_string();
+        self.command(|reply| ApiCommand::Download {
+            file_id,
+            peer,
+            reply,
+        })
+        .await?
+    }
+
+    /// Cancel a download, withdraw an offer or delete a finished download
+    pub async fn remove_file(&self, file_id: &str) -> io::Result<FileRemoval> {
+        let file_id = file_id.to_string();
+        self.command(|reply| ApiCommand::RemoveFile { file_id, reply })
+            .await?
+    }
+
+    /// Full metadata, chunk bitmap, sources, speed and errors of a file
+    pub async fn file_detail(&self, file_id: &str) -> io::Result<Option<FileDetail>> {
+        let file_id = file_id.to_string();
+        self.command(|reply| ApiCommand::FileDetail { file_id, reply })
+            .await
+    }
+
+    /// Progress of a running, queued or finished download
+    pub async fn transfer(&self, file_id: &str) -> io::Result<Option<TransferSummary>> {
+        let file_id = file_id.to_string();
+        self.command(|reply| ApiCommand::Transfer { file_id, reply })
+            .await
+    }
+
+    /// Progress of every running and queued download
+    pub async fn transfers(&self) -> io::Result<Vec<TransferSummary>> {
+        self.command(|reply| ApiCommand::Transfers { reply }).await
+    }
+
+    /// Re-read the config file, returning the changed settings that need a
+    /// restart
+    pub async fn reload_config(&self) -> io::Result<Vec<String>> {
+        self.command(|reply| ApiCommand::ReloadConfig { reply })
+            .await?
+            .map_err(io::Error::other)
+    }
+
+    /// Events from now on: peers coming and going, file offers, transfer
+    /// progress and periodic status snapshots
+    pub fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+        self.events.subscribe()
+    }
+
+    /// Wait until an admin asks through the REST API for the node to shut
+    /// down or restart. The node is then already stopping;
+    /// [`shutdown`](Self::shutdown) waits for it to finish.
+    pub async fn stop_requested(&self) -> StopRequest {
+        let mut stopping = self.stopping.clone();
+        if let Ok(stop) = stopping.wait_for(Option::is_some).await {
+            if let Some(stop) = *stop {
+                return stop;
+            }
+        }
+        std::future::pending().await
+    }
+
+    /// Stop the node, saving its state and closing peer connections
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        if let Err(e) = self.task.await {
+            warn!("Node task failed: {}", e);
+        }
+    }
+
+    async fn command<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<T>) -> ApiCommand,
+    ) -> io::Result<T> {
+        let stopped = || io::Error::new(io::ErrorKind::BrokenPipe, "Node has stopped");
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.commands
+            .send(command(reply_tx))
+            .await
+            .map_err(|_| stopped())?;
+        reply_rx.await.map_err(|_| stopped())
+    }
+}
+
+/// Drive the node until asked to stop, by its handle or through the API's
+/// admin endpoints, then shut down in order
+///
+/// The driver's event loop is supervised: a panic while handling an event
+/// restarts the loop on the same swarm, so peers and transfers survive it.
+async fn run(
+    driver: SwarmDriver,
+    shutdown: oneshot::Receiver<()>,
+    mut stop: mpsc::Receiver<StopRequest>,
+    stopping: watch::Sender<Option<StopRequest>>,
+    servers: watch::Sender<bool>,
+    control_socket: Option<PathBuf>,
+    background: Vec<JoinHandle<()>>,
+) {
+    let driver = Arc::new(tokio::sync::Mutex::new(driver));
+    let (stop_loop, stop_loop_rx) = watch::channel(false);
+    let event_loop = {
+        let driver = driver.clone();
+        supervise("swarm-driver", move || {
+            let driver = driver.clone();
+            let mut stop_loop = stop_loop_rx.clone();
+            async move {
+                // A panicking run drops its guard, leaving the driver to the next run
+                let mut driver = driver.lock().await;
+                driver
+                    .run(async move {
+                        let _ = stop_loop.wait_for(|stop| *stop).await;
+                    })
+                    .await;
+            }
+        })
+    };
+
+    // A dropped handle closes `shutdown` without sending; the node then
+    // keeps running until an admin stops it
+    tokio::select! {
+        Ok(()) = shutdown => {}
+        Some(request) = stop.recv() => {
+            let _ = stopping.send(Some(request));
+        }
+        else => std::future::pending().await,
+    }
+    let _ = stop_loop.send(true);
+    if let Err(e) = event_loop.await {
+        warn!("Swarm driver task failed: {}", e);
+    }
+    let mut driver = driver.lock().await;
+
+    // No more API commands; requests still in flight get "not accepting commands"
+    driver.close_commands();
+    driver.flush();
+
+    // Let the API finish in-flight requests and WebSocket clients receive
+    // their queued events and a close frame
+    let _ = servers.send(true);
+    if time::timeout(SHUTDOWN_GRACE, servers.closed())
+        .await
+        .is_err()
+    {
+        warn!("API and WebSocket servers did not stop in time");
+    }
+
+    driver.disconnect().await;
+
+    if let Some(path) = &control_socket {
+        let _ = std::fs::remove_file(path);
+    }
+
+    // The API bridge and maintenance would otherwise outlive the node
+    for task in background {
+        task.abort();
+    }
+
+    info!("👋 Node stopped");
+}
+
+/// Package identity, config, state and preserved blocks into `archive`
+pub fn write_backup(config: &NodeConfig, archive: &Path) -> io::Result<()> {
+    let (store, _, blocks) = open_storage(config)?;
+    let identity_path = config.storage_path.join("identity.key");
+    load_or_create_identity(&identity_path)?;
+    let files =
+        FileTransferManager::new(config.storage_path.clone(), store.clone())?.with_blocks(blocks);
+    NodeBackup::capture(config, &identity_path, &store, &files)?.write(archive)
+}
+
+/// Open persistent storage shared by the peer store and file transfer state
+fn open_storage(
+    config: &NodeConfig,
+) -> io::Result<(Storage, Option<Arc<TieredObjectStore>>, BlockStore)> {
+    std::fs::create_dir_all(&config.storage_path)?;
+    let store = Storage::open(&config.storage_path.join("db")).map_err(io::Error::other)?;
+    let tiers = config.open_tiers()?;
+    let mut blocks = BlockStore::with_backend(match &tiers {
+        Some(tiers) => config.with_encryption(tiers.clone())?,
+        None => config.open_object_store()?,
+    });
+    if config.compression_level != 0 {
+        blocks = blocks.with_compression(config.compression_level);
+    }
+    Ok((store, tiers, blocks))
+}
+
+/// Load the node keypair from `path`, generating and saving a new one on first run
+fn load_or_create_identity(path: &Path) -> io::Result<identity::Keypair> {
+    if path.exists() {
+        return identity::Keypair::from_protobuf_encoding(&std::fs::read(path)?)
+            .map_err(io::Error::other);
+    }
+
+    let keypair = identity::Keypair::generate_ed25519();
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    std::io::Write::write_all(
+        &mut options.open(path)?,
+        &keypair.to_protobuf_encoding().map_err(io::Error::other)?,
+    )?;
+
+    info!("🔑 Generated new node identity at {:?}", path);
+    Ok(keypair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_embedded_node_offers_file() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("notes.txt");
+        std::fs::write(&file, b"hello")?;
+        let config = NodeConfig {
+            port: 0,
+            storage_path: dir.path().join("storage"),
+            mdns: false,
+            ..NodeConfig::default()
+        };
+
+        let node = NodeBuilder::new(config).without_servers().start().await?;
+        let mut events = node.subscribe();
+        let offered = node.offer_file(&file).await?;
+        assert_eq!(offered.name, "notes.txt");
+        assert!(matches!(
+            events.recv().await,
+            Ok(NodeEvent::FileAdded(file)) if file.file_id == offered.file_id
+        ));
+        assert!(node.download("no-such-file").await.is_err());
+        assert!(node.transfers().await?.is_empty());
+        node.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dropped_handle_leaves_node_running() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = NodeConfig {
+            port: 0,
+            storage_path: dir.path().join("storage"),
+            mdns: false,
+            ..NodeConfig::default()
+        };
+        let closed = |mut events: broadcast::Receiver<NodeEvent>| async move {
+            loop {
+                if let Err(broadcast::error::RecvError::Closed) = events.recv().await {
+                    return;
+                }
+            }
+        };
+
+        let node = NodeBuilder::new(config.clone())
+            .without_servers()
+            .start()
+            .await?;
+        let events = node.subscribe();
+        drop(node);
+        assert!(time::timeout(Duration::from_millis(500), closed(events))
+            .await
+            .is_err());
+
+        // Shutting down stops every task that could still send events
+        let dir = tempfile::tempdir()?;
+        let node = NodeBuilder::new(NodeConfig {
+            storage_path: dir.path().join("storage"),
+            ..config
+        })
+        .without_servers()
+        .start()
+        .await?;
+        let events = node.subscribe();
+        node.shutdown().await;
+        time::timeout(Duration::from_secs(5), closed(events))
+            .await
+            .map_err(|_| io::Error::other("node events were never closed"))?;
+        Ok(())
+    }
+}
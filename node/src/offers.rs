@@ -0,0 +1,144 @@
+use corelink_core::file::FileMetadata;
+use libp2p_identity::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Whether files peers offer are downloaded without asking
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OfferMode {
+    /// Download every offered file as soon as it is offered
+    #[default]
+    Automatic,
+    /// Hold offers until they are accepted or rejected, or expire
+    Manual,
+}
+
+/// A file offered in manual mode, waiting to be accepted or rejected
+#[derive(Debug, Clone)]
+pub struct PendingOffer {
+    pub metadata: FileMetadata,
+    /// Peers that offered it
+    pub peers: HashSet<PeerId>,
+    /// Unix time of the first offer
+    pub offered_at: u64,
+    /// Unix time the offer is forgotten unless answered before
+    pub expires_at: u64,
+}
+
+/// Offers waiting for an answer, by file id
+pub struct PendingOffers {
+    offers: HashMap<String, PendingOffer>,
+    /// Seconds an offer waits for an answer
+    expiry_secs: u64,
+}
+
+impl PendingOffers {
+    pub fn new(expiry_secs: u64) -> Self {
+        Self {
+            offers: HashMap::new(),
+            expiry_secs,
+        }
+    }
+
+    /// Record that `peer` offered a file at unix time `now`. Returns whether
+    /// the file was not already waiting, from `peer` or another peer.
+    pub fn offered(&mut self, metadata: &FileMetadata, peer: PeerId, now: u64) -> bool {
+        let mut new = false;
+        self.offers
+            .entry(metadata.file_id.clone())
+            .or_insert_with(|| {
+                new = true;
+                PendingOffer {
+                    metadata: metadata.clone(),
+                    peers: HashSet::new(),
+                    offered_at: now,
+                    expires_at: now + self.expiry_secs,
+                }
+            })
+            .peers
+            .insert(peer);
+        new
+    }
+
+    /// `peer` no longer offers the file. Returns whether that was the last
+    /// peer offering it, so the offer is gone.
+    pub fn withdrawn(&mut self, file_id: &str, peer: &PeerId) -> bool {
+        let Some(offer) = self.offers.get_mut(file_id) else {
+            return false;
+        };
+        offer.peers.remove(peer);
+        if !offer.peers.is_empty() {
+            return false;
+        }
+        self.offers.remove(file_id);
+        true
+    }
+
+    /// Stop waiting for an answer to the offer of `file_id`
+    pub fn take(&mut self, file_id: &str) -> Option<PendingOffer> {
+        self.offers.remove(file_id)
+    }
+
+    /// Wait for an answer again, e.g. after accepting failed
+    pub fn restore(&mut self, offer: PendingOffer) {
+        self.offers.insert(offer.metadata.file_id.clone(), offer);
+    }
+
+    /// Forget the offers that expired by unix time `now`
+    pub fn expire(&mut self, now: u64) -> Vec<PendingOffer> {
+        let expired: Vec<String> = self
+            .offers
+            .iter()
+            .filter(|(_, offer)| offer.expires_at <= now)
+            .map(|(file_id, _)| file_id.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|file_id| self.offers.remove(file_id))
+            .collect()
+    }
+
+    /// Offers waiting, oldest first
+    pub fn list(&self) -> Vec<&PendingOffer> {
+        let mut offers: Vec<&PendingOffer> = self.offers.values().collect();
+        offers.sort_by(|a, b| {
+            (a.offered_at, &a.metadata.file_id).cmp(&(b.offered_at, &b.metadata.file_id))
+        });
+        offers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(file_id: &str) -> FileMetadata {
+        FileMetadata {
+            file_id: file_id.to_string(),
+            ..FileMetadata::new(format!("{}.txt", file_id), 10, vec![])
+        }
+    }
+
+    #[test]
+    fn test_offers_wait_until_answered_or_expired() {
+        let mut offers = PendingOffers::new(60);
+        let (first, second) = (PeerId::random(), PeerId::random());
+
+        assert!(offers.offered(&metadata("a"), first, 1000));
+        // Another source of the same file waits with it
+        assert!(!offers.offered(&metadata("a"), second, 1030));
+        assert!(offers.offered(&metadata("b"), first, 1020));
+        let listed: Vec<_> = offers.list().iter().map(|o| o.peers.len()).collect();
+        assert_eq!(listed, [2, 1]);
+
+        assert!(!offers.withdrawn("a", &second));
+        assert!(offers.withdrawn("b", &first));
+        assert_eq!(offers.list().len(), 1);
+
+        assert!(offers.expire(1059).is_empty());
+        let expired = offers.expire(1060);
+        assert_eq!(expired[0].metadata.file_id, "a");
+        assert!(offers.take("a").is_none());
+    }
+}
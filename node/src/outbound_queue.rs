@@ -0,0 +1,222 @@
+use crate::protocol_handler::Outgoing;
+use libp2p_identity::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// Messages handed to a peer's connection at once; the rest wait here
+pub const MAX_IN_FLIGHT_PER_PEER: usize = 4;
+
+/// What happens to a message for a peer whose queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Drop the peer's oldest queued message to make room
+    #[default]
+    DropOldest,
+    /// Refuse the new message
+    Reject,
+    /// Hold application messages until there is room, slowing their
+    /// senders down; the node's own messages are refused as with `reject`
+    Backpressure,
+}
+
+/// Caps on messages waiting to be sent to each peer
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueLimits {
+    /// Messages waiting per peer, besides those being written
+    pub max_queued_per_peer: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            max_queued_per_peer: 256,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// Outcome of queueing a message
+#[derive(Debug)]
+pub enum Queued {
+    Accepted,
+    /// Queued after dropping the peer's oldest message
+    Evicted(Outgoing),
+    /// The queue is full; the message was not queued
+    Full(Outgoing),
+}
+
+/// Messages waiting to be handed to each peer's connection, and how many
+/// each connection is writing
+pub struct OutboundQueues {
+    limits: QueueLimits,
+    queues: HashMap<PeerId, VecDeque<Outgoing>>,
+    in_flight: HashMap<PeerId, usize>,
+    /// Messages dropped or refused for want of room, or lost with a peer
+    dropped: u64,
+}
+
+impl OutboundQueues {
+    pub fn new(limits: QueueLimits) -> Self {
+        Self {
+            limits,
+            queues: HashMap::new(),
+            in_flight: HashMap::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn limits(&self) -> &QueueLimits {
+        &self.limits
+    }
+
+    /// Change the limits. Queues longer than the new cap keep their messages
+    /// but take no more until they shrink below it.
+    pub fn set_limits(&mut self, limits: QueueLimits) {
+        self.limits = limits;
+    }
+
+    /// Whether a message for `peer` would be queued without dropping another
+    pub fn has_room(&self, peer: &PeerId) -> bool {
+        self.queues.get(peer).map_or(0, VecDeque::len) < self.limits.max_queued_per_peer
+    }
+
+    /// Queue a message for `peer`, following the overflow policy if its
+    /// queue is full
+    pub fn push(&mut self, peer: PeerId, outgoing: Outgoing) -> Queued {
+        if !self.has_room(&peer) {
+            let queue = self.queues.entry(peer).or_default();
+            if self.limits.overflow != OverflowPolicy::DropOldest || queue.is_empty() {
+                self.dropped += 1;
+                return Queued::Full(outgoing);
+            }
+            let evicted = queue.pop_front().expect("queue is not empty");
+            queue.push_back(outgoing);
+            self.dropped += 1;
+            return Queued::Evicted(evicted);
+        }
+        self.queues.entry(peer).or_default().push_back(outgoing);
+        Queued::Accepted
+    }
+
+    /// Take the next message for a peer whose connection has room for it
+    pub fn next(&mut self) -> Option<(PeerId, Outgoing)> {
+        let peer = *self.queues.iter().find_map(|(peer, queue)| {
+            let busy = self.in_flight.get(peer).copied().unwrap_or(0);
+            (!queue.is_empty() && busy < MAX_IN_FLIGHT_PER_PEER).then_some(peer)
+        })?;
+        let queue = self.queues.get_mut(&peer)?;
+        let outgoing = queue.pop_front()?;
+        if queue.is_empty() {
+            self.queues.remove(&peer);
+        }
+        *self.in_flight.entry(peer).or_default() += 1;
+        Some((peer, outgoing))
+    }
+
+    /// A message handed to `peer`'s connection was written or failed
+    pub fn settled(&mut self, peer: &PeerId) {
+        if let Some(busy) = self.in_flight.get_mut(peer) {
+            *busy = busy.saturating_sub(1);
+            if *busy == 0 {
+                self.in_flight.remove(peer);
+            }
+        }
+    }
+
+    /// A connection to `peer` closed, losing whatever it was writing. The
+    /// queued messages are dropped too when it was the last one.
+    pub fn connection_closed(&mut self, peer: &PeerId, last: bool) {
+        self.in_flight.remove(peer);
+        if last {
+            let lost = self.queues.remove(peer).unwrap_or_default();
+            self.dropped += lost.len() as u64;
+        }
+    }
+
+    /// Messages waiting, across all peers
+    pub fn queued(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use corelink_core::identity::NodeId;
+    use corelink_core::message::{Message, MessageType};
+
+    fn outgoing(id: u64) -> Outgoing {
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
+        (
+            Message::new(NodeId::from_pubkey(&key), MessageType::Ping),
+            id,
+        )
+    }
+
+    fn queues(overflow: OverflowPolicy) -> OutboundQueues {
+        OutboundQueues::new(QueueLimits {
+            max_queued_per_peer: 2,
+            overflow,
+        })
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let peer = PeerId::random();
+
+        let mut drop_oldest = queues(OverflowPolicy::DropOldest);
+        drop_oldest.push(peer, outgoing(0));
+        drop_oldest.push(peer, outgoing(1));
+        assert!(matches!(
+            drop_oldest.push(peer, outgoing(2)),
+            Queued::Evicted((_, 0))
+        ));
+        assert_eq!(drop_oldest.queued(), 2);
+        assert_eq!(drop_oldest.dropped(), 1);
+
+        for overflow in [OverflowPolicy::Reject, OverflowPolicy::Backpressure] {
+            let mut queues = queues(overflow);
+            queues.push(peer, outgoing(0));
+            queues.push(peer, outgoing(1));
+            assert!(!queues.has_room(&peer));
+            assert!(matches!(
+                queues.push(peer, outgoing(2)),
+                Queued::Full((_, 2))
+            ));
+            assert_eq!(queues.queued(), 2);
+            assert_eq!(queues.dropped(), 1);
+            // Other peers have their own queue
+            assert!(queues.has_room(&PeerId::random()));
+        }
+    }
+
+    #[test]
+    fn test_in_flight_limit() {
+        let peer = PeerId::random();
+        let mut queues = OutboundQueues::new(QueueLimits::default());
+        for id in 0..MAX_IN_FLIGHT_PER_PEER as u64 + 1 {
+            queues.push(peer, outgoing(id));
+        }
+
+        for id in 0..MAX_IN_FLIGHT_PER_PEER as u64 {
+            let (to, (_, next)) = queues.next().unwrap();
+            assert_eq!((to, next), (peer, id));
+        }
+        // The connection has its hands full until one settles
+        assert!(queues.next().is_none());
+        queues.settled(&peer);
+        assert!(queues.next().is_some());
+
+        queues.push(peer, outgoing(9));
+        queues.connection_closed(&peer, true);
+        assert_eq!(queues.queued(), 0);
+        assert_eq!(queues.dropped(), 1);
+    }
+}
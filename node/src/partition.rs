@@ -0,0 +1,185 @@
+use crate::config::peer_addresses;
+use libp2p_core::Multiaddr;
+use libp2p_identity::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Settings for detecting that the node has been cut off from the network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PartitionConfig {
+    /// Well-known peers (multiaddrs ending in `/p2p/<peer id>`) that should
+    /// always be reachable
+    pub anchors: Vec<String>,
+    /// Fraction of anchors, or of recently connected peers, that must become
+    /// unreachable together to suspect a partition
+    pub threshold: f64,
+    /// Losses within this window count as simultaneous
+    pub window_secs: u64,
+    /// Connectivity drops are ignored below this many peers
+    pub min_peers: usize,
+}
+
+impl Default for PartitionConfig {
+    fn default() -> Self {
+        Self {
+            anchors: Vec::new(),
+            threshold: 0.5,
+            window_secs: 60,
+            min_peers: 3,
+        }
+    }
+}
+
+impl PartitionConfig {
+    /// Anchor addresses with the peer each one belongs to. Addresses without
+    /// a `/p2p/` component are skipped.
+    pub fn anchor_addresses(&self) -> Vec<(PeerId, Multiaddr)> {
+        peer_addresses(&self.anchors)
+    }
+}
+
+/// Current view of the node's reachability
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionStatus {
+    pub suspected: bool,
+    /// Configured anchors that are not connected
+    pub unreachable_anchors: Vec<String>,
+    /// Connected peers relative to the most seen within the window
+    pub connectivity: f64,
+}
+
+/// Watches anchor reachability and peer connectivity for sudden losses
+pub struct PartitionDetector {
+    config: PartitionConfig,
+    anchors: HashSet<PeerId>,
+    /// Recent connected peer counts, oldest first
+    samples: VecDeque<(Instant, usize)>,
+    status: PartitionStatus,
+}
+
+impl PartitionDetector {
+    pub fn new(config: PartitionConfig) -> Self {
+        let anchors = config
+            .anchor_addresses()
+            .into_iter()
+            .map(|(peer, _)| peer)
+            .collect();
+        Self {
+            config,
+            anchors,
+            samples: VecDeque::new(),
+            status: PartitionStatus {
+                suspected: false,
+                unreachable_anchors: Vec::new(),
+                connectivity: 1.0,
+            },
+        }
+    }
+
+    pub fn status(&self) -> &PartitionStatus {
+        &self.status
+    }
+
+    /// Update with the currently connected peers. Returns the new status if
+    /// a partition started or ended.
+    pub fn check(&mut self, connected: &HashSet<PeerId>) -> Option<PartitionStatus> {
+        self.check_at(connected, Instant::now())
+    }
+
+    fn check_at(&mut self, connected: &HashSet<PeerId>, now: Instant) -> Option<PartitionStatus> {
+        let window = Duration::from_secs(self.config.window_secs);
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > window)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, connected.len()));
+
+        let mut unreachable_anchors: Vec<String> = self
+            .anchors
+            .iter()
+            .filter(|anchor| !connected.contains(anchor))
+            .map(PeerId::to_string)
+            .collect();
+        unreachable_anchors.sort();
+        let anchors_lost = !self.anchors.is_empty()
+            && unreachable_anchors.len() as f64 / self.anchors.len() as f64
+                >= self.config.threshold;
+
+        let peak = self
+            .samples
+            .iter()
+            .map(|(_, count)| *count)
+            .max()
+            .unwrap_or(0);
+        let connectivity = if peak == 0 {
+            1.0
+        } else {
+            connected.len() as f64 / peak as f64
+        };
+        let peers_lost =
+            peak >= self.config.min_peers && 1.0 - connectivity >= self.config.threshold;
+
+        let suspected = anchors_lost || peers_lost;
+
+        let changed = suspected != self.status.suspected;
+        self.status = PartitionStatus {
+            suspected,
+            unreachable_anchors,
+            connectivity,
+        };
+        changed.then(|| self.status.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_sudden_loss() {
+        let peers: Vec<PeerId> = (0..4).map(|_| PeerId::random()).collect();
+        let anchor = format!("/ip4/10.0.0.1/tcp/4001/p2p/{}", peers[0]);
+        let mut detector = PartitionDetector::new(PartitionConfig {
+            anchors: vec![anchor, "/ip4/10.0.0.2/tcp/4001".to_string()],
+            ..PartitionConfig::default()
+        });
+        let start = Instant::now();
+        let all: HashSet<PeerId> = peers.iter().copied().collect();
+
+        assert_eq!(detector.check_at(&all, start), None);
+
+        // Losing the anchor and most peers at once is a partition
+        let cut_off: HashSet<PeerId> = peers[3..].iter().copied().collect();
+        let status = detector
+            .check_at(&cut_off, start + Duration::from_secs(5))
+            .unwrap();
+        assert!(status.suspected);
+        assert_eq!(status.unreachable_anchors, vec![peers[0].to_string()]);
+        assert_eq!(status.connectivity, 0.25);
+
+        // Reconnecting ends it
+        let status = detector
+            .check_at(&all, start + Duration::from_secs(10))
+            .unwrap();
+        assert!(!status.suspected);
+    }
+
+    #[test]
+    fn test_gradual_loss_is_not_a_partition() {
+        let peers: Vec<PeerId> = (0..4).map(|_| PeerId::random()).collect();
+        let mut detector = PartitionDetector::new(PartitionConfig::default());
+        let start = Instant::now();
+
+        // One peer leaves every two windows
+        for (i, n) in (1..=4).rev().enumerate() {
+            let connected: HashSet<PeerId> = peers[..n].iter().copied().collect();
+            let at = start + Duration::from_secs(120 * i as u64);
+            assert_eq!(detector.check_at(&connected, at), None);
+        }
+    }
+}
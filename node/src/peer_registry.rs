@@ -0,0 +1,191 @@
+use libp2p::identify;
+use libp2p::swarm::ConnectionId;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// How many ping round trips each peer remembers
+pub const MAX_RTT_SAMPLES: usize = 30;
+
+/// An open connection to a peer
+#[derive(Debug, Clone)]
+pub struct PeerConnection {
+    pub address: Multiaddr,
+    /// Unix time the connection was established
+    pub established_at: u64,
+}
+
+/// What a peer told us about itself through identify
+#[derive(Debug, Clone, Default)]
+pub struct PeerIdentity {
+    pub protocol_version: String,
+    pub agent_version: String,
+    pub listen_addresses: Vec<Multiaddr>,
+    pub protocols: Vec<String>,
+}
+
+/// One ping round trip
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RttSample {
+    /// Unix time of the ping
+    pub timestamp: u64,
+    pub rtt_ms: f64,
+}
+
+/// A connected peer, its open connections and its identity once known
+#[derive(Debug, Clone, Default)]
+pub struct ConnectedPeer {
+    pub connections: HashMap<ConnectionId, PeerConnection>,
+    pub identity: Option<PeerIdentity>,
+    /// Latest ping round trips, oldest first
+    pub rtt_history: VecDeque<RttSample>,
+}
+
+impl ConnectedPeer {
+    /// When the oldest open connection was established
+    pub fn connected_since(&self) -> u64 {
+        self.connections
+            .values()
+            .map(|c| c.established_at)
+            .min()
+            .unwrap_or_default()
+    }
+
+    /// Remote addresses of the open connections, oldest first
+    pub fn addresses(&self) -> Vec<Multiaddr> {
+        let mut connections: Vec<&PeerConnection> = self.connections.values().collect();
+        connections.sort_by_key(|c| c.established_at);
+        let mut addresses: Vec<Multiaddr> = Vec::new();
+        for connection in connections {
+            if !addresses.contains(&connection.address) {
+                addresses.push(connection.address.clone());
+            }
+        }
+        addresses
+    }
+}
+
+/// Live metadata of connected peers, kept up to date from swarm events
+#[derive(Debug, Default)]
+pub struct PeerRegistry {
+    peers: HashMap<PeerId, ConnectedPeer>,
+}
+
+impl PeerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn connection_established(
+        &mut self,
+        peer: PeerId,
+        connection_id: ConnectionId,
+        connection: PeerConnection,
+    ) {
+        self.peers
+            .entry(peer)
+            .or_default()
+            .connections
+            .insert(connection_id, connection);
+    }
+
+    /// Forget a closed connection, and the peer with its last connection
+    pub fn connection_closed(&mut self, peer: &PeerId, connection_id: ConnectionId) {
+        if let Some(connected) = self.peers.get_mut(peer) {
+            connected.connections.remove(&connection_id);
+            if connected.connections.is_empty() {
+                self.peers.remove(peer);
+            }
+        }
+    }
+
+    pub fn identified(&mut self, peer: &PeerId, info: &identify::Info) {
+        if let Some(connected) = self.peers.get_mut(peer) {
+            connected.identity = Some(PeerIdentity {
+                protocol_version: info.protocol_version.clone(),
+                agent_version: info.agent_version.clone(),
+                listen_addresses: info.listen_addrs.clone(),
+                protocols: info.protocols.iter().map(|p| p.to_string()).collect(),
+            });
+        }
+    }
+
+    /// Remember a ping round trip, forgetting the oldest beyond [`MAX_RTT_SAMPLES`]
+    pub fn record_rtt(&mut self, peer: &PeerId, rtt: Duration, timestamp: u64) {
+        if let Some(connected) = self.peers.get_mut(peer) {
+            if connected.rtt_history.len() == MAX_RTT_SAMPLES {
+                connected.rtt_history.pop_front();
+            }
+            connected.rtt_history.push_back(RttSample {
+                timestamp,
+                rtt_ms: rtt.as_secs_f64() * 1000.0,
+            });
+        }
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&ConnectedPeer> {
+        self.peers.get(peer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(address: &str, established_at: u64) -> PeerConnection {
+        PeerConnection {
+            address: address.parse().unwrap(),
+            established_at,
+        }
+    }
+
+    #[test]
+    fn test_tracks_connections_per_peer() {
+        let mut registry = PeerRegistry::new();
+        let peer = PeerId::random();
+        let (first, second) = (
+            ConnectionId::new_unchecked(1),
+            ConnectionId::new_unchecked(2),
+        );
+        registry.connection_established(peer, second, connection("/ip4/10.0.0.2/tcp/4001", 200));
+        registry.connection_established(peer, first, connection("/ip4/10.0.0.1/tcp/4001", 100));
+
+        let connected = registry.get(&peer).unwrap();
+        assert_eq!(connected.connected_since(), 100);
+        assert_eq!(
+            connected.addresses(),
+            vec![
+                "/ip4/10.0.0.1/tcp/4001".parse::<Multiaddr>().unwrap(),
+                "/ip4/10.0.0.2/tcp/4001".parse().unwrap(),
+            ]
+        );
+
+        registry.connection_closed(&peer, first);
+        assert_eq!(registry.get(&peer).unwrap().connected_since(), 200);
+        registry.connection_closed(&peer, second);
+        assert!(registry.get(&peer).is_none());
+    }
+
+    #[test]
+    fn test_rtt_history_is_capped() {
+        let mut registry = PeerRegistry::new();
+        let peer = PeerId::random();
+        // Pings of peers that are not connected are not kept
+        registry.record_rtt(&peer, Duration::from_millis(5), 0);
+        registry.connection_established(
+            peer,
+            ConnectionId::new_unchecked(1),
+            connection("/ip4/10.0.0.1/tcp/4001", 100),
+        );
+
+        for ms in 0..MAX_RTT_SAMPLES as u64 + 5 {
+            registry.record_rtt(&peer, Duration::from_millis(ms), 100 + ms);
+        }
+        let history = &registry.get(&peer).unwrap().rtt_history;
+        assert_eq!(history.len(), MAX_RTT_SAMPLES);
+        assert_eq!(history.front().unwrap().timestamp, 105);
+        assert_eq!(history.back().unwrap().rtt_ms, (MAX_RTT_SAMPLES + 4) as f64);
+    }
+}
@@ -0,0 +1,165 @@
+use corelink_core::Storage;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::warn;
+
+/// Bucket holding one PeerRecord per peer id
+const PEERS_BUCKET: &str = "peers";
+
+/// Peers preferred as download sources
+pub const TRUSTED_TAG: &str = "trusted";
+/// Peers preferred for replicas of our files
+pub const BACKUP_TARGET_TAG: &str = "backup-target";
+/// Peers used only when nothing else will do
+pub const FLAKY_TAG: &str = "flaky";
+
+/// What we remember about a peer across restarts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+    pub last_seen: u64,
+    /// Operator-assigned labels, e.g. [`TRUSTED_TAG`]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Free-form operator note
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+impl PeerRecord {
+    fn new(peer_id: &PeerId) -> Self {
+        Self {
+            peer_id: peer_id.to_string(),
+            addresses: Vec::new(),
+            last_seen: 0,
+            tags: Vec::new(),
+            note: None,
+        }
+    }
+}
+
+/// Persistent record of peers this node has connected to
+pub struct PeerStore {
+    store: Storage,
+    peers: HashMap<String, PeerRecord>,
+}
+
+impl PeerStore {
+    pub fn new(store: Storage) -> corelink_core::Result<Self> {
+        let store = store.open_bucket(PEERS_BUCKET)?;
+        let peers = match store.iter_json() {
+            Ok(peers) => peers.into_iter().collect(),
+            Err(e) => {
+                warn!("Failed to load peer store: {}", e);
+                HashMap::new()
+            }
+        };
+
+        Ok(Self { store, peers })
+    }
+
+    /// Record a connection to `peer_id`, remembering `address` if it can be dialed again
+    pub fn record_connection(&mut self, peer_id: &PeerId, address: Option<&Multiaddr>) {
+        let record = self
+            .peers
+            .entry(peer_id.to_string())
+            .or_insert_with(|| PeerRecord::new(peer_id));
+
+        if let Some(address) = address {
+            let address = address.to_string();
+            if !record.addresses.contains(&address) {
+                record.addresses.push(address);
+            }
+        }
+        record.last_seen = current_timestamp();
+        Self::persist(&self.store, record);
+    }
+
+    /// Replace a peer's tags and note. Peers can be tagged before we have
+    /// ever connected to them.
+    pub fn set_tags(&mut self, peer_id: &PeerId, tags: Vec<String>, note: Option<String>) {
+        let record = self
+            .peers
+            .entry(peer_id.to_string())
+            .or_insert_with(|| PeerRecord::new(peer_id));
+        record.tags = tags;
+        record.tags.sort();
+        record.tags.dedup();
+        record.note = note.filter(|note| !note.is_empty());
+        Self::persist(&self.store, record);
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<&PeerRecord> {
+        self.peers.get(&peer_id.to_string())
+    }
+
+    /// Tags of every peer that has any
+    pub fn all_tags(&self) -> Vec<(PeerId, Vec<String>)> {
+        self.peers
+            .values()
+            .filter(|record| !record.tags.is_empty())
+            .filter_map(|record| Some((record.peer_id.parse().ok()?, record.tags.clone())))
+            .collect()
+    }
+
+    /// Write pending changes to disk
+    pub fn flush(&self) -> corelink_core::Result<()> {
+        self.store.flush()
+    }
+
+    fn persist(store: &Storage, record: &PeerRecord) {
+        if let Err(e) = store.insert_json(record.peer_id.clone(), record) {
+            warn!("Failed to persist peer {}: {}", record.peer_id, e);
+        }
+    }
+
+    /// Dialable addresses of every known peer
+    pub fn known_addresses(&self) -> Vec<(PeerId, Multiaddr)> {
+        self.peers
+            .values()
+            .filter_map(|record| Some((record.peer_id.parse().ok()?, &record.addresses)))
+            .flat_map(|(peer_id, addresses)| {
+                addresses
+                    .iter()
+                    .filter_map(move |addr| Some((peer_id, addr.parse().ok()?)))
+            })
+            .collect()
+    }
+}
+
+fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_persist() {
+        let store = Storage::new();
+        let peer = PeerId::random();
+
+        let mut peers = PeerStore::new(store.clone()).unwrap();
+        peers.set_tags(
+            &peer,
+            vec![
+                FLAKY_TAG.to_string(),
+                TRUSTED_TAG.to_string(),
+                FLAKY_TAG.to_string(),
+            ],
+            Some("office NAS".to_string()),
+        );
+
+        let peers = PeerStore::new(store).unwrap();
+        let record = peers.get(&peer).unwrap();
+        assert_eq!(record.tags, vec![FLAKY_TAG, TRUSTED_TAG]);
+        assert_eq!(record.note.as_deref(), Some("office NAS"));
+        assert_eq!(peers.all_tags(), vec![(peer, record.tags.clone())]);
+    }
+}
@@ -0,0 +1,164 @@
+use corelink_core::message::MessageType;
+use corelink_core::{MESSAGE_PROTOCOL, PROTOCOL_VERSION};
+use libp2p_identity::PeerId;
+use libp2p_swarm::StreamProtocol;
+use std::collections::{HashMap, HashSet};
+
+/// What a peer's CoreLink implementation understands, as far as it said
+#[derive(Debug, Clone, Default)]
+pub struct PeerVersion {
+    /// Protocol version reported through identify, e.g. `/corelink/1.0.0`
+    pub protocol_version: Option<String>,
+    /// Stream protocols reported through identify
+    pub protocols: Option<HashSet<String>>,
+    /// Features advertised in discovery
+    pub features: Option<HashSet<String>>,
+    /// Incompatibilities already reported, so each is reported once
+    reported: HashSet<String>,
+}
+
+/// Versions and features of connected peers
+#[derive(Debug, Default)]
+pub struct PeerVersions {
+    peers: HashMap<PeerId, PeerVersion>,
+}
+
+impl PeerVersions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record what `peer` reported through identify
+    pub fn identified(
+        &mut self,
+        peer: PeerId,
+        protocol_version: String,
+        protocols: &[StreamProtocol],
+    ) {
+        let version = self.peers.entry(peer).or_default();
+        version.protocol_version = Some(protocol_version);
+        version.protocols = Some(protocols.iter().map(|p| p.to_string()).collect());
+    }
+
+    /// Record the features `peer` advertised in discovery
+    pub fn discovered(&mut self, peer: PeerId, features: &[String]) {
+        self.peers.entry(peer).or_default().features = Some(features.iter().cloned().collect());
+    }
+
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&PeerVersion> {
+        self.peers.get(peer)
+    }
+
+    /// Why `peer` cannot understand a message of this type, if it cannot.
+    /// Peers that have not identified yet are assumed to speak our version.
+    pub fn incompatibility(&self, peer: &PeerId, msg_type: &MessageType) -> Option<String> {
+        let version = self.peers.get(peer);
+        if let Some(reason) = version.and_then(version_mismatch) {
+            return Some(reason);
+        }
+        if let Some(reason) = self.missing_protocol(peer, &MESSAGE_PROTOCOL) {
+            return Some(reason);
+        }
+        let feature = msg_type.required_feature()?;
+        (!self.supports(peer, feature))
+            .then(|| format!("Peer does not support {} messages", feature))
+    }
+
+    /// Whether `peer` advertised `feature` in discovery
+    pub fn supports(&self, peer: &PeerId, feature: &str) -> bool {
+        self.peers
+            .get(peer)
+            .and_then(|version| version.features.as_ref())
+            .is_some_and(|features| features.contains(feature))
+    }
+
+    /// Why `peer` cannot be asked over `protocol`, if identify says it does
+    /// not speak it
+    pub fn missing_protocol(&self, peer: &PeerId, protocol: &StreamProtocol) -> Option<String> {
+        let version = self.peers.get(peer)?;
+        if let Some(reason) = version_mismatch(version) {
+            return Some(reason);
+        }
+        let protocols = version.protocols.as_ref()?;
+        (!protocols.contains(protocol.as_ref()))
+            .then(|| format!("Peer does not support {}", protocol))
+    }
+
+    /// Whether `reason` is news about `peer`, as opposed to reported before
+    pub fn first_report(&mut self, peer: PeerId, reason: &str) -> bool {
+        self.peers
+            .entry(peer)
+            .or_default()
+            .reported
+            .insert(reason.to_string())
+    }
+}
+
+/// Why a peer's protocol version is not one we understand, if it is not
+fn version_mismatch(version: &PeerVersion) -> Option<String> {
+    let reported = version.protocol_version.as_deref()?;
+    (major_version(reported)? != major_version(PROTOCOL_VERSION)?).then(|| {
+        format!(
+            "Peer speaks {}, which is incompatible with {}",
+            reported, PROTOCOL_VERSION
+        )
+    })
+}
+
+/// Major version of a `/corelink/<major>.<minor>.<patch>` protocol version
+fn major_version(version: &str) -> Option<u32> {
+    let version = version.strip_prefix("/corelink/")?;
+    version.split('.').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use corelink_core::TRANSFER_PROTOCOL;
+
+    #[test]
+    fn test_incompatible_peers() {
+        let mut versions = PeerVersions::new();
+        let (old, new, future) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let rejected = MessageType::OfferRejected {
+            file_id: "a".to_string(),
+        };
+
+        // Unknown peers get the benefit of the doubt, except for features
+        assert!(versions.incompatibility(&old, &MessageType::Ping).is_none());
+        assert!(versions.incompatibility(&old, &rejected).is_some());
+
+        versions.identified(old, "/corelink/1.0.0".to_string(), &[MESSAGE_PROTOCOL]);
+        versions.discovered(old, &[]);
+        versions.identified(
+            new,
+            "/corelink/1.3.0".to_string(),
+            &[MESSAGE_PROTOCOL, TRANSFER_PROTOCOL],
+        );
+        versions.discovered(new, &["offer-rejection".to_string()]);
+        versions.identified(future, "/corelink/2.0.0".to_string(), &[MESSAGE_PROTOCOL]);
+
+        assert!(versions.incompatibility(&old, &MessageType::Ping).is_none());
+        assert!(versions.incompatibility(&old, &rejected).is_some());
+        assert!(versions
+            .missing_protocol(&old, &TRANSFER_PROTOCOL)
+            .is_some());
+        assert!(versions.incompatibility(&new, &rejected).is_none());
+        assert!(versions.supports(&new, "offer-rejection"));
+        assert!(!versions.supports(&future, "offer-rejection"));
+        assert!(versions
+            .missing_protocol(&new, &TRANSFER_PROTOCOL)
+            .is_none());
+        let reason = versions
+            .incompatibility(&future, &MessageType::Ping)
+            .unwrap();
+        assert!(reason.contains("/corelink/2.0.0"));
+
+        assert!(versions.first_report(future, &reason));
+        assert!(!versions.first_report(future, &reason));
+    }
+}
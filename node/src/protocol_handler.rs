@@ -1,5 +1,6 @@
-use corelink_core::{CoreLinkCodec, Message};
-use futures::{AsyncRead, AsyncWrite, Future};
+use corelink_core::{CoreLinkCodec, Message, MESSAGE_PROTOCOL};
+use futures::stream::FuturesUnordered;
+use futures::{AsyncRead, AsyncWrite, Future, StreamExt};
 use libp2p_core::upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeInfo};
 use libp2p_swarm::{
     handler::ConnectionEvent, ConnectionHandler, ConnectionHandlerEvent, Stream, StreamProtocol,
@@ -9,7 +10,7 @@ use std::collections::VecDeque;
 use std::io;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 #[derive(Debug, Clone)]
 pub struct CoreLinkProtocol;
@@ -19,7 +20,7 @@ impl UpgradeInfo for CoreLinkProtocol {
     type InfoIter = std::iter::Once<Self::Info>;
 
     fn protocol_info(&self) -> Self::InfoIter {
-        std::iter::once(StreamProtocol::new("/corelink/msg/1.0.0"))
+        std::iter::once(MESSAGE_PROTOCOL)
     }
 }
 
@@ -51,55 +52,207 @@ where
     }
 }
 
+/// A message for the handler to send, and the id to report how the send
+/// went under
+pub type Outgoing = (Message, u64);
+
+/// What the behaviour tells a handler
+#[derive(Debug)]
+pub enum CoreLinkHandlerIn {
+    Send(Box<Outgoing>),
+    /// Whether transfers with the peer are under way, which keeps the
+    /// connection open while no message is being written
+    KeepAlive(bool),
+}
+
 #[derive(Debug)]
 pub enum CoreLinkHandlerEvent {
-    MessageReceived(Message),
-    MessageSent,
-    SendError(String),
+    /// A message and its size on the wire
+    MessageReceived(Box<Message>, usize),
+    /// A message of this many bytes was written
+    MessageSent(usize, u64),
+    SendError(String, u64),
+    /// Whether the connection can carry messages: a stream to write them on
+    /// was negotiated, or no more can be opened. Reported when it changes.
+    MessagingStream(bool),
 }
 
-type ReadFuture = Pin<Box<dyn Future<Output = Result<(Stream, Message), io::Error>> + Send>>;
-type WriteFuture = Pin<Box<dyn Future<Output = Result<Stream, io::Error>> + Send>>;
+/// Outbound substreams open at once per connection
+const MAX_OUTBOUND_STREAMS: usize = 4;
+/// Inbound substreams read at once per connection; more are dropped
+const MAX_INBOUND_STREAMS: usize = 8;
+/// Why messages fail once streams to the peer can no longer be opened
+const NO_STREAM: &str = "Could not open a stream to the peer";
+/// Messages queued on a stream in one go, written without waiting on the
+/// handler in between
+const MAX_PIPELINED_WRITES: usize = 8;
+
+type ReadFuture = Pin<Box<dyn Future<Output = Result<(Stream, Message, usize), io::Error>> + Send>>;
+/// A batch of writes and whether it went out on the ordered stream
+type WriteFuture = Pin<Box<dyn Future<Output = (WriteBatch, bool)> + Send>>;
 
-enum StreamState {
-    Idle,
-    Reading(ReadFuture),
-    Writing(WriteFuture),
+/// Messages written back to back on one stream
+struct WriteBatch {
+    /// The stream, unless a write on it failed
+    stream: Option<Stream>,
+    /// Bytes written or why writing failed, under each message's id.
+    /// Messages after a failed write fail with it.
+    outcomes: Vec<(u64, Result<usize, String>)>,
 }
 
+/// Reads and writes messages on several substreams at once, so a slow write
+/// does not hold up others on the connection. Registry updates all go out
+/// on one stream, so peers apply them in the order they were sent.
+///
+/// One outbound stream is negotiated as soon as the connection is up, so
+/// the first message does not wait for it, and messages waiting for a
+/// stream are written back to back on it rather than one per turn.
+///
+/// Its streams stay open between messages without keeping the connection
+/// alive; the connection is kept while messages are being sent or the
+/// behaviour says transfers with the peer are under way.
 pub struct CoreLinkHandler {
-    inbound_stream: Option<Stream>,
-    outbound_stream: Option<Stream>,
-    inbound_state: StreamState,
-    outbound_state: StreamState,
-    pending_messages: VecDeque<Message>,
+    /// Inbound streams, each reading its next message
+    reads: FuturesUnordered<ReadFuture>,
+    /// Messages being written
+    writes: FuturesUnordered<WriteFuture>,
+    /// Outbound streams with nothing to write
+    idle_streams: Vec<Stream>,
+    /// Outbound stream registry updates are written on, when not in use
+    ordered_stream: Option<Stream>,
+    /// A registry update is being written on the ordered stream
+    ordered_writing: bool,
+    /// Outbound streams open, idle or writing
+    outbound_streams: usize,
+    /// Outbound streams requested but not yet negotiated
+    outbound_requested: usize,
+    pending_messages: VecDeque<Outgoing>,
     events: VecDeque<CoreLinkHandlerEvent>,
     dial_upgrade_failures: u32,
     listen_upgrade_failures: u32,
     can_request_outbound: bool,
-    outbound_requested: bool,
+    transfers_active: bool,
+    /// Last reported whether the connection can carry messages
+    usable: Option<bool>,
 }
 
 impl CoreLinkHandler {
     pub fn new() -> Self {
         debug!("Creating new CoreLinkHandler");
         Self {
-            inbound_stream: None,
-            outbound_stream: None,
-            inbound_state: StreamState::Idle,
-            outbound_state: StreamState::Idle,
+            reads: FuturesUnordered::new(),
+            writes: FuturesUnordered::new(),
+            idle_streams: Vec::new(),
+            ordered_stream: None,
+            ordered_writing: false,
+            outbound_streams: 0,
+            outbound_requested: 0,
             pending_messages: VecDeque::new(),
             events: VecDeque::new(),
             dial_upgrade_failures: 0,
             listen_upgrade_failures: 0,
             can_request_outbound: true, // Start enabled to allow initial requests
-            outbound_requested: false,
+            transfers_active: false,
+            usable: None,
+        }
+    }
+
+    fn read(mut stream: Stream) -> ReadFuture {
+        Box::pin(async move {
+            let (msg, bytes) = CoreLinkCodec::read_message(&mut stream).await?;
+            Ok((stream, msg, bytes))
+        })
+    }
+
+    /// Start writing pending messages on free streams, oldest first, each
+    /// stream taking a batch of them. Returns whether a message is left
+    /// waiting that another stream would take.
+    fn start_writes(&mut self) -> bool {
+        let mut wants_stream = false;
+        if self.waiting(true) && !self.ordered_writing {
+            match self.ordered_stream.take() {
+                Some(stream) => {
+                    self.ordered_writing = true;
+                    let batch = self.take_batch(true);
+                    self.write(stream, batch, true);
+                }
+                None => wants_stream = true,
+            }
+        }
+        while self.waiting(false) {
+            let Some(stream) = self.idle_streams.pop() else {
+                wants_stream = true;
+                break;
+            };
+            let batch = self.take_batch(false);
+            self.write(stream, batch, false);
+        }
+        wants_stream
+    }
+
+    /// Whether a registry update, or another message, is pending
+    fn waiting(&self, ordered: bool) -> bool {
+        self.pending_messages
+            .iter()
+            .any(|(msg, _)| msg.msg_type.affects_registry() == ordered)
+    }
+
+    /// Take the oldest pending registry updates, or other messages, to
+    /// write on one stream
+    fn take_batch(&mut self, ordered: bool) -> Vec<Outgoing> {
+        let mut batch = Vec::new();
+        let mut index = 0;
+        while index < self.pending_messages.len() && batch.len() < MAX_PIPELINED_WRITES {
+            if self.pending_messages[index].0.msg_type.affects_registry() == ordered {
+                batch.extend(self.pending_messages.remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        batch
+    }
+
+    fn write(&mut self, stream: Stream, batch: Vec<Outgoing>, ordered: bool) {
+        info!("🔴 Starting outbound write of {} messages", batch.len());
+        self.writes.push(Box::pin(async move {
+            let mut stream = Some(stream);
+            let mut outcomes: Vec<(u64, Result<usize, String>)> = Vec::with_capacity(batch.len());
+            for (msg, id) in batch {
+                let result = match stream.as_mut() {
+                    Some(writing) => CoreLinkCodec::send_message(writing, &msg)
+                        .await
+                        .map_err(|e| e.to_string()),
+                    None => outcomes.last().map_or(Ok(0), |(_, failed)| failed.clone()),
+                };
+                if result.is_err() {
+                    stream = None;
+                }
+                outcomes.push((id, result));
+            }
+            (WriteBatch { stream, outcomes }, ordered)
+        }));
+    }
+
+    fn report_usable(&mut self, usable: bool) {
+        if self.usable != Some(usable) {
+            self.usable = Some(usable);
+            self.events
+                .push_back(CoreLinkHandlerEvent::MessagingStream(usable));
+        }
+    }
+
+    /// Put a negotiated or written outbound stream back to use
+    fn stream_ready(&mut self, stream: Stream) {
+        if self.ordered_stream.is_none() && !self.ordered_writing {
+            self.ordered_stream = Some(stream);
+        } else {
+            self.idle_streams.push(stream);
         }
     }
 }
 
 impl ConnectionHandler for CoreLinkHandler {
-    type FromBehaviour = Message;
+    type FromBehaviour = CoreLinkHandlerIn;
     type ToBehaviour = CoreLinkHandlerEvent;
     type InboundProtocol = CoreLinkProtocol;
     type OutboundProtocol = CoreLinkProtocol;
@@ -110,12 +263,33 @@ impl ConnectionHandler for CoreLinkHandler {
         SubstreamProtocol::new(CoreLinkProtocol, ())
     }
 
-    fn on_behaviour_event(&mut self, message: Self::FromBehaviour) {
+    fn on_behaviour_event(&mut self, event: Self::FromBehaviour) {
+        let outgoing = match event {
+            CoreLinkHandlerIn::Send(outgoing) => *outgoing,
+            CoreLinkHandlerIn::KeepAlive(active) => {
+                self.transfers_active = active;
+                return;
+            }
+        };
         info!(
             "🟢 Handler received message from behaviour: {:?}",
-            message.msg_type
+            outgoing.0.msg_type
         );
-        self.pending_messages.push_back(message);
+        if !self.can_request_outbound && self.outbound_streams == 0 {
+            self.events.push_back(CoreLinkHandlerEvent::SendError(
+                NO_STREAM.to_string(),
+                outgoing.1,
+            ));
+            return;
+        }
+        self.pending_messages.push_back(outgoing);
+    }
+
+    fn connection_keep_alive(&self) -> bool {
+        self.transfers_active
+            || !self.pending_messages.is_empty()
+            || !self.writes.is_empty()
+            || self.outbound_requested > 0
     }
 
     fn poll(
@@ -124,82 +298,74 @@ impl ConnectionHandler for CoreLinkHandler {
     ) -> Poll<
         ConnectionHandlerEvent<Self::OutboundProtocol, Self::OutboundOpenInfo, Self::ToBehaviour>,
     > {
-        if let Some(event) = self.events.pop_front() {
-            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(event));
-        }
-
-        // Handle inbound reading
-        match &mut self.inbound_state {
-            StreamState::Idle => {
-                if let Some(mut stream) = self.inbound_stream.take() {
-                    info!("🔵 Starting inbound read");
-                    let fut: ReadFuture = Box::pin(async move {
-                        let msg = CoreLinkCodec::read_message(&mut stream).await?;
-                        Ok((stream, msg))
-                    });
-                    self.inbound_state = StreamState::Reading(fut);
+        let wants_stream = loop {
+            // Handle inbound reading, each stream reading its next message
+            // once one arrives
+            while let Poll::Ready(Some(read)) = self.reads.poll_next_unpin(cx) {
+                match read {
+                    Ok((stream, msg, bytes)) => {
+                        info!("📨 Received message: {:?}", msg.msg_type);
+                        self.events
+                            .push_back(CoreLinkHandlerEvent::MessageReceived(Box::new(msg), bytes));
+                        self.reads.push(Self::read(stream));
+                    }
+                    // Also how streams closed by the peer end
+                    Err(e) => debug!("Inbound stream ended: {}", e),
                 }
             }
-            StreamState::Reading(fut) => match fut.as_mut().poll(cx) {
-                Poll::Ready(Ok((stream, msg))) => {
-                    info!("📨 Received message: {:?}", msg.msg_type);
-                    self.events
-                        .push_back(CoreLinkHandlerEvent::MessageReceived(msg));
-                    self.inbound_stream = Some(stream);
-                    self.inbound_state = StreamState::Idle;
-                    return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(
-                        self.events.pop_front().unwrap(),
-                    ));
+
+            // Handle finished writes, putting their streams back to use
+            while let Poll::Ready(Some((batch, ordered))) = self.writes.poll_next_unpin(cx) {
+                if ordered {
+                    self.ordered_writing = false;
                 }
-                Poll::Ready(Err(e)) => {
-                    error!("❌ Failed to read message: {}", e);
-                    self.inbound_state = StreamState::Idle;
+                for (id, result) in batch.outcomes {
+                    self.events.push_back(match result {
+                        Ok(bytes) => {
+                            info!("📤 Sent message successfully");
+                            CoreLinkHandlerEvent::MessageSent(bytes, id)
+                        }
+                        Err(e) => {
+                            error!("❌ Failed to send message: {}", e);
+                            CoreLinkHandlerEvent::SendError(e, id)
+                        }
+                    });
                 }
-                Poll::Pending => {}
-            },
-            _ => {}
-        }
-
-        // Handle outbound writing
-        match &mut self.outbound_state {
-            StreamState::Idle => {
-                if !self.pending_messages.is_empty() && self.can_request_outbound {
-                    if self.outbound_stream.is_none() && !self.outbound_requested {
-                        info!("🔴 Requesting outbound substream");
-                        self.outbound_requested = true;
-                        return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
-                            protocol: SubstreamProtocol::new(CoreLinkProtocol, ()),
-                        });
-                    }
-
-                    if let Some(mut stream) = self.outbound_stream.take() {
-                        if let Some(msg) = self.pending_messages.pop_front() {
-                            info!("🔴 Starting outbound write: {:?}", msg.msg_type);
-                            let fut: WriteFuture = Box::pin(async move {
-                                CoreLinkCodec::send_message(&mut stream, &msg).await?;
-                                Ok(stream)
-                            });
-                            self.outbound_state = StreamState::Writing(fut);
+                match batch.stream {
+                    Some(stream) => self.stream_ready(stream),
+                    None => {
+                        self.outbound_streams -= 1;
+                        if self.outbound_streams == 0 && !self.can_request_outbound {
+                            self.report_usable(false);
                         }
                     }
                 }
             }
-            StreamState::Writing(fut) => match fut.as_mut().poll(cx) {
-                Poll::Ready(Ok(stream)) => {
-                    info!("📤 Sent message successfully");
-                    self.events.push_back(CoreLinkHandlerEvent::MessageSent);
-                    self.outbound_stream = Some(stream);
-                    self.outbound_state = StreamState::Idle;
-                }
-                Poll::Ready(Err(e)) => {
-                    error!("❌ Failed to send message: {}", e);
-                    self.events
-                        .push_back(CoreLinkHandlerEvent::SendError(e.to_string()));
-                    self.outbound_state = StreamState::Idle;
-                }
-                Poll::Pending => {}
-            },
-            _ => {}
+
+            // Handle outbound writing, polling new writes so they register
+            // for wakeups
+            let writing = self.writes.len();
+            let wants_stream = self.start_writes();
+            if self.writes.len() == writing {
+                break wants_stream;
+            }
+        };
+
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(event));
+        }
+
+        // Keep one stream negotiated ahead of the first message
+        let wants_stream = wants_stream || self.outbound_streams + self.outbound_requested == 0;
+        if wants_stream
+            && self.can_request_outbound
+            && self.outbound_streams + self.outbound_requested < MAX_OUTBOUND_STREAMS
+        {
+            info!("🔴 Requesting outbound substream");
+            self.outbound_requested += 1;
+            return Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest {
+                protocol: SubstreamProtocol::new(CoreLinkProtocol, ()),
+            });
         }
 
         Poll::Pending
@@ -216,21 +382,34 @@ impl ConnectionHandler for CoreLinkHandler {
     ) {
         match event {
             ConnectionEvent::FullyNegotiatedInbound(stream) => {
+                if self.reads.len() >= MAX_INBOUND_STREAMS {
+                    warn!(
+                        "Dropping inbound stream over the limit of {}",
+                        MAX_INBOUND_STREAMS
+                    );
+                    return;
+                }
                 info!("🔵 Inbound stream fully negotiated");
-                self.inbound_stream = Some(stream.protocol);
+                let mut stream = stream.protocol;
+                stream.ignore_for_keep_alive();
+                self.reads.push(Self::read(stream));
                 // Allow outbound requests after inbound is established
                 self.can_request_outbound = true;
             }
             ConnectionEvent::FullyNegotiatedOutbound(stream) => {
                 info!("🔴 Outbound stream fully negotiated");
-                self.outbound_stream = Some(stream.protocol);
-                self.outbound_requested = false; // Reset flag - upgrade completed
-                                                 // Allow future outbound requests after one succeeds
+                self.outbound_requested -= 1;
+                self.outbound_streams += 1;
+                let mut stream = stream.protocol;
+                stream.ignore_for_keep_alive();
+                self.stream_ready(stream);
+                self.report_usable(true);
+                // Allow future outbound requests after one succeeds
                 self.can_request_outbound = true;
             }
             ConnectionEvent::DialUpgradeError(err) => {
                 self.dial_upgrade_failures += 1;
-                self.outbound_requested = false; // Reset flag - can retry if allowed
+                self.outbound_requested -= 1; // Can retry if allowed
 
                 if self.dial_upgrade_failures <= 2 {
                     info!(
@@ -244,16 +423,25 @@ impl ConnectionHandler for CoreLinkHandler {
                     );
                 }
 
-                // After 3 failures, stop trying and clear pending messages
+                // After 3 failures, stop trying and fail pending messages
+                // unless streams opened earlier can still take them
                 if self.dial_upgrade_failures >= 3 {
-                    if !self.pending_messages.is_empty() {
+                    if self.outbound_streams == 0 && !self.pending_messages.is_empty() {
                         debug!(
                             "Clearing {} pending messages due to repeated failures",
                             self.pending_messages.len()
                         );
-                        self.pending_messages.clear();
+                        for (_, id) in self.pending_messages.drain(..) {
+                            self.events.push_back(CoreLinkHandlerEvent::SendError(
+                                NO_STREAM.to_string(),
+                                id,
+                            ));
+                        }
                     }
                     self.can_request_outbound = false;
+                    if self.outbound_streams == 0 {
+                        self.report_usable(false);
+                    }
                 }
             }
             ConnectionEvent::ListenUpgradeError(err) => {
@@ -274,3 +462,64 @@ impl ConnectionHandler for CoreLinkHandler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use corelink_core::identity::NodeId;
+    use corelink_core::message::MessageType;
+
+    fn message(msg_type: MessageType) -> Message {
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
+        Message::new(NodeId::from_pubkey(&key), msg_type)
+    }
+
+    #[test]
+    fn test_keep_alive_follows_transfers_and_messages() {
+        let mut handler = CoreLinkHandler::new();
+        assert!(!handler.connection_keep_alive());
+
+        handler.on_behaviour_event(CoreLinkHandlerIn::KeepAlive(true));
+        assert!(handler.connection_keep_alive());
+        handler.on_behaviour_event(CoreLinkHandlerIn::KeepAlive(false));
+        assert!(!handler.connection_keep_alive());
+
+        // A message waiting for a stream keeps the connection too
+        let outgoing = (message(MessageType::Ping), 0);
+        handler.on_behaviour_event(CoreLinkHandlerIn::Send(Box::new(outgoing)));
+        assert!(handler.connection_keep_alive());
+    }
+
+    #[test]
+    fn test_stream_requested_before_any_message() {
+        let mut handler = CoreLinkHandler::new();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        assert!(matches!(
+            handler.poll(&mut cx),
+            Poll::Ready(ConnectionHandlerEvent::OutboundSubstreamRequest { .. })
+        ));
+        // Only one until it is negotiated or messages wait for more
+        assert!(handler.poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn test_batches_keep_registry_updates_apart_and_in_order() {
+        let mut handler = CoreLinkHandler::new();
+        let withdraw = |file_id: &str| MessageType::FileWithdraw {
+            file_id: file_id.to_string(),
+        };
+        for (id, msg_type) in [
+            (0, withdraw("a")),
+            (1, MessageType::Ping),
+            (2, withdraw("b")),
+            (3, MessageType::Ping),
+        ] {
+            let outgoing = (message(msg_type), id);
+            handler.on_behaviour_event(CoreLinkHandlerIn::Send(Box::new(outgoing)));
+        }
+        let ids = |batch: Vec<Outgoing>| batch.into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+        assert_eq!(ids(handler.take_batch(true)), [0, 2]);
+        assert_eq!(ids(handler.take_batch(false)), [1, 3]);
+        assert!(handler.pending_messages.is_empty());
+    }
+}
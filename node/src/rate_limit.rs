@@ -0,0 +1,240 @@
+use crate::api::same_token;
+use crate::api_error::ApiError;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Clients tracked before those with full buckets are forgotten
+const MAX_CLIENTS: usize = 1024;
+
+/// Requests each REST API client may make per minute, in bursts of up to a
+/// minute's worth. 0 lifts the limit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// GET requests
+    pub reads_per_minute: u32,
+    /// Requests that change something, e.g. POST and DELETE
+    pub writes_per_minute: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            reads_per_minute: 600,
+            writes_per_minute: 60,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    fn per_minute(&self, write: bool) -> u32 {
+        if write {
+            self.writes_per_minute
+        } else {
+            self.reads_per_minute
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets per client and kind of request
+#[derive(Default)]
+pub struct RateLimiter {
+    config: RwLock<RateLimitConfig>,
+    /// Bearer token that identifies a client on its own; others are told
+    /// apart by IP, so made-up tokens do not each get a fresh bucket
+    trusted_token: Option<String>,
+    buckets: Mutex<HashMap<(String, bool), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            trusted_token: None,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Key requests bearing `token` on the token instead of the client's IP
+    pub fn with_trusted_token(mut self, token: Option<String>) -> Self {
+        self.trusted_token = token.filter(|token| !token.is_empty());
+        self
+    }
+
+    /// Apply new limits to every client, keeping what they have used
+    pub fn set_config(&self, config: RateLimitConfig) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+    }
+
+    /// Take a token for a request by `client`, or tell how long until one
+    /// is available
+    pub fn check(&self, client: &str, write: bool) -> Result<(), Duration> {
+        self.check_at(client, write, Instant::now())
+    }
+
+    fn check_at(&self, client: &str, write: bool, now: Instant) -> Result<(), Duration> {
+        let config = self
+            .config
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let capacity = config.per_minute(write) as f64;
+        if capacity == 0.0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_CLIENTS {
+            buckets.retain(|(_, write), bucket| {
+                refilled(&config, bucket, *write, now) < config.per_minute(*write) as f64
+            });
+        }
+        let bucket = buckets
+            .entry((client.to_string(), write))
+            .or_insert(Bucket {
+                tokens: capacity,
+                updated: now,
+            });
+        bucket.tokens = refilled(&config, bucket, write, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) * 60.0 / capacity,
+            ))
+        }
+    }
+
+    /// Who a request comes from: the trusted token if it bears it, else its IP
+    fn client_of(&self, request: &Request) -> String {
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match (token, &self.trusted_token) {
+            (Some(given), Some(trusted)) if same_token(given, trusted) => "token".to_string(),
+            _ => request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Tokens in `bucket` at `now`, up to a minute's worth
+fn refilled(config: &RateLimitConfig, bucket: &Bucket, write: bool, now: Instant) -> f64 {
+    let per_minute = config.per_minute(write) as f64;
+    let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * per_minute / 60.0).min(per_minute)
+}
+
+/// Middleware answering 429 with `Retry-After` to clients over their limit
+pub async fn limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let write = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    match limiter.check(&limiter.client_of(&request), write) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+            let mut response = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "Too many requests")
+                .with_details(serde_json::json!({ "retry_after_secs": secs }))
+                .into_response();
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_per_client_and_kind() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            reads_per_minute: 2,
+            writes_per_minute: 0,
+        });
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("a", false, now), Ok(()));
+        assert_eq!(limiter.check_at("a", false, now), Ok(()));
+        let wait = limiter.check_at("a", false, now).unwrap_err();
+        assert_eq!(wait.as_secs_f64().round(), 30.0);
+        // Other clients and unlimited writes are unaffected
+        assert_eq!(limiter.check_at("b", false, now), Ok(()));
+        assert_eq!(limiter.check_at("a", true, now), Ok(()));
+
+        // One token every 30 seconds
+        let later = now + Duration::from_secs(31);
+        assert_eq!(limiter.check_at("a", false, later), Ok(()));
+        assert!(limiter.check_at("a", false, later).is_err());
+    }
+
+    #[test]
+    fn test_reloaded_limits_apply_at_once() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let now = Instant::now();
+        assert_eq!(limiter.check_at("a", true, now), Ok(()));
+
+        limiter.set_config(RateLimitConfig {
+            reads_per_minute: 600,
+            writes_per_minute: 1,
+        });
+        assert_eq!(limiter.check_at("a", true, now), Ok(()));
+        assert!(limiter.check_at("a", true, now).is_err());
+    }
+
+    #[test]
+    fn test_only_the_trusted_token_gets_its_own_bucket() {
+        let limiter = RateLimiter::default().with_trusted_token(Some("secret".to_string()));
+        let request = |token: Option<&str>, ip: [u8; 4]| {
+            let mut builder = Request::builder();
+            if let Some(token) = token {
+                builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let mut request = builder.body(axum::body::Body::empty()).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from((ip, 1234))));
+            request
+        };
+
+        assert_eq!(limiter.client_of(&request(None, [10, 0, 0, 1])), "10.0.0.1");
+        // Made-up tokens do not escape the client's IP bucket
+        assert_eq!(
+            limiter.client_of(&request(Some("random"), [10, 0, 0, 1])),
+            "10.0.0.1"
+        );
+        assert_eq!(
+            limiter.client_of(&request(Some("secret"), [10, 0, 0, 2])),
+            "token"
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use libp2p_identity::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use utoipa::ToSchema;
+
+/// Replication status of a single file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicationStatus {
+    Healthy,
+    Replicating,
+    UnderReplicated,
+}
+
+/// Replication health report for a single file
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReplicationHealth {
+    pub file_id: String,
+    pub target: usize,
+    pub holders: usize,
+    pub pending: usize,
+    pub status: ReplicationStatus,
+}
+
+/// Tracks which peers hold copies of our files and which peers should receive more
+#[derive(Default)]
+pub struct ReplicationManager {
+    targets: HashMap<String, usize>,
+    holders: HashMap<String, HashSet<PeerId>>,
+    pending: HashMap<String, HashSet<PeerId>>,
+}
+
+impl ReplicationManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the desired number of copies for a file. A target of 0 disables replication.
+    pub fn set_target(&mut self, file_id: &str, target: usize) {
+        if target == 0 {
+            self.targets.remove(file_id);
+        } else {
+            self.targets.insert(file_id.to_string(), target);
+        }
+    }
+
+    /// Record that a peer has been offered a file and is expected to fetch it
+    pub fn mark_pending(&mut self, file_id: &str, peer: PeerId) {
+        if !self.holds(file_id, &peer) {
+            self.pending
+                .entry(file_id.to_string())
+                .or_default()
+                .insert(peer);
+        }
+    }
+
+    /// Record that a peer now holds a complete copy of a file
+    pub fn record_holder(&mut self, file_id: &str, peer: PeerId) {
+        if let Some(pending) = self.pending.get_mut(file_id) {
+            pending.remove(&peer);
+        }
+        self.holders
+            .entry(file_id.to_string())
+            .or_default()
+            .insert(peer);
+    }
+
+    /// Record that a peer no longer holds a file (e.g. it failed a storage challenge)
+    pub fn remove_holder(&mut self, file_id: &str, peer: &PeerId) {
+        if let Some(holders) = self.holders.get_mut(file_id) {
+            holders.remove(peer);
+        }
+    }
+
+    /// Forget an outstanding replication attempt (peer failed or disconnected)
+    pub fn clear_pending(&mut self, file_id: &str, peer: &PeerId) {
+        if let Some(pending) = self.pending.get_mut(file_id) {
+            pending.remove(peer);
+        }
+    }
+
+    /// Forget all outstanding replication attempts with a peer
+    pub fn peer_disconnected(&mut self, peer: &PeerId) {
+        for pending in self.pending.values_mut() {
+            pending.remove(peer);
+        }
+    }
+
+    /// Stop tracking a file entirely
+    pub fn remove_file(&mut self, file_id: &str) {
+        self.targets.remove(file_id);
+        self.holders.remove(file_id);
+        self.pending.remove(file_id);
+    }
+
+    /// Choose peers to offer under-replicated files to, marking them pending
+    pub fn plan_offers(&mut self, candidates: &[PeerId]) -> Vec<(String, PeerId)> {
+        let mut offers = Vec::new();
+
+        for (file_id, &target) in &self.targets {
+            let holders = self.holders.get(file_id);
+            let pending = self.pending.entry(file_id.clone()).or_default();
+            let have = holders.map_or(0, |h| h.len()) + pending.len();
+
+            for peer in candidates
+                .iter()
+                .filter(|peer| !holders.is_some_and(|h| h.contains(peer)))
+                .filter(|peer| !pending.contains(peer))
+                .take(target.saturating_sub(have))
+                .copied()
+                .collect::<Vec<_>>()
+            {
+                pending.insert(peer);
+                offers.push((file_id.clone(), peer));
+            }
+        }
+
+        offers
+    }
+
+    /// Health report for every file with a replication target
+    pub fn health(&self) -> Vec<ReplicationHealth> {
+        let mut report: Vec<ReplicationHealth> = self
+            .targets
+            .iter()
+            .map(|(file_id, &target)| {
+                let holders = self.holders.get(file_id).map_or(0, |h| h.len());
+                let pending = self.pending.get(file_id).map_or(0, |p| p.len());
+                let status = if holders >= target {
+                    ReplicationStatus::Healthy
+                } else if holders + pending >= target {
+                    ReplicationStatus::Replicating
+                } else {
+                    ReplicationStatus::UnderReplicated
+                };
+
+                ReplicationHealth {
+                    file_id: file_id.clone(),
+                    target,
+                    holders,
+                    pending,
+                    status,
+                }
+            })
+            .collect();
+
+        report.sort_by(|a, b| a.file_id.cmp(&b.file_id));
+        report
+    }
+
+    fn holds(&self, file_id: &str, peer: &PeerId) -> bool {
+        self.holders
+            .get(file_id)
+            .is_some_and(|holders| holders.contains(peer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_offers_fills_deficit() {
+        let mut manager = ReplicationManager::new();
+        let peers: Vec<PeerId> = (0..4).map(|_| PeerId::random()).collect();
+
+        manager.set_target("file", 2);
+        manager.record_holder("file", peers[0]);
+
+        // One more copy needed, and the existing holder is never chosen
+        let offers = manager.plan_offers(&peers);
+        assert_eq!(offers.len(), 1);
+        assert_ne!(offers[0].1, peers[0]);
+        assert_eq!(manager.health()[0].status, ReplicationStatus::Replicating);
+
+        // Pending peers count toward the target, so nothing more is planned
+        assert!(manager.plan_offers(&peers).is_empty());
+
+        manager.record_holder("file", offers[0].1);
+        let health = manager.health();
+        assert_eq!(health[0].holders, 2);
+        assert_eq!(health[0].pending, 0);
+        assert_eq!(health[0].status, ReplicationStatus::Healthy);
+    }
+
+    #[test]
+    fn test_failed_replica_is_retried_elsewhere() {
+        let mut manager = ReplicationManager::new();
+        let peers: Vec<PeerId> = (0..2).map(|_| PeerId::random()).collect();
+
+        manager.set_target("file", 1);
+        let offers = manager.plan_offers(&peers[..1]);
+        assert_eq!(offers, vec![("file".to_string(), peers[0])]);
+
+        manager.peer_disconnected(&peers[0]);
+        assert_eq!(
+            manager.health()[0].status,
+            ReplicationStatus::UnderReplicated
+        );
+
+        let offers = manager.plan_offers(&peers[1..]);
+        assert_eq!(offers, vec![("file".to_string(), peers[1])]);
+    }
+}
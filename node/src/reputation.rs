@@ -0,0 +1,45 @@
+use libp2p_identity::PeerId;
+use std::collections::HashMap;
+
+/// Lowest and highest possible reputation scores
+pub const MIN_SCORE: i32 = -100;
+pub const MAX_SCORE: i32 = 100;
+
+/// Tracks how trustworthy each peer has proven to be. New peers start at 0.
+#[derive(Default)]
+pub struct ReputationTracker {
+    scores: HashMap<PeerId, i32>,
+}
+
+impl ReputationTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adjust a peer's score by `delta`, clamped to [MIN_SCORE, MAX_SCORE]. Returns the new score.
+    pub fn adjust(&mut self, peer: &PeerId, delta: i32) -> i32 {
+        let score = self.scores.entry(*peer).or_insert(0);
+        *score = score.saturating_add(delta).clamp(MIN_SCORE, MAX_SCORE);
+        *score
+    }
+
+    pub fn score(&self, peer: &PeerId) -> i32 {
+        self.scores.get(peer).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores_are_clamped() {
+        let mut tracker = ReputationTracker::new();
+        let peer = PeerId::random();
+
+        assert_eq!(tracker.score(&peer), 0);
+        assert_eq!(tracker.adjust(&peer, 5), 5);
+        assert_eq!(tracker.adjust(&peer, -500), MIN_SCORE);
+        assert_eq!(tracker.adjust(&peer, 1000), MAX_SCORE);
+    }
+}
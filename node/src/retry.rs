@@ -0,0 +1,247 @@
+use corelink_core::message::Message;
+use libp2p_identity::PeerId;
+use libp2p_swarm::ConnectionId;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How often and how patiently failed sends are tried again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts at sending a message, the first included; 1 never retries
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for each one after
+    pub initial_backoff_ms: u64,
+    /// Longest wait between attempts
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 200,
+            max_backoff_ms: 10_000,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait after the `attempts`th failed attempt: the exponential backoff,
+    /// less up to half of it at random so retries to many peers spread out
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let doubled = self
+            .initial_backoff_ms
+            .saturating_mul(1 << attempts.saturating_sub(1).min(31));
+        let backoff = doubled.min(self.max_backoff_ms);
+        let jitter = rand::thread_rng().gen_range(0..=backoff / 2);
+        Duration::from_millis(backoff - jitter)
+    }
+}
+
+/// A message handed to the outbound queues and not yet written
+#[derive(Debug, Clone)]
+pub struct Unacked {
+    pub peer: PeerId,
+    pub message: Message,
+    /// Receipt the application is told the outcome under, if any
+    pub receipt: Option<u64>,
+    /// Attempts made so far, the one in progress included
+    pub attempts: u32,
+    /// Connection writing it, unless it waits in a queue or to be retried
+    pub connection: Option<ConnectionId>,
+    /// When the message is tried again after failing
+    pub retry_at: Option<Instant>,
+}
+
+/// What happens to a message whose send failed
+#[derive(Debug)]
+pub enum Retry {
+    /// Tried again after this long
+    After(Duration),
+    /// Out of attempts
+    GaveUp(Box<Unacked>),
+}
+
+/// Messages awaiting acknowledgment that they were written, by the id the
+/// connection handler reports their outcome under
+pub struct PendingSends {
+    policy: RetryPolicy,
+    sends: HashMap<u64, Unacked>,
+    next_id: u64,
+}
+
+impl PendingSends {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            sends: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Change the policy. Messages waiting to be retried keep their times.
+    pub fn set_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    /// Start tracking a message for `peer`, returning its id
+    pub fn track(&mut self, peer: PeerId, message: Message, receipt: Option<u64>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sends.insert(
+            id,
+            Unacked {
+                peer,
+                message,
+                receipt,
+                attempts: 1,
+                connection: None,
+                retry_at: None,
+            },
+        );
+        id
+    }
+
+    /// The message was handed to `connection` to write
+    pub fn handed_over(&mut self, id: u64, connection: ConnectionId) {
+        if let Some(send) = self.sends.get_mut(&id) {
+            send.connection = Some(connection);
+        }
+    }
+
+    /// The message was written, or will not be sent; stop tracking it
+    pub fn remove(&mut self, id: u64) -> Option<Unacked> {
+        self.sends.remove(&id)
+    }
+
+    /// Sending the message failed at `now`: schedule another attempt, or
+    /// give up if it had its last. None for messages not tracked.
+    pub fn failed(&mut self, id: u64, now: Instant) -> Option<Retry> {
+        let send = self.sends.get_mut(&id)?;
+        if send.attempts >= self.policy.max_attempts {
+            return self
+                .sends
+                .remove(&id)
+                .map(|send| Retry::GaveUp(Box::new(send)));
+        }
+        let backoff = self.policy.backoff(send.attempts);
+        send.connection = None;
+        send.retry_at = Some(now + backoff);
+        Some(Retry::After(backoff))
+    }
+
+    /// Messages to try again by `now`, with their ids, counted as another
+    /// attempt each
+    pub fn due(&mut self, now: Instant) -> Vec<(u64, PeerId, Message)> {
+        let mut due: Vec<(u64, PeerId, Message)> = self
+            .sends
+            .iter_mut()
+            .filter(|(_, send)| send.retry_at.is_some_and(|at| at <= now))
+            .map(|(&id, send)| {
+                send.retry_at = None;
+                send.attempts += 1;
+                (id, send.peer, send.message.clone())
+            })
+            .collect();
+        // Retries to a peer go out in the order the messages were first sent
+        due.sort_by_key(|(id, _, _)| *id);
+        due
+    }
+
+    /// When the next retry is due
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.sends.values().filter_map(|send| send.retry_at).min()
+    }
+
+    /// Peers with messages waiting, being written or to be retried
+    pub fn peers(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.sends.values().map(|send| send.peer)
+    }
+
+    /// Ids of the messages `connection` was writing, oldest first
+    pub fn written_on(&self, connection: ConnectionId) -> Vec<u64> {
+        self.ids(|send| send.connection == Some(connection))
+    }
+
+    /// Ids of every message for `peer`, oldest first
+    pub fn to_peer(&self, peer: &PeerId) -> Vec<u64> {
+        self.ids(|send| send.peer == *peer)
+    }
+
+    fn ids(&self, filter: impl Fn(&Unacked) -> bool) -> Vec<u64> {
+        let mut ids: Vec<u64> = self
+            .sends
+            .iter()
+            .filter(|(_, send)| filter(send))
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use corelink_core::identity::NodeId;
+    use corelink_core::message::MessageType;
+
+    fn message() -> Message {
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&[0u8; 32]).unwrap();
+        Message::new(NodeId::from_pubkey(&key), MessageType::Ping)
+    }
+
+    #[test]
+    fn test_backoff_doubles_with_jitter_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+        };
+        for (attempts, full) in [
+            (1, 100),
+            (2, 200),
+            (3, 400),
+            (4, 800),
+            (5, 1000),
+            (40, 1000),
+        ] {
+            let backoff = policy.backoff(attempts).as_millis() as u64;
+            assert!(backoff <= full && backoff >= full / 2, "{}", backoff);
+        }
+    }
+
+    #[test]
+    fn test_retries_until_out_of_attempts() {
+        let mut sends = PendingSends::new(RetryPolicy {
+            max_attempts: 2,
+            ..RetryPolicy::default()
+        });
+        let peer = PeerId::random();
+        let id = sends.track(peer, message(), Some(7));
+        let connection = ConnectionId::new_unchecked(0);
+        sends.handed_over(id, connection);
+        assert_eq!(sends.written_on(connection), [id]);
+
+        let now = Instant::now();
+        let Some(Retry::After(backoff)) = sends.failed(id, now) else {
+            panic!("Expected a retry");
+        };
+        assert!(sends.written_on(connection).is_empty());
+        assert_eq!(sends.to_peer(&peer), [id]);
+        assert!(sends.due(now).is_empty());
+        assert_eq!(sends.next_retry(), Some(now + backoff));
+        assert_eq!(sends.due(now + backoff).len(), 1);
+        assert_eq!(sends.next_retry(), None);
+
+        let Some(Retry::GaveUp(send)) = sends.failed(id, now) else {
+            panic!("Expected to give up");
+        };
+        assert_eq!((send.attempts, send.receipt), (2, Some(7)));
+        assert!(sends.to_peer(&peer).is_empty());
+        assert!(sends.failed(id, now).is_none());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::messaging_behaviour::{COMPUTE_CAPABILITY, RELAY_CAPABILITY, STORAGE_CAPABILITY};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// What a node does for the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeRole {
+    /// Downloads offered files and keeps replicas for other peers
+    #[default]
+    Storage,
+    /// Relays connections between peers; never downloads or stores files
+    RelayOnly,
+    /// Downloads files for local use but keeps no replicas for others
+    Edge,
+}
+
+impl NodeRole {
+    /// Capabilities advertised to peers in discovery
+    pub fn capabilities(self) -> Vec<String> {
+        let capabilities: &[&str] = match self {
+            NodeRole::Storage => &[STORAGE_CAPABILITY, COMPUTE_CAPABILITY],
+            NodeRole::RelayOnly => &[RELAY_CAPABILITY],
+            NodeRole::Edge => &[COMPUTE_CAPABILITY],
+        };
+        capabilities.iter().map(|c| c.to_string()).collect()
+    }
+
+    pub fn accepts_downloads(self) -> bool {
+        self != NodeRole::RelayOnly
+    }
+
+    pub fn is_relay(self) -> bool {
+        self == NodeRole::RelayOnly
+    }
+}
+
+impl fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            NodeRole::Storage => "storage",
+            NodeRole::RelayOnly => "relay-only",
+            NodeRole::Edge => "edge",
+        })
+    }
+}
+
+impl FromStr for NodeRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "storage" => Ok(NodeRole::Storage),
+            "relay-only" => Ok(NodeRole::RelayOnly),
+            "edge" => Ok(NodeRole::Edge),
+            _ => Err(format!("unknown role: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles_round_trip() {
+        for role in [NodeRole::Storage, NodeRole::RelayOnly, NodeRole::Edge] {
+            assert_eq!(role.to_string().parse(), Ok(role));
+        }
+        assert!(!NodeRole::RelayOnly.accepts_downloads());
+        assert!(!NodeRole::Edge
+            .capabilities()
+            .contains(&STORAGE_CAPABILITY.to_string()));
+    }
+}
@@ -0,0 +1,120 @@
+//! Status notifications for service managers, using systemd's `sd_notify`
+//! protocol. Everything here does nothing unless the node was started by a
+//! manager that set `NOTIFY_SOCKET`.
+
+use std::io;
+use std::time::Duration;
+
+/// The node is serving: the swarm, REST API and WebSocket server all listen
+pub fn notify_ready(status: &str) {
+    notify(&format!("READY=1\nSTATUS={}", status));
+}
+
+/// The node has begun shutting down
+pub fn notify_stopping() {
+    notify("STOPPING=1");
+}
+
+/// The node is stopping to start again in place
+pub fn notify_restarting() {
+    notify("RELOADING=1\nSTATUS=Restarting");
+}
+
+/// The event loop is still making progress
+pub fn notify_watchdog() {
+    notify("WATCHDOG=1");
+}
+
+/// How often to ping the watchdog, if the manager wants pings: half the
+/// timeout it set in `WATCHDOG_USEC`
+pub fn watchdog_interval() -> Option<Duration> {
+    watchdog_interval_from(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+fn watchdog_interval_from(usec: Option<&str>, pid: Option<&str>, our_pid: u32) -> Option<Duration> {
+    // The watchdog may be meant for another process, e.g. a wrapper script
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok()? != our_pid {
+            return None;
+        }
+    }
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = send(&socket, state) {
+        tracing::warn!("Failed to notify service manager: {}", e);
+    }
+}
+
+#[cfg(unix)]
+fn send(socket: &std::ffi::OsStr, state: &str) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    // A leading '@' names a socket in Linux's abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.as_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        datagram.send_to_addr(state.as_bytes(), &addr)?;
+        return Ok(());
+    }
+    datagram.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send(_socket: &std::ffi::OsStr, _state: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Service notifications need Unix sockets",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(
+            watchdog_interval_from(Some("10000000"), None, 42),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            watchdog_interval_from(Some("10000000"), Some("42"), 42),
+            Some(Duration::from_secs(5))
+        );
+        // Meant for another process
+        assert_eq!(
+            watchdog_interval_from(Some("10000000"), Some("7"), 42),
+            None
+        );
+        assert_eq!(watchdog_interval_from(None, None, 42), None);
+        assert_eq!(watchdog_interval_from(Some("0"), None, 42), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_send_reaches_socket() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("notify.sock");
+        let listener = std::os::unix::net::UnixDatagram::bind(&path)?;
+
+        send(path.as_os_str(), "READY=1")?;
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf)?;
+        assert_eq!(&buf[..len], b"READY=1");
+        Ok(())
+    }
+}
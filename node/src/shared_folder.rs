@@ -0,0 +1,277 @@
+use corelink_core::Storage;
+use glob::Pattern;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Bucket mapping shared file names to what was offered for them
+const SHARED_BUCKET: &str = "shared";
+
+/// How long a file must go without changes before it is offered, so that
+/// files still being copied in are not offered half-written
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Names never offered unless the config says otherwise: hidden files and
+/// common temporary/partial download files
+pub const DEFAULT_IGNORE: &[&str] = &[".*", "*.tmp", "*.part", "*.swp", "*~"];
+
+/// What was offered for a shared file, used to tell real changes from
+/// spurious events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct SharedEntry {
+    file_id: String,
+    size: u64,
+    modified: u64,
+}
+
+/// Action the node should take for a file in the shared folder
+#[derive(Debug, Clone, PartialEq)]
+pub enum SharedChange {
+    /// Offer a new or changed file, withdrawing the offer it replaces
+    Offer {
+        path: PathBuf,
+        replaces: Option<String>,
+    },
+    /// The file was deleted; withdraw its offer
+    Withdraw { path: PathBuf, file_id: String },
+}
+
+/// Directory whose files are automatically offered to the network.
+///
+/// Only files directly inside the folder are shared. Changes are collected
+/// with [`SharedFolder::touch`] and turned into offers once they settle.
+pub struct SharedFolder {
+    root: PathBuf,
+    ignore: Vec<Pattern>,
+    store: Storage,
+    pending: HashMap<String, Instant>,
+}
+
+impl SharedFolder {
+    pub fn new(root: PathBuf, ignore: &[String], store: Storage) -> io::Result<Self> {
+        fs::create_dir_all(&root)?;
+        // Watch events carry absolute paths
+        let root = fs::canonicalize(&root)?;
+        let ignore = ignore
+            .iter()
+            .map(|pattern| Pattern::new(pattern))
+            .collect::<Result<_, _>>()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let store = store.open_bucket(SHARED_BUCKET).map_err(io::Error::other)?;
+
+        Ok(Self {
+            root,
+            ignore,
+            store,
+            pending: HashMap::new(),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Start watching the folder. Changed paths arrive on the returned channel
+    /// for as long as the watcher is kept alive.
+    pub fn watch(&self) -> io::Result<(RecommendedWatcher, mpsc::UnboundedReceiver<PathBuf>)> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    for path in event.paths {
+                        let _ = tx.send(path);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Shared folder watch error: {}", e),
+            })
+            .map_err(io::Error::other)?;
+        watcher
+            .watch(&self.root, RecursiveMode::NonRecursive)
+            .map_err(io::Error::other)?;
+
+        Ok((watcher, rx))
+    }
+
+    /// Queue every file in the folder, and every file shared before, for a
+    /// check. Catches changes made while the node was not running.
+    pub fn scan(&mut self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.root)? {
+            self.touch(&entry?.path());
+        }
+        for (name, _) in self.store.iter().map_err(io::Error::other)? {
+            self.pending.insert(name, Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Note that `path` may have changed
+    pub fn touch(&mut self, path: &Path) {
+        let parent = path
+            .parent()
+            .and_then(|parent| fs::canonicalize(parent).ok());
+        if parent.as_deref() != Some(self.root.as_path()) {
+            return;
+        }
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return;
+        };
+        if self.is_ignored(name) {
+            return;
+        }
+        self.pending.insert(name.to_string(), Instant::now());
+    }
+
+    fn is_ignored(&self, name: &str) -> bool {
+        self.ignore.iter().any(|pattern| pattern.matches(name))
+    }
+
+    /// Changes to act on for files that have not been touched for a while
+    pub fn settled(&mut self) -> Vec<SharedChange> {
+        self.settled_after(SETTLE_TIME)
+    }
+
+    fn settled_after(&mut self, settle_time: Duration) -> Vec<SharedChange> {
+        let ready: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, touched)| touched.elapsed() >= settle_time)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        let mut changes = Vec::new();
+        for name in ready {
+            self.pending.remove(&name);
+            let path = self.root.join(&name);
+            let previous = match self.store.get_json::<SharedEntry>(&name) {
+                Ok(previous) => previous,
+                Err(e) => {
+                    warn!("Failed to read shared file state for {}: {}", name, e);
+                    None
+                }
+            };
+
+            match (file_stamp(&path), previous) {
+                (Some((size, modified)), Some(previous))
+                    if previous.size == size && previous.modified == modified => {}
+                (Some(_), previous) => changes.push(SharedChange::Offer {
+                    path,
+                    replaces: previous.map(|entry| entry.file_id),
+                }),
+                (None, Some(previous)) => changes.push(SharedChange::Withdraw {
+                    path,
+                    file_id: previous.file_id,
+                }),
+                (None, None) => {}
+            }
+        }
+        changes
+    }
+
+    /// Remember that `path` is now offered as `file_id`
+    pub fn record_offer(&self, path: &Path, file_id: &str) {
+        let (Some(name), Some((size, modified))) = (file_name(path), file_stamp(path)) else {
+            return;
+        };
+        let entry = SharedEntry {
+            file_id: file_id.to_string(),
+            size,
+            modified,
+        };
+        if let Err(e) = self.store.insert_json(name, &entry) {
+            warn!("Failed to persist shared file {}: {}", path.display(), e);
+        }
+    }
+
+    /// Forget a file whose offer was withdrawn
+    pub fn forget(&self, path: &Path) {
+        let Some(name) = file_name(path) else {
+            return;
+        };
+        if let Err(e) = self.store.remove(&name) {
+            warn!("Failed to forget shared file {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn file_name(path: &Path) -> Option<String> {
+    path.file_name()?.to_str().map(str::to_string)
+}
+
+/// Size and modification time (millis) of a regular file
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_millis() as u64;
+    Some((metadata.len(), modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_shared_folder_changes() -> io::Result<()> {
+        let dir = tempdir()?;
+        let root = fs::canonicalize(dir.path())?.join("shared");
+        let ignore: Vec<String> = DEFAULT_IGNORE.iter().map(|s| s.to_string()).collect();
+        let mut shared = SharedFolder::new(root.clone(), &ignore, Storage::new())?;
+
+        let file = root.join("report.txt");
+        fs::write(&file, b"v1")?;
+        fs::write(root.join("download.part"), b"partial")?;
+        shared.scan()?;
+
+        // Only the file that is not ignored is offered
+        let changes = shared.settled_after(Duration::ZERO);
+        assert_eq!(
+            changes,
+            vec![SharedChange::Offer {
+                path: file.clone(),
+                replaces: None
+            }]
+        );
+        shared.record_offer(&file, "first");
+
+        // Touching an unchanged file does nothing
+        shared.touch(&file);
+        assert!(shared.settled_after(Duration::ZERO).is_empty());
+
+        // A changed file replaces the old offer
+        fs::write(&file, b"version 2")?;
+        shared.touch(&file);
+        assert_eq!(
+            shared.settled_after(Duration::ZERO),
+            vec![SharedChange::Offer {
+                path: file.clone(),
+                replaces: Some("first".to_string())
+            }]
+        );
+        shared.record_offer(&file, "second");
+
+        // Deleting it withdraws the offer
+        fs::remove_file(&file)?;
+        shared.touch(&file);
+        assert!(shared.settled_after(Duration::from_secs(60)).is_empty());
+        assert_eq!(
+            shared.settled_after(Duration::ZERO),
+            vec![SharedChange::Withdraw {
+                path: file.clone(),
+                file_id: "second".to_string()
+            }]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+use std::future::Future;
+use std::time::Duration;
+use tokio::task::{AbortHandle, JoinHandle};
+use tracing::error;
+
+/// Wait before the first restart of a crashed task
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+/// Longest wait between restarts of a task that keeps crashing
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Run the task made by `start`, making a fresh one whenever it panics so a
+/// bug in one subsystem does not take down the node. Finishes when a run of
+/// the task returns normally; aborting it aborts the run in progress too.
+pub fn supervise<F, Fut>(name: &'static str, mut start: F) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let run = tokio::spawn(start());
+            let _abort = AbortOnDrop(run.abort_handle());
+            match run.await {
+                Ok(()) => return,
+                Err(e) if e.is_panic() => {
+                    error!("💥 {} task panicked, restarting in {:?}", name, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Err(_) => return,
+            }
+        }
+    })
+}
+
+/// Aborts a task when dropped, so it does not outlive the supervisor
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_restarts_after_panic() {
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        supervise("flaky", move || {
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("first run fails");
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_locked_state_survives_a_panic() {
+        let state = Arc::new(tokio::sync::Mutex::new(Vec::new()));
+        let shared = state.clone();
+        supervise("stateful", move || {
+            let shared = shared.clone();
+            async move {
+                let mut runs = shared.lock().await;
+                let run = runs.len();
+                runs.push(run);
+                if runs.len() == 1 {
+                    panic!("first run fails while holding the lock");
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(*state.lock().await, vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_abort_stops_the_running_task() {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<()>(1);
+        let supervisor = supervise("endless", move || {
+            let tx = tx.clone();
+            async move {
+                let _tx = tx;
+                std::future::pending::<()>().await;
+            }
+        });
+        tokio::task::yield_now().await;
+
+        supervisor.abort();
+        // The run held the last sender; it is gone once the run is aborted
+        assert!(rx.recv().await.is_none());
+    }
+}
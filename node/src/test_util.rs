@@ -0,0 +1,249 @@
+//! In-process networks of full nodes for integration tests. Each node gets
+//! its own temporary storage and a free port, and runs without mDNS so tests
+//! running side by side do not find each other.
+
+use crate::{NodeBuilder, NodeConfig, NodeEvent, NodeHandle};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::io;
+use std::net::{Ipv4Addr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::broadcast;
+use tokio::time;
+
+/// How long [`TestNode::wait_for`] waits before giving up
+pub const EVENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A running node with its own storage
+pub struct TestNode {
+    pub handle: NodeHandle,
+    /// Where peers can dial this node, ending in its `/p2p/` id
+    pub address: Multiaddr,
+    storage_path: PathBuf,
+    /// Subscribed from the start, so no event is missed
+    events: broadcast::Receiver<NodeEvent>,
+    _dir: TempDir,
+}
+
+impl TestNode {
+    pub async fn start() -> io::Result<Self> {
+        Self::start_with(|_| {}).await
+    }
+
+    /// Start a node after `configure` has adjusted its config
+    pub async fn start_with(configure: impl FnOnce(&mut NodeConfig)) -> io::Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let port = free_port()?;
+        let mut config = NodeConfig {
+            port,
+            storage_path: dir.path().join("storage"),
+            mdns: false,
+            ..NodeConfig::default()
+        };
+        configure(&mut config);
+
+        let handle = NodeBuilder::new(config.clone())
+            .without_servers()
+            .start()
+            .await?;
+        let address = Multiaddr::empty()
+            .with(Protocol::Ip4(Ipv4Addr::LOCALHOST))
+            .with(Protocol::Tcp(config.port))
+            .with(Protocol::P2p(handle.peer_id()));
+        Ok(Self {
+            events: handle.subscribe(),
+            handle,
+            address,
+            storage_path: config.storage_path,
+            _dir: dir,
+        })
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.handle.peer_id()
+    }
+
+    pub fn storage_path(&self) -> &Path {
+        &self.storage_path
+    }
+
+    /// Connect to `other`, returning once both sides see the connection
+    pub async fn connect(&self, other: &TestNode) -> io::Result<()> {
+        let mut other_events = other.handle.subscribe();
+        let peer = self.handle.dial(other.address.clone()).await?;
+        if peer != other.peer_id() {
+            return Err(io::Error::other(format!(
+                "Dialed {} but reached {}",
+                other.peer_id(),
+                peer
+            )));
+        }
+        let us = self.peer_id().to_string();
+        wait_for(&mut other_events, |event| match event {
+            NodeEvent::PeerConnected { peer_id, .. } if *peer_id == us => Some(()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Skip events until `matches` picks one out, for up to
+    /// [`EVENT_TIMEOUT`]
+    pub async fn wait_for<T>(
+        &mut self,
+        matches: impl FnMut(&NodeEvent) -> Option<T>,
+    ) -> io::Result<T> {
+        wait_for(&mut self.events, matches).await
+    }
+
+    pub async fn shutdown(self) {
+        self.handle.shutdown().await;
+    }
+}
+
+/// Several test nodes, connected to each other
+pub struct TestNetwork {
+    pub nodes: Vec<TestNode>,
+}
+
+impl TestNetwork {
+    /// Start `count` nodes connected in a full mesh
+    pub async fn connected(count: usize) -> io::Result<Self> {
+        let mut nodes = Vec::with_capacity(count);
+        for _ in 0..count {
+            nodes.push(TestNode::start().await?);
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            for other in &nodes[i + 1..] {
+                node.connect(other).await?;
+            }
+        }
+        Ok(Self { nodes })
+    }
+
+    pub async fn shutdown(self) {
+        for node in self.nodes {
+            node.shutdown().await;
+        }
+    }
+}
+
+async fn wait_for<T>(
+    events: &mut broadcast::Receiver<NodeEvent>,
+    mut matches: impl FnMut(&NodeEvent) -> Option<T>,
+) -> io::Result<T> {
+    let wait = async {
+        loop {
+            match events.recv().await {
+                Ok(event) => {
+                    if let Some(found) = matches(&event) {
+                        return Ok(found);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Err(io::Error::other("Node stopped"));
+                }
+            }
+        }
+    };
+    time::timeout(EVENT_TIMEOUT, wait)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "No matching event"))?
+}
+
+/// A port nothing is listening on right now
+fn free_port() -> io::Result<u16> {
+    Ok(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::role::NodeRole;
+
+    fn write_file(dir: &Path, name: &str, contents: &[u8]) -> io::Result<PathBuf> {
+        let path = dir.join(name);
+        std::fs::write(&path, contents)?;
+        Ok(path)
+    }
+
+    #[tokio::test]
+    async fn test_file_transfers_between_nodes() -> io::Result<()> {
+        let mut network = TestNetwork::connected(2).await?;
+        let dir = tempfile::tempdir()?;
+        let path = write_file(dir.path(), "report.txt", b"quarterly numbers")?;
+
+        let offered = network.nodes[0].handle.offer_file(&path).await?;
+        let receiver = &mut network.nodes[1];
+        let (name, size, saved_to) = receiver
+            .wait_for(|event| match event {
+                NodeEvent::TransferComplete {
+                    file_id,
+                    name,
+                    size,
+                    path,
+                } if *file_id == offered.file_id => Some((name.clone(), *size, path.clone())),
+                _ => None,
+            })
+            .await?;
+        assert_eq!((name.as_str(), size), ("report.txt", 17));
+
+        let saved_to = saved_to.expect("download saved to disk");
+        assert_eq!(
+            saved_to,
+            receiver.storage_path().join("complete/report.txt")
+        );
+        assert_eq!(std::fs::read(saved_to)?, b"quarterly numbers");
+        network.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_offer_reaches_every_peer() -> io::Result<()> {
+        let mut network = TestNetwork::connected(3).await?;
+        let dir = tempfile::tempdir()?;
+        let path = write_file(dir.path(), "notes.txt", b"hello")?;
+
+        let offered = network.nodes[0].handle.offer_file(&path).await?;
+        let offerer = network.nodes[0].peer_id().to_string();
+        for node in &mut network.nodes[1..] {
+            let from = node
+                .wait_for(|event| match event {
+                    NodeEvent::FileOffered(file) if file.file_id == offered.file_id => {
+                        file.peer_id.clone()
+                    }
+                    _ => None,
+                })
+                .await?;
+            assert_eq!(from, offerer);
+        }
+        network.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_relay_only_node_refuses_downloads() -> io::Result<()> {
+        let offerer = TestNode::start().await?;
+        let mut relay = TestNode::start_with(|config| config.role = NodeRole::RelayOnly).await?;
+        relay.connect(&offerer).await?;
+        let dir = tempfile::tempdir()?;
+        let path = write_file(dir.path(), "big.bin", &[7; 1024])?;
+
+        let offered = offerer.handle.offer_file(&path).await?;
+        relay
+            .wait_for(|event| match event {
+                NodeEvent::FileOffered(file) if file.file_id == offered.file_id => Some(()),
+                _ => None,
+            })
+            .await?;
+        assert!(relay.handle.download(&offered.file_id).await.is_err());
+
+        relay.shutdown().await;
+        offerer.shutdown().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+//! What happened during a download beyond the chunks persisted for it:
+//! which peers delivered chunks, how fast they arrived and what went wrong.
+//! Likewise for uploads, which peers fetched chunks of our files and how
+//! fast. Kept in memory only, so it starts over when the node restarts.
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+
+/// How many errors each download remembers
+pub const MAX_TRANSFER_ERRORS: usize = 20;
+
+/// How far back the current speed of a transfer looks
+pub const SPEED_WINDOW: Duration = Duration::from_secs(10);
+
+/// A running transfer that moved nothing for this long is stalled
+pub const STALL_AFTER: Duration = Duration::from_secs(30);
+
+/// Uploads nobody fetched a chunk of for this long are forgotten
+pub const UPLOAD_EXPIRY: Duration = Duration::from_secs(300);
+
+/// Whether a transfer is moving data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ActivityState {
+    Active,
+    /// Running, but nothing moved for [`STALL_AFTER`]
+    Stalled,
+    /// Not running, e.g. waiting for a free download slot
+    Paused,
+}
+
+/// Bytes moved within the last [`SPEED_WINDOW`]
+#[derive(Debug, Default)]
+struct RecentBytes {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RecentBytes {
+    fn record(&mut self, at: Instant, bytes: u64) {
+        self.samples.push_back((at, bytes));
+        while let Some((oldest, _)) = self.samples.front() {
+            if at.saturating_duration_since(*oldest) <= SPEED_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    fn speed_at(&self, now: Instant) -> f64 {
+        let bytes: u64 = self
+            .samples
+            .iter()
+            .filter(|(at, _)| now.saturating_duration_since(*at) <= SPEED_WINDOW)
+            .map(|(_, bytes)| bytes)
+            .sum();
+        bytes as f64 / SPEED_WINDOW.as_secs_f64()
+    }
+
+    /// When bytes last moved
+    fn last(&self) -> Option<Instant> {
+        self.samples.back().map(|(at, _)| *at)
+    }
+}
+
+/// Chunks one peer delivered for a download
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceActivity {
+    pub chunks_received: u32,
+    pub bytes_received: u64,
+}
+
+/// Something that went wrong during a download
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct TransferError {
+    /// Unix time of the error
+    pub timestamp: u64,
+    /// Peer involved, if any
+    pub peer_id: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct TransferActivity {
+    started: Instant,
+    finished: Option<Instant>,
+    /// Bytes received since `started`; chunks held from before do not count
+    bytes_received: u64,
+    recent: RecentBytes,
+    sources: HashMap<PeerId, SourceActivity>,
+    errors: VecDeque<TransferError>,
+}
+
+impl Default for TransferActivity {
+    fn default() -> Self {
+        Self::started_at(Instant::now())
+    }
+}
+
+impl TransferActivity {
+    fn started_at(started: Instant) -> Self {
+        Self {
+            started,
+            finished: None,
+            bytes_received: 0,
+            recent: RecentBytes::default(),
+            sources: HashMap::new(),
+            errors: VecDeque::new(),
+        }
+    }
+
+    pub fn record_chunk(&mut self, peer: PeerId, bytes: u64) {
+        self.record_chunk_at(peer, bytes, Instant::now());
+    }
+
+    fn record_chunk_at(&mut self, peer: PeerId, bytes: u64, at: Instant) {
+        self.bytes_received += bytes;
+        self.recent.record(at, bytes);
+        let source = self.sources.entry(peer).or_default();
+        source.chunks_received += 1;
+        source.bytes_received += bytes;
+    }
+
+    /// Remember an error, forgetting the oldest beyond [`MAX_TRANSFER_ERRORS`]
+    pub fn record_error(&mut self, peer: Option<PeerId>, message: String) {
+        if self.errors.len() == MAX_TRANSFER_ERRORS {
+            self.errors.pop_front();
+        }
+        self.errors.push_back(TransferError {
+            timestamp: current_timestamp(),
+            peer_id: peer.map(|peer| peer.to_string()),
+            message,
+        });
+    }
+
+    /// Stop the clock, so the speed stays that of the whole download
+    pub fn finish(&mut self) {
+        self.finished.get_or_insert_with(Instant::now);
+    }
+
+    /// Average bytes per second since the download started
+    pub fn speed(&self) -> f64 {
+        self.speed_at(Instant::now())
+    }
+
+    fn speed_at(&self, now: Instant) -> f64 {
+        let elapsed = self
+            .finished
+            .unwrap_or(now)
+            .duration_since(self.started)
+            .as_secs_f64();
+        if elapsed > 0.0 {
+            self.bytes_received as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Seconds to fetch `remaining` bytes at the current speed, unknown
+    /// until some bytes arrived
+    pub fn eta(&self, remaining: u64) -> Option<u64> {
+        eta_at(remaining, self.speed())
+    }
+
+    /// Bytes per second over the last [`SPEED_WINDOW`]
+    pub fn recent_speed(&self) -> f64 {
+        self.recent.speed_at(Instant::now())
+    }
+
+    /// Seconds to fetch `remaining` bytes at the recent speed
+    pub fn recent_eta(&self, remaining: u64) -> Option<u64> {
+        eta_at(remaining, self.recent_speed())
+    }
+
+    /// Active until nothing arrived for [`STALL_AFTER`], counting from the
+    /// start if nothing arrived yet
+    pub fn state(&self) -> ActivityState {
+        self.state_at(Instant::now())
+    }
+
+    fn state_at(&self, now: Instant) -> ActivityState {
+        let last = self.recent.last().unwrap_or(self.started);
+        if now.saturating_duration_since(last) < STALL_AFTER {
+            ActivityState::Active
+        } else {
+            ActivityState::Stalled
+        }
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = (&PeerId, &SourceActivity)> {
+        self.sources.iter()
+    }
+
+    /// What `peer` delivered so far
+    pub fn source(&self, peer: &PeerId) -> Option<&SourceActivity> {
+        self.sources.get(peer)
+    }
+
+    /// Remembered errors, oldest first
+    pub fn errors(&self) -> impl Iterator<Item = &TransferError> {
+        self.errors.iter()
+    }
+}
+
+/// Chunks one peer fetched of a file we offer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PeerUpload {
+    pub chunks_sent: u32,
+    pub bytes_sent: u64,
+}
+
+/// Chunks of one file served to peers, until nobody fetched any for
+/// [`UPLOAD_EXPIRY`]
+#[derive(Debug, Default)]
+pub struct UploadActivity {
+    bytes_sent: u64,
+    recent: RecentBytes,
+    peers: HashMap<PeerId, PeerUpload>,
+}
+
+impl UploadActivity {
+    pub fn record_chunk(&mut self, peer: PeerId, bytes: u64) {
+        self.record_chunk_at(peer, bytes, Instant::now());
+    }
+
+    fn record_chunk_at(&mut self, peer: PeerId, bytes: u64, at: Instant) {
+        self.bytes_sent += bytes;
+        self.recent.record(at, bytes);
+        let upload = self.peers.entry(peer).or_default();
+        upload.chunks_sent += 1;
+        upload.bytes_sent += bytes;
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Bytes per second over the last [`SPEED_WINDOW`]
+    pub fn speed(&self) -> f64 {
+        self.recent.speed_at(Instant::now())
+    }
+
+    pub fn state(&self) -> ActivityState {
+        self.state_at(Instant::now())
+    }
+
+    fn state_at(&self, now: Instant) -> ActivityState {
+        match self.recent.last() {
+            Some(last) if now.saturating_duration_since(last) < STALL_AFTER => {
+                ActivityState::Active
+            }
+            _ => ActivityState::Stalled,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.recent
+            .last()
+            .is_none_or(|last| last.elapsed() >= UPLOAD_EXPIRY)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &PeerUpload)> {
+        self.peers.iter()
+    }
+}
+
+fn eta_at(remaining: u64, speed: f64) -> Option<u64> {
+    (speed > 0.0).then(|| (remaining as f64 / speed).ceil() as u64)
+}
+
+fn current_timestamp() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_speed_and_eta() {
+        let now = Instant::now();
+        let mut activity = TransferActivity::started_at(now - Duration::from_secs(10));
+        assert_eq!(activity.speed_at(now), 0.0);
+        assert_eq!(eta_at(1000, activity.speed_at(now)), None);
+
+        let (first, second) = (PeerId::random(), PeerId::random());
+        activity.record_chunk(first, 600);
+        activity.record_chunk(second, 300);
+        activity.record_chunk(first, 100);
+        assert_eq!(activity.speed_at(now), 100.0);
+        assert_eq!(eta_at(250, activity.speed_at(now)), Some(3));
+
+        let sources: HashMap<_, _> = activity.sources().collect();
+        assert_eq!(
+            sources[&first],
+            &SourceActivity {
+                chunks_received: 2,
+                bytes_received: 700,
+            }
+        );
+        assert_eq!(sources[&second].chunks_received, 1);
+    }
+
+    #[test]
+    fn test_recent_speed_and_stalls() {
+        let start = Instant::now();
+        let mut download = TransferActivity::started_at(start);
+        assert_eq!(download.state_at(start), ActivityState::Active);
+        assert_eq!(
+            download.state_at(start + STALL_AFTER),
+            ActivityState::Stalled
+        );
+
+        let peer = PeerId::random();
+        download.record_chunk_at(peer, 5000, start + Duration::from_secs(20));
+        download.record_chunk_at(peer, 1000, start + Duration::from_secs(35));
+        let now = start + Duration::from_secs(40);
+        // Only the chunk within the window counts
+        assert_eq!(download.recent.speed_at(now), 100.0);
+        assert_eq!(download.state_at(now), ActivityState::Active);
+
+        let mut upload = UploadActivity::default();
+        upload.record_chunk_at(peer, 2000, start);
+        upload.record_chunk_at(peer, 2000, start + Duration::from_secs(5));
+        assert_eq!(upload.bytes_sent(), 4000);
+        assert_eq!(upload.peers[&peer].chunks_sent, 2);
+        assert_eq!(
+            upload.recent.speed_at(start + Duration::from_secs(5)),
+            400.0
+        );
+        assert_eq!(
+            upload.state_at(start + Duration::from_secs(40)),
+            ActivityState::Stalled
+        );
+    }
+
+    #[test]
+    fn test_errors_are_capped() {
+        let mut activity = TransferActivity::default();
+        for i in 0..MAX_TRANSFER_ERRORS + 5 {
+            activity.record_error(None, format!("error {}", i));
+        }
+
+        let errors: Vec<&TransferError> = activity.errors().collect();
+        assert_eq!(errors.len(), MAX_TRANSFER_ERRORS);
+        assert_eq!(errors[0].message, "error 5");
+    }
+}
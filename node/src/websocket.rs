@@ -1,9 +1,88 @@
+use crate::api::{
+    ApiState, DownloadInfo, FileInfo, NodeStats, PeerInfo, WebSocketClient, WebSocketMetrics,
+};
+use crate::bridge::ErrorCategory;
+use crate::file_transfer::FileRemoval;
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::broadcast;
-use tokio_tungstenite::{accept_async, tungstenite::Message};
-use tracing::{error, info, warn};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, watch, Notify};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, Uri};
+use tokio_tungstenite::tungstenite::protocol::frame::{coding::CloseCode, CloseFrame};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{accept_hdr_async, WebSocketStream};
+use tracing::{debug, error, info, warn};
+
+/// Events kept for clients reconnecting with `?since_seq=`
+const EVENT_HISTORY: usize = 1000;
+
+/// How often clients are pinged. Those that send nothing back, not even a
+/// pong, until the next ping are dropped.
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+
+/// Clients that take longer than this to accept a message are dropped
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long clients get on shutdown to receive what is queued for them and
+/// answer the close frame, before their connections are cut
+const CLOSE_GRACE: Duration = Duration::from_secs(3);
+
+/// Events queued for a client beyond which new progress events are dropped
+const CLIENT_QUEUE_CAPACITY: usize = 256;
+
+/// Events queued for a client beyond which it is disconnected, as it fell
+/// behind even on events that are never dropped
+const CLIENT_QUEUE_LIMIT: usize = 4 * CLIENT_QUEUE_CAPACITY;
+
+/// How messages are framed on a connection, chosen by the client with the
+/// `Sec-WebSocket-Protocol` header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// JSON in text frames, the default
+    #[default]
+    Json,
+    /// MessagePack in binary frames, with the same field names as JSON
+    MessagePack,
+}
+
+impl Encoding {
+    /// The subprotocol naming the encoding
+    pub fn protocol(self) -> &'static str {
+        match self {
+            Encoding::Json => "corelink.json",
+            Encoding::MessagePack => "corelink.msgpack",
+        }
+    }
+
+    /// The first of the comma separated subprotocols a client offers that
+    /// names an encoding
+    pub(crate) fn negotiate(offered: &str) -> Option<Self> {
+        offered.split(',').map(str::trim).find_map(|protocol| {
+            [Encoding::Json, Encoding::MessagePack]
+                .into_iter()
+                .find(|encoding| encoding.protocol() == protocol)
+        })
+    }
+
+    fn frame<T: Serialize>(self, value: &T) -> std::io::Result<Message> {
+        Ok(match self {
+            Encoding::Json => Message::Text(serde_json::to_string(value)?),
+            Encoding::MessagePack => {
+                Message::Binary(rmp_serde::to_vec_named(value).map_err(std::io::Error::other)?)
+            }
+        })
+    }
+}
 
 /// Events that are broadcast to WebSocket clients
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +98,14 @@ pub enum WsEvent {
     /// Peer disconnected from the network
     PeerDisconnected { peer_id: String, timestamp: u64 },
 
+    /// A peer runs a protocol version or lacks features needed to
+    /// understand some of the node's messages, which are not sent to it
+    IncompatiblePeer {
+        peer_id: String,
+        reason: String,
+        timestamp: u64,
+    },
+
     /// File offered by a peer
     FileOffered {
         peer_id: String,
@@ -26,6 +113,16 @@ pub enum WsEvent {
         name: String,
         size: u64,
         chunks: u32,
+        /// Waiting to be accepted or rejected, as the node runs in manual
+        /// offer mode, rather than downloading
+        pending: bool,
+        timestamp: u64,
+    },
+
+    /// A peer declined a file this node offered it
+    OfferRejected {
+        peer_id: String,
+        file_id: String,
         timestamp: u64,
     },
 
@@ -33,7 +130,27 @@ pub enum WsEvent {
     ChunkReceived {
         file_id: String,
         chunk_index: u32,
+        /// Peer that sent the chunk
+        peer_id: String,
+        /// Size of this chunk in bytes
+        chunk_size: u64,
+        /// Bytes of the file received so far
+        bytes_received: u64,
+        /// Size of the whole file
+        total_bytes: u64,
+        /// Chunks still to fetch
+        chunks_remaining: u32,
         progress: f32,
+        /// Download speed over the last few seconds
+        bytes_per_second: f64,
+        timestamp: u64,
+    },
+
+    /// Summary of a running download, sent about once a second while it
+    /// makes progress, for views that need no per-chunk detail
+    TransferProgress {
+        #[serde(flatten)]
+        download: DownloadInfo,
         timestamp: u64,
     },
 
@@ -42,6 +159,8 @@ pub enum WsEvent {
         file_id: String,
         name: String,
         size: u64,
+        /// Where the file was saved, unless it only lives in the block store
+        path: Option<String>,
         timestamp: u64,
     },
 
@@ -52,6 +171,46 @@ pub enum WsEvent {
         timestamp: u64,
     },
 
+    /// A download was cancelled, an offer withdrawn or a file deleted
+    FileRemoved {
+        file_id: String,
+        removal: FileRemoval,
+        timestamp: u64,
+    },
+
+    /// A peer sent an application message
+    MessageReceived {
+        peer_id: String,
+        msg_type: String,
+        payload: serde_json::Value,
+        timestamp: u64,
+    },
+
+    /// An application message sent through the API was written to its peer
+    MessageSent {
+        message_id: u64,
+        peer_id: String,
+        timestamp: u64,
+    },
+
+    /// An application message sent through the API will not be delivered
+    MessageFailed {
+        message_id: u64,
+        peer_id: String,
+        error: String,
+        timestamp: u64,
+    },
+
+    /// Many peers or anchors became unreachable at once
+    PartitionSuspected {
+        unreachable_anchors: Vec<String>,
+        connectivity: f64,
+        timestamp: u64,
+    },
+
+    /// Connectivity recovered after a suspected partition
+    PartitionHealed { connectivity: f64, timestamp: u64 },
+
     /// Node status update
     NodeStatus {
         peer_count: usize,
@@ -59,35 +218,610 @@ pub enum WsEvent {
         active_downloads: usize,
         timestamp: u64,
     },
+
+    /// Latency and bandwidth of a connected peer, sampled with every status
+    /// update
+    PeerMetrics {
+        peer_id: String,
+        rtt_ms: Option<f64>,
+        /// Bytes per second measured on recent transfers
+        throughput: Option<f64>,
+        bytes_sent: u64,
+        bytes_received: u64,
+        timestamp: u64,
+    },
+
+    /// A connection, protocol or authentication error involving a peer
+    Error {
+        category: ErrorCategory,
+        peer_id: Option<String>,
+        message: String,
+        timestamp: u64,
+    },
+
+    /// Free space on the storage volume fell below `min_free_bytes`
+    StorageLow {
+        free_bytes: u64,
+        min_free_bytes: u64,
+        timestamp: u64,
+    },
+
+    /// Free space is back above the threshold after a `StorageLow`
+    StorageRecovered { free_bytes: u64, timestamp: u64 },
+
+    /// Messages for a peer were dropped from its queue without being sent:
+    /// to make room, for want of it, or because the peer disconnected
+    MessagesDropped {
+        peer_id: String,
+        count: usize,
+        reason: String,
+        timestamp: u64,
+    },
+
+    /// The node adopted a newer consensus epoch seen on a peer's message
+    EpochChanged {
+        peer_id: String,
+        previous_epoch: u64,
+        epoch: u64,
+        timestamp: u64,
+    },
+
+    /// A registry update was rejected for carrying a stale epoch, e.g. from
+    /// a deposed leader
+    StaleEpochRejected {
+        peer_id: String,
+        msg_type: String,
+        epoch: u64,
+        current_epoch: u64,
+        timestamp: u64,
+    },
+
+    /// First message on every connection: the node's state as of event
+    /// `seq`. Events after `since_seq`, if the client gave one, and then new
+    /// events follow.
+    Snapshot {
+        seq: u64,
+        stats: NodeStats,
+        peers: Vec<PeerInfo>,
+        files: Vec<FileInfo>,
+        /// Some events after `since_seq` are no longer kept, or were from
+        /// before the node restarted, so could not be replayed
+        history_truncated: bool,
+        timestamp: u64,
+    },
+
+    /// Reply to a client's `subscribe` or `unsubscribe`: the filter now
+    /// applied to its events
+    Subscribed { filter: EventFilter, timestamp: u64 },
+
+    /// Reply to a client message the node could not understand
+    InvalidMessage { error: String, timestamp: u64 },
+
+    /// Reply to a client's `accept_offer` or `reject_offer`: whether it
+    /// went through, and why not
+    OfferAnswered {
+        file_id: String,
+        /// True for `accept_offer`, false for `reject_offer`
+        accepted: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        timestamp: u64,
+    },
+
+    /// The client read too slowly, so `count` progress events meant for it
+    /// were dropped
+    EventsDropped { count: u64, timestamp: u64 },
+}
+
+/// Names of the events clients can subscribe to, as in their `type` field
+pub const EVENT_TYPES: &[&str] = &[
+    "PeerConnected",
+    "PeerDisconnected",
+    "IncompatiblePeer",
+    "FileOffered",
+    "OfferRejected",
+    "ChunkReceived",
+    "TransferProgress",
+    "TransferComplete",
+    "TransferFailed",
+    "FileRemoved",
+    "MessageReceived",
+    "MessageSent",
+    "MessageFailed",
+    "MessagesDropped",
+    "PartitionSuspected",
+    "PartitionHealed",
+    "NodeStatus",
+    "PeerMetrics",
+    "Error",
+    "StorageLow",
+    "StorageRecovered",
+    "EpochChanged",
+    "StaleEpochRejected",
+];
+
+impl WsEvent {
+    /// The event's `type` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WsEvent::PeerConnected { .. } => "PeerConnected",
+            WsEvent::PeerDisconnected { .. } => "PeerDisconnected",
+            WsEvent::IncompatiblePeer { .. } => "IncompatiblePeer",
+            WsEvent::FileOffered { .. } => "FileOffered",
+            WsEvent::OfferRejected { .. } => "OfferRejected",
+            WsEvent::ChunkReceived { .. } => "ChunkReceived",
+            WsEvent::TransferProgress { .. } => "TransferProgress",
+            WsEvent::TransferComplete { .. } => "TransferComplete",
+            WsEvent::TransferFailed { .. } => "TransferFailed",
+            WsEvent::FileRemoved { .. } => "FileRemoved",
+            WsEvent::MessageReceived { .. } => "MessageReceived",
+            WsEvent::MessageSent { .. } => "MessageSent",
+            WsEvent::MessageFailed { .. } => "MessageFailed",
+            WsEvent::MessagesDropped { .. } => "MessagesDropped",
+            WsEvent::PartitionSuspected { .. } => "PartitionSuspected",
+            WsEvent::PartitionHealed { .. } => "PartitionHealed",
+            WsEvent::NodeStatus { .. } => "NodeStatus",
+            WsEvent::PeerMetrics { .. } => "PeerMetrics",
+            WsEvent::Error { .. } => "Error",
+            WsEvent::StorageLow { .. } => "StorageLow",
+            WsEvent::StorageRecovered { .. } => "StorageRecovered",
+            WsEvent::EpochChanged { .. } => "EpochChanged",
+            WsEvent::StaleEpochRejected { .. } => "StaleEpochRejected",
+            WsEvent::Snapshot { .. } => "Snapshot",
+            WsEvent::Subscribed { .. } => "Subscribed",
+            WsEvent::InvalidMessage { .. } => "InvalidMessage",
+            WsEvent::OfferAnswered { .. } => "OfferAnswered",
+            WsEvent::EventsDropped { .. } => "EventsDropped",
+        }
+    }
+
+    /// Whether the event only tells how far something got, so a newer one
+    /// of the same kind about the same file supersedes it
+    fn is_progress(&self) -> bool {
+        matches!(
+            self,
+            WsEvent::ChunkReceived { .. }
+                | WsEvent::TransferProgress { .. }
+                | WsEvent::NodeStatus { .. }
+                | WsEvent::PeerMetrics { .. }
+        )
+    }
+
+    /// Whether this progress event makes `older` obsolete
+    fn supersedes(&self, older: &WsEvent) -> bool {
+        match (self, older) {
+            (WsEvent::PeerMetrics { peer_id, .. }, WsEvent::PeerMetrics { peer_id: older, .. }) => {
+                peer_id == older
+            }
+            _ => self.kind() == older.kind() && self.file_id() == older.file_id(),
+        }
+    }
+
+    /// The file the event is about, if any
+    pub fn file_id(&self) -> Option<&str> {
+        match self {
+            WsEvent::FileOffered { file_id, .. }
+            | WsEvent::OfferRejected { file_id, .. }
+            | WsEvent::ChunkReceived { file_id, .. }
+            | WsEvent::TransferComplete { file_id, .. }
+            | WsEvent::TransferFailed { file_id, .. }
+            | WsEvent::FileRemoved { file_id, .. } => Some(file_id),
+            WsEvent::TransferProgress { download, .. } => Some(&download.transfer.file_id),
+            _ => None,
+        }
+    }
+
+    /// The peer the event is about, if any
+    pub fn peer_id(&self) -> Option<&str> {
+        match self {
+            WsEvent::PeerConnected { peer_id, .. }
+            | WsEvent::PeerDisconnected { peer_id, .. }
+            | WsEvent::IncompatiblePeer { peer_id, .. }
+            | WsEvent::FileOffered { peer_id, .. }
+            | WsEvent::OfferRejected { peer_id, .. }
+            | WsEvent::ChunkReceived { peer_id, .. }
+            | WsEvent::MessageReceived { peer_id, .. }
+            | WsEvent::MessageSent { peer_id, .. }
+            | WsEvent::MessageFailed { peer_id, .. }
+            | WsEvent::MessagesDropped { peer_id, .. }
+            | WsEvent::PeerMetrics { peer_id, .. }
+            | WsEvent::EpochChanged { peer_id, .. }
+            | WsEvent::StaleEpochRejected { peer_id, .. } => Some(peer_id),
+            WsEvent::Error { peer_id, .. } => peer_id.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// The channel the event is published on: `files/<file id>`, `peers`,
+    /// `node`, `consensus`, `errors` or `messages`. Replies to a client,
+    /// which are not published, are on `client`.
+    pub fn topic(&self) -> String {
+        if let Some(file_id) = self.file_id() {
+            return format!("files/{}", file_id);
+        }
+        match self {
+            WsEvent::PeerConnected { .. }
+            | WsEvent::PeerDisconnected { .. }
+            | WsEvent::IncompatiblePeer { .. }
+            | WsEvent::PeerMetrics { .. } => "peers",
+            WsEvent::PartitionSuspected { .. }
+            | WsEvent::PartitionHealed { .. }
+            | WsEvent::NodeStatus { .. }
+            | WsEvent::StorageLow { .. }
+            | WsEvent::StorageRecovered { .. } => "node",
+            WsEvent::EpochChanged { .. } | WsEvent::StaleEpochRejected { .. } => "consensus",
+            WsEvent::Error { .. } => "errors",
+            WsEvent::MessageReceived { .. }
+            | WsEvent::MessageSent { .. }
+            | WsEvent::MessageFailed { .. }
+            | WsEvent::MessagesDropped { .. } => "messages",
+            _ => "client",
+        }
+        .to_string()
+    }
+}
+
+/// First segments of the topics clients can subscribe to
+pub const TOPICS: &[&str] = &["files", "peers", "node", "consensus", "errors", "messages"];
+
+/// Whether `topic` is matched by `pattern`, whose `*` segments match any one
+/// segment and a final `**` any number of them, e.g. `files/*`
+fn topic_matches(pattern: &str, topic: &str) -> bool {
+    let mut topic = topic.split('/');
+    for segment in pattern.split('/') {
+        match (segment, topic.next()) {
+            ("**", _) => return true,
+            ("*", Some(_)) => {}
+            (segment, Some(part)) if segment == part => {}
+            _ => return false,
+        }
+    }
+    topic.next().is_none()
+}
+
+/// Which events a client receives. An event must be on one of `topics`, be
+/// of one of `events` and, if any ids are given, be about one of `file_ids`
+/// or `peer_ids`; empty lists match everything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EventFilter {
+    /// Topic patterns, e.g. `files/*` or `consensus`
+    pub topics: Vec<String>,
+    /// Event types, e.g. `ChunkReceived`
+    pub events: Vec<String>,
+    pub file_ids: Vec<String>,
+    pub peer_ids: Vec<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &WsEvent) -> bool {
+        if !self.topics.is_empty() {
+            let topic = event.topic();
+            if !self
+                .topics
+                .iter()
+                .any(|pattern| topic_matches(pattern, &topic))
+            {
+                return false;
+            }
+        }
+        if !self.events.is_empty() && !self.events.iter().any(|kind| kind == event.kind()) {
+            return false;
+        }
+        if self.file_ids.is_empty() && self.peer_ids.is_empty() {
+            return true;
+        }
+        event
+            .file_id()
+            .is_some_and(|id| self.file_ids.iter().any(|file_id| file_id == id))
+            || event
+                .peer_id()
+                .is_some_and(|id| self.peer_ids.iter().any(|peer_id| peer_id == id))
+    }
+}
+
+/// Messages clients send, e.g.
+/// `{"type": "subscribe", "events": ["ChunkReceived"], "file_ids": ["..."]}`,
+/// `{"type": "join", "topics": ["files/*"]}` or
+/// `{"type": "accept_offer", "file_id": "..."}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Replace the connection's filter
+    Subscribe(EventFilter),
+    /// Receive every event again
+    Unsubscribe,
+    /// Add topics to the filter
+    Join { topics: Vec<String> },
+    /// Remove topics from the filter; leaving the last one means receiving
+    /// every topic again
+    Leave { topics: Vec<String> },
+    /// Download a file waiting in manual offer mode
+    AcceptOffer { file_id: String },
+    /// Decline a file waiting in manual offer mode
+    RejectOffer { file_id: String },
 }
 
 /// WebSocket event sender (clone this to broadcast events)
 pub type WsEventSender = broadcast::Sender<WsEvent>;
 
-/// Start WebSocket server on specified address
+/// An event as sent to clients, numbered in the order it happened.
+///
+/// Numbers go up by one with every event the node emits, so a client that
+/// sees one missing knows it missed an event: one the node lost before
+/// numbering it, or a progress event dropped because the client read too
+/// slowly, which an `EventsDropped` reply announces. Replies to the client's
+/// own messages carry no number. Numbers start over when the node restarts.
+#[derive(Debug, Clone, Serialize)]
+struct Sequenced {
+    seq: u64,
+    topic: String,
+    #[serde(flatten)]
+    event: WsEvent,
+}
+
+impl Sequenced {
+    fn new(seq: u64, event: WsEvent) -> Self {
+        Self {
+            seq,
+            topic: event.topic(),
+            event,
+        }
+    }
+}
+
+/// Events waiting to be sent to one client.
+///
+/// A client that reads slower than events happen degrades in steps: a
+/// progress event replaces a queued one of the same kind about the same
+/// file; beyond [`CLIENT_QUEUE_CAPACITY`] new progress events that replace
+/// nothing are dropped; lifecycle events, e.g. a transfer completing, are
+/// always queued, but past [`CLIENT_QUEUE_LIMIT`] the client is dropped.
+#[derive(Default)]
+struct ClientQueue {
+    state: Mutex<QueueState>,
+    ready: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    events: VecDeque<Sequenced>,
+    filter: EventFilter,
+    /// Progress events dropped since the client was last told
+    dropped: u64,
+    overflowed: bool,
+    /// Who the client is and what it was sent, for the node's metrics
+    client: WebSocketClient,
+}
+
+impl ClientQueue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue `event` if the client wants it; false if it had to be dropped
+    fn push(&self, event: &Sequenced) -> bool {
+        let mut state = self.lock();
+        if state.overflowed || !state.filter.matches(&event.event) {
+            return true;
+        }
+        if event.event.is_progress() {
+            let superseded = state
+                .events
+                .iter()
+                .position(|queued| event.event.supersedes(&queued.event));
+            if let Some(index) = superseded {
+                state.events.remove(index);
+            } else if state.events.len() >= CLIENT_QUEUE_CAPACITY {
+                state.dropped += 1;
+                state.client.events_dropped += 1;
+                return false;
+            }
+        } else if state.events.len() >= CLIENT_QUEUE_LIMIT {
+            state.overflowed = true;
+            state.events.clear();
+            self.ready.notify_one();
+            return true;
+        }
+        state.events.push_back(event.clone());
+        self.ready.notify_one();
+        true
+    }
+
+    /// How many events were dropped since last time, and everything
+    /// queued; None once the client fell too far behind
+    fn take(&self) -> Option<(u64, Vec<Sequenced>)> {
+        let mut state = self.lock();
+        if state.overflowed {
+            return None;
+        }
+        let dropped = std::mem::take(&mut state.dropped);
+        state.client.events_sent += state.events.len() as u64;
+        Some((dropped, state.events.drain(..).collect()))
+    }
+
+    /// The client as it stands
+    fn client(&self) -> WebSocketClient {
+        let state = self.lock();
+        WebSocketClient {
+            queued_events: state.events.len(),
+            ..state.client.clone()
+        }
+    }
+}
+
+/// The most recent events, and the queues of connected clients
+struct History {
+    events: VecDeque<Sequenced>,
+    last_seq: u64,
+    clients: Vec<Weak<ClientQueue>>,
+    /// Progress events dropped for slow clients, connected or not
+    events_dropped: u64,
+    /// Clients disconnected for reading too slowly or not answering pings
+    clients_dropped: u64,
+}
+
+impl History {
+    fn new() -> Self {
+        Self {
+            events: VecDeque::with_capacity(EVENT_HISTORY),
+            last_seq: 0,
+            clients: Vec::new(),
+            events_dropped: 0,
+            clients_dropped: 0,
+        }
+    }
+
+    /// Use up the numbers of `count` events that were lost before they
+    /// could be recorded, so clients see the gap
+    fn skip(&mut self, count: u64) {
+        self.last_seq += count;
+    }
+
+    /// Number `event`, keep it and pass it on to clients
+    fn record(&mut self, event: WsEvent) {
+        self.last_seq += 1;
+        let event = Sequenced::new(self.last_seq, event);
+        if self.events.len() == EVENT_HISTORY {
+            self.events.pop_front();
+        }
+        let mut dropped = 0;
+        self.clients.retain(|client| match client.upgrade() {
+            Some(client) => {
+                if !client.push(&event) {
+                    dropped += 1;
+                }
+                true
+            }
+            None => false,
+        });
+        self.events_dropped += dropped;
+        self.events.push_back(event);
+    }
+
+    /// Kept events after `since`, whether any are missing from them, and a
+    /// queue for the events that follow to `client`
+    fn replay(
+        &mut self,
+        since: u64,
+        client: WebSocketClient,
+    ) -> (Vec<Sequenced>, bool, Arc<ClientQueue>) {
+        let events: Vec<Sequenced> = self
+            .events
+            .iter()
+            .filter(|event| event.seq > since)
+            .cloned()
+            .collect();
+        // Numbers past ours come from before a restart; numbers missing in
+        // between were dropped from the history or skipped
+        let truncated = since > self.last_seq
+            || (since + 1..=self.last_seq).ne(events.iter().map(|event| event.seq));
+        let queue = Arc::new(ClientQueue::default());
+        queue.lock().client = client;
+        self.clients.push(Arc::downgrade(&queue));
+        (events, truncated, queue)
+    }
+
+    fn metrics(&self) -> WebSocketMetrics {
+        WebSocketMetrics {
+            clients: self
+                .clients
+                .iter()
+                .filter_map(Weak::upgrade)
+                .map(|client| client.client())
+                .collect(),
+            events_dropped: self.events_dropped,
+            clients_dropped: self.clients_dropped,
+        }
+    }
+}
+
+fn lock(history: &Mutex<History>) -> std::sync::MutexGuard<'_, History> {
+    history.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Numbered events and the clients following them, shared by the REST
+/// API's `/ws` route and the standalone WebSocket server.
+///
+/// Clients get a snapshot of the API state on connecting, and can catch up
+/// on events they missed by connecting with `?since_seq=<seq>`. Once the
+/// shutdown flag becomes true, they are closed with a close frame and given
+/// [`CLOSE_GRACE`] to answer.
+#[derive(Clone)]
+pub struct EventHub {
+    history: Arc<Mutex<History>>,
+    shutdown: watch::Sender<bool>,
+}
+
+impl EventHub {
+    /// Number and keep every event sent on the returned sender, whether or
+    /// not anyone is connected
+    pub fn start(shutdown: watch::Sender<bool>) -> (WsEventSender, Self) {
+        // Create broadcast channel (capacity: 100 events)
+        let (tx, rx) = broadcast::channel::<WsEvent>(100);
+        let history = Arc::new(Mutex::new(History::new()));
+        tokio::spawn(record_events(rx, history.clone()));
+        (tx, Self { history, shutdown })
+    }
+
+    /// Connected clients and how well they keep up
+    pub fn metrics(&self) -> WebSocketMetrics {
+        lock(&self.history).metrics()
+    }
+}
+
+/// Start the standalone WebSocket server on its own address, from before
+/// the REST API served `/ws`
+///
+/// With `tls`, clients connect over `wss://` only. Returns the task
+/// accepting clients, which only finishes on shutdown once every connection
+/// is closed.
 pub async fn start_websocket_server(
     addr: &str,
-) -> Result<WsEventSender, Box<dyn std::error::Error>> {
-    // Create broadcast channel (capacity: 100 events)
-    let (tx, _rx) = broadcast::channel::<WsEvent>(100);
-    let tx_clone = tx.clone();
-
+    hub: EventHub,
+    state: ApiState,
+    tls: Option<Arc<rustls::ServerConfig>>,
+) -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
     let listener = TcpListener::bind(addr).await?;
     info!("🌐 WebSocket server listening on {}", addr);
+    let tls = tls.map(TlsAcceptor::from);
+    let mut shutdown = hub.shutdown.subscribe();
 
     // Spawn task to accept connections
-    tokio::spawn(async move {
+    Ok(tokio::spawn(async move {
+        let mut connections = JoinSet::new();
         loop {
-            match listener.accept().await {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                // Forget connections as they end
+                Some(_) = connections.join_next() => continue,
+                _ = stopped(&mut shutdown) => break,
+            };
+            match accepted {
                 Ok((stream, peer_addr)) => {
-                    info!("📱 WebSocket client connected: {}", peer_addr);
-                    let tx = tx_clone.clone();
+                    let hub = hub.clone();
+                    let state = state.clone();
+                    let tls = tls.clone();
 
-                    tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, tx).await {
+                    connections.spawn(async move {
+                        let result = match tls {
+                            // A client stuck in the handshake is as good as one not reading
+                            Some(tls) => {
+                                match time::timeout(SEND_TIMEOUT, tls.accept(stream)).await {
+                                    Ok(Ok(stream)) => {
+                                        handle_connection(stream, peer_addr, hub, state).await
+                                    }
+                                    Ok(Err(e)) => {
+                                        Err(format!("TLS handshake failed: {}", e).into())
+                                    }
+                                    Err(_) => Err("TLS handshake timed out".into()),
+                                }
+                            }
+                            None => handle_connection(stream, peer_addr, hub, state).await,
+                        };
+                        if let Err(e) = result {
                             warn!("WebSocket connection error: {}", e);
                         }
-                        info!("📱 WebSocket client disconnected: {}", peer_addr);
                     });
                 }
                 Err(e) => {
@@ -95,73 +829,417 @@ pub async fn start_websocket_server(
                 }
             }
         }
-    });
 
-    Ok(tx)
+        // Every connection saw the shutdown too and is saying goodbye
+        drop(listener);
+        if !connections.is_empty() {
+            info!("🌐 Closing {} WebSocket connection(s)", connections.len());
+        }
+        let closed = async { while connections.join_next().await.is_some() {} };
+        if time::timeout(CLOSE_GRACE, closed).await.is_err() {
+            warn!(
+                "{} WebSocket client(s) did not close in time, dropping them",
+                connections.len()
+            );
+            connections.shutdown().await;
+        }
+    }))
+}
+
+/// Pings a client and notices when it stops answering
+#[derive(Debug, Default)]
+struct Keepalive {
+    pings_sent: u64,
+    /// Payload and send time of a ping not yet answered
+    outstanding: Option<(u64, Instant)>,
+    /// Round trip of the last answered ping
+    latency: Option<Duration>,
+}
+
+impl Keepalive {
+    /// Payload of the next ping, or None if the client did not answer the
+    /// last one or anything else since
+    fn ping(&mut self, now: Instant) -> Option<Vec<u8>> {
+        if self.outstanding.is_some() {
+            return None;
+        }
+        self.pings_sent += 1;
+        self.outstanding = Some((self.pings_sent, now));
+        Some(self.pings_sent.to_be_bytes().to_vec())
+    }
+
+    /// The client sent something, so it is alive
+    fn heard(&mut self) {
+        self.outstanding = None;
+    }
+
+    /// The client answered a ping, timing the round trip if it was ours
+    fn pong(&mut self, payload: &[u8], now: Instant) {
+        if let Some((id, sent)) = self.outstanding {
+            if payload == id.to_be_bytes() {
+                self.latency = Some(now.saturating_duration_since(sent));
+            }
+        }
+        self.heard();
+    }
+}
+
+/// Number events from the bridge into `history`
+async fn record_events(mut events: broadcast::Receiver<WsEvent>, history: Arc<Mutex<History>>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => lock(&history).record(event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("WebSocket event history skipped {} events", skipped);
+                lock(&history).skip(skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
 }
 
 /// Handle individual WebSocket connection
-async fn handle_connection(
-    stream: TcpStream,
-    event_tx: WsEventSender,
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    address: SocketAddr,
+    hub: EventHub,
+    state: ApiState,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Upgrade to WebSocket
-    let ws_stream = accept_async(stream).await?;
-    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
-
-    // Subscribe to events
-    let mut event_rx = event_tx.subscribe();
-
-    // Send initial connection confirmation
-    let welcome = WsEvent::NodeStatus {
-        peer_count: 0,
-        active_uploads: 0,
-        active_downloads: 0,
-        timestamp: current_timestamp(),
-    };
-    let msg = serde_json::to_string(&welcome)?;
-    ws_sender.send(Message::Text(msg)).await?;
+    // Upgrade to WebSocket, noting where the client wants to resume from
+    // and how it wants messages framed
+    let mut since_seq = None;
+    let mut encoding = Encoding::default();
+    // The callback's error type is tungstenite's
+    #[allow(clippy::result_large_err)]
+    let ws_stream = accept_hdr_async(stream, |request: &Request, mut response: Response| {
+        since_seq = since_seq_of(request.uri());
+        let offered = request
+            .headers()
+            .get(SEC_WEBSOCKET_PROTOCOL)
+            .and_then(|value| value.to_str().ok());
+        if let Some(chosen) = offered.and_then(Encoding::negotiate) {
+            encoding = chosen;
+            response.headers_mut().insert(
+                SEC_WEBSOCKET_PROTOCOL,
+                HeaderValue::from_static(chosen.protocol()),
+            );
+        }
+        Ok(response)
+    })
+    .await?;
+    hub.serve(ws_stream, address, encoding, since_seq, state)
+        .await
+}
 
-    // Handle both incoming messages and outgoing events
-    loop {
-        tokio::select! {
-            // Receive event from broadcast channel
-            event = event_rx.recv() => {
-                match event {
-                    Ok(evt) => {
-                        let json = serde_json::to_string(&evt)?;
-                        if let Err(e) = ws_sender.send(Message::Text(json)).await {
+impl EventHub {
+    /// Follow a client through to the end of its connection, once its
+    /// WebSocket handshake is done
+    pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        ws_stream: WebSocketStream<S>,
+        address: SocketAddr,
+        encoding: Encoding,
+        since_seq: Option<u64>,
+        state: ApiState,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        info!("📱 WebSocket client connected: {}", address);
+        let result = self
+            .follow(ws_stream, address, encoding, since_seq, state)
+            .await;
+        info!("📱 WebSocket client disconnected: {}", address);
+        result
+    }
+
+    async fn follow<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        ws_stream: WebSocketStream<S>,
+        address: SocketAddr,
+        encoding: Encoding,
+        since_seq: Option<u64>,
+        state: ApiState,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let history = self.history.clone();
+        let mut shutdown = self.shutdown.subscribe();
+        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+        let client = WebSocketClient {
+            address: address.to_string(),
+            protocol: encoding.protocol().to_string(),
+            connected_at: current_timestamp(),
+            ..Default::default()
+        };
+        let (stats, peers, files) = (
+            state.get_stats().await,
+            state.get_peers().await,
+            state.get_files().await,
+        );
+        // Events from here on are replayed or received, none twice
+        let (seq, (replay, history_truncated, queue)) = {
+            let mut history = lock(&history);
+            let since = since_seq.unwrap_or(history.last_seq);
+            (history.last_seq, history.replay(since, client))
+        };
+
+        let snapshot = WsEvent::Snapshot {
+            seq,
+            stats,
+            peers,
+            files,
+            history_truncated,
+            timestamp: current_timestamp(),
+        };
+        ws_sender.send(encoding.frame(&snapshot)?).await?;
+        for event in replay {
+            ws_sender.send(encoding.frame(&event)?).await?;
+        }
+
+        let mut keepalive = Keepalive::default();
+        let mut ping_interval =
+            time::interval_at(time::Instant::now() + PING_INTERVAL, PING_INTERVAL);
+
+        // Handle both incoming messages and outgoing events
+        loop {
+            tokio::select! {
+                // Node is shutting down: send what is queued, then say goodbye
+                // properly and wait for the client to answer
+                _ = stopped(&mut shutdown) => {
+                    let mut frames = Vec::new();
+                    for event in queue.take().map(|(_, events)| events).unwrap_or_default() {
+                        frames.push(encoding.frame(&event)?);
+                    }
+                    frames.push(Message::Close(Some(CloseFrame {
+                        code: CloseCode::Away,
+                        reason: "server shutting down".into(),
+                    })));
+                    let goodbye = async {
+                        for frame in frames {
+                            ws_sender.feed(frame).await?;
+                        }
+                        ws_sender.flush().await?;
+                        // The client's close frame ends the stream
+                        while let Some(Ok(_)) = ws_receiver.next().await {}
+                        Ok::<_, tokio_tungstenite::tungstenite::Error>(())
+                    };
+                    if let Ok(Err(e)) = time::timeout(CLOSE_GRACE, goodbye).await {
+                        debug!("WebSocket client went away while closing: {}", e);
+                    }
+                    break;
+                }
+
+                // Check the client is still there
+                _ = ping_interval.tick() => {
+                    let Some(payload) = keepalive.ping(Instant::now()) else {
+                        warn!("WebSocket client stopped answering pings, dropping it");
+                        lock(&history).clients_dropped += 1;
+                        // Try again later: a client that is merely slow can reconnect
+                        let frame = CloseFrame {
+                            code: CloseCode::Again,
+                            reason: "ping timeout".into(),
+                        };
+                        let _ = time::timeout(SEND_TIMEOUT, ws_sender.send(Message::Close(Some(frame)))).await;
+                        break;
+                    };
+                    ws_sender.send(Message::Ping(payload)).await?;
+                }
+
+                // Send the events queued for the client meanwhile
+                _ = queue.ready.notified() => {
+                    let Some((dropped, events)) = queue.take() else {
+                        warn!("WebSocket client fell too far behind, dropping it");
+                        lock(&history).clients_dropped += 1;
+                        let frame = CloseFrame {
+                            code: CloseCode::Again,
+                            reason: "too slow".into(),
+                        };
+                        let _ = time::timeout(SEND_TIMEOUT, ws_sender.send(Message::Close(Some(frame)))).await;
+                        break;
+                    };
+                    let mut frames = Vec::with_capacity(events.len() + 1);
+                    if dropped > 0 {
+                        debug!("WebSocket client is slow, dropped {} progress events", dropped);
+                        frames.push(encoding.frame(&WsEvent::EventsDropped {
+                            count: dropped,
+                            timestamp: current_timestamp(),
+                        })?);
+                    }
+                    for event in &events {
+                        frames.push(encoding.frame(event)?);
+                    }
+                    let sent = time::timeout(SEND_TIMEOUT, async {
+                        for frame in frames {
+                            ws_sender.feed(frame).await?;
+                        }
+                        ws_sender.flush().await
+                    });
+                    match sent.await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => {
                             warn!("Failed to send event: {}", e);
                             break;
                         }
+                        Err(_) => {
+                            warn!("WebSocket client stopped reading events, dropping it");
+                            lock(&history).clients_dropped += 1;
+                            break;
+                        }
                     }
-                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
-                        warn!("WebSocket client lagged, skipped {} events", skipped);
-                    }
-                    Err(_) => break,
                 }
-            }
 
-            // Receive message from WebSocket client (ping/pong)
-            msg = ws_receiver.next() => {
-                match msg {
-                    Some(Ok(Message::Ping(data))) => {
-                        ws_sender.send(Message::Pong(data)).await?;
-                    }
-                    Some(Ok(Message::Close(_))) | None => {
-                        break;
+                // Receive message from WebSocket client (subscriptions, ping/pong)
+                msg = ws_receiver.next() => {
+                    // Anything but a pong, which is timed below
+                    if let Some(Ok(message)) = &msg {
+                        if !message.is_pong() {
+                            keepalive.heard();
+                        }
                     }
-                    Some(Err(e)) => {
-                        warn!("WebSocket receive error: {}", e);
-                        break;
+                    match msg {
+                        Some(Ok(Message::Pong(payload))) => {
+                            keepalive.pong(&payload, Instant::now());
+                            if let Some(latency) = keepalive.latency {
+                                debug!("WebSocket client ping: {:?}", latency);
+                            }
+                        }
+                        // Clients may write either encoding, whatever they read
+                        Some(Ok(Message::Text(text))) => {
+                            let message = serde_json::from_str(&text).map_err(|e| e.to_string());
+                            let reply = match answer_offer(&message, &state).await {
+                                Some(reply) => reply,
+                                None => handle_client_message(message, &mut queue.lock().filter),
+                            };
+                            ws_sender.send(encoding.frame(&reply)?).await?;
+                        }
+                        Some(Ok(Message::Binary(data))) => {
+                            let message = rmp_serde::from_slice(&data).map_err(|e| e.to_string());
+                            let reply = match answer_offer(&message, &state).await {
+                                Some(reply) => reply,
+                                None => handle_client_message(message, &mut queue.lock().filter),
+                            };
+                            ws_sender.send(encoding.frame(&reply)?).await?;
+                        }
+                        Some(Ok(Message::Ping(data))) => {
+                            ws_sender.send(Message::Pong(data)).await?;
+                        }
+                        Some(Ok(Message::Close(_))) | None => {
+                            break;
+                        }
+                        Some(Err(e)) => {
+                            warn!("WebSocket receive error: {}", e);
+                            break;
+                        }
+                        _ => {}
                     }
-                    _ => {}
                 }
             }
         }
+
+        Ok(())
+    }
+}
+
+/// The `since_seq` query parameter of a connection request
+pub(crate) fn since_seq_of(uri: &Uri) -> Option<u64> {
+    uri.query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("since_seq="))?
+        .parse()
+        .ok()
+}
+
+/// Accept or reject an offer through the node if that is what the client
+/// asked, returning the reply
+async fn answer_offer(
+    message: &Result<ClientMessage, String>,
+    state: &ApiState,
+) -> Option<WsEvent> {
+    let (file_id, result) = match message {
+        Ok(ClientMessage::AcceptOffer { file_id }) => (
+            file_id,
+            state.accept_offer(file_id.clone()).await.map(|_| ()),
+        ),
+        Ok(ClientMessage::RejectOffer { file_id }) => (
+            file_id,
+            state.reject_offer(file_id.clone()).await.map(|_| ()),
+        ),
+        _ => return None,
+    };
+    Some(WsEvent::OfferAnswered {
+        file_id: file_id.clone(),
+        accepted: matches!(message, Ok(ClientMessage::AcceptOffer { .. })),
+        error: result.err().map(|e| e.message().to_string()),
+        timestamp: current_timestamp(),
+    })
+}
+
+/// Apply a client's message, or why it could not be decoded, to its
+/// `filter`, returning the reply
+fn handle_client_message(
+    message: Result<ClientMessage, String>,
+    filter: &mut EventFilter,
+) -> WsEvent {
+    let invalid = |error: String| WsEvent::InvalidMessage {
+        error,
+        timestamp: current_timestamp(),
+    };
+    let wanted = match message {
+        Ok(ClientMessage::Subscribe(wanted)) => wanted,
+        Ok(ClientMessage::Unsubscribe) => EventFilter::default(),
+        Ok(ClientMessage::Join { topics }) => {
+            let mut wanted = filter.clone();
+            for topic in topics {
+                if !wanted.topics.contains(&topic) {
+                    wanted.topics.push(topic);
+                }
+            }
+            wanted
+        }
+        Ok(ClientMessage::Leave { topics }) => EventFilter {
+            topics: filter
+                .topics
+                .iter()
+                .filter(|topic| !topics.contains(topic))
+                .cloned()
+                .collect(),
+            ..filter.clone()
+        },
+        // Answered by `answer_offer` instead
+        Ok(ClientMessage::AcceptOffer { .. } | ClientMessage::RejectOffer { .. }) => {
+            return invalid("Offers are not answered here".to_string())
+        }
+        Err(e) => return invalid(e),
+    };
+    if let Some(unknown) = wanted.topics.iter().find(|pattern| {
+        let root = pattern.split('/').next().unwrap_or_default();
+        !(TOPICS.contains(&root) || root == "*" || root == "**")
+    }) {
+        return invalid(format!(
+            "Unknown topic {}; topics start with one of {}",
+            unknown,
+            TOPICS.join(", ")
+        ));
+    }
+    if let Some(unknown) = wanted
+        .events
+        .iter()
+        .find(|kind| !EVENT_TYPES.contains(&kind.as_str()))
+    {
+        return invalid(format!(
+            "Unknown event type {}; expected one of {}",
+            unknown,
+            EVENT_TYPES.join(", ")
+        ));
+    }
+    *filter = wanted;
+    WsEvent::Subscribed {
+        filter: filter.clone(),
+        timestamp: current_timestamp(),
     }
+}
 
-    Ok(())
+/// Resolves once the node starts shutting down
+async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    // An error means the node is gone, which counts as stopped
+    let _ = shutdown.wait_for(|stop| *stop).await;
 }
 
 /// Get current Unix timestamp
@@ -178,10 +1256,106 @@ mod tests {
 
     #[tokio::test]
     async fn test_websocket_server_starts() {
-        let result = start_websocket_server("127.0.0.1:0").await;
+        let (shutdown_tx, _) = watch::channel(false);
+        let (_, hub) = EventHub::start(shutdown_tx);
+        let result = start_websocket_server("127.0.0.1:0", hub, ApiState::new(), None).await;
         assert!(result.is_ok());
     }
 
+    /// An address nothing is listening on right now
+    fn free_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let addr = free_addr();
+        let (shutdown_tx, _) = watch::channel(false);
+        let (_, hub) = EventHub::start(shutdown_tx.clone());
+        let server = start_websocket_server(&addr, hub.clone(), ApiState::new(), None)
+            .await
+            .unwrap();
+
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}", addr))
+            .await
+            .unwrap();
+        let snapshot = client.next().await.unwrap().unwrap();
+        assert!(snapshot.to_text().unwrap().contains("Snapshot"));
+        let metrics = hub.metrics();
+        assert_eq!(metrics.clients.len(), 1);
+        assert_eq!(metrics.clients[0].protocol, "corelink.json");
+        assert_eq!(metrics.clients[0].queued_events, 0);
+
+        shutdown_tx.send(true).unwrap();
+        match client.next().await {
+            Some(Ok(Message::Close(Some(frame)))) => {
+                assert_eq!(frame.code, CloseCode::Away);
+                assert_eq!(frame.reason, "server shutting down");
+            }
+            other => panic!("Expected a close frame, got {:?}", other),
+        }
+        // Reading on answers the close, after which the server is done
+        assert!(client.next().await.is_none());
+        time::timeout(Duration::from_secs(1), server)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tls() {
+        use crate::config::TlsConfig;
+        use rustls::pki_types::ServerName;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let tls = TlsConfig {
+            cert_path: dir.path().join("cert.pem"),
+            key_path: dir.path().join("key.pem"),
+        };
+        std::fs::write(&tls.cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&tls.key_path, cert.serialize_private_key_pem()).unwrap();
+
+        let addr = free_addr();
+        let (shutdown_tx, _) = watch::channel(false);
+        let (_, hub) = EventHub::start(shutdown_tx);
+        let server_config = tls.server_config().unwrap();
+        let _server = start_websocket_server(&addr, hub, ApiState::new(), Some(server_config))
+            .await
+            .unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(cert.serialize_der().unwrap().into()).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let stream = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        let stream = tokio_rustls::TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        let (mut client, _) = tokio_tungstenite::client_async("wss://localhost/", stream)
+            .await
+            .unwrap();
+        let snapshot = client.next().await.unwrap().unwrap();
+        assert!(snapshot.to_text().unwrap().contains("Snapshot"));
+
+        // Plain WebSocket is not spoken on a TLS port
+        let plain = tokio_tungstenite::connect_async(format!("ws://{}", addr)).await;
+        assert!(plain.is_err());
+
+        let missing = TlsConfig {
+            cert_path: dir.path().join("missing.pem"),
+            key_path: tls.key_path.clone(),
+        };
+        assert!(missing.server_config().is_err());
+    }
+
     #[test]
     fn test_ws_event_serialization() {
         let event = WsEvent::PeerConnected {
@@ -194,4 +1368,366 @@ mod tests {
         assert!(json.contains("PeerConnected"));
         assert!(json.contains("12D3Koo"));
     }
+
+    #[test]
+    fn test_transfer_progress_serialization() {
+        use crate::file_transfer::{TransferState, TransferSummary};
+        use crate::transfer_activity::ActivityState;
+
+        let event = WsEvent::TransferProgress {
+            download: DownloadInfo {
+                transfer: TransferSummary {
+                    file_id: "f1".to_string(),
+                    name: "big.bin".to_string(),
+                    size: 4096,
+                    state: TransferState::Downloading,
+                    chunks_received: 1,
+                    total_chunks: 4,
+                    bytes_received: 1024,
+                    progress: 0.25,
+                    sources: vec!["peer-a".to_string()],
+                },
+                activity: ActivityState::Active,
+                speed: 512.0,
+                eta_seconds: Some(6),
+                peers: Vec::new(),
+            },
+            timestamp: 7,
+        };
+        assert_eq!(event.file_id(), Some("f1"));
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "TransferProgress");
+        assert_eq!(json["file_id"], "f1");
+        assert_eq!(json["bytes_received"], 1024);
+        assert_eq!(json["speed"], 512.0);
+        assert_eq!(json["eta_seconds"], 6);
+        assert_eq!(json["timestamp"], 7);
+    }
+
+    #[test]
+    fn test_keepalive() {
+        let start = Instant::now();
+        let mut keepalive = Keepalive::default();
+        let payload = keepalive.ping(start).unwrap();
+        keepalive.pong(&payload, start + Duration::from_millis(30));
+        assert_eq!(keepalive.latency, Some(Duration::from_millis(30)));
+
+        // Any message shows the client is alive, but only pongs are timed
+        keepalive.ping(start + PING_INTERVAL).unwrap();
+        keepalive.heard();
+        keepalive.ping(start + PING_INTERVAL * 2).unwrap();
+        keepalive.pong(b"stale", start + PING_INTERVAL * 3);
+        assert_eq!(keepalive.latency, Some(Duration::from_millis(30)));
+
+        // Silent until the next ping is due
+        assert!(keepalive.ping(start + PING_INTERVAL * 3).is_some());
+        assert_eq!(keepalive.ping(start + PING_INTERVAL * 4), None);
+    }
+
+    #[test]
+    fn test_history_replay() {
+        let mut history = History::new();
+        let event = |peer_id: &str| WsEvent::PeerDisconnected {
+            peer_id: peer_id.to_string(),
+            timestamp: 0,
+        };
+        for i in 0..EVENT_HISTORY + 5 {
+            history.record(event(&i.to_string()));
+        }
+        assert_eq!(history.last_seq, EVENT_HISTORY as u64 + 5);
+
+        let (events, truncated, _) = history.replay(history.last_seq - 2, Default::default());
+        assert!(!truncated);
+        assert_eq!(
+            events.iter().map(|event| event.seq).collect::<Vec<_>>(),
+            vec![history.last_seq - 1, history.last_seq]
+        );
+        // The first five events were dropped
+        let (events, truncated, _) = history.replay(5, Default::default());
+        assert!(!truncated);
+        assert_eq!(events.len(), EVENT_HISTORY);
+        let (events, truncated, _) = history.replay(4, Default::default());
+        assert!(truncated);
+        assert_eq!(events.len(), EVENT_HISTORY);
+        // From a previous run
+        let (events, truncated, queue) = history.replay(history.last_seq + 10, Default::default());
+        assert!(truncated && events.is_empty());
+
+        history.record(event("new"));
+        let (_, queued) = queue.take().unwrap();
+        let next = &queued[0];
+        assert_eq!(next.seq, EVENT_HISTORY as u64 + 6);
+        let json = serde_json::to_value(next).unwrap();
+        assert_eq!(json["type"], "PeerDisconnected");
+        assert_eq!(json["seq"], EVENT_HISTORY as u64 + 6);
+
+        // Events lost before they were numbered leave a gap
+        let before_gap = history.last_seq;
+        history.skip(3);
+        history.record(event("after gap"));
+        assert_eq!(queue.take().unwrap().1[0].seq, before_gap + 4);
+        let (events, truncated, _) = history.replay(before_gap, Default::default());
+        assert!(truncated);
+        assert_eq!(events.len(), 1);
+        let (_, truncated, _) = history.replay(before_gap + 3, Default::default());
+        assert!(!truncated);
+
+        assert_eq!(since_seq_of(&"/?since_seq=42".parse().unwrap()), Some(42));
+        assert_eq!(since_seq_of(&"/?a=b&since_seq=7".parse().unwrap()), Some(7));
+        assert_eq!(since_seq_of(&"/".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_slow_client_queue() {
+        let chunk = |seq: u64, file_id: &str| {
+            Sequenced::new(
+                seq,
+                WsEvent::ChunkReceived {
+                    file_id: file_id.to_string(),
+                    chunk_index: seq as u32,
+                    peer_id: "peer-a".to_string(),
+                    chunk_size: 1,
+                    bytes_received: seq,
+                    total_bytes: 10_000,
+                    chunks_remaining: 1,
+                    progress: 0.5,
+                    bytes_per_second: 1.0,
+                    timestamp: 0,
+                },
+            )
+        };
+        let removed = |seq: u64| {
+            Sequenced::new(
+                seq,
+                WsEvent::FileRemoved {
+                    file_id: format!("file-{}", seq),
+                    removal: FileRemoval::Withdrawn,
+                    timestamp: 0,
+                },
+            )
+        };
+
+        // Newer progress replaces older about the same file, moving to the back
+        let queue = ClientQueue::default();
+        queue.push(&chunk(1, "a"));
+        queue.push(&removed(2));
+        queue.push(&chunk(3, "a"));
+        queue.push(&chunk(4, "b"));
+        let (dropped, events) = queue.take().unwrap();
+        assert_eq!(dropped, 0);
+        assert_eq!(
+            events.iter().map(|event| event.seq).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+
+        // Full: progress about new files is dropped, lifecycle events kept
+        let mut seq = 10;
+        for _ in 0..CLIENT_QUEUE_CAPACITY {
+            seq += 1;
+            queue.push(&removed(seq));
+        }
+        assert!(!queue.push(&chunk(seq + 1, "c")));
+        assert!(queue.push(&removed(seq + 2)));
+        assert_eq!(queue.client().queued_events, CLIENT_QUEUE_CAPACITY + 1);
+        let (dropped, events) = queue.take().unwrap();
+        assert_eq!(dropped, 1);
+        assert_eq!(queue.client().events_dropped, 1);
+        assert_eq!(events.len(), CLIENT_QUEUE_CAPACITY + 1);
+        assert_eq!(events.last().unwrap().seq, seq + 2);
+
+        // Too far behind on lifecycle events too
+        for seq in 0..=CLIENT_QUEUE_LIMIT as u64 {
+            queue.push(&removed(seq));
+        }
+        assert!(queue.take().is_none());
+    }
+
+    #[test]
+    fn test_network_events() {
+        let metrics = |seq: u64, peer_id: &str| {
+            Sequenced::new(
+                seq,
+                WsEvent::PeerMetrics {
+                    peer_id: peer_id.to_string(),
+                    rtt_ms: Some(12.5),
+                    throughput: None,
+                    bytes_sent: seq,
+                    bytes_received: 0,
+                    timestamp: 0,
+                },
+            )
+        };
+        // Samples only replace older ones about the same peer
+        let queue = ClientQueue::default();
+        queue.push(&metrics(1, "peer-a"));
+        queue.push(&metrics(2, "peer-b"));
+        queue.push(&metrics(3, "peer-a"));
+        let (_, events) = queue.take().unwrap();
+        assert_eq!(
+            events.iter().map(|event| event.seq).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+
+        let error = WsEvent::Error {
+            category: ErrorCategory::Auth,
+            peer_id: Some("peer-a".to_string()),
+            message: "Badly signed chat message".to_string(),
+            timestamp: 0,
+        };
+        assert_eq!(error.peer_id(), Some("peer-a"));
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["type"], "Error");
+        assert_eq!(json["category"], "auth");
+
+        let filter = EventFilter {
+            events: vec!["StaleEpochRejected".to_string()],
+            file_ids: Vec::new(),
+            peer_ids: vec!["peer-b".to_string()],
+            ..Default::default()
+        };
+        let stale = |peer_id: &str| WsEvent::StaleEpochRejected {
+            peer_id: peer_id.to_string(),
+            msg_type: "FileOffer".to_string(),
+            epoch: 2,
+            current_epoch: 3,
+            timestamp: 0,
+        };
+        assert!(filter.matches(&stale("peer-b")));
+        assert!(!filter.matches(&stale("peer-a")));
+        assert!(!filter.matches(&error));
+    }
+
+    #[test]
+    fn test_subscription_filters() {
+        let chunk = |file_id: &str| WsEvent::ChunkReceived {
+            file_id: file_id.to_string(),
+            chunk_index: 0,
+            peer_id: "peer-b".to_string(),
+            chunk_size: 1,
+            bytes_received: 1,
+            total_bytes: 2,
+            chunks_remaining: 1,
+            progress: 0.5,
+            bytes_per_second: 1.0,
+            timestamp: 0,
+        };
+        let offer = WsEvent::FileOffered {
+            peer_id: "peer-a".to_string(),
+            file_id: "file-b".to_string(),
+            name: "b.txt".to_string(),
+            size: 1,
+            chunks: 1,
+            pending: false,
+            timestamp: 0,
+        };
+        let status = WsEvent::NodeStatus {
+            peer_count: 0,
+            active_uploads: 0,
+            active_downloads: 0,
+            timestamp: 0,
+        };
+
+        let json = |text: &str| serde_json::from_str(text).map_err(|e| e.to_string());
+        let mut filter = EventFilter::default();
+        assert!(filter.matches(&status));
+
+        let reply = handle_client_message(
+            json(r#"{"type": "subscribe", "file_ids": ["file-a"], "peer_ids": ["peer-a"]}"#),
+            &mut filter,
+        );
+        assert!(matches!(reply, WsEvent::Subscribed { .. }));
+        assert!(filter.matches(&chunk("file-a")));
+        assert!(!filter.matches(&chunk("file-b")));
+        // Offered by a watched peer, though the file is not watched
+        assert!(filter.matches(&offer));
+        assert!(!filter.matches(&status));
+
+        handle_client_message(
+            json(r#"{"type": "subscribe", "events": ["NodeStatus"]}"#),
+            &mut filter,
+        );
+        assert!(filter.matches(&status));
+        assert!(!filter.matches(&chunk("file-a")));
+
+        // Bad messages leave the filter alone
+        for text in [r#"{"type": "subscribe", "events": ["Nope"]}"#, "hello"] {
+            let reply = handle_client_message(json(text), &mut filter);
+            assert!(matches!(reply, WsEvent::InvalidMessage { .. }));
+            assert_eq!(filter.events, vec!["NodeStatus"]);
+        }
+
+        handle_client_message(json(r#"{"type": "unsubscribe"}"#), &mut filter);
+        assert_eq!(filter, EventFilter::default());
+
+        // Topics are joined and left one by one
+        assert_eq!(chunk("file-a").topic(), "files/file-a");
+        assert_eq!(status.topic(), "node");
+        handle_client_message(
+            json(r#"{"type": "join", "topics": ["files/*", "consensus"]}"#),
+            &mut filter,
+        );
+        assert!(filter.matches(&chunk("file-a")));
+        assert!(!filter.matches(&status));
+        handle_client_message(json(r#"{"type": "join", "topics": ["node"]}"#), &mut filter);
+        handle_client_message(
+            json(r#"{"type": "leave", "topics": ["files/*"]}"#),
+            &mut filter,
+        );
+        assert_eq!(filter.topics, vec!["consensus", "node"]);
+        assert!(filter.matches(&status));
+        assert!(!filter.matches(&chunk("file-a")));
+        let reply = handle_client_message(
+            json(r#"{"type": "join", "topics": ["weather"]}"#),
+            &mut filter,
+        );
+        assert!(matches!(reply, WsEvent::InvalidMessage { .. }));
+
+        assert!(topic_matches("files/*", "files/abc"));
+        assert!(!topic_matches("files/*", "files"));
+        assert!(!topic_matches("files", "files/abc"));
+        assert!(topic_matches("**", "files/abc"));
+        assert!(topic_matches("*", "peers"));
+        assert!(!topic_matches("*", "files/abc"));
+    }
+
+    #[test]
+    fn test_encodings() {
+        assert_eq!(
+            Encoding::negotiate("chat, corelink.msgpack, corelink.json"),
+            Some(Encoding::MessagePack)
+        );
+        assert_eq!(Encoding::negotiate("corelink.json"), Some(Encoding::Json));
+        assert_eq!(Encoding::negotiate("chat"), None);
+
+        let event = Sequenced::new(
+            3,
+            WsEvent::PeerConnected {
+                peer_id: "peer-a".to_string(),
+                address: "/ip4/127.0.0.1/tcp/4001".to_string(),
+                timestamp: 9,
+            },
+        );
+        let Message::Text(text) = Encoding::Json.frame(&event).unwrap() else {
+            panic!("JSON goes in text frames");
+        };
+        let Message::Binary(data) = Encoding::MessagePack.frame(&event).unwrap() else {
+            panic!("MessagePack goes in binary frames");
+        };
+        // Same fields either way
+        let from_json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let from_msgpack: serde_json::Value = rmp_serde::from_slice(&data).unwrap();
+        assert_eq!(from_json, from_msgpack);
+        assert!(data.len() < text.len());
+
+        let subscribe = rmp_serde::to_vec_named(&serde_json::json!({
+            "type": "subscribe",
+            "events": ["PeerConnected"],
+        }))
+        .unwrap();
+        let mut filter = EventFilter::default();
+        let message = rmp_serde::from_slice(&subscribe).map_err(|e| e.to_string());
+        handle_client_message(message, &mut filter);
+        assert_eq!(filter.events, vec!["PeerConnected"]);
+    }
 }